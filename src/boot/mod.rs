@@ -0,0 +1,524 @@
+use core::{slice, str};
+
+use flagset::{flags, FlagSet};
+use spin::Once;
+
+use util::math::align_up_to;
+use x86_64::PhysicalAddress;
+
+/// The maximum amount of memory map entries that are kept. The boot information is parsed before
+/// the heap exists, so all entries are stored inline.
+pub const MAX_MEMORY_REGIONS: usize = 32;
+
+/// The maximum amount of ELF sections that are kept.
+pub const MAX_ELF_SECTIONS: usize = 64;
+
+/// The maximum amount of boot modules that are kept.
+pub const MAX_MODULES: usize = 8;
+
+const TAG_END: u32 = 0;
+const TAG_BOOTLOADER_NAME: u32 = 2;
+const TAG_MODULE: u32 = 3;
+const TAG_MEMORY_MAP: u32 = 6;
+const TAG_FRAMEBUFFER: u32 = 8;
+const TAG_ELF_SECTIONS: u32 = 9;
+
+const MEMORY_MAP_ENTRY_SIZE: usize = 24;
+const ELF_SECTION_HEADER_SIZE: usize = 64;
+
+static BOOT_INFO: Once<BootInfo> = Once::new();
+
+pub type Result<T> = core::result::Result<T, BootInfoError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootInfoError {
+    /// The structure is located at address 0 or is not 8 byte aligned.
+    InvalidAddress,
+    /// The structure or one of its tags is smaller than its contents, or a tag extends past the
+    /// end of the structure.
+    InvalidSize,
+    /// The tag list is not terminated by an end tag.
+    MissingEndTag,
+    MissingMemoryMap,
+    MissingElfSections,
+    /// A tag has more entries than `BootInfo` can hold.
+    TooManyEntries,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegionType {
+    Usable,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    Defective,
+}
+
+impl MemoryRegionType {
+    fn from_u32(value: u32) -> MemoryRegionType {
+        match value {
+            1 => MemoryRegionType::Usable,
+            3 => MemoryRegionType::AcpiReclaimable,
+            4 => MemoryRegionType::AcpiNvs,
+            5 => MemoryRegionType::Defective,
+            _ => MemoryRegionType::Reserved,
+        }
+    }
+}
+
+/// A region of physical memory from the bootloader's memory map.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    start: u64,
+    size: u64,
+    region_type: MemoryRegionType,
+}
+
+impl MemoryRegion {
+    const EMPTY: MemoryRegion = MemoryRegion {
+        start: 0,
+        size: 0,
+        region_type: MemoryRegionType::Reserved,
+    };
+
+    pub fn new(start: u64, size: u64, region_type: MemoryRegionType) -> MemoryRegion {
+        MemoryRegion {
+            start,
+            size,
+            region_type,
+        }
+    }
+
+    pub fn start_address(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the address right after the end of the region.
+    pub fn end_address(&self) -> u64 {
+        self.start + self.size
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn region_type(&self) -> MemoryRegionType {
+        self.region_type
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.region_type == MemoryRegionType::Usable
+    }
+}
+
+flags! {
+    pub enum ElfSectionFlags: u64 {
+        Writable = 1,
+        Allocated = 1 << 1,
+        Executable = 1 << 2,
+    }
+}
+
+/// A section of the kernel ELF file.
+#[derive(Debug, Clone, Copy)]
+pub struct ElfSection {
+    start: u64,
+    size: u64,
+    flags: u64,
+}
+
+impl ElfSection {
+    const EMPTY: ElfSection = ElfSection {
+        start: 0,
+        size: 0,
+        flags: 0,
+    };
+
+    pub fn start_address(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the address right after the end of the section.
+    pub fn end_address(&self) -> u64 {
+        self.start + self.size
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn flags(&self) -> FlagSet<ElfSectionFlags> {
+        FlagSet::new_truncated(self.flags)
+    }
+
+    pub fn is_allocated(&self) -> bool {
+        self.flags().contains(ElfSectionFlags::Allocated)
+    }
+}
+
+/// A module the bootloader has loaded into physical memory, like an initial ramdisk.
+#[derive(Debug, Clone, Copy)]
+pub struct BootModule {
+    start: u64,
+    end: u64,
+    name: Option<&'static str>,
+}
+
+impl BootModule {
+    const EMPTY: BootModule = BootModule {
+        start: 0,
+        end: 0,
+        name: None,
+    };
+
+    pub fn start_address(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.start)
+    }
+
+    /// Returns the address right after the end of the module.
+    pub fn end_address(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.end)
+    }
+
+    pub fn size(&self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    /// Returns the command line the module was loaded with, which is usually its name.
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Returns the contents of the module.
+    ///
+    /// # Safety
+    /// The module has to be identity mapped, which `remap_kernel` does for all modules.
+    pub unsafe fn data(&self) -> &'static [u8] {
+        slice::from_raw_parts(self.start as *const u8, self.size())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramebufferType {
+    Indexed,
+    Rgb,
+    Text,
+}
+
+/// The position of the least significant bit and the size in bits of a color channel in a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ColorField {
+    pub position: u8,
+    pub size: u8,
+}
+
+/// Where the color channels are in the pixels of an RGB framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RgbFields {
+    pub red: ColorField,
+    pub green: ColorField,
+    pub blue: ColorField,
+}
+
+/// The framebuffer the bootloader has set up.
+#[derive(Debug, Clone, Copy)]
+pub struct FramebufferInfo {
+    pub address: PhysicalAddress,
+    pub pitch: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bpp: u8,
+    pub framebuffer_type: FramebufferType,
+    /// Only set for `FramebufferType::Rgb`.
+    pub rgb_fields: RgbFields,
+}
+
+/// The multiboot2 boot information structure, parsed into owned types.
+pub struct BootInfo {
+    start_address: PhysicalAddress,
+    end_address: PhysicalAddress,
+    memory_map: [MemoryRegion; MAX_MEMORY_REGIONS],
+    memory_map_len: usize,
+    elf_sections: [ElfSection; MAX_ELF_SECTIONS],
+    elf_sections_len: usize,
+    modules: [BootModule; MAX_MODULES],
+    modules_len: usize,
+    bootloader_name: Option<&'static str>,
+    framebuffer: Option<FramebufferInfo>,
+}
+
+impl BootInfo {
+    /// Parses the boot information structure at `address`.
+    ///
+    /// # Safety
+    /// `address` has to point to a multiboot2 boot information structure that stays mapped and
+    /// unmodified for the rest of the kernel's lifetime.
+    pub unsafe fn load(address: usize) -> Result<BootInfo> {
+        if address == 0 || address % 8 != 0 {
+            return Err(BootInfoError::InvalidAddress);
+        }
+
+        let total_size = *(address as *const u32) as usize;
+        BootInfo::parse(slice::from_raw_parts(address as *const u8, total_size))
+    }
+
+    /// Parses a boot information structure from `bytes`, which starts with the fixed header.
+    pub fn parse(bytes: &'static [u8]) -> Result<BootInfo> {
+        let total_size = read_u32(bytes, 0)? as usize;
+        if total_size < 16 || total_size > bytes.len() {
+            return Err(BootInfoError::InvalidSize);
+        }
+
+        let start_address = PhysicalAddress::new(bytes.as_ptr() as u64);
+        let mut boot_info = BootInfo {
+            start_address,
+            end_address: PhysicalAddress::new(start_address.as_u64() + total_size as u64),
+            memory_map: [MemoryRegion::EMPTY; MAX_MEMORY_REGIONS],
+            memory_map_len: 0,
+            elf_sections: [ElfSection::EMPTY; MAX_ELF_SECTIONS],
+            elf_sections_len: 0,
+            modules: [BootModule::EMPTY; MAX_MODULES],
+            modules_len: 0,
+            bootloader_name: None,
+            framebuffer: None,
+        };
+
+        let mut has_memory_map = false;
+        let mut has_elf_sections = false;
+        let mut offset = 8;
+
+        loop {
+            if offset + 8 > total_size {
+                return Err(BootInfoError::MissingEndTag);
+            }
+
+            let tag_type = read_u32(bytes, offset)?;
+            let tag_size = read_u32(bytes, offset + 4)? as usize;
+            if tag_size < 8 || offset + tag_size > total_size {
+                return Err(BootInfoError::InvalidSize);
+            }
+
+            let tag = &bytes[offset..offset + tag_size];
+            match tag_type {
+                TAG_END => break,
+                TAG_BOOTLOADER_NAME => boot_info.bootloader_name = parse_string(&tag[8..]),
+                TAG_MODULE => boot_info.parse_module(tag)?,
+                TAG_MEMORY_MAP => {
+                    boot_info.parse_memory_map(tag)?;
+                    has_memory_map = true;
+                }
+                TAG_ELF_SECTIONS => {
+                    boot_info.parse_elf_sections(tag)?;
+                    has_elf_sections = true;
+                }
+                TAG_FRAMEBUFFER => boot_info.framebuffer = Some(parse_framebuffer(tag)?),
+                _ => (),
+            }
+
+            offset += align_up_to(tag_size, 8);
+        }
+
+        if !has_memory_map {
+            return Err(BootInfoError::MissingMemoryMap);
+        }
+
+        if !has_elf_sections {
+            return Err(BootInfoError::MissingElfSections);
+        }
+
+        Ok(boot_info)
+    }
+
+    fn parse_memory_map(&mut self, tag: &[u8]) -> Result<()> {
+        let entry_size = read_u32(tag, 8)? as usize;
+        if entry_size < MEMORY_MAP_ENTRY_SIZE {
+            return Err(BootInfoError::InvalidSize);
+        }
+
+        let mut offset = 16;
+        while offset + entry_size <= tag.len() {
+            if self.memory_map_len == MAX_MEMORY_REGIONS {
+                return Err(BootInfoError::TooManyEntries);
+            }
+
+            self.memory_map[self.memory_map_len] = MemoryRegion {
+                start: read_u64(tag, offset)?,
+                size: read_u64(tag, offset + 8)?,
+                region_type: MemoryRegionType::from_u32(read_u32(tag, offset + 16)?),
+            };
+
+            self.memory_map_len += 1;
+            offset += entry_size;
+        }
+
+        Ok(())
+    }
+
+    fn parse_elf_sections(&mut self, tag: &[u8]) -> Result<()> {
+        let count = read_u32(tag, 8)? as usize;
+        let entry_size = read_u32(tag, 12)? as usize;
+        if entry_size < ELF_SECTION_HEADER_SIZE {
+            return Err(BootInfoError::InvalidSize);
+        }
+
+        for i in 0..count {
+            let offset = 20 + i * entry_size;
+
+            // Section type 0 marks an unused section header.
+            if read_u32(tag, offset + 4)? == 0 {
+                continue;
+            }
+
+            if self.elf_sections_len == MAX_ELF_SECTIONS {
+                return Err(BootInfoError::TooManyEntries);
+            }
+
+            self.elf_sections[self.elf_sections_len] = ElfSection {
+                flags: read_u64(tag, offset + 8)?,
+                start: read_u64(tag, offset + 16)?,
+                size: read_u64(tag, offset + 32)?,
+            };
+
+            self.elf_sections_len += 1;
+        }
+
+        Ok(())
+    }
+
+    fn parse_module(&mut self, tag: &'static [u8]) -> Result<()> {
+        if self.modules_len == MAX_MODULES {
+            return Err(BootInfoError::TooManyEntries);
+        }
+
+        let start = u64::from(read_u32(tag, 8)?);
+        let end = u64::from(read_u32(tag, 12)?);
+        if end < start || tag.len() < 16 {
+            return Err(BootInfoError::InvalidSize);
+        }
+
+        self.modules[self.modules_len] = BootModule {
+            start,
+            end,
+            name: parse_string(&tag[16..]),
+        };
+
+        self.modules_len += 1;
+        Ok(())
+    }
+
+    /// Returns the address of the first byte of the boot information structure.
+    pub fn start_address(&self) -> PhysicalAddress {
+        self.start_address
+    }
+
+    /// Returns the address right after the end of the boot information structure.
+    pub fn end_address(&self) -> PhysicalAddress {
+        self.end_address
+    }
+
+    pub fn memory_map(&self) -> &[MemoryRegion] {
+        &self.memory_map[..self.memory_map_len]
+    }
+
+    pub fn elf_sections(&self) -> &[ElfSection] {
+        &self.elf_sections[..self.elf_sections_len]
+    }
+
+    pub fn modules(&self) -> &[BootModule] {
+        &self.modules[..self.modules_len]
+    }
+
+    /// Returns the module loaded with the name `name`.
+    pub fn module(&self, name: &str) -> Option<&BootModule> {
+        self.modules().iter().find(|module| module.name() == Some(name))
+    }
+
+    pub fn bootloader_name(&self) -> Option<&'static str> {
+        self.bootloader_name
+    }
+
+    pub fn framebuffer(&self) -> Option<&FramebufferInfo> {
+        self.framebuffer.as_ref()
+    }
+
+    /// Returns the lowest start address of all kernel ELF sections.
+    pub fn kernel_start(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.elf_sections().iter().map(|s| s.start_address()).min().unwrap_or(0))
+    }
+
+    /// Returns the highest end address of all kernel ELF sections.
+    pub fn kernel_end(&self) -> PhysicalAddress {
+        PhysicalAddress::new(self.elf_sections().iter().map(|s| s.end_address()).max().unwrap_or(0))
+    }
+}
+
+/// Parses the boot information structure at `address` and stores it, so it can be accessed with
+/// `boot_info` for the rest of the kernel's lifetime.
+///
+/// # Safety
+/// See `BootInfo::load`.
+pub unsafe fn init(address: usize) -> Result<&'static BootInfo> {
+    let boot_info = BootInfo::load(address)?;
+    Ok(BOOT_INFO.call_once(|| boot_info))
+}
+
+/// Returns the boot information, if `init` has been called.
+pub fn boot_info() -> Option<&'static BootInfo> {
+    BOOT_INFO.try()
+}
+
+fn parse_framebuffer(tag: &[u8]) -> Result<FramebufferInfo> {
+    let framebuffer_type = match read_u8(tag, 29)? {
+        0 => FramebufferType::Indexed,
+        1 => FramebufferType::Rgb,
+        _ => FramebufferType::Text,
+    };
+
+    let rgb_fields = if framebuffer_type == FramebufferType::Rgb {
+        let field = |offset| -> Result<ColorField> {
+            Ok(ColorField {
+                position: read_u8(tag, offset)?,
+                size: read_u8(tag, offset + 1)?,
+            })
+        };
+
+        RgbFields {
+            red: field(32)?,
+            green: field(34)?,
+            blue: field(36)?,
+        }
+    } else {
+        RgbFields::default()
+    };
+
+    Ok(FramebufferInfo {
+        address: PhysicalAddress::new(read_u64(tag, 8)?),
+        pitch: read_u32(tag, 16)?,
+        width: read_u32(tag, 20)?,
+        height: read_u32(tag, 24)?,
+        bpp: read_u8(tag, 28)?,
+        framebuffer_type,
+        rgb_fields,
+    })
+}
+
+/// Parses a null terminated string. Returns `None` if it is not valid UTF-8.
+fn parse_string(bytes: &'static [u8]) -> Option<&'static str> {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or_else(|| bytes.len());
+    str::from_utf8(&bytes[..len]).ok()
+}
+
+fn read_u8(bytes: &[u8], offset: usize) -> Result<u8> {
+    bytes.get(offset).cloned().ok_or(BootInfoError::InvalidSize)
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let b = bytes.get(offset..offset + 4).ok_or(BootInfoError::InvalidSize)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64> {
+    let low = u64::from(read_u32(bytes, offset)?);
+    let high = u64::from(read_u32(bytes, offset + 4)?);
+    Ok(high << 32 | low)
+}
\ No newline at end of file
@@ -0,0 +1,2808 @@
+//! The tests that run during boot with the `testutil` feature, which `make test` enables. They
+//! are split in phases that `kmain` runs as soon as the parts they test are set up, and fail the
+//! boot with `kassert!` like the rest of the kernel.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use boot::BootInfo;
+use clock;
+use driver;
+use fs;
+use fs::block::{BlockDevice, CachedBlockDevice, MemBlockDevice};
+use fs::cache::CachedFs;
+use fs::dev::DevFS;
+use fs::dev::console::{LINE_KILL, LineDiscipline};
+use fs::dev::pipe::{PIPE_CAPACITY, PipeDevice};
+use fs::ext2::Ext2Fs;
+use fs::mount::MountFS;
+use fs::ramdisk::{CONTENT_CHUNK_SIZE, DEFAULT_MAX_FILE_SIZE, LockedRamdiskINode, Ramdisk};
+use fs::vfs::{FileSystem, FileType, FsError, INode, PollStatus};
+use gdt;
+use interrupts;
+use klog;
+use loader;
+use memory;
+use memory::frame::AreaFrameAllocator;
+use memory::paging::entry::EntryFlags;
+use memory::paging::{ActivePageTable, Page};
+use panic;
+use panic::PanicAction;
+use percpu;
+use shell;
+use task;
+use task::address_space::{self, AddressSpace};
+use time;
+use util;
+use watchdog;
+use x86_64;
+use x86_64::{PhysicalAddress, VirtualAddress};
+use PANIC_ACTION;
+
+/// Tests the page tables and the frame allocator, right after the kernel was remapped.
+pub fn before_heap(active_table: &mut ActivePageTable, frame_allocator: &mut AreaFrameAllocator<'static>) {
+    let lapic = PhysicalAddress::new(memory::paging::mmio::LAPIC_ADDRESS);
+    crate::kassert!(memory::paging::mmio::find(lapic).is_some());
+    crate::kassert!(active_table.translate_with_flags(VirtualAddress::new(lapic.as_u64()))
+        .map_or(false, |(address, flags)| address.as_u64() == lapic.as_u64() && flags.contains(EntryFlags::NoCache)));
+
+    {
+        use memory::paging::mmio::{MAX_MMIO_REGIONS, MmioError, MmioRegions};
+
+        let mut regions = MmioRegions::new();
+        let address = |offset: usize| PhysicalAddress::new(0x1000_0000 + offset as u64);
+        let spans = |regions: &MmioRegions| {
+            let mut spans: Vec<_> = regions.regions().iter().filter_map(|region| *region)
+                .map(|region| (region.start.as_u64() - 0x1000_0000, region.size))
+                .collect();
+            spans.sort();
+            spans
+        };
+
+        for i in 0..MAX_MMIO_REGIONS {
+            crate::kassert_eq!(regions.add("test", address(i * 0x1_0000), 0x1000), Ok(()));
+        }
+
+        let outside = address(MAX_MMIO_REGIONS * 0x1_0000 + 0x8000);
+        crate::kassert_eq!(regions.add("test", outside, 0x1000), Err(MmioError::TooManyRegions));
+
+        // Ranges that touch or overlap a region grow it instead of taking a slot.
+        crate::kassert_eq!(regions.add("test", address(0x1000), 0x800), Ok(()));
+        crate::kassert_eq!(regions.add("test", address(0x1_0800), 0x1000), Ok(()));
+        crate::kassert_eq!(&spans(&regions)[..2], &[(0, 0x1800), (0x1_0000, 0x1800)]);
+
+        // A range between two regions merges both, which frees a slot.
+        crate::kassert_eq!(regions.add("test", address(0x2_1000), 0xf000), Ok(()));
+        crate::kassert_eq!(spans(&regions).len(), MAX_MMIO_REGIONS - 1);
+        crate::kassert_eq!(spans(&regions)[2], (0x2_0000, 0x1_1000));
+        crate::kassert_eq!(regions.add("test", outside, 0x1000), Ok(()));
+    }
+
+    {
+        use memory::frame::{Frame, FrameAllocator};
+        use memory::paging::table::{Level4, NotRecursive, P4, PageTable, RECURSIVE_ENTRY, RecursivePageTable};
+        use x86_64::registers::control::Cr3;
+
+        let frame = frame_allocator.allocate_zeroed_frame(active_table).unwrap();
+        active_table.with_frame_mapped(Frame(frame.0), frame_allocator, |contents| {
+            let table = contents as *mut _ as *mut PageTable<Level4>;
+            unsafe {
+                crate::kassert_eq!(RecursivePageTable::new(table, Frame(frame.0)).err(), Some(NotRecursive));
+
+                (*table)[RECURSIVE_ENTRY].set(Frame(frame.0 + 1), EntryFlags::Present | EntryFlags::Writable);
+                crate::kassert_eq!(RecursivePageTable::new(table, Frame(frame.0)).err(), Some(NotRecursive));
+
+                // A recursive table that isn't the active one can't be walked through `P4`.
+                (*table)[RECURSIVE_ENTRY].set(Frame(frame.0), EntryFlags::Present | EntryFlags::Writable);
+                crate::kassert_eq!(RecursivePageTable::new(table, Frame(frame.0)).err(), Some(NotRecursive));
+            }
+        });
+
+        let active = Frame::containing_address(Cr3::read());
+        unsafe {
+            crate::kassert_eq!(RecursivePageTable::new(P4, Frame(frame.0)).err(), Some(NotRecursive));
+            crate::kassert!(RecursivePageTable::new(P4, active).is_ok());
+        }
+
+        frame_allocator.deallocate_frame(frame);
+    }
+
+    {
+        use memory::frame::{Frame, FrameAllocator};
+
+        // The frame goes back to the recycle list with its contents, and is handed out again by
+        // the next allocation.
+        let frame = frame_allocator.allocate_frame().unwrap();
+        active_table.with_frame_mapped(Frame(frame.0), frame_allocator, |contents| {
+            for byte in contents.iter_mut() {
+                *byte = 0xaa;
+            }
+        });
+        frame_allocator.deallocate_frame(Frame(frame.0));
+
+        let zeroed = frame_allocator.allocate_zeroed_frame(active_table).unwrap();
+        crate::kassert_eq!(zeroed, frame);
+        crate::kassert!(active_table.with_frame_mapped(Frame(zeroed.0), frame_allocator, |contents| {
+            contents.iter().all(|&byte| byte == 0)
+        }));
+        frame_allocator.deallocate_frame(zeroed);
+    }
+}
+
+/// Tests the heap, the mappings and everything else that uses the page table and the frame
+/// allocator directly, before they are handed to `memory::init_global`.
+pub fn before_global_memory(boot_info: &BootInfo, active_table: &mut ActivePageTable, frame_allocator: &mut AreaFrameAllocator<'static>) {
+    {
+        use boot::{MemoryRegion, MemoryRegionType};
+        use memory::ByteSize;
+
+        let regions = [
+            MemoryRegion::new(0, 0x9_fc00, MemoryRegionType::Usable),
+            MemoryRegion::new(0x9_fc00, 0x400, MemoryRegionType::Reserved),
+            MemoryRegion::new(0x10_0000, 0x7ee_0000, MemoryRegionType::Usable),
+            MemoryRegion::new(0x7fe_0000, 0x2_0000, MemoryRegionType::AcpiReclaimable),
+            MemoryRegion::new(0xfffc_0000, 0x4_0000, MemoryRegionType::Reserved),
+        ];
+        crate::kassert_eq!(memory::usable_bytes(&regions), 0x9_fc00 + 0x7ee_0000);
+        crate::kassert_eq!(memory::usable_bytes(&regions[1..2]), 0);
+
+        crate::kassert_eq!(format!("{}", ByteSize(1023)), "1023 B");
+        crate::kassert_eq!(format!("{}", ByteSize(0x9_fc00)), "639.0 KiB");
+        crate::kassert_eq!(format!("{}", ByteSize(3 * 1024 * 1024 / 2)), "1.5 MiB");
+    }
+
+    {
+        use memory::frame::FrameAllocator;
+
+        let before = memory::stats();
+        crate::kassert_eq!(before.usable_bytes, memory::usable_bytes(boot_info.memory_map()));
+        crate::kassert!(before.total_frames <= before.usable_bytes / memory::PAGE_SIZE);
+
+        let frames = [frame_allocator.allocate_frame().unwrap(), frame_allocator.allocate_frame().unwrap()];
+        let buffer: Vec<u8> = Vec::with_capacity(4096);
+        let during = memory::stats();
+        crate::kassert_eq!((during.used_frames, during.free_frames), (before.used_frames + 2, before.free_frames - 2));
+
+        // The debug heap adds canaries to every allocation, which are counted as well.
+        if !cfg!(feature = "debug_heap") {
+            crate::kassert_eq!((during.heap_used, during.heap_free), (before.heap_used + 4096, before.heap_free - 4096));
+        }
+
+        drop(buffer);
+        for frame in frames.iter() {
+            frame_allocator.deallocate_frame(memory::frame::Frame(frame.0));
+        }
+
+        let after = memory::stats();
+        crate::kassert_eq!((after.used_frames, after.heap_used), (before.used_frames, before.heap_used));
+    }
+
+    {
+        use memory::frame::{Frame, FrameAllocator, RECYCLED_FRAMES};
+
+        // One frame more than the recycle list holds, the last one is counted as leaked.
+        let frames: Vec<Frame> = (0..=RECYCLED_FRAMES).map(|_| frame_allocator.allocate_frame().unwrap()).collect();
+        let (used, leaked) = (memory::frame::used_frames(), memory::frame::leaked_frames());
+
+        for frame in frames {
+            frame_allocator.deallocate_frame(frame);
+        }
+
+        crate::kassert_eq!(memory::frame::leaked_frames(), leaked + 1);
+        crate::kassert_eq!(memory::frame::used_frames(), used - RECYCLED_FRAMES);
+    }
+
+    {
+        use memory::frame::{Frame, FrameAllocator};
+
+        let start = Page::containing_address(VirtualAddress::new(0x7777_0000_0000));
+        let pages = Page::range_inclusive(start, Page(start.0 + 2));
+
+        active_table.map_range(pages.clone(), EntryFlags::Writable | EntryFlags::NoExecute, frame_allocator);
+        let mut frames: Vec<usize> = pages.clone().map(|page| active_table.translate_page(page).unwrap().0).collect();
+        frames.sort();
+        frames.dedup();
+        crate::kassert_eq!(frames.len(), 3);
+
+        active_table.unmap_range(pages.clone(), frame_allocator);
+        crate::kassert!(pages.clone().all(|page| active_table.translate_page(page).is_none()));
+
+        // The frames were handed back, so the next allocations reuse them.
+        let reused: Vec<Frame> = (0..3).map(|_| frame_allocator.allocate_frame().unwrap()).collect();
+        crate::kassert!(reused.iter().all(|frame| frames.contains(&frame.0)));
+
+        for frame in reused {
+            frame_allocator.deallocate_frame(frame);
+        }
+    }
+
+    {
+        use x86_64::instructions::{TLB, TLB_FLUSH_ALL_THRESHOLD};
+
+        let start = Page::containing_address(VirtualAddress::new(0x7777_0001_0000));
+        let mut unmap_pages = |count: usize| {
+            let pages = Page::range_inclusive(start, Page(start.0 + count - 1));
+            active_table.map_range(pages.clone(), EntryFlags::Writable | EntryFlags::NoExecute, frame_allocator);
+
+            let flushes = TLB::full_flushes();
+            active_table.unmap_range(pages, frame_allocator);
+            TLB::full_flushes() - flushes
+        };
+
+        crate::kassert_eq!(unmap_pages(2), 0);
+        crate::kassert_eq!(unmap_pages(TLB_FLUSH_ALL_THRESHOLD), 0);
+        crate::kassert_eq!(unmap_pages(TLB_FLUSH_ALL_THRESHOLD + 1), 1);
+    }
+
+    {
+        let page = Page::containing_address(VirtualAddress::new(0x7777_0002_0000));
+        let address = VirtualAddress::new(page.start_address().as_u64() + 0x123);
+        crate::kassert!(!active_table.is_mapped(address));
+        crate::kassert!(active_table.translate_with_flags(address).is_none());
+
+        active_table.map(page, EntryFlags::NoExecute, frame_allocator);
+        let frame = active_table.translate_page(page).unwrap();
+        crate::kassert!(active_table.is_mapped(address));
+        crate::kassert_eq!(active_table.translate_with_flags(address).map(|(physical, flags)| (physical.as_u64(), flags)),
+            Some((frame.start_address().as_u64() + 0x123, EntryFlags::Present | EntryFlags::NoExecute)));
+
+        active_table.unmap(page, frame_allocator);
+        crate::kassert!(!active_table.is_mapped(address));
+    }
+
+    {
+        use memory::paging::entry::mmio_flags;
+
+        // The range crosses a page boundary, so it takes two pages.
+        let start = Page::containing_address(VirtualAddress::new(0x7777_0003_0000));
+        let physical = PhysicalAddress::new(0xb8ff0);
+        let address = active_table.map_mmio(start, physical, 0x20, frame_allocator);
+        crate::kassert_eq!(address.as_u64(), start.start_address().as_u64() + 0xff0);
+
+        for (i, frame) in [0xb8, 0xb9].iter().enumerate() {
+            let entry = active_table.translate_page_with_flags(Page(start.0 + i));
+            crate::kassert!(entry.map_or(false, |(mapped, flags)| {
+                mapped.0 == *frame && flags.contains(EntryFlags::NoCache | EntryFlags::WriteThrough) && flags.contains(mmio_flags())
+            }));
+        }
+
+        // The frames belong to the device, so they aren't handed to the frame allocator.
+        for i in 0..2 {
+            active_table.unmap_borrowed(Page(start.0 + i));
+        }
+    }
+
+    {
+        use core::ptr;
+        use memory::frame::Frame;
+        use memory::paging::mapper::HUGE_PAGE_PAGES;
+
+        // The first 2 MiB of physical memory, which contain the VGA text buffer.
+        let start = Page::containing_address(VirtualAddress::new(0x7777_0040_0000));
+        active_table.map_huge_to(start, Frame(0), EntryFlags::NoExecute, frame_allocator);
+
+        let middle = Page(start.0 + 0xb8);
+        let entry = active_table.translate_page_with_flags(middle);
+        crate::kassert!(entry.map_or(false, |(frame, flags)| frame.0 == 0xb8 && flags.contains(EntryFlags::HugePage)));
+
+        let address = middle.start_address().as_u64() + 0x12;
+        crate::kassert_eq!(active_table.translate(VirtualAddress::new(address)).map(|address| address.as_u64()), Some(0xb8012));
+        crate::kassert_eq!(unsafe { ptr::read_volatile(address as *const u8) }, unsafe { ptr::read_volatile(0xb8012 as *const u8) });
+
+        // Unmapping any page of the huge page unmaps all of it. The frames aren't ours to free.
+        crate::kassert_eq!(active_table.unmap_borrowed(middle).0, 0);
+        crate::kassert!(!active_table.is_mapped(start.start_address()));
+        crate::kassert!(!active_table.is_mapped(Page(start.0 + HUGE_PAGE_PAGES - 1).start_address()));
+
+        // The P2 entry is free again, so the huge page can be mapped again. Unmapping it with the
+        // allocator leaves its frames alone, the allocator never handed them out.
+        active_table.map_huge_to(start, Frame(0), EntryFlags::NoExecute, frame_allocator);
+        let (used, leaked) = (memory::frame::used_frames(), memory::frame::leaked_frames());
+        active_table.unmap_range(Page::range_inclusive(start, Page(start.0 + 1)), frame_allocator);
+        crate::kassert!(!active_table.is_mapped(middle.start_address()));
+        crate::kassert_eq!((memory::frame::used_frames(), memory::frame::leaked_frames()), (used, leaked));
+
+        active_table.map_huge_to(start, Frame(0), EntryFlags::NoExecute, frame_allocator);
+        active_table.unmap(middle, frame_allocator);
+        crate::kassert_eq!((memory::frame::used_frames(), memory::frame::leaked_frames()), (used, leaked));
+    }
+
+    {
+        use x86_64::instructions::interrupts::{are_enabled, disable, enable, with_disabled};
+
+        crate::kassert!(are_enabled());
+        let states = with_disabled(|| {
+            let inner = with_disabled(are_enabled);
+            (inner, are_enabled())
+        });
+        crate::kassert_eq!(states, (false, false));
+        crate::kassert!(are_enabled());
+
+        // Interrupts that were disabled before stay disabled afterwards.
+        disable();
+        with_disabled(|| ());
+        let still_disabled = !are_enabled();
+        enable();
+        crate::kassert!(still_disabled);
+    }
+
+    {
+        use driver::pic::{self, PIC_1_OFFSET, PICS};
+
+        crate::kassert!(pic::is_spurious(0, 7));
+        crate::kassert!(!pic::is_spurious(1 << 7, 7));
+        crate::kassert!(pic::is_spurious(1 << 7 | 1 << 2, 15));
+        crate::kassert!(!pic::is_spurious(1 << 15 | 1 << 2, 15));
+
+        // Nothing is in service outside of an interrupt handler, so IRQ 7 and 15 look spurious.
+        // Other IRQs never are.
+        let mut pics = PICS.lock();
+        crate::kassert_eq!(pics.read_isr(), 0);
+        crate::kassert!(pics.check_spurious(PIC_1_OFFSET + 7));
+        crate::kassert!(pics.check_spurious(PIC_1_OFFSET + 15));
+        crate::kassert!(!pics.check_spurious(PIC_1_OFFSET));
+    }
+
+    {
+        use interrupts::irq;
+
+        static FIRST_CALLS: AtomicU64 = AtomicU64::new(0);
+        static SECOND_CALLS: AtomicU64 = AtomicU64::new(0);
+
+        fn first() {
+            FIRST_CALLS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn second() {
+            SECOND_CALLS.fetch_add(1, Ordering::SeqCst);
+        }
+
+        // IRQ 5 has no device in QEMU, so only the software interrupts raise it.
+        let raise = || unsafe { asm!("int $$0x25" :::: "volatile") };
+        let calls = || (FIRST_CALLS.load(Ordering::SeqCst), SECOND_CALLS.load(Ordering::SeqCst));
+
+        irq::register_irq(5, first);
+        raise();
+        crate::kassert_eq!(calls(), (1, 0));
+
+        irq::register_irq(5, second);
+        raise();
+        crate::kassert_eq!(calls(), (1, 1));
+
+        irq::unregister_irq(5);
+        raise();
+        crate::kassert_eq!(calls(), (1, 1));
+    }
+
+    {
+        use driver::vga::{ScreenWriter, WRITER};
+
+        // The raw writer works while `WRITER` is locked, and leaves its cursor alone.
+        let writer = WRITER.lock();
+        let cursor = writer.cursor_position();
+        let mut raw = ScreenWriter::raw();
+        raw.write_str_raw("Raw panic output");
+        crate::kassert_eq!(raw.cursor_position(), (16, 24));
+
+        let text: Vec<u8> = (0..16).map(|x| writer.cell(x, 24).0).collect();
+        crate::kassert_eq!(&text[..], b"Raw panic output");
+        crate::kassert_eq!(writer.cursor_position(), cursor);
+    }
+
+    {
+        use util::irq_lock::IrqLock;
+        use x86_64::instructions::interrupts::are_enabled;
+
+        let lock = IrqLock::new(5);
+        {
+            let guard = lock.lock();
+            crate::kassert!(lock.try_lock().is_none());
+            crate::kassert!(!are_enabled());
+            crate::kassert_eq!(*guard, 5);
+        }
+
+        crate::kassert!(are_enabled());
+        let value = lock.try_lock().map(|mut guard| {
+            *guard += 1;
+            (*guard, are_enabled())
+        });
+        crate::kassert_eq!(value, Some((6, false)));
+        crate::kassert!(are_enabled());
+    }
+
+    {
+        use util::math::{align_down_log2, align_down_to, align_up_log2, align_up_to};
+
+        crate::kassert_eq!((align_down_log2(0x1fff, 12), align_up_log2(0x1001, 12)), (0x1000, 0x2000));
+        crate::kassert_eq!((align_down_to(0x1fff, 0x1000), align_up_to(0x1001, 0x1000)), (0x1000, 0x2000));
+        crate::kassert_eq!((align_down_to(0x2000, 0x1000), align_up_to(0x2000, 0x1000)), (0x2000, 0x2000));
+        crate::kassert_eq!((align_up_to(0, 8), align_up_to(1, 1)), (0, 1));
+
+        let address = VirtualAddress::new(0x12_3456);
+        crate::kassert_eq!((address.align_down(0x1000).as_u64(), address.align_up(0x1000).as_u64()), (0x12_3000, 0x12_4000));
+        crate::kassert!(address.is_aligned(2) && !address.is_aligned(4));
+
+        let address = PhysicalAddress::new(0x20_0000);
+        crate::kassert_eq!((address.align_down(0x20_0000).as_u64(), address.align_up(0x20_0000).as_u64()), (0x20_0000, 0x20_0000));
+        crate::kassert!(address.is_aligned(0x20_0000) && !address.is_aligned(0x40_0000));
+    }
+
+    {
+        use util::bitmap::Bitmap;
+
+        let mut bitmap = Bitmap::new([0u8; 3]);
+        crate::kassert_eq!((bitmap.len(), bitmap.is_empty(), bitmap.count_ones()), (24, false, 0));
+
+        bitmap.set(0);
+        bitmap.set(7);
+        bitmap.set(8);
+        bitmap.set(23);
+        crate::kassert!(bitmap.get(7) && bitmap.get(8) && !bitmap.get(9));
+        crate::kassert_eq!(bitmap.count_ones(), 4);
+        crate::kassert_eq!(bitmap.find_first_clear(), Some(1));
+
+        bitmap.clear(8);
+        crate::kassert!(!bitmap.get(8));
+        crate::kassert_eq!(bitmap.count_ones(), 3);
+
+        // Bits 1 to 6 are free, so a run of 7 has to cross into the second and third byte.
+        crate::kassert_eq!(bitmap.find_first_clear_range(6), Some(1));
+        crate::kassert_eq!(bitmap.find_first_clear_range(7), Some(8));
+        crate::kassert_eq!(bitmap.find_first_clear_range(15), Some(8));
+        crate::kassert_eq!(bitmap.find_first_clear_range(16), None);
+        crate::kassert_eq!(bitmap.find_first_clear_range(0), None);
+
+        for index in 0..24 {
+            bitmap.set(index);
+        }
+        crate::kassert_eq!((bitmap.find_first_clear(), bitmap.find_first_clear_range(1)), (None, None));
+        crate::kassert!(Bitmap::new([0u8; 0]).is_empty());
+    }
+
+    {
+        use memory::guard_page;
+
+        // Every interrupt stack comes from the stack allocator, so it has a guard page below it.
+        for &index in gdt::IST_INDICES.iter() {
+            let top = gdt::interrupt_stack(index);
+            let stack = guard_page::stack_containing(VirtualAddress::new(top.as_u64() - 8));
+            crate::kassert!(stack.map_or(false, |stack| stack.stack_top.as_u64() == top.as_u64()), "IST {} at {:?}", index, top);
+            crate::kassert!(!active_table.is_mapped(VirtualAddress::new(stack.unwrap().stack_bottom.as_u64() - 1)));
+        }
+
+        let first = memory::alloc_kernel_stack(active_table, frame_allocator, 2).unwrap();
+        let second = memory::alloc_kernel_stack(active_table, frame_allocator, 1).unwrap();
+        crate::kassert_eq!(first.top().as_u64() - first.bottom().as_u64(), 2 * memory::PAGE_SIZE as u64);
+        crate::kassert_eq!(second.bottom().as_u64(), first.top().as_u64() + memory::PAGE_SIZE as u64);
+        crate::kassert!(active_table.is_mapped(first.bottom()) && !active_table.is_mapped(first.top()));
+        crate::kassert!(memory::alloc_kernel_stack(active_table, frame_allocator, 0).is_none());
+    }
+
+    {
+        use memory::dma::{DMA_POOL_PAGES, DmaError, reserve};
+        use memory::frame::{Frame, FrameAllocator};
+
+        /// Hands out `remaining` frames from `next` on, skipping frame 3, and records frees.
+        struct FakeAllocator {
+            next: usize,
+            remaining: usize,
+            freed: Vec<usize>,
+        }
+
+        impl FrameAllocator for FakeAllocator {
+            fn allocate_frame(&mut self) -> Option<Frame> {
+                if self.remaining == 0 {
+                    return None;
+                }
+
+                let frame = self.next;
+                self.remaining -= 1;
+                self.next += if frame == 2 { 2 } else { 1 };
+                Some(Frame(frame))
+            }
+
+            fn deallocate_frame(&mut self, frame: Frame) {
+                self.freed.push(frame.0);
+            }
+        }
+
+        let mut allocator = FakeAllocator { next: 0, remaining: 10, freed: Vec::new() };
+        crate::kassert_eq!(reserve(&mut allocator), Err(DmaError::OutOfFrames));
+        allocator.freed.sort();
+        crate::kassert_eq!(allocator.freed, (0..11).filter(|&frame| frame != 3).collect::<Vec<_>>());
+
+        // The frames before the gap are freed once the run is complete.
+        let mut allocator = FakeAllocator { next: 0, remaining: DMA_POOL_PAGES + 3, freed: Vec::new() };
+        crate::kassert_eq!(reserve(&mut allocator), Ok(Frame(4)));
+        crate::kassert_eq!(allocator.freed, vec![0, 1, 2]);
+
+        let mut allocator = FakeAllocator { next: 0x1000 - 2, remaining: DMA_POOL_PAGES, freed: Vec::new() };
+        crate::kassert_eq!(reserve(&mut allocator), Err(DmaError::AboveBoundary));
+        allocator.freed.sort();
+        crate::kassert_eq!(allocator.freed, vec![0xffe, 0xfff, 0x1000]);
+    }
+
+    {
+        use memory::frame::{Frame, FrameAllocator};
+        use memory::paging::mapper::MapError;
+
+        /// Hands out at most `remaining` frames of the real allocator, as if memory ran out then.
+        struct Limited<'a> {
+            inner: &'a mut AreaFrameAllocator<'static>,
+            remaining: usize,
+            freed: usize,
+        }
+
+        impl<'a> FrameAllocator for Limited<'a> {
+            fn allocate_frame(&mut self) -> Option<Frame> {
+                if self.remaining == 0 {
+                    return None;
+                }
+
+                self.remaining -= 1;
+                self.inner.allocate_frame()
+            }
+
+            fn deallocate_frame(&mut self, frame: Frame) {
+                self.freed += 1;
+                self.inner.deallocate_frame(frame);
+            }
+        }
+
+        // The P1 table of this page doesn't exist yet, so mapping it takes two frames.
+        let page = Page::containing_address(VirtualAddress::new(0x7777_0080_0000));
+        let flags = EntryFlags::Writable | EntryFlags::NoExecute;
+
+        for &remaining in &[0, 1] {
+            let mut allocator = Limited { inner: &mut *frame_allocator, remaining, freed: 0 };
+            crate::kassert_eq!(active_table.try_map(page, flags, &mut allocator), Err(MapError::OutOfFrames));
+            crate::kassert_eq!(allocator.freed, remaining, "The frame of the page was not returned");
+            crate::kassert!(!active_table.is_mapped(page.start_address()));
+        }
+
+        let mut allocator = Limited { inner: &mut *frame_allocator, remaining: 0, freed: 0 };
+        crate::kassert_eq!(active_table.try_map_zeroed(page, flags, &mut allocator), Err(MapError::OutOfFrames));
+        crate::kassert_eq!(active_table.try_map_to(page, Frame(0xb8), flags, &mut allocator), Err(MapError::OutOfFrames));
+
+        let mut allocator = Limited { inner: &mut *frame_allocator, remaining: 2, freed: 0 };
+        crate::kassert_eq!(active_table.try_map(page, flags, &mut allocator), Ok(()));
+        crate::kassert!(active_table.is_mapped(page.start_address()));
+        active_table.unmap(page, frame_allocator);
+
+        // Nothing is mapped below this P4 entry, so the P3, P2 and P1 tables all have to be created.
+        let page = Page::containing_address(VirtualAddress::new(0x7a00_0000_0000));
+
+        for &remaining in &[1, 2] {
+            let mut allocator = Limited { inner: &mut *frame_allocator, remaining, freed: 0 };
+            crate::kassert_eq!(active_table.try_map_to(page, Frame(0xb8), flags, &mut allocator), Err(MapError::OutOfFrames));
+            crate::kassert_eq!(allocator.freed, remaining, "The new page tables were not freed");
+        }
+
+        // The tables are created again from scratch, so none of them was left behind.
+        let mut allocator = Limited { inner: &mut *frame_allocator, remaining: 3, freed: 0 };
+        crate::kassert_eq!(active_table.try_map_to(page, Frame(0xb8), flags, &mut allocator), Ok(()));
+        crate::kassert_eq!(allocator.remaining, 0);
+        active_table.unmap_borrowed(page);
+    }
+
+    {
+        use boot::{ColorField, RgbFields};
+        use driver::framebuffer::Framebuffer;
+
+        let field = |position, size| ColorField { position, size };
+
+        // A 3x2 framebuffer with 32 bit BGR pixels and a pitch with padding.
+        let mut buffer = vec![0u32; 8];
+        let bgr = RgbFields { red: field(0, 8), green: field(8, 8), blue: field(16, 8) };
+        let mut framebuffer = Framebuffer::new(VirtualAddress::new(buffer.as_mut_ptr() as u64), 3, 2, 16, 32, bgr);
+
+        framebuffer.put_pixel(1, 1, 0x112233);
+        framebuffer.put_pixel(3, 0, 0xffffff);
+        framebuffer.put_pixel(0, 2, 0xffffff);
+        crate::kassert_eq!(buffer, vec![0, 0, 0, 0, 0, 0x332211, 0, 0]);
+
+        // 16 bit 5:6:5 pixels keep the most significant bits of every channel.
+        let rgb565 = RgbFields { red: field(11, 5), green: field(5, 6), blue: field(0, 5) };
+        let framebuffer = Framebuffer::new(VirtualAddress::new(0), 1, 1, 2, 16, rgb565);
+        crate::kassert_eq!(framebuffer.encode(0xff0000), 0xf800);
+        crate::kassert_eq!(framebuffer.encode(0x00ff00), 0x07e0);
+        crate::kassert_eq!(framebuffer.encode(0x0808ff), 0x085f);
+    }
+
+    {
+        use alloc::boxed::Box;
+        use boot::{BootInfo, BootInfoError, ColorField, FramebufferType, MemoryRegionType};
+
+        fn tag(info: &mut Vec<u8>, type_: u32, payload: &[u8]) {
+            info.extend_from_slice(&type_.to_le_bytes());
+            info.extend_from_slice(&(8 + payload.len() as u32).to_le_bytes());
+            info.extend_from_slice(payload);
+            info.resize(util::math::align_up_to(info.len(), 8), 0);
+        }
+
+        fn finish(mut info: Vec<u8>) -> &'static [u8] {
+            let len = (info.len() as u32).to_le_bytes();
+            info[..4].copy_from_slice(&len);
+            Box::leak(info.into_boxed_slice())
+        }
+
+        let mut memory_map = Vec::new();
+        memory_map.extend_from_slice(&24u32.to_le_bytes());
+        memory_map.extend_from_slice(&0u32.to_le_bytes());
+        for &(start, size, type_) in &[(0u64, 0x9_f000u64, 1u32), (0xf_0000, 0x1_0000, 2)] {
+            memory_map.extend_from_slice(&start.to_le_bytes());
+            memory_map.extend_from_slice(&size.to_le_bytes());
+            memory_map.extend_from_slice(&type_.to_le_bytes());
+            memory_map.extend_from_slice(&0u32.to_le_bytes());
+        }
+
+        // Two section headers, the first one is unused.
+        let mut elf_sections = vec![0u8; 12 + 2 * 64];
+        elf_sections[..4].copy_from_slice(&2u32.to_le_bytes());
+        elf_sections[4..8].copy_from_slice(&64u32.to_le_bytes());
+        let section = &mut elf_sections[12 + 64..];
+        section[4..8].copy_from_slice(&1u32.to_le_bytes());
+        section[8..16].copy_from_slice(&3u64.to_le_bytes());
+        section[16..24].copy_from_slice(&0x10_0000u64.to_le_bytes());
+        section[32..40].copy_from_slice(&0x2000u64.to_le_bytes());
+
+        let mut framebuffer = Vec::new();
+        framebuffer.extend_from_slice(&0xfd00_0000u64.to_le_bytes());
+        framebuffer.extend_from_slice(&4096u32.to_le_bytes());
+        framebuffer.extend_from_slice(&1024u32.to_le_bytes());
+        framebuffer.extend_from_slice(&768u32.to_le_bytes());
+        framebuffer.extend_from_slice(&[32, 1, 0, 0, 16, 8, 8, 8, 0, 8]);
+
+        let mut info = vec![0u8; 8];
+        tag(&mut info, 2, b"test loader\0");
+        tag(&mut info, 6, &memory_map);
+        tag(&mut info, 9, &elf_sections);
+        tag(&mut info, 8, &framebuffer);
+        let without_end = info.clone();
+        tag(&mut info, 0, &[]);
+
+        let info = finish(info);
+        let boot_info = BootInfo::parse(info).unwrap();
+        crate::kassert_eq!(boot_info.bootloader_name(), Some("test loader"));
+
+        let regions: Vec<_> = boot_info.memory_map().iter()
+            .map(|region| (region.start_address(), region.end_address(), region.region_type()))
+            .collect();
+        crate::kassert_eq!(regions, vec![(0, 0x9_f000, MemoryRegionType::Usable), (0xf_0000, 0x10_0000, MemoryRegionType::Reserved)]);
+
+        crate::kassert_eq!(boot_info.elf_sections().len(), 1);
+        crate::kassert!(boot_info.elf_sections()[0].is_allocated());
+        crate::kassert_eq!(boot_info.kernel_end(), PhysicalAddress::new(0x10_2000));
+
+        let framebuffer = boot_info.framebuffer().unwrap();
+        crate::kassert_eq!((framebuffer.width, framebuffer.height, framebuffer.bpp), (1024, 768, 32));
+        crate::kassert_eq!(framebuffer.framebuffer_type, FramebufferType::Rgb);
+        crate::kassert_eq!(framebuffer.rgb_fields.red, ColorField { position: 16, size: 8 });
+        crate::kassert_eq!(framebuffer.rgb_fields.blue, ColorField { position: 0, size: 8 });
+
+        crate::kassert_eq!(BootInfo::parse(finish(without_end)).err(), Some(BootInfoError::MissingEndTag));
+
+        let mut no_memory_map = vec![0u8; 8];
+        tag(&mut no_memory_map, 9, &elf_sections);
+        tag(&mut no_memory_map, 0, &[]);
+        crate::kassert_eq!(BootInfo::parse(finish(no_memory_map)).err(), Some(BootInfoError::MissingMemoryMap));
+
+        // The total size in the header is larger than the structure.
+        crate::kassert_eq!(BootInfo::parse(&info[..info.len() - 8]).err(), Some(BootInfoError::InvalidSize));
+    }
+}
+
+/// Tests the drivers, interrupts and the rest of the kernel once the scheduler and the interrupt
+/// controller run. `watchdog_running` is what `watchdog::init` returned.
+pub fn kernel(watchdog_running: bool) {
+    {
+        let start = driver::pit::ticks();
+        for _ in 0..1000 {
+            if driver::pit::ticks() != start {
+                break;
+            }
+
+            x86_64::instructions::interrupts::enable_and_hlt();
+        }
+
+        crate::kassert!(driver::pit::ticks() != start, "The PIT doesn't tick on the current interrupt controller");
+    }
+
+    if watchdog_running {
+        // The checks are raised by unhalted CPU time, which busy waiting uses up.
+        let checks = watchdog::checks();
+        for _ in 0..50 {
+            if watchdog::checks() != checks {
+                break;
+            }
+
+            time::delay_ms(10);
+        }
+
+        crate::kassert!(watchdog::checks() != checks, "The watchdog NMI doesn't fire");
+    }
+
+    {
+        use driver::vga::color::{Color, ColorCode};
+
+        let mut writer = driver::vga::WRITER.lock();
+        let (x, y) = writer.cursor_position();
+
+        writer.set_color(Color::Yellow, Color::Blue);
+        writer.write_string("Colors");
+        crate::kassert_eq!(writer.cell(x, y), (b'C', ColorCode::new(Color::Yellow, Color::Blue)));
+
+        {
+            let mut guard = writer.color_guard();
+            guard.fg(Color::LightRed);
+            guard.write_string("!");
+            crate::kassert_eq!(guard.cell(x + 6, y), (b'!', ColorCode::new(Color::LightRed, Color::Blue)));
+        }
+
+        crate::kassert_eq!(writer.color(), ColorCode::new(Color::Yellow, Color::Blue));
+        writer.reset_color();
+        writer.write_string("\n");
+
+        let start = writer.cursor_position();
+        writer.save_cursor();
+        writer.set_cursor(200, 3);
+        crate::kassert_eq!(writer.cursor_position(), (79, 3));
+        writer.set_color(Color::White, Color::Red);
+        writer.restore_cursor();
+        crate::kassert_eq!((writer.cursor_position(), writer.color()), (start, ColorCode::new(Color::LightGray, Color::Black)));
+
+        // The escapes work in the middle of text: the `!` overwrites the `a`.
+        writer.write_string("\x1b7ab\x1b8\x1b[93m!\x1b[0m");
+        crate::kassert_eq!(writer.cell(start.0, start.1), (b'!', ColorCode::new(Color::Yellow, Color::Black)));
+        crate::kassert_eq!(writer.cell(start.0 + 1, start.1).0, b'b');
+        crate::kassert_eq!(writer.cursor_position(), (start.0 + 1, start.1));
+        writer.write_string("\n");
+    }
+
+    {
+        use driver::vga::{ScreenBuffer, ScreenWriter};
+        use driver::vga::color::{Color, ColorCode};
+
+        let mut writer = ScreenWriter::with_buffer(ScreenBuffer::mock());
+        writer.clear_screen();
+
+        // Rows 0 to 24 get the letters `a` to `y`, the newline after `y` scrolls.
+        for letter in b'a'..=b'y' {
+            writer.write_byte(letter);
+            if letter != b'y' {
+                writer.write_byte(b'\n');
+            }
+        }
+
+        crate::kassert_eq!((writer.cell(0, 0).0, writer.cell(0, 24).0, writer.cursor_position()), (b'a', b'y', (1, 24)));
+
+        writer.set_color(Color::White, Color::Blue);
+        writer.write_string("\n");
+        crate::kassert_eq!(writer.cell(0, 0).0, b'b');
+        crate::kassert_eq!(writer.cell(0, 23).0, b'y');
+        crate::kassert_eq!(writer.cell(0, 24), (b' ', ColorCode::new(Color::White, Color::Blue)));
+        crate::kassert_eq!(writer.cursor_position(), (0, 24));
+
+        // Backspace at the start of a line stays in column 0.
+        writer.write_string("x\x08\x08");
+        crate::kassert_eq!((writer.cell(0, 24).0, writer.cursor_position()), (b' ', (0, 24)));
+    }
+
+    {
+        use driver::vga::{ScreenBuffer, ScreenWriter};
+        use driver::vga::mode::TextMode;
+
+        let mut writer = ScreenWriter::with_buffer(ScreenBuffer::mock());
+        writer.set_mode(TextMode::Mode80x50);
+        crate::kassert_eq!(writer.size(), (80, 50));
+
+        // With 50 rows, the newline after row 24 doesn't scroll yet.
+        for row in 0..50u8 {
+            writer.write_byte(b'0' + row % 10);
+            if row != 49 {
+                writer.write_byte(b'\n');
+            }
+        }
+
+        crate::kassert_eq!((writer.cell(0, 0).0, writer.cell(0, 25).0, writer.cursor_position()), (b'0', b'5', (1, 49)));
+
+        // The newline after row 49 does.
+        writer.write_byte(b'\n');
+        crate::kassert_eq!((writer.cell(0, 0).0, writer.cell(0, 48).0, writer.cell(0, 49).0), (b'1', b'9', b' '));
+        crate::kassert_eq!(writer.cursor_position(), (0, 49));
+
+        // Going back to 25 rows clears the screen and keeps the cursor on it.
+        writer.set_cursor(79, 49);
+        writer.set_mode(TextMode::Mode80x25);
+        crate::kassert_eq!((writer.size(), writer.cursor_position()), ((80, 25), (0, 0)));
+        writer.set_cursor(79, 49);
+        crate::kassert_eq!(writer.cursor_position(), (79, 24));
+    }
+
+    {
+        use driver::vga::{ScreenBuffer, ScreenWriter};
+        use driver::vga::color::Color;
+
+        let mut writer = ScreenWriter::with_buffer(ScreenBuffer::mock());
+        writer.write_string("\x1b[1;31mA\x1b[22mB\x1b[91mC\x1b[22mD");
+
+        let foreground = |x| writer.cell(x, 0).1.foreground();
+        crate::kassert_eq!([foreground(0), foreground(1), foreground(2), foreground(3)],
+                    [Color::LightRed, Color::Red, Color::LightRed, Color::LightRed]);
+    }
+
+    {
+        use driver::vga::{ScreenBuffer, ScreenWriter};
+
+        // Enough lines to scroll, with colors, a backspace and saved cursors.
+        let mut input = String::new();
+        for line in 0..30 {
+            input.push_str(&format!("\x1b[{}mline {}\x1b[0m ab\x08c\x1b7\n", 31 + line % 7, line));
+        }
+        input.push_str("\x1b8end");
+
+        let mut plain = ScreenWriter::with_buffer(ScreenBuffer::mock());
+        plain.clear_screen();
+        plain.write_string(&input);
+
+        let mut batched = ScreenWriter::with_buffer(ScreenBuffer::mock());
+        batched.clear_screen();
+        batched.begin_batch();
+        batched.write_string(&input);
+        batched.flush();
+
+        let (width, height) = plain.size();
+        let same = (0..height).all(|y| (0..width).all(|x| plain.cell(x, y) == batched.cell(x, y)));
+        crate::kassert!(same, "Batched output differs from unbatched output");
+        crate::kassert_eq!(batched.cursor_position(), plain.cursor_position());
+
+        // The panic path builds a writer on an interrupt stack of a single page.
+        crate::kassert!(core::mem::size_of::<ScreenWriter>() < 256);
+    }
+
+    {
+        use driver::vga::ansi::{AnsiParseIterator, AnsiSequencePart::*};
+
+        let parse = |string| AnsiParseIterator::new(string).collect::<Vec<_>>();
+
+        crate::kassert_eq!(parse("\x1b7"), vec![SaveCursor]);
+        crate::kassert_eq!(parse("a\x1b7b\x1b8"), vec![Text("a"), SaveCursor, Text("b"), RestoreCursor]);
+        crate::kassert_eq!(parse("\x1bc"), vec![Reset]);
+        crate::kassert_eq!(parse("\x1b[31m\x1bcx"), vec![SGR(31), Reset, Text("x")]);
+
+        // Unknown escapes are dropped, the character after them is text.
+        crate::kassert_eq!(parse("\x1bx"), vec![Text("x")]);
+
+        crate::kassert_eq!(parse("\x1b[99D"), vec![CursorBack(99)]);
+        crate::kassert_eq!(parse("\x1b[999Dx"), vec![CursorBack(255), Text("x")]);
+        crate::kassert_eq!(parse("\x1b[A\x1b[0B\x1b[2C"), vec![CursorUp(1), CursorDown(1), CursorForward(2)]);
+
+        let mut writer = driver::vga::WRITER.lock();
+        let (_, y) = writer.cursor_position();
+        writer.save_cursor();
+
+        writer.write_string("abc\x1b[99D");
+        crate::kassert_eq!(writer.cursor_position(), (0, y));
+        writer.write_string("\x1b[300C");
+        crate::kassert_eq!(writer.cursor_position(), (79, y));
+        writer.write_string("\x1b[99A");
+        crate::kassert_eq!(writer.cursor_position(), (79, 0));
+        writer.write_string("\x1b[99B\x1b[2D");
+        crate::kassert_eq!(writer.cursor_position(), (77, 24));
+
+        writer.restore_cursor();
+        writer.write_string("   \r");
+    }
+
+    {
+        use core::cell::Cell;
+
+        let exited = Cell::new(None);
+        let rebooted = Cell::new(false);
+
+        panic::run_action(PanicAction::QemuExit(3), |code| exited.set(Some(code)), || rebooted.set(true));
+        crate::kassert_eq!((exited.get(), rebooted.get()), (Some(3), false));
+
+        exited.set(None);
+        panic::run_action(PanicAction::Halt, |code| exited.set(Some(code)), || rebooted.set(true));
+        crate::kassert_eq!((exited.get(), rebooted.get()), (None, false));
+
+        panic::set_action(PanicAction::QemuExit(0x7fff_ffff));
+        crate::kassert_eq!(panic::action(), PanicAction::QemuExit(0x7fff_ffff));
+        panic::set_action(PANIC_ACTION);
+    }
+
+    {
+        use flagset::FlagSet;
+        use x86_64::registers::msr::{ApicBase, ApicBaseFlags, Msr, read_msr};
+
+        let decode = |value| {
+            let (address, flags) = ApicBase::decode(value);
+            (address.as_u64(), flags)
+        };
+
+        let flags = ApicBaseFlags::Enable | ApicBaseFlags::BootstrapProcessor;
+        let value = ApicBase::encode(PhysicalAddress::new(0xfee0_0000), flags);
+        crate::kassert_eq!(value, 0xfee0_0900);
+        crate::kassert_eq!(decode(value), (0xfee0_0000, flags));
+
+        // Reserved bits and the unaligned part of the address are dropped.
+        crate::kassert_eq!(decode(0xfee0_0023 | 1 << 63), (0xfee0_0000, FlagSet::new_truncated(0)));
+        crate::kassert_eq!(ApicBase::encode(PhysicalAddress::new(0xfee0_0fff), ApicBaseFlags::X2ApicEnable), 0xfee0_0400);
+
+        let (address, flags) = ApicBase::read();
+        crate::kassert_eq!(decode(read_msr(Msr::ApicBase)), (address.as_u64(), flags));
+        crate::kassert!(flags.contains(ApicBaseFlags::BootstrapProcessor));
+    }
+
+    {
+        use memory::guard_page;
+        use x86_64::instructions::{read_rbp, read_rsp};
+
+        // The tests run on the boot stack of `kmain`, which `remap_kernel` registered.
+        let rsp = read_rsp();
+        let stack = guard_page::stack_containing(rsp);
+        crate::kassert!(stack.map_or(false, |stack| stack.stack_top.as_u64() - stack.stack_bottom.as_u64() == 32768), "{:?}", rsp);
+        crate::kassert!(stack.map_or(false, |stack| stack.guards(read_rbp())));
+        crate::kassert!(read_rbp().as_u64() >= rsp.as_u64());
+
+        // The boot page tables below the boot stack are unmapped, so the guard starts right below
+        // the stack.
+        let stack_bottom = stack.map_or(0, |stack| stack.stack_bottom.as_u64());
+        for offset in 1..4 {
+            let address = VirtualAddress::new(stack_bottom - offset * memory::PAGE_SIZE as u64);
+            crate::kassert!(guard_page::find(address).map_or(false, |guard| guard.stack_bottom.as_u64() == stack_bottom));
+            crate::kassert!(!memory::with_memory(|active_table, _| active_table.is_mapped(address)));
+        }
+
+        // Three fake frames, the last one ends the chain with a null frame pointer.
+        let mut frames = [0u64; 6];
+        let base = frames.as_ptr() as u64;
+        frames.copy_from_slice(&[base + 16, 0x1111, base + 32, 0x2222, 0, 0x3333]);
+
+        let mut addresses = Vec::new();
+        panic::walk_stack(VirtualAddress::new(base), |_| true, |address| addresses.push(address));
+        crate::kassert_eq!(addresses, vec![0x1111, 0x2222, 0x3333]);
+
+        addresses.clear();
+        panic::walk_stack(VirtualAddress::new(base), |address| address.as_u64() < base + 16, |address| addresses.push(address));
+        crate::kassert_eq!(addresses, vec![0x1111]);
+
+        addresses.clear();
+        panic::walk_stack(VirtualAddress::new(base + 4), |_| true, |address| addresses.push(address));
+        crate::kassert!(addresses.is_empty());
+    }
+
+    {
+        use x86_64::registers::rflags::RFlags;
+
+        let flags = RFlags::read_raw();
+        let with_trap = RFlags::with_trap_flag(flags, true);
+        crate::kassert_eq!(with_trap, flags | 1 << 8);
+        crate::kassert_eq!(RFlags::with_trap_flag(with_trap, false), flags & !(1 << 8));
+        crate::kassert_eq!(RFlags::with_trap_flag(with_trap, true), with_trap);
+
+        // Every instruction while the trap flag is set raises a debug exception, which counts it.
+        {
+            use interrupts::testutil;
+
+            testutil::count_single_steps(true);
+            let steps = testutil::single_steps();
+            let traced = unsafe {
+                RFlags::set_trap_flag(true);
+                let flags = RFlags::read();
+                RFlags::set_trap_flag(false);
+                flags
+            };
+            testutil::count_single_steps(false);
+
+            crate::kassert!(traced.contains(RFlags::TrapFlag));
+            crate::kassert!(!RFlags::read().contains(RFlags::TrapFlag));
+            crate::kassert!(testutil::single_steps() > steps);
+        }
+
+        // The direction flag is cleared again before any other code runs. The arithmetic flags
+        // change with every comparison, so only the other system flags are compared.
+        let system_flags = RFlags::InterruptFlag | RFlags::TrapFlag | RFlags::IOPLHigh | RFlags::IOPLLow;
+        let direction_set = unsafe {
+            RFlags::write(RFlags::read() | RFlags::DirectionFlag);
+            let flags = RFlags::read();
+            RFlags::clear_direction_flag();
+            flags
+        };
+
+        crate::kassert!(direction_set.contains(RFlags::DirectionFlag));
+        crate::kassert!(!RFlags::read().contains(RFlags::DirectionFlag));
+        crate::kassert_eq!(direction_set & system_flags, RFlags::read() & system_flags);
+    }
+
+    {
+        use x86_64::registers::msr::GsBase;
+
+        let per_cpu = percpu::current();
+        crate::kassert_eq!(per_cpu as *const _ as u64, GsBase::read());
+        crate::kassert_eq!(per_cpu.id, 0);
+
+        per_cpu.preempt_count.set(3);
+        crate::kassert_eq!(percpu::current().preempt_count.get(), 3);
+        per_cpu.preempt_count.set(0);
+        crate::kassert!(percpu::try_current().is_some());
+    }
+
+    {
+        use util::preempt_lock::{PreemptMutex, PreemptRwLock};
+
+        // Preemption is disabled exactly while a lock is held, and a failed `try_lock` leaves it
+        // enabled.
+        let count = || percpu::current().preempt_count.get();
+        let mutex = PreemptMutex::new(5);
+        {
+            let guard = mutex.lock();
+            crate::kassert_eq!(count(), 1);
+            crate::kassert!(mutex.try_lock().is_none());
+            crate::kassert_eq!(count(), 1);
+            crate::kassert_eq!(*guard, 5);
+        }
+        crate::kassert_eq!(count(), 0);
+
+        let lock = PreemptRwLock::new(5);
+        {
+            let first = lock.read();
+            let second = lock.read();
+            crate::kassert_eq!(count(), 2);
+            crate::kassert_eq!(*first + *second, 10);
+        }
+        *lock.write() += 1;
+        crate::kassert_eq!(count(), 0);
+        crate::kassert_eq!(*lock.read(), 6);
+    }
+
+    {
+        use driver::uart16550::UART;
+        use driver::vga::WRITER;
+
+        // Both console locks are held, so this would hang if `emergency_print` took either.
+        let writer = WRITER.lock();
+        let _uart = UART.lock();
+        panic::emergency_print(format_args!("Emergency"));
+
+        let text: Vec<u8> = (0..9).map(|x| writer.cell(x, 24).0).collect();
+        crate::kassert_eq!(&text[..], b"Emergency");
+    }
+
+    {
+        use util::ring_buffer::RingBuffer;
+
+        let mut ring = RingBuffer::new([0; 8]);
+        let mut contents = [0; 8];
+        ring.push(b"abc");
+        ring.push(b"def");
+        crate::kassert_eq!(&contents[..ring.read_at(0, &mut contents)], b"abcdef");
+        crate::kassert_eq!(&contents[..ring.read_at(4, &mut contents)], b"ef");
+
+        // The oldest bytes are discarded once the buffer is full.
+        ring.push(b"ghij");
+        crate::kassert_eq!(&contents[..ring.read_at(0, &mut contents)], b"cdefghij");
+        ring.push(b"0123456789");
+        crate::kassert_eq!(&contents[..ring.read_at(0, &mut contents)], b"23456789");
+
+        crate::kprintln!("Testing the kernel message log...");
+        crate::kprintln!("Printed messages are logged in order");
+        crate::kassert!(klog::dmesg().ends_with(
+            b"Testing the kernel message log...\x1b[37m\nPrinted messages are logged in order\x1b[37m\n"
+        ));
+    }
+
+    {
+        use driver::uart16550::UART;
+        use interrupts::InterruptContext;
+
+        // An interrupt handler that prints while the interrupted code holds the serial port lock
+        // must not spin on it. The message is still logged.
+        {
+            let _uart = UART.lock();
+            let _context = InterruptContext::enter();
+            crate::kprintln!("Printed while the serial port is locked");
+        }
+
+        crate::kassert!(!interrupts::in_interrupt());
+        crate::kassert!(klog::dmesg().ends_with(b"Printed while the serial port is locked\x1b[37m\n"));
+    }
+
+    {
+        use driver::uart16550::UART;
+        use x86_64::instructions::interrupts::with_disabled;
+
+        // Every use refers to the same instance, so holding the lock excludes everything else.
+        let first = &UART;
+        let second = &driver::uart16550::UART;
+        crate::kassert!(core::ptr::eq(first, second));
+        {
+            let _uart = first.lock();
+            crate::kassert!(second.try_lock().is_none());
+        }
+
+        // Only the first `init` has an effect, a later one keeps the changed baud rate. Nothing may
+        // be printed while the baud rate is changed.
+        let (baud, after_init) = with_disabled(|| {
+            let mut uart = UART.lock();
+            let baud = uart.baud_rate();
+            uart.set_baud_rate(57600);
+            let _ = uart.init();
+            let after_init = uart.baud_rate();
+            uart.set_baud_rate(baud);
+            (baud, after_init)
+        });
+        crate::kassert_eq!(after_init, 57600);
+        crate::kassert_eq!(UART.lock().baud_rate(), baud);
+    }
+
+    {
+        use core::cell::Cell;
+        use driver::cmos::BytePort;
+        use driver::uart16550::{self, UART16550, UartError};
+
+        /// A scratch register that keeps the last value written to it.
+        struct Scratch(Cell<u8>);
+
+        impl BytePort for Scratch {
+            fn read(&self) -> u8 {
+                self.0.get()
+            }
+
+            fn write(&self, value: u8) {
+                self.0.set(value);
+            }
+        }
+
+        /// A port without a device, reads return the floating bus.
+        struct Floating;
+
+        impl BytePort for Floating {
+            fn read(&self) -> u8 {
+                0xff
+            }
+
+            fn write(&self, _value: u8) {}
+        }
+
+        crate::kassert!(uart16550::probe_scratch(&Scratch(Cell::new(0))));
+        crate::kassert!(!uart16550::probe_scratch(&Floating));
+
+        // QEMU has no fourth serial port. Sending to it returns right away instead of spinning.
+        let mut missing = UART16550::new(0x2e8);
+        crate::kassert_eq!(missing.init(), Err(UartError::Missing));
+        crate::kassert!(!missing.is_present());
+        missing.send_byte(b'x');
+        crate::kassert_eq!(missing.receive_byte(), None);
+    }
+
+    {
+        use core::cell::Cell;
+        use driver::cmos::BytePort;
+        use driver::uart16550;
+
+        /// The registers of a fake UART. In loopback mode, a written byte is received again if
+        /// `echo` is set.
+        struct FakeUart {
+            echo: bool,
+            modem_ctrl: Cell<u8>,
+            received: Cell<Option<u8>>,
+        }
+
+        enum Register {
+            ModemCtrl,
+            LineSts,
+            Data,
+        }
+
+        struct FakePort<'a>(&'a FakeUart, Register);
+
+        impl<'a> BytePort for FakePort<'a> {
+            fn read(&self) -> u8 {
+                let uart = self.0;
+                match self.1 {
+                    Register::ModemCtrl => uart.modem_ctrl.get(),
+                    Register::LineSts => if uart.received.get().is_some() { 0x21 } else { 0x20 },
+                    Register::Data => uart.received.take().unwrap_or(0),
+                }
+            }
+
+            fn write(&self, value: u8) {
+                let uart = self.0;
+                match self.1 {
+                    Register::ModemCtrl => uart.modem_ctrl.set(value),
+                    Register::LineSts => {},
+                    Register::Data => if uart.echo && uart.modem_ctrl.get() & 0x10 != 0 {
+                        uart.received.set(Some(value));
+                    },
+                }
+            }
+        }
+
+        let test = |echo, received| {
+            let uart = FakeUart { echo, modem_ctrl: Cell::new(0), received: Cell::new(received) };
+            let passed = uart16550::loopback_test(
+                &FakePort(&uart, Register::ModemCtrl), &FakePort(&uart, Register::LineSts), &FakePort(&uart, Register::Data));
+            (passed, uart.modem_ctrl.get())
+        };
+
+        crate::kassert_eq!(test(true, None), (true, 0x1e));
+        // A byte that was received before the test is drained, not mistaken for the echo.
+        crate::kassert_eq!(test(true, Some(0x55)), (true, 0x1e));
+        crate::kassert_eq!(test(false, None), (false, 0x1e));
+        crate::kassert_eq!(test(false, Some(0xae)), (false, 0x1e));
+    }
+
+    {
+        use boot::ElfSectionFlags;
+        use flagset::FlagSet;
+        use memory::paging::{protection_violation, section_entry_flags};
+
+        let text = ElfSectionFlags::Allocated | ElfSectionFlags::Executable;
+        let rodata = FlagSet::from(ElfSectionFlags::Allocated);
+        let data = ElfSectionFlags::Allocated | ElfSectionFlags::Writable;
+
+        crate::kassert_eq!(section_entry_flags(text), FlagSet::from(EntryFlags::Present));
+        crate::kassert_eq!(section_entry_flags(rodata), EntryFlags::Present | EntryFlags::NoExecute);
+        crate::kassert_eq!(section_entry_flags(data), EntryFlags::Present | EntryFlags::Writable | EntryFlags::NoExecute);
+
+        for &section in [text, rodata, data].iter() {
+            crate::kassert_eq!(protection_violation(section, section_entry_flags(section)), None);
+        }
+
+        crate::kassert!(protection_violation(text, EntryFlags::Present | EntryFlags::Writable).is_some());
+        crate::kassert!(protection_violation(rodata, EntryFlags::Present | EntryFlags::Writable | EntryFlags::NoExecute).is_some());
+        crate::kassert!(protection_violation(data, EntryFlags::Present | EntryFlags::Writable).is_some());
+        crate::kassert!(protection_violation(data, FlagSet::new_truncated(0)).is_some());
+    }
+
+    {
+        use memory::frame::Frame;
+
+        let pages: Vec<usize> = Page::range_inclusive(Page(usize::MAX - 2), Page(usize::MAX)).map(|page| page.0).collect();
+        crate::kassert_eq!(pages, vec![usize::MAX - 2, usize::MAX - 1, usize::MAX]);
+
+        let frames: Vec<Frame> = Frame::range_inclusive(Frame(usize::MAX - 1), Frame(usize::MAX)).rev().collect();
+        crate::kassert_eq!(frames, vec![Frame(usize::MAX), Frame(usize::MAX - 1)]);
+
+        crate::kassert_eq!(Page::range_inclusive(Page(5), Page(4)).next().map(|page| page.0), None);
+        crate::kassert_eq!(Frame::range_inclusive(Frame(5), Frame(4)).len(), 0);
+        crate::kassert_eq!(Frame::range_inclusive(Frame(0), Frame(0)).len(), 1);
+        crate::kassert_eq!(Frame::range_inclusive(Frame(0), Frame(usize::MAX)).len(), usize::MAX);
+
+        let mut pages = Page::range_inclusive(Page(10), Page(19));
+        crate::kassert_eq!(pages.len(), 10);
+        crate::kassert_eq!(pages.nth(3).map(|page| page.0), Some(13));
+        crate::kassert_eq!(pages.next_back().map(|page| page.0), Some(19));
+        crate::kassert_eq!(pages.len(), 5);
+        crate::kassert_eq!(pages.nth(5).map(|page| page.0), None);
+        crate::kassert_eq!(pages.next().map(|page| page.0), None);
+
+        let mut pages = Page::range_inclusive(Page(usize::MAX - 1), Page(usize::MAX));
+        crate::kassert_eq!(pages.nth(1).map(|page| page.0), Some(usize::MAX));
+        crate::kassert_eq!(pages.next().map(|page| page.0), None);
+    }
+
+    {
+        use memory::frame::Frame;
+
+        let page = Page::containing_address(VirtualAddress::new(0x0080_8060_4123));
+        crate::kassert_eq!(format!("{:?}", page), "Page(V:0x8080604000 [p4: 1, p3: 2, p2: 3, p1: 4])");
+        crate::kassert_eq!(format!("{:?}", Frame::containing_address(PhysicalAddress::new(0xb8123))), "Frame(P:0xb8000)");
+
+        // Without a start address, the index is printed instead of overflowing.
+        crate::kassert_eq!(format!("{:?}", Page(usize::MAX)), format!("Page(#{})", usize::MAX));
+        crate::kassert_eq!(format!("{:?}", Frame(usize::MAX)), format!("Frame(#{})", usize::MAX));
+    }
+
+    {
+        use core::cell::Cell;
+        use x86_64::instructions::{TLB, TLB_FLUSH_ALL_THRESHOLD};
+
+        let flush_range = |start: u64, end: u64| {
+            let flushed = Cell::new(Vec::new());
+            let flushed_all = Cell::new(0);
+
+            TLB::flush_range_with(VirtualAddress::new(start), VirtualAddress::new(end), |address| {
+                let mut addresses = flushed.take();
+                addresses.push(address.as_u64());
+                flushed.set(addresses);
+            }, || flushed_all.set(flushed_all.get() + 1));
+
+            (flushed.into_inner(), flushed_all.get())
+        };
+
+        crate::kassert_eq!(flush_range(0x1800, 0x4000), (vec![0x1000, 0x2000, 0x3000], 0));
+        crate::kassert_eq!(flush_range(0x1000, 0x1001), (vec![0x1000], 0));
+        crate::kassert_eq!(flush_range(0x1000, 0x1000), (vec![], 0));
+        crate::kassert_eq!(flush_range(0, (TLB_FLUSH_ALL_THRESHOLD as u64) * 0x1000).0.len(), TLB_FLUSH_ALL_THRESHOLD);
+        crate::kassert_eq!(flush_range(0, (TLB_FLUSH_ALL_THRESHOLD as u64 + 1) * 0x1000), (vec![], 1));
+
+        let flush_pages = |first: u64, last: u64| {
+            let mut flushed = Vec::new();
+            TLB::flush_pages_with(VirtualAddress::new(first), VirtualAddress::new(last),
+                                  |address| flushed.push(address.as_u64()), || {});
+            flushed
+        };
+
+        crate::kassert_eq!(flush_pages(0x1800, 0x3000), vec![0x1000, 0x2000, 0x3000]);
+        crate::kassert!(flush_pages(0x2000, 0x1fff).is_empty());
+        crate::kassert_eq!(flush_pages(0xffff_ffff_ffff_f000, 0xffff_ffff_ffff_ffff), vec![0xffff_ffff_ffff_f000]);
+    }
+
+    {
+        use driver::vga::cp437::{REPLACEMENT, from_char};
+
+        crate::kassert_eq!(from_char('A'), b'A');
+        crate::kassert_eq!(from_char('é'), 0x82);
+        crate::kassert_eq!(from_char('½'), 0xab);
+        crate::kassert_eq!(from_char('─'), 0xc4);
+        crate::kassert_eq!(from_char('╔'), 0xc9);
+        crate::kassert_eq!(from_char('→'), 0x1a);
+        crate::kassert_eq!(from_char('\u{a0}'), 0xff);
+        crate::kassert_eq!(from_char('©'), REPLACEMENT);
+    }
+
+    {
+        use core::fmt::Write;
+        use panic::Register;
+
+        let format = |register: Register| {
+            let mut out = String::new();
+            write!(out, "{}", register).unwrap();
+            out
+        };
+
+        crate::kassert_eq!(format(Register("R8", 1)), "\x1b[37mR8:  \x1b[97m0x0000000000000001  ");
+        crate::kassert_eq!(format(Register("RAX", 0)).len(), format(Register("R15", u64::max_value())).len());
+        crate::kassert_eq!(format(Register("Stack Pointer", 0x1000)).len(), format(Register("Instruction Pointer", 0)).len());
+    }
+
+    {
+        use macros::RateLimit;
+
+        let printed = (0..3).filter(|i| crate::log_once!("Logged once, at call {}", i)).count();
+        crate::kassert_eq!(printed, 1);
+
+        let limit = RateLimit::new();
+        crate::kassert_eq!(limit.check(100, 0), Some(0));
+        crate::kassert_eq!(limit.check(100, 50), None);
+        crate::kassert_eq!(limit.check(100, 99), None);
+        crate::kassert_eq!(limit.check(100, 100), Some(2));
+        crate::kassert_eq!(limit.check(100, 250), Some(0));
+    }
+
+    {
+        use core::cell::Cell;
+        use watchdog::Watchdog;
+
+        let now = Cell::new(0);
+        let fired = Cell::new(None);
+        let mut watchdog = Watchdog::new(100, 0, || now.get());
+
+        now.set(90);
+        watchdog.check(1, |stalled| fired.set(Some(stalled)));
+        now.set(180);
+        watchdog.check(1, |stalled| fired.set(Some(stalled)));
+        crate::kassert_eq!(fired.get(), None);
+
+        now.set(200);
+        watchdog.check(1, |stalled| fired.set(Some(stalled)));
+        crate::kassert_eq!(fired.get(), Some(110));
+    }
+
+    {
+        use driver::apic::{REG_EOI, REG_TIMER_DIVIDE, register_address, timer_divide_configuration};
+
+        let base = VirtualAddress::new(memory::paging::mmio::LAPIC_ADDRESS);
+        crate::kassert_eq!(register_address(base, REG_EOI).as_u64(), 0xfee0_00b0);
+        crate::kassert_eq!(register_address(base, REG_TIMER_DIVIDE).as_u64(), 0xfee0_03e0);
+
+        crate::kassert_eq!(timer_divide_configuration(1), Some(0b1011));
+        crate::kassert_eq!(timer_divide_configuration(2), Some(0b0000));
+        crate::kassert_eq!(timer_divide_configuration(16), Some(0b0011));
+        crate::kassert_eq!(timer_divide_configuration(32), Some(0b1000));
+        crate::kassert_eq!(timer_divide_configuration(128), Some(0b1010));
+        crate::kassert_eq!(timer_divide_configuration(3), None);
+        crate::kassert_eq!(timer_divide_configuration(256), None);
+    }
+
+    {
+        use driver::mmio::Mmio;
+
+        let mut backing = [0u64; 4];
+        let base = VirtualAddress::from_ptr(backing.as_mut_ptr() as *const u64);
+        unsafe {
+            let bytes = Mmio::<u8>::new(base);
+            let words = Mmio::<u16>::new(base);
+            let doubles = Mmio::<u32>::new(base);
+            let quads = Mmio::<u64>::new(base);
+
+            bytes.write(1, 0xab);
+            words.write(4, 0x1234);
+            doubles.write(8, 0xdead_beef);
+            quads.write(24, 0x0102_0304_0506_0708);
+
+            crate::kassert_eq!(bytes.read(1), 0xab);
+            crate::kassert_eq!(words.read(4), 0x1234);
+            crate::kassert_eq!(doubles.read(12), 0);
+            crate::kassert_eq!(quads.read(8), 0xdead_beef);
+            crate::kassert_eq!(bytes.read(24), 0x08);
+            crate::kassert_eq!(doubles.read(28), 0x0102_0304);
+        }
+
+        crate::kassert_eq!(backing, [0x0000_1234_0000_ab00, 0xdead_beef, 0, 0x0102_0304_0506_0708]);
+    }
+
+    {
+        crate::kassert_eq!(time::pit_cycles(1000), 1194);
+
+        // The tick that is running when the delay starts only partly overlaps it.
+        let start = driver::pit::ticks();
+        time::delay_ms(10);
+        let elapsed = driver::pit::ticks() - start;
+        crate::kassert!(elapsed + 1 >= 10 * driver::pit::TICK_FREQUENCY / 1000, "Delay of 10 ms took {} ticks", elapsed);
+
+        // A running one-shot count doesn't keep the PIT locked, but can't be restarted by anyone
+        // else until it is done.
+        crate::kassert!(driver::pit::PIT.lock().start_one_shot(u16::max_value()));
+        crate::kassert!(!driver::pit::PIT.lock().start_one_shot(1));
+        crate::kassert!(driver::pit::PIT.try_lock().is_some());
+        while !driver::pit::PIT.lock().one_shot_done() {}
+        crate::kassert!(driver::pit::PIT.lock().start_one_shot(1));
+        while !driver::pit::PIT.lock().one_shot_done() {}
+    }
+
+    {
+        use driver::rtc::{self, DateTime};
+
+        crate::kassert_eq!(rtc::bcd_to_binary(0x00), 0);
+        crate::kassert_eq!(rtc::bcd_to_binary(0x09), 9);
+        crate::kassert_eq!(rtc::bcd_to_binary(0x10), 10);
+        crate::kassert_eq!(rtc::bcd_to_binary(0x59), 59);
+        crate::kassert_eq!(rtc::bcd_to_binary(0x99), 99);
+
+        crate::kassert_eq!(rtc::days_since_epoch(1970, 1, 1), 0);
+        crate::kassert_eq!(rtc::days_since_epoch(1969, 12, 31), -1);
+        crate::kassert_eq!(rtc::days_since_epoch(2000, 3, 1), 11_017);
+        crate::kassert_eq!(rtc::days_since_epoch(2024, 2, 29), 19_782);
+
+        let date = DateTime { year: 2021, month: 7, day: 14, hour: 13, minute: 37, second: 42 };
+        crate::kassert_eq!(date.unix_timestamp(), 1_626_269_862);
+
+        // The clock is advanced by the ticks, not by reading the RTC again.
+        let saved = clock::now();
+        clock::set(fs::vfs::Timespec { sec: 1_000_000, nanosec: 0 });
+        time::delay_ms(20);
+        let now = clock::now();
+        crate::kassert!(now.sec == 1_000_000 && now.nanosec >= 10_000_000, "Clock did not advance: {:?}", now);
+        clock::set(saved);
+    }
+
+    {
+        use core::cell::RefCell;
+        use driver::cmos::{BytePort, Cmos, CmosError, NVRAM_START, Settings};
+
+        /// A fake CMOS port that logs every access, with the written value or `None` for reads.
+        struct FakePort<'a> {
+            port: u16,
+            log: &'a RefCell<Vec<(u16, Option<u8>)>>,
+        }
+
+        impl<'a> BytePort for FakePort<'a> {
+            fn read(&self) -> u8 {
+                self.log.borrow_mut().push((self.port, None));
+                0
+            }
+
+            fn write(&self, value: u8) {
+                self.log.borrow_mut().push((self.port, Some(value)));
+            }
+        }
+
+        let log = RefCell::new(Vec::new());
+        let mut cmos = Cmos::new(FakePort { port: 0x70, log: &log }, FakePort { port: 0x71, log: &log });
+        crate::kassert_eq!(cmos.write_nvram(0x40, 0x12), Ok(()));
+        cmos.set_nmi_disabled(true);
+        crate::kassert_eq!(cmos.read_nvram(0x41), Ok(0));
+        crate::kassert_eq!(&log.borrow()[..], &[(0x70, Some(0x40)), (0x71, Some(0x12)), (0x70, Some(0xc1)), (0x71, None)]);
+
+        // The RTC, the BIOS configuration and its checksum are never touched.
+        log.borrow_mut().clear();
+        crate::kassert_eq!(cmos.write_nvram(0x00, 0x12), Err(CmosError::ReservedRegister(0x00)));
+        crate::kassert_eq!(cmos.write_nvram(0x2e, 0x12), Err(CmosError::ReservedRegister(0x2e)));
+        crate::kassert_eq!(cmos.write_nvram(NVRAM_START - 1, 0x12), Err(CmosError::ReservedRegister(NVRAM_START - 1)));
+        crate::kassert_eq!(cmos.read_nvram(0x10), Err(CmosError::ReservedRegister(0x10)));
+        crate::kassert_eq!(cmos.write_nvram(0x80, 0x12), Err(CmosError::ReservedRegister(0x80)));
+        crate::kassert!(log.borrow().is_empty());
+        crate::kassert_eq!(cmos.write_nvram(NVRAM_START, 0x12), Ok(()));
+
+        // The fake NVRAM reads as zeroes, which must not pass as valid settings.
+        crate::kassert!(Settings::load(&mut cmos).is_none());
+    }
+
+    {
+        use driver::pci::config_address;
+
+        crate::kassert_eq!(config_address(0, 0, 0, 0), 0x8000_0000);
+        crate::kassert_eq!(config_address(1, 2, 3, 0x10), 0x8001_1310);
+        crate::kassert_eq!(config_address(255, 31, 7, 0xff), 0x80ff_fffc);
+    }
+
+    {
+        use x86_64::power::try_in_order;
+
+        // Every method is tried in order while they fail.
+        let mut attempted = Vec::new();
+        crate::kassert_eq!(try_in_order(&[3, 1, 2], |method| { attempted.push(method); false }), None);
+        crate::kassert_eq!(&attempted[..], &[3, 1, 2]);
+
+        // Nothing after the first method that works is tried.
+        attempted.clear();
+        crate::kassert_eq!(try_in_order(&[3, 1, 2], |method| { attempted.push(method); method == 1 }), Some(1));
+        crate::kassert_eq!(&attempted[..], &[3, 1]);
+
+        attempted.clear();
+        crate::kassert_eq!(try_in_order(&[3, 1, 2], |method| { attempted.push(method); method == 2 }), Some(2));
+        crate::kassert_eq!(&attempted[..], &[3, 1, 2]);
+
+        crate::kassert_eq!(try_in_order(&[] as &[u8], |_| true), None);
+    }
+
+    {
+        use interrupts::exceptions::{DescriptorTable, ErrorCode, PageFaultErrorCode, SelectorErrorCode};
+
+        // A write from user space to a present page.
+        crate::kassert_eq!(ErrorCode::decode(0x0e, 0b111), ErrorCode::PageFault(
+            PageFaultErrorCode::ProtectionViolation | PageFaultErrorCode::Write | PageFaultErrorCode::UserSpace
+        ));
+        crate::kassert_eq!(ErrorCode::decode(0x0e, 0b10000), ErrorCode::PageFault(PageFaultErrorCode::InstructionFetch.into()));
+
+        crate::kassert_eq!(ErrorCode::decode(0x0d, 0x1a), ErrorCode::Selector(Some(SelectorErrorCode {
+            external: false,
+            table: DescriptorTable::Idt,
+            index: 3,
+        })));
+        crate::kassert_eq!(ErrorCode::decode(0x0d, 0), ErrorCode::Selector(None));
+        crate::kassert_eq!(ErrorCode::decode(0x08, 0), ErrorCode::Other(0));
+    }
+
+    {
+        use debug::gdbstub::{Packet, REGISTERS_SIZE, checksum, parse_hex, read_registers, write_packet, write_registers};
+        use gdt::SegmentSelector;
+        use interrupts::StackFrame;
+
+        crate::kassert_eq!(checksum(b"OK"), 0x9a);
+        crate::kassert_eq!(parse_hex(b"ffff8000"), Some(0xffff_8000));
+        crate::kassert_eq!(parse_hex(b"12g"), None);
+
+        let mut framed = Vec::new();
+        write_packet(b"OK", |byte| framed.push(byte));
+        crate::kassert_eq!(&framed[..], b"$OK#9a");
+
+        framed.clear();
+        write_packet(b"a}b$", |byte| framed.push(byte));
+        crate::kassert_eq!(&framed[..], b"$a}]b}\x04#1e");
+
+        // rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8-r15 and rip, then eflags, cs and ss.
+        let frame = StackFrame {
+            rax: 1, rbx: 2, rcx: 3, rdx: 4, rsi: 5, rdi: 6, rbp: 7,
+            stack_pointer: VirtualAddress::new(8),
+            r8: 9, r9: 10, r10: 11, r11: 12, r12: 13, r13: 14, r14: 15, r15: 16,
+            instruction_pointer: VirtualAddress::new(17),
+            cpu_flags: 0x202,
+            code_segment: SegmentSelector(0x08),
+            stack_segment: SegmentSelector(0x10),
+            ..Default::default()
+        };
+
+        let mut packet = Packet::new();
+        write_registers(&frame, &mut packet);
+        let registers = packet.as_bytes();
+
+        crate::kassert_eq!(registers.len(), REGISTERS_SIZE * 2);
+        crate::kassert_eq!(&registers[..16], b"0100000000000000");
+        crate::kassert_eq!(&registers[7 * 16..8 * 16], b"0800000000000000");
+        crate::kassert_eq!(&registers[15 * 16..16 * 16], b"1000000000000000");
+        crate::kassert_eq!(&registers[16 * 16..17 * 16], b"1100000000000000");
+        crate::kassert_eq!(&registers[17 * 16..17 * 16 + 24], b"020200000800000010000000");
+
+        let mut copy = StackFrame::default();
+        crate::kassert!(read_registers(&mut copy, registers));
+        crate::kassert_eq!((copy.rax, copy.rbp, copy.r15, copy.cpu_flags), (1, 7, 16, 0x202));
+        crate::kassert_eq!(copy.stack_pointer.as_u64(), 8);
+        crate::kassert_eq!(copy.instruction_pointer.as_u64(), 17);
+    }
+}
+
+/// Tests the filesystems and devices. `root` is the filesystem the kernel booted with, which has
+/// `devfs` mounted at `/dev`.
+pub fn filesystem(root: &Arc<MountFS>, devfs: &Arc<DevFS>) {
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting filesystems...");
+    let ramdisk = Ramdisk::new();
+    {
+        let inode = ramdisk.root().create("hello.txt", FileType::File, 0o777)
+            .expect("Error while creating inode for 'hello.txt'");
+        inode.write_at(0, b"This is a file!").unwrap();
+
+        crate::kassert_eq!(inode.write_at(usize::max_value(), b"overflow"), Err(FsError::InvalidArgument));
+        crate::kassert_eq!(inode.write_at(DEFAULT_MAX_FILE_SIZE, b"too large"), Err(FsError::FileTooLarge));
+        crate::kassert_eq!(inode.read_at(usize::max_value(), &mut [0; 8]), Ok(0));
+
+        let mut content = Vec::new();
+        crate::kassert_eq!(inode.read_until_eof_into(&mut content), Ok(15));
+        crate::kassert_eq!(&content[..], b"This is a file!");
+    }
+
+    {
+        use fs::inode_id::INodeIdAllocator;
+
+        let ids = INodeIdAllocator::new();
+        let (first, second) = (ids.alloc(), ids.alloc());
+        crate::kassert_eq!((first, second, ids.live_count()), (1, 2, 2));
+        ids.free(first);
+        crate::kassert_eq!((ids.alloc(), ids.alloc(), ids.live_count()), (1, 3, 3));
+
+        let ramdisk = Ramdisk::new();
+        let root = ramdisk.root();
+        let id = |name| root.find(name).unwrap().metadata().unwrap().inode;
+
+        root.create("a", FileType::File, 0o644).unwrap();
+        root.create("b", FileType::File, 0o644).unwrap();
+        let (a, b) = (id("a"), id("b"));
+        crate::kassert!(a != b && a != root.metadata().unwrap().inode);
+
+        // Once the last reference is gone the id is free, and the next inode reuses it.
+        root.unlink("a").unwrap();
+        root.create("c", FileType::File, 0o644).unwrap();
+        crate::kassert_eq!(id("c"), a);
+
+        let mut live = vec![root.metadata().unwrap().inode, id("b"), id("c")];
+        live.sort();
+        live.dedup();
+        crate::kassert_eq!(live.len(), 3);
+        crate::kassert_eq!(ramdisk.metadata().files, 3);
+    }
+
+    {
+        use fs::vfs::Timespec;
+
+        let file = Ramdisk::new().root().create("atime", FileType::File, 0o644).unwrap();
+        file.write_at(0, b"data").unwrap();
+
+        let mut metadata = file.metadata().unwrap();
+        metadata.access_time = Timespec { sec: 0, nanosec: 0 };
+        file.set_metadata(metadata).unwrap();
+        crate::kassert_eq!(file.metadata().unwrap().access_time.sec, 0);
+
+        // A read only needs the read lock, so it works while another reader holds it, and it
+        // still updates the access time.
+        let mut buf = [0; 4];
+        {
+            let _reader = file.downcast_ref::<LockedRamdiskINode>().unwrap().read();
+            crate::kassert_eq!(file.read_at(0, &mut buf), Ok(4));
+        }
+
+        crate::kassert_eq!(&buf, b"data");
+        crate::kassert!(file.metadata().unwrap().access_time.sec > 0);
+    }
+
+    {
+        // The same layout in every filesystem, so "dir" has the same inode id in all of them.
+        let filesystem = |marker| {
+            let ramdisk = Ramdisk::new();
+            ramdisk.root().create("dir", FileType::Directory, 0o755).unwrap();
+            ramdisk.root().create(marker, FileType::File, 0o644).unwrap();
+            ramdisk
+        };
+
+        let a = filesystem("a");
+        let mountpoint_id = a.root().find("dir").unwrap().metadata().unwrap().inode;
+        let root = MountFS::new(a);
+        root.root().find("dir").unwrap().mount(filesystem("b")).unwrap();
+
+        let dir = root.root().find("dir").unwrap();
+        let inner = dir.find("dir").unwrap();
+        crate::kassert_eq!(inner.metadata().unwrap().inode, mountpoint_id);
+        crate::kassert!(dir.find("b").is_ok());
+
+        // The inner "dir" has the id of the mountpoint, but isn't one.
+        crate::kassert!(inner.find("b").is_err());
+        crate::kassert_eq!(inner.get_entry(2), Err(FsError::EntryNotFound));
+
+        inner.mount(filesystem("c")).unwrap();
+        crate::kassert!(root.root().resolve_follow("dir/dir/c", 0).is_ok());
+        crate::kassert!(root.root().resolve_follow("dir/b", 0).is_ok());
+    }
+
+    {
+        use flagset::FlagSet;
+        use fs::file::{File, OpenFlags};
+
+        let ramdisk = Ramdisk::new();
+        let inode = ramdisk.root().create("log", FileType::File, 0o644).unwrap();
+        crate::kassert_eq!(inode.append(b"a"), Ok(1));
+        crate::kassert_eq!(inode.append(b"bc"), Ok(2));
+
+        // Both handles start at offset 0, but every append lands after the other's writes.
+        let mut first = File::new(inode.clone(), OpenFlags::Append);
+        let mut second = File::new(inode.clone(), OpenFlags::Append);
+        crate::kassert_eq!(first.write(b"de"), Ok(2));
+        crate::kassert_eq!(second.write(b"f"), Ok(1));
+        crate::kassert_eq!(first.write(b"g"), Ok(1));
+        crate::kassert_eq!((first.offset(), second.offset()), (7, 6));
+
+        // Without the flag, the write goes to the offset and overwrites.
+        let mut plain = File::new(inode.clone(), FlagSet::new_truncated(0));
+        crate::kassert_eq!(plain.write(b"x"), Ok(1));
+
+        let mut contents = [0; 8];
+        crate::kassert_eq!(inode.read_at(0, &mut contents), Ok(7));
+        crate::kassert_eq!(&contents[..7], b"xbcdefg");
+    }
+
+    {
+        use fs::file::OpenFlags;
+
+        let ramdisk = Ramdisk::new();
+        let root = ramdisk.root();
+        root.create("dir", FileType::Directory, 0o755).unwrap();
+
+        crate::kassert_eq!(fs::open(&root, "dir/new", OpenFlags::Truncate, 0o644).err(), Some(FsError::EntryNotFound));
+
+        let mut file = fs::open(&root, "dir/new", OpenFlags::Create | OpenFlags::Exclusive, 0o644).unwrap();
+        crate::kassert_eq!(file.write(b"hello"), Ok(5));
+        crate::kassert_eq!(file.inode().metadata().unwrap().permissions, 0o644);
+
+        // Creating an existing file exclusively fails and leaves it alone.
+        let existing = fs::open(&root, "dir/new", OpenFlags::Create | OpenFlags::Exclusive, 0o644);
+        crate::kassert_eq!(existing.err(), Some(FsError::EntryExists));
+
+        let file = fs::open(&root, "dir/new", OpenFlags::Create, 0o644).unwrap();
+        crate::kassert_eq!((file.offset(), file.inode().metadata().unwrap().size), (0, 5));
+
+        let mut file = fs::open(&root, "dir/new", OpenFlags::Create | OpenFlags::Append, 0o644).unwrap();
+        crate::kassert_eq!(file.offset(), 5);
+        crate::kassert_eq!(file.write(b"!"), Ok(1));
+
+        let file = fs::open(&root, "dir/new", OpenFlags::Truncate, 0o644).unwrap();
+        crate::kassert_eq!(file.inode().metadata().unwrap().size, 0);
+
+        crate::kassert_eq!(fs::open(&root, "dir", OpenFlags::Truncate, 0).err(), Some(FsError::IsDirectory));
+    }
+
+    {
+        let file = ramdisk.root().create("append.txt", FileType::File, 0o777).unwrap();
+        let mut capacity = 0;
+        let mut reallocations = 0;
+
+        for i in 0..1000 {
+            file.write_at(i * 10, b"0123456789").unwrap();
+
+            let current = file.downcast_ref::<LockedRamdiskINode>().unwrap().read().capacity();
+            if current != capacity {
+                capacity = current;
+                reallocations += 1;
+            }
+        }
+
+        crate::kassert_eq!(file.metadata().unwrap().size, 10_000);
+        crate::kassert!(reallocations <= 3, "1000 small writes reallocated {} times", reallocations);
+
+        // A write far past the end only allocates the chunk it lands in, the hole reads as zeroes.
+        let sparse = ramdisk.root().create("sparse.txt", FileType::File, 0o777).unwrap();
+        sparse.write_at(1_000_000, b"end").unwrap();
+        crate::kassert_eq!(sparse.metadata().unwrap().size, 1_000_003);
+        crate::kassert_eq!(sparse.downcast_ref::<LockedRamdiskINode>().unwrap().read().capacity(), CONTENT_CHUNK_SIZE);
+
+        let mut hole = [0xff; 16];
+        crate::kassert_eq!(sparse.read_at(500_000, &mut hole), Ok(16));
+        crate::kassert_eq!(hole, [0; 16]);
+
+        ramdisk.root().unlink("append.txt").unwrap();
+        ramdisk.root().unlink("sparse.txt").unwrap();
+    }
+
+    {
+        let ramdisk = Ramdisk::new();
+        let root_dir = ramdisk.root();
+        root_dir.create("old.txt", FileType::File, 0o777).unwrap().write_at(0, b"old").unwrap();
+        root_dir.create("new.txt", FileType::File, 0o777).unwrap().write_at(0, b"new").unwrap();
+        root_dir.move_("new.txt", &root_dir, "old.txt").unwrap();
+
+        let mut content = Vec::new();
+        root_dir.find("old.txt").unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"new");
+        crate::kassert!(root_dir.find("new.txt").is_err());
+
+        let a = root_dir.create("a", FileType::Directory, 0o777).unwrap();
+        let c = root_dir.create("c", FileType::Directory, 0o777).unwrap();
+        a.create("b", FileType::Directory, 0o777).unwrap();
+        a.move_("b", &c, "b").unwrap();
+
+        let parent = root_dir.resolve_follow("c/b/..", 0).unwrap();
+        crate::kassert_eq!(parent.metadata().unwrap().inode, c.metadata().unwrap().inode);
+    }
+
+    {
+        /// Appends the path, link count and size of `inode` and everything below it to `entries`.
+        fn snapshot(inode: &Arc<dyn INode>, path: String, entries: &mut Vec<(String, usize, usize)>) {
+            let metadata = inode.metadata().unwrap();
+            entries.push((path.clone(), metadata.links, metadata.size));
+
+            if metadata.type_ == FileType::Directory {
+                for name in inode.list().unwrap().into_iter().filter(|name| name != "." && name != "..") {
+                    snapshot(&inode.find(&name).unwrap(), format!("{}/{}", path, name), entries);
+                }
+            }
+        }
+
+        let ramdisk = Ramdisk::new();
+        let root = ramdisk.root();
+        let full = root.create("full", FileType::Directory, 0o755).unwrap();
+        full.create("inner", FileType::Directory, 0o755).unwrap();
+        let empty = root.create("empty", FileType::Directory, 0o755).unwrap();
+        let file = root.create("file", FileType::File, 0o644).unwrap();
+        file.write_at(0, b"contents").unwrap();
+        full.link("file", &file).unwrap();
+
+        let state = || {
+            let mut entries = Vec::new();
+            snapshot(&root, String::new(), &mut entries);
+            entries
+        };
+        let before = state();
+
+        // Every move fails at a different check, and each leaves the tree as it was.
+        crate::kassert_eq!(root.move_("file", &root, "empty"), Err(FsError::IsDirectory));
+        crate::kassert_eq!(root.move_("empty", &root, "file"), Err(FsError::NotDirectory));
+        crate::kassert_eq!(root.move_("empty", &root, "full"), Err(FsError::DirectoryNotEmpty));
+        crate::kassert_eq!(root.move_("full", &full.find("inner").unwrap(), "full"), Err(FsError::InvalidArgument));
+        crate::kassert_eq!(root.move_("missing", &empty, "file"), Err(FsError::EntryNotFound));
+        crate::kassert!(state() == before);
+        crate::kassert_eq!(ramdisk.check(), Ok(()));
+
+        // Replacing a file that has another link keeps it reachable through that link.
+        empty.create("new", FileType::File, 0o644).unwrap();
+        empty.move_("new", &root, "file").unwrap();
+        crate::kassert_eq!(full.find("file").unwrap().metadata().unwrap().links, 1);
+        crate::kassert_eq!(root.find("file").unwrap().metadata().unwrap().size, 0);
+        crate::kassert_eq!(ramdisk.check(), Ok(()));
+    }
+
+    {
+        let ramdisk = Ramdisk::new();
+        let dir = ramdisk.root().create("a", FileType::Directory, 0o755).unwrap();
+        let file = dir.create("f", FileType::File, 0o644).unwrap();
+        dir.link("g", &file).unwrap();
+        crate::kassert_eq!(ramdisk.check(), Ok(()));
+
+        let set_links = |inode: &Arc<dyn INode>, links| {
+            inode.downcast_ref::<LockedRamdiskINode>().unwrap().write().set_links(links);
+        };
+
+        set_links(&file, 1);
+        crate::kassert_eq!(ramdisk.check(), Err(String::from("/a/f: has 1 links, but is referenced 2 times")));
+        set_links(&file, 2);
+        crate::kassert_eq!(ramdisk.check(), Ok(()));
+
+        let links = dir.metadata().unwrap().links;
+        set_links(&dir, links + 1);
+        crate::kassert_eq!(ramdisk.check(), Err(format!("/a: has {} links, but is referenced {} times", links + 1, links)));
+        set_links(&dir, links);
+        crate::kassert_eq!(ramdisk.check(), Ok(()));
+    }
+
+    {
+        let image = Vec::from(&include_bytes!("fs/ext2_test.img")[..]);
+        let ext2 = Ext2Fs::new(Arc::new(MemBlockDevice::new(image, 512))).unwrap();
+        let root = ext2.root();
+
+        let mut content = Vec::new();
+        root.find("hello.txt").unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"Hello from ext2!\n");
+
+        // A fast symlink, with the target stored in the inode.
+        let hello = root.find("hello.txt").unwrap().metadata().unwrap().inode;
+        crate::kassert_eq!(root.resolve_follow("link", 1).unwrap().metadata().unwrap().inode, hello);
+
+        content.clear();
+        root.resolve_follow("nested-link", 1).unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"nested\n");
+
+        // Larger than the direct blocks, so the end is read through the singly indirect block.
+        content.clear();
+        crate::kassert_eq!(root.find("big.bin").unwrap().read_until_eof_into(&mut content), Ok(14000));
+        crate::kassert!(content.iter().enumerate().all(|(i, &byte)| byte as usize == i * 7 % 251));
+    }
+
+    {
+        let image = Vec::from(&include_bytes!("fs/ext2_test.img")[..]);
+        let device: Arc<dyn BlockDevice> = Arc::new(MemBlockDevice::new(image, 512));
+        let cache = Arc::new(CachedBlockDevice::new(device.clone()));
+        let ext2 = Ext2Fs::new(cache.clone()).unwrap();
+
+        let write_time = || {
+            let mut time = [0; 4];
+            device.read_bytes(1024 + 48, &mut time).unwrap();
+            u32::from_le_bytes(time)
+        };
+        let old_write_time = write_time();
+
+        let file = ext2.root().find("hello.txt").unwrap();
+        crate::kassert_eq!(file.write_at(0, b"Howdy"), Ok(5));
+        crate::kassert_eq!(file.write_at(17, b"!"), Err(FsError::Unsupported));
+        crate::kassert_eq!(cache.dirty_count(), 1);
+
+        let mut content = Vec::new();
+        file.read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"Howdy from ext2!\n");
+
+        file.sync_data().unwrap();
+        crate::kassert_eq!(cache.dirty_count(), 0);
+        crate::kassert_eq!(write_time(), old_write_time);
+
+        ext2.sync().unwrap();
+        crate::kassert!(write_time() != old_write_time);
+        crate::kassert_eq!(cache.dirty_count(), 0);
+    }
+
+    {
+        use alloc::alloc::{GlobalAlloc, Layout};
+        use memory::debug_heap::{CANARY_BYTE, DebugHeap, GlobalHeap, HeapCorruption};
+
+        let heap = DebugHeap::new(GlobalHeap);
+        let layout = Layout::from_size_align(24, 32).unwrap();
+
+        unsafe {
+            let ptr = heap.alloc(layout);
+            crate::kassert_eq!(ptr as usize % 32, 0);
+            crate::kassert_eq!(heap.check(ptr, layout), Ok(()));
+
+            // One byte past the end, then one byte before the start.
+            *ptr.add(24) = 0;
+            crate::kassert_eq!(heap.check(ptr, layout), Err(HeapCorruption::Overflow));
+            *ptr.add(24) = CANARY_BYTE;
+
+            *ptr.sub(1) = 0;
+            crate::kassert_eq!(heap.check(ptr, layout), Err(HeapCorruption::Underflow));
+            *ptr.sub(1) = CANARY_BYTE;
+
+            heap.dealloc(ptr, layout);
+        }
+    }
+
+    {
+        use alloc::alloc::{GlobalAlloc, Layout};
+        use memory::debug_heap::GlobalHeap;
+        use memory::heap_stats::{Allocation, StatsHeap};
+
+        let heap = StatsHeap::new(GlobalHeap);
+        let small = Layout::from_size_align(16, 8).unwrap();
+        let large = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let first = heap.alloc(large);
+            let second = heap.alloc(small);
+            crate::kassert_eq!((heap.stats().current, heap.stats().peak), (116, 116));
+
+            heap.dealloc(first, large);
+            crate::kassert_eq!((heap.stats().current, heap.stats().peak), (16, 116));
+
+            heap.checkpoint();
+            let third = heap.alloc(small);
+            crate::kassert_eq!((heap.stats().current, heap.stats().peak), (32, 116));
+
+            let mut outstanding = Vec::new();
+            heap.outstanding(|allocation| outstanding.push(allocation));
+            crate::kassert_eq!(&outstanding[..], &[Allocation { address: third as usize, size: 16 }]);
+
+            heap.dealloc(second, small);
+            heap.dealloc(third, small);
+        }
+
+        let stats = heap.stats();
+        crate::kassert_eq!((stats.current, stats.allocations, stats.frees), (0, 3, 3));
+    }
+
+    {
+        let fs = crate::tree! {
+            dir "etc" {
+                file "hostname" = "os\n";
+                dir "empty" {}
+            }
+            symlink "hostname" -> "etc/hostname";
+        };
+        let root = fs.root();
+
+        crate::kassert_eq!(root.list(), Ok(vec![".".into(), "..".into(), "etc".into(), "hostname".into()]));
+        crate::kassert_eq!(root.find("etc").unwrap().list(), Ok(vec![".".into(), "..".into(), "empty".into(), "hostname".into()]));
+
+        let mut content = Vec::new();
+        root.resolve_follow("hostname", 1).unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"os\n");
+    }
+
+    {
+        let cached = CachedFs::new(Ramdisk::new());
+        let file = cached.root().create("cached.txt", FileType::File, 0o777).unwrap();
+        file.write_at(0, b"cached").unwrap();
+
+        let mut content = [0; 6];
+        file.read_at(0, &mut content).unwrap();
+        file.read_at(0, &mut content).unwrap();
+        crate::kassert_eq!(cached.stats().hits, 1);
+
+        file.write_at(0, b"change").unwrap();
+        file.read_at(0, &mut content).unwrap();
+        crate::kassert_eq!(&content, b"change");
+    }
+
+    {
+        use core::ptr;
+        use task::scheduler;
+
+        // The last character of the VGA text buffer, the writer is locked so nothing prints there.
+        let _writer = driver::vga::WRITER.lock();
+        let address = 0xb8000 + 2 * (80 * 25 - 1);
+        let virtual_address = address as *mut u8;
+        let mem = devfs.root().find("mem").unwrap();
+
+        let mut original = [0; 2];
+        crate::kassert_eq!(mem.read_at(address, &mut original), Ok(2));
+        crate::kassert_eq!(original[0], unsafe { ptr::read_volatile(virtual_address) });
+
+        crate::kassert_eq!(mem.write_at(address, b"#"), Ok(1));
+        crate::kassert_eq!(unsafe { ptr::read_volatile(virtual_address) }, b'#');
+
+        // Only root may use the device.
+        scheduler::set_uid(1000);
+        crate::kassert_eq!(mem.read_at(address, &mut [0; 2]), Err(FsError::PermissionDenied));
+        crate::kassert_eq!(mem.write_at(address, &original), Err(FsError::PermissionDenied));
+        scheduler::set_uid(0);
+
+        crate::kassert_eq!(mem.write_at(address, &original), Ok(2));
+        crate::kassert_eq!(mem.read_at(0xfffe_0000, &mut [0; 2]), Err(FsError::InvalidArgument));
+    }
+
+    {
+        use alloc::boxed::Box;
+        use core::ptr;
+        use memory::frame::Frame;
+        use memory::paging::{self, entry};
+
+        let vga = Frame::containing_address(PhysicalAddress::new(0xb8000));
+        crate::kassert_eq!(paging::frame_mapping_flags(vga), entry::mmio_flags());
+
+        // A heap value is in RAM, written through the temporary mapping of /dev/mem and read back
+        // through the permanent mapping of the heap.
+        let value = Box::new(0u64);
+        let address = memory::with_memory(|active_table, _| {
+            active_table.translate(VirtualAddress::new(&*value as *const u64 as u64))
+        }).unwrap();
+        let ram = Frame::containing_address(address);
+        crate::kassert_eq!(paging::frame_mapping_flags(ram), EntryFlags::Writable | EntryFlags::NoExecute);
+
+        let mem = devfs.root().find("mem").unwrap();
+        crate::kassert_eq!(mem.write_at(address.as_u64() as usize, &0x0123_4567_89ab_cdefu64.to_le_bytes()), Ok(8));
+        crate::kassert_eq!(unsafe { ptr::read_volatile(&*value) }, 0x0123_4567_89ab_cdef);
+    }
+
+    {
+        let archive = include_bytes!("fs/tar_test.tar");
+        let ramdisk = Ramdisk::new();
+        crate::kassert_eq!(fs::tar::unpack(archive, &ramdisk.root()), Ok(4));
+
+        let root = ramdisk.root();
+        crate::kassert_eq!(root.list(), Ok(vec![".".into(), "..".into(), "etc".into(), "hostname".into(), "readme.txt".into()]));
+        crate::kassert_eq!(root.find("etc").unwrap().metadata().unwrap().type_, FileType::Directory);
+
+        let mut content = Vec::new();
+        root.resolve_follow("hostname", 1).unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"os\n");
+
+        crate::kassert_eq!(fs::tar::unpack(&archive[..1024], &Ramdisk::new().root()), Err(fs::tar::TarError::Truncated));
+
+        let mut corrupt = Vec::from(&archive[..]);
+        corrupt[0] ^= 1;
+        crate::kassert_eq!(fs::tar::unpack(&corrupt, &Ramdisk::new().root()), Err(fs::tar::TarError::InvalidHeader));
+    }
+
+    {
+        use fs::tar::{Archive, EntryKind};
+
+        fn tar_entry(archive: &mut Vec<u8>, name: &str, type_: u8, link: &str, data: &[u8]) {
+            let mut header = [0u8; 512];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[100..108].copy_from_slice(b"0000644\0");
+            header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+            header[156] = type_;
+            header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+            header[257..265].copy_from_slice(b"ustar\000");
+
+            header[148..156].copy_from_slice(b"        ");
+            let checksum: usize = header.iter().map(|&b| usize::from(b)).sum();
+            header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+            archive.extend_from_slice(&header);
+            archive.extend_from_slice(data);
+            archive.resize(util::math::align_up_to(archive.len(), 512), 0);
+        }
+
+        let init = vec![0x42u8; 600];
+        let mut archive = Vec::new();
+        tar_entry(&mut archive, "bin/", b'5', "", b"");
+        tar_entry(&mut archive, "bin/init", b'0', "", &init);
+        tar_entry(&mut archive, "sbin", b'2', "bin", b"");
+        tar_entry(&mut archive, "usr/share/motd", b'0', "", b"hello");
+        tar_entry(&mut archive, "dev/null", b'3', "", b"");
+        archive.resize(archive.len() + 1024, 0);
+
+        let kinds: Vec<_> = Archive::new(&archive).map(|entry| entry.unwrap().kind).collect();
+        crate::kassert_eq!(kinds, vec![EntryKind::Directory, EntryKind::File, EntryKind::SymbolicLink("bin"), EntryKind::File, EntryKind::Other(b'3')]);
+
+        let file = Ramdisk::new().root().create("initrd.tar", FileType::File, 0o644).unwrap();
+        file.write_all(0, &archive).unwrap();
+        let ramdisk = Ramdisk::new();
+        crate::kassert_eq!(fs::tar::unpack_file(&file, &ramdisk.root()), Ok(4));
+
+        let root = ramdisk.root();
+        crate::kassert_eq!(root.list(), Ok(vec![".".into(), "..".into(), "bin".into(), "dev".into(), "sbin".into(), "usr".into()]));
+        crate::kassert_eq!(root.find("dev").unwrap().list(), Ok(vec![".".into(), "..".into()]));
+
+        let mut content = Vec::new();
+        root.resolve_follow("sbin/init", 1).unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(content, init);
+
+        content.clear();
+        root.resolve_follow("usr/share/motd", 0).unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"hello");
+
+        // A single zero block followed by anything else is not an end-of-archive marker.
+        let len = archive.len();
+        archive[len - 1] = 1;
+        crate::kassert_eq!(fs::tar::unpack(&archive, &Ramdisk::new().root()), Err(fs::tar::TarError::InvalidHeader));
+
+        let mut escape = Vec::new();
+        tar_entry(&mut escape, "bin/../../motd", b'0', "", b"hello");
+        escape.resize(escape.len() + 1024, 0);
+        let ramdisk = Ramdisk::new();
+        crate::kassert_eq!(fs::tar::unpack(&escape, &ramdisk.root()), Err(fs::tar::TarError::InvalidName));
+        crate::kassert_eq!(ramdisk.root().list(), Ok(vec![".".into(), "..".into()]));
+
+        let mut replace = Vec::new();
+        tar_entry(&mut replace, "bin", b'0', "", b"hello");
+        replace.resize(replace.len() + 1024, 0);
+        crate::kassert_eq!(fs::tar::unpack(&replace, &root), Err(fs::tar::TarError::Fs(FsError::EntryExists)));
+        crate::kassert_eq!(root.find("bin").unwrap().metadata().unwrap().type_, FileType::Directory);
+
+        let mut motd = Vec::new();
+        tar_entry(&mut motd, "usr/share/motd", b'0', "", b"hi");
+        motd.resize(motd.len() + 1024, 0);
+        crate::kassert_eq!(fs::tar::unpack(&motd, &root), Ok(1));
+        content.clear();
+        root.resolve_follow("usr/share/motd", 0).unwrap().read_until_eof_into(&mut content).unwrap();
+        crate::kassert_eq!(&content[..], b"hi");
+    }
+
+    {
+        let full = root.root().resolve_follow("dev/full", 0).unwrap();
+
+        let mut buf = [0xff; 8];
+        crate::kassert_eq!(full.read_at(0, &mut buf), Ok(8));
+        crate::kassert_eq!(buf, [0; 8]);
+        crate::kassert_eq!(full.write_at(0, b"data"), Err(FsError::NoSpace));
+        crate::kassert_eq!(full.append(b"data"), Err(FsError::NoSpace));
+    }
+
+    {
+        devfs.add("pipe", PipeDevice::new(devfs.clone())).unwrap();
+        let pipe = root.root().resolve_follow("dev/pipe", 0).unwrap();
+
+        crate::kassert_eq!(pipe.poll(), Ok(PollStatus { readable: false, writable: true, error: false }));
+        let mut buf = [0; 4];
+        crate::kassert_eq!(pipe.read_at(0, &mut buf), Ok(0));
+
+        crate::kassert_eq!(pipe.write_at(0, b"abc"), Ok(3));
+        crate::kassert_eq!(pipe.poll(), Ok(PollStatus { readable: true, writable: true, error: false }));
+        crate::kassert_eq!(pipe.read_at(0, &mut buf[..2]), Ok(2));
+        crate::kassert_eq!(&buf[..2], b"ab");
+        crate::kassert_eq!(pipe.read_at(0, &mut buf), Ok(1));
+        crate::kassert_eq!(buf[0], b'c');
+        crate::kassert!(!pipe.poll().unwrap().readable);
+
+        let data = vec![0x55; PIPE_CAPACITY + 1];
+        crate::kassert_eq!(pipe.write_at(0, &data), Ok(PIPE_CAPACITY));
+        crate::kassert_eq!(pipe.poll(), Ok(PollStatus { readable: true, writable: false, error: false }));
+        crate::kassert_eq!(pipe.write_at(0, b"x"), Ok(0));
+
+        // Files are always ready.
+        let file = root.root().find("tmp").unwrap().create("poll", FileType::File, 0o644).unwrap();
+        crate::kassert_eq!(file.poll(), Ok(PollStatus { readable: true, writable: true, error: false }));
+        root.root().find("tmp").unwrap().unlink("poll").unwrap();
+        devfs.remove("pipe").unwrap();
+    }
+
+    {
+        // More than one chunk, so the reads continue after the first one.
+        let stream: Vec<u8> = (0..3000u32).map(|i| (i % 251) as u8).collect();
+
+        let pipe: Arc<dyn INode> = PipeDevice::new(devfs.clone());
+        crate::kassert_eq!(pipe.write_at(0, &stream), Ok(stream.len()));
+        crate::kassert_eq!(pipe.metadata().unwrap().size, stream.len());
+
+        // The data is appended to what is already in the buffer.
+        let mut content = b"prefix".to_vec();
+        crate::kassert_eq!(pipe.read_until_eof_into(&mut content), Ok(stream.len()));
+        crate::kassert_eq!(&content[..6], b"prefix");
+        crate::kassert!(content[6..] == stream[..]);
+
+        let file = Ramdisk::new().root().create("stream", FileType::File, 0o644).unwrap();
+        file.write_at(0, &stream).unwrap();
+        let mut content = Vec::new();
+        crate::kassert_eq!(file.read_until_eof_into(&mut content), Ok(stream.len()));
+        crate::kassert!(content == stream);
+
+        // A device without data reads nothing.
+        let mut content = Vec::new();
+        crate::kassert_eq!(pipe.read_until_eof_into(&mut content), Ok(0));
+        crate::kassert!(content.is_empty());
+    }
+
+    {
+        use core::cell::RefCell;
+
+        let echoed = RefCell::new(Vec::new());
+        let echo = |byte| echoed.borrow_mut().push(byte);
+        let mut discipline = LineDiscipline::new();
+        let mut buf = [0; 16];
+
+        for &byte in b"ab\x08c" {
+            discipline.input(byte, echo);
+        }
+        crate::kassert!(!discipline.has_data());
+        crate::kassert_eq!(discipline.read(&mut buf), 0);
+
+        discipline.input(b'\n', echo);
+        crate::kassert_eq!(&echoed.borrow()[..], b"ab\x08c\n");
+        crate::kassert_eq!(discipline.read(&mut buf), 2);
+        crate::kassert_eq!(&buf[..2], b"ac");
+
+        // A backspace on an empty line isn't echoed, a line kill erases everything typed so far.
+        echoed.borrow_mut().clear();
+        for &byte in &[0x08, b'x', b'y', LINE_KILL, b'o', b'k', b'\r'] {
+            discipline.input(byte, echo);
+        }
+        crate::kassert_eq!(&echoed.borrow()[..], b"xy\x08\x08ok\n");
+        crate::kassert_eq!(discipline.read(&mut buf), 2);
+        crate::kassert_eq!(&buf[..2], b"ok");
+
+        // Lines are handed out one at a time, raw mode hands out everything without echoing.
+        for &byte in b"1\n2\n" {
+            discipline.input(byte, echo);
+        }
+        crate::kassert_eq!(discipline.read(&mut buf), 1);
+        crate::kassert_eq!(discipline.read(&mut buf), 1);
+        crate::kassert_eq!(buf[0], b'2');
+        crate::kassert!(!discipline.has_data());
+
+        echoed.borrow_mut().clear();
+        discipline.input(b'p', echo);
+        discipline.set_cooked(false);
+        discipline.input(0x08, echo);
+        crate::kassert_eq!(&echoed.borrow()[..], b"p");
+        crate::kassert_eq!(discipline.read(&mut buf), 2);
+        crate::kassert_eq!(&buf[..2], b"p\x08");
+    }
+
+    {
+        use fs::ioctl::{TIOCGBAUD, TIOCSBAUD};
+        use x86_64::instructions::interrupts::with_disabled;
+
+        let null = root.root().resolve_follow("dev/null", 0).unwrap();
+        crate::kassert_eq!(null.io_control(TIOCGBAUD, 0), Err(FsError::Unsupported));
+
+        let serial = root.root().resolve_follow("dev/ttyS0", 0).unwrap();
+        crate::kassert_eq!(serial.io_control(0xdead, 0), Err(FsError::Unsupported));
+        crate::kassert_eq!(serial.io_control(TIOCSBAUD, 7), Err(FsError::InvalidArgument));
+
+        // Changing the baud rate garbles whatever the other end receives meanwhile, so nothing may
+        // be printed while it differs from the one the other end expects.
+        let (changed, restored) = with_disabled(|| {
+            let baud = serial.io_control(TIOCGBAUD, 0).unwrap();
+            serial.io_control(TIOCSBAUD, 57600).unwrap();
+            let changed = serial.io_control(TIOCGBAUD, 0);
+            serial.io_control(TIOCSBAUD, baud).unwrap();
+            (changed, serial.io_control(TIOCGBAUD, 0) == Ok(baud))
+        });
+        crate::kassert_eq!(changed, Ok(57600));
+        crate::kassert!(restored);
+    }
+
+    crate::kassert!(root.root().resolve_follow("dev/block/zero", 0).is_ok());
+
+    {
+        use fs::path::Path;
+
+        crate::kassert_eq!(shell::tokenize("  cat\tetc/hostname  > out \n"), vec!["cat", "etc/hostname", ">", "out"]);
+        crate::kassert!(shell::tokenize(" \t ").is_empty());
+
+        let cwd = Path::root().join("tmp/./folder/");
+        crate::kassert_eq!(cwd.as_str(), "/tmp/folder");
+        crate::kassert_eq!(cwd.join("../hello.txt").relative(), "tmp/hello.txt");
+        crate::kassert_eq!(cwd.join("/dev//null").as_str(), "/dev/null");
+        crate::kassert_eq!(Path::root().join("..").as_str(), "/");
+
+        // The shell writes its output to the console, so a pipe captures it.
+        let console = PipeDevice::new(devfs.clone());
+        let ramdisk = Ramdisk::new();
+        let etc = ramdisk.root().create("etc", FileType::Directory, 0o755).unwrap();
+        etc.create("hostname", FileType::File, 0o644).unwrap().write_all(0, b"os").unwrap();
+        let mut shell = shell::Shell::new(ramdisk.root(), console.clone());
+        let mut output = [0; 16];
+
+        crate::kassert_eq!(shell.execute(&["cd", "etc"]), Ok(()));
+        crate::kassert_eq!(shell.execute(&["cat", "hostname"]), Ok(()));
+        crate::kassert_eq!(console.read_at(0, &mut output), Ok(3));
+        crate::kassert_eq!(&output[..3], b"os\n");
+
+        crate::kassert_eq!(shell.execute(&["cat", "/etc/missing"]), Err(FsError::EntryNotFound));
+        crate::kassert_eq!(shell.execute(&["cat"]), Err(FsError::InvalidArgument));
+        crate::kassert_eq!(console.read_at(0, &mut output), Ok(0));
+    }
+}
+
+/// Tests file mappings, address spaces, ELF loading and the scheduler, with the filesystems of
+/// `filesystem`.
+pub fn tasks(root: &Arc<MountFS>, devfs: &Arc<DevFS>) {
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting file mappings...");
+    {
+        let file = root.root().find("text.txt").unwrap();
+        let size = file.metadata().unwrap().size;
+
+        memory::with_memory(|active_table, frame_allocator| {
+            let address = memory::mmap_inode(&file, active_table, frame_allocator).unwrap();
+            let contents = unsafe { core::slice::from_raw_parts(address.as_ptr::<u8>(), memory::PAGE_SIZE) };
+
+            crate::kassert_eq!(&contents[..size], b"test file");
+            crate::kassert!(contents[size..].iter().all(|&byte| byte == 0));
+
+            memory::munmap(address, size, active_table, frame_allocator);
+        });
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting demand paging...");
+    {
+        let address = memory::mmap_anonymous(2 * memory::PAGE_SIZE, EntryFlags::Writable | EntryFlags::NoExecute).unwrap();
+        let second = VirtualAddress::new(address.as_u64() + memory::PAGE_SIZE as u64);
+        let is_mapped = |address| memory::with_memory(|active_table, _| active_table.is_mapped(address));
+
+        crate::kassert!(!is_mapped(address) && !is_mapped(second));
+
+        // Both accesses fault, the page fault handler maps the pages and retries them.
+        unsafe { core::ptr::write_volatile(second.as_mut_ptr::<u64>(), 0x1234_5678) };
+        crate::kassert_eq!(unsafe { core::ptr::read_volatile(second.as_ptr::<u64>()) }, 0x1234_5678);
+        crate::kassert!(is_mapped(second) && !is_mapped(address));
+
+        crate::kassert_eq!(unsafe { core::ptr::read_volatile(address.as_ptr::<u64>()) }, 0);
+        crate::kassert!(is_mapped(address));
+
+        memory::with_memory(|active_table, _| {
+            let page = Page::containing_address(address);
+            let frame = active_table.translate_page(page);
+
+            active_table.update_flags(page, EntryFlags::NoExecute);
+            crate::kassert_eq!(active_table.translate_page_with_flags(page), frame.map(|frame| (frame, EntryFlags::Present | EntryFlags::NoExecute)));
+        });
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting address spaces...");
+    {
+        let page = Page::containing_address(address_space::USER_START);
+        let mut first = AddressSpace::new();
+        let mut second = AddressSpace::new();
+
+        first.map_user(page, EntryFlags::Writable | EntryFlags::NoExecute);
+        second.map_user(page, EntryFlags::Writable | EntryFlags::NoExecute);
+
+        let first_frame = first.translate(page);
+        crate::kassert!(first_frame.is_some());
+        crate::kassert!(first_frame != second.translate(page), "Address spaces share {:?}", page.start_address());
+
+        // A kernel mapping under a P4 entry (242) that didn't exist yet when `first` was created.
+        let kernel_page = Page::containing_address(VirtualAddress::new(0x7900_0000_0000));
+        crate::kassert!(memory::with_memory(|active_table, _| active_table.p4()[242].is_unused()));
+        memory::with_memory(|active_table, frame_allocator| {
+            active_table.map(kernel_page, EntryFlags::Writable | EntryFlags::NoExecute, frame_allocator);
+        });
+        unsafe { *kernel_page.start_address().as_mut_ptr::<u64>() = 0x5ca1ab1e };
+
+        // The scheduler owns the kernel address space, so no other thread may run while it's not
+        // the active one.
+        let preempt = task::scheduler::disable_preemption();
+        let mut kernel = unsafe { AddressSpace::active() };
+        kernel.switch_to(&mut first);
+        let value = unsafe { core::ptr::read_volatile(kernel_page.start_address().as_ptr::<u64>()) };
+        first.switch_to(&mut kernel);
+        drop(preempt);
+
+        crate::kassert_eq!(value, 0x5ca1ab1e);
+        memory::with_memory(|active_table, frame_allocator| active_table.unmap(kernel_page, frame_allocator));
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting ELF loading...");
+    {
+        let file = root.root().find("tmp").unwrap().create("test.elf", FileType::File, 0o777).unwrap();
+        file.write_at(0, include_bytes!("loader/test.elf")).unwrap();
+
+        let elf = loader::elf::parse(&file).unwrap();
+        crate::kassert_eq!(elf.entry.as_u64(), address_space::USER_START.as_u64() + 0x10b0);
+        crate::kassert_eq!(elf.segments.len(), 2);
+        crate::kassert_eq!(elf.segments[0].virtual_address.as_u64(), elf.entry.as_u64());
+        crate::kassert!(!elf.segments[0].flags.contains(EntryFlags::Writable));
+        crate::kassert!(!elf.segments[0].flags.contains(EntryFlags::NoExecute));
+        crate::kassert_eq!(elf.segments[1].virtual_address.as_u64(), address_space::USER_START.as_u64() + 0x30c0);
+        crate::kassert_eq!(elf.segments[1].flags, EntryFlags::Writable | EntryFlags::NoExecute);
+
+        let mut program = AddressSpace::new();
+        let entry = loader::elf::load(&file, &mut program).unwrap();
+        crate::kassert_eq!(loader::elf::load(&file, &mut program).err(), Some(loader::elf::ElfError::Overlap));
+
+        let preempt = task::scheduler::disable_preemption();
+        let mut kernel = unsafe { AddressSpace::active() };
+        kernel.switch_to(&mut program);
+
+        let function: extern "C" fn() -> u64 = unsafe { core::mem::transmute(entry.as_u64()) };
+        let data = unsafe { core::slice::from_raw_parts(elf.segments[1].virtual_address.as_ptr::<u8>(), 0x1000) };
+        let (result, data_loaded, bss_zeroed) = (function(), &data[..8] == b"ELF DATA", data[8..].iter().all(|&byte| byte == 0));
+
+        program.switch_to(&mut kernel);
+        drop(preempt);
+        crate::kassert_eq!(result, 42);
+        crate::kassert!(data_loaded);
+        crate::kassert!(bss_zeroed);
+    }
+
+    {
+        use flagset::FlagSet;
+        use loader::elf::{self, ElfError};
+
+        // An executable whose segments are `(file offset, address, file size, memory size, flags)`.
+        let executable = |segments: &[(u64, u64, u64, u64, u32)]| {
+            let mut bytes = vec![0u8; 0x200];
+            bytes[0..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+            bytes[16..20].copy_from_slice(&[2, 0, 0x3e, 0]);
+            bytes[24..32].copy_from_slice(&(address_space::USER_START.as_u64() + 0x1000).to_le_bytes());
+            bytes[32..40].copy_from_slice(&64u64.to_le_bytes());
+            bytes[54..56].copy_from_slice(&56u16.to_le_bytes());
+            bytes[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+            for (i, &(offset, address, file_size, memory_size, flags)) in segments.iter().enumerate() {
+                let header = &mut bytes[64 + i * 56..64 + (i + 1) * 56];
+                header[0..4].copy_from_slice(&1u32.to_le_bytes());
+                header[4..8].copy_from_slice(&flags.to_le_bytes());
+                header[8..16].copy_from_slice(&offset.to_le_bytes());
+                header[16..24].copy_from_slice(&(address_space::USER_START.as_u64() + address).to_le_bytes());
+                header[32..40].copy_from_slice(&file_size.to_le_bytes());
+                header[40..48].copy_from_slice(&memory_size.to_le_bytes());
+            }
+
+            bytes[0x180..0x190].copy_from_slice(b"CODECODECODECODE");
+            bytes[0x190..0x198].copy_from_slice(b"DATADATA");
+            bytes
+        };
+
+        let tmp = root.root().find("tmp").unwrap();
+        let page = |offset| Page::containing_address(VirtualAddress::new(address_space::USER_START.as_u64() + offset));
+
+        // Executable code and writable data with .bss share the page at 0x1000.
+        let file = tmp.create("shared.elf", FileType::File, 0o777).unwrap();
+        file.write_at(0, &executable(&[(0x180, 0x1000, 0x10, 0x10, 5), (0x190, 0x1800, 8, 0x1000, 6)])).unwrap();
+
+        let mut program = AddressSpace::new();
+        crate::kassert!(elf::load(&file, &mut program).is_ok());
+
+        let preempt = task::scheduler::disable_preemption();
+        let mut kernel = unsafe { AddressSpace::active() };
+        kernel.switch_to(&mut program);
+
+        let flags = |offset| memory::with_memory(|active_table, _| active_table.translate_page_with_flags(page(offset)).map(|(_, flags)| flags));
+        let (shared_flags, data_flags) = (flags(0x1000), flags(0x2000));
+        let contents = unsafe { core::slice::from_raw_parts(page(0x1000).start_address().as_ptr::<u8>(), 2 * memory::PAGE_SIZE) };
+        let code_loaded = &contents[..0x10] == b"CODECODECODECODE";
+        let data_loaded = &contents[0x800..0x808] == b"DATADATA";
+        let rest_zeroed = contents[0x10..0x800].iter().chain(&contents[0x808..]).all(|&byte| byte == 0);
+
+        program.switch_to(&mut kernel);
+        drop(preempt);
+        crate::kassert_eq!(shared_flags, Some(EntryFlags::Present | EntryFlags::Writable));
+        crate::kassert_eq!(data_flags, Some(EntryFlags::Present | EntryFlags::Writable | EntryFlags::NoExecute));
+        crate::kassert!(code_loaded);
+        crate::kassert!(data_loaded);
+        crate::kassert!(rest_zeroed);
+
+        crate::kassert_eq!(elf::merge_flags(EntryFlags::NoExecute.into(), EntryFlags::NoExecute.into()), FlagSet::from(EntryFlags::NoExecute));
+
+        // The last segment reads past the end of the file, so the pages of the others are unmapped.
+        let file = tmp.create("truncated.elf", FileType::File, 0o777).unwrap();
+        file.write_at(0, &executable(&[(0x180, 0x1000, 0x10, 0x10, 5), (0x190, 0x1800, 8, 0x1000, 6), (0x190, 0x5000, 0x1000, 0x1000, 6)])).unwrap();
+
+        let mut program = AddressSpace::new();
+        crate::kassert_eq!(elf::load(&file, &mut program).err(), Some(ElfError::InvalidHeader));
+        crate::kassert!(program.translate(page(0x1000)).is_none());
+        crate::kassert!(program.translate(page(0x2000)).is_none());
+        crate::kassert!(program.translate(page(0x5000)).is_none());
+
+        // The program headers would wrap around the end of the address space.
+        let mut bytes = executable(&[(0x180, 0x1000, 0x10, 0x10, 5), (0x190, 0x1800, 8, 0x1000, 6)]);
+        bytes[32..40].copy_from_slice(&(u64::max_value() - 0x20).to_le_bytes());
+        let file = tmp.create("wrapping.elf", FileType::File, 0o777).unwrap();
+        file.write_at(0, &bytes).unwrap();
+        crate::kassert_eq!(elf::parse(&file).err(), Some(ElfError::InvalidHeader));
+
+        tmp.unlink("shared.elf").unwrap();
+        tmp.unlink("truncated.elf").unwrap();
+        tmp.unlink("wrapping.elf").unwrap();
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting preemption...");
+    {
+        use task::scheduler;
+
+        // Neither the threads nor this loop yield, so they can only all progress when preempted.
+        for index in 0..2 {
+            crate::kassert!(scheduler::spawn(spin_thread, index).is_some());
+        }
+
+        // Halting waits for the next tick, which also counts down the time slice of this thread.
+        for _ in 0..1000 {
+            if SPIN_COUNTERS.iter().all(|counter| counter.load(Ordering::SeqCst) > 0) {
+                break;
+            }
+
+            x86_64::instructions::interrupts::enable_and_hlt();
+        }
+
+        SPIN_STOP.store(true, Ordering::SeqCst);
+        for _ in 0..1000 {
+            if scheduler::thread_count() == 1 {
+                break;
+            }
+
+            x86_64::instructions::interrupts::enable_and_hlt();
+        }
+
+        crate::kassert!(SPIN_COUNTERS.iter().all(|counter| counter.load(Ordering::SeqCst) > 0));
+        crate::kassert_eq!(scheduler::thread_count(), 1);
+        crate::kassert_eq!(scheduler::current_id(), Some(0));
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting threads with an address space...");
+    {
+        use task::scheduler;
+
+        let page = Page::containing_address(address_space::USER_START);
+        let mut program = AddressSpace::new();
+        program.map_user(page, EntryFlags::Writable | EntryFlags::NoExecute);
+        crate::kassert_eq!(program.update_user_with(page, EntryFlags::NoExecute, |bytes| {
+            bytes[..8].copy_from_slice(&0x0ddba11u64.to_le_bytes());
+            Ok::<(), ()>(())
+        }), Ok(()));
+
+        crate::kassert!(scheduler::spawn_in(address_space_thread, 0, program).is_some());
+        for _ in 0..1000 {
+            if scheduler::thread_count() == 1 {
+                break;
+            }
+
+            x86_64::instructions::interrupts::enable_and_hlt();
+        }
+
+        // The page is only mapped in the address space of the thread, and CR3 was switched back.
+        crate::kassert_eq!(ADDRESS_SPACE_VALUE.load(Ordering::SeqCst), 0x0ddba11);
+        crate::kassert!(!memory::with_memory(|active_table, _| active_table.is_mapped(page.start_address())));
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting blocking locks...");
+    {
+        use task::scheduler;
+
+        let wait_for = |condition: &dyn Fn() -> bool| {
+            for _ in 0..1000 {
+                if condition() {
+                    break;
+                }
+
+                x86_64::instructions::interrupts::enable_and_hlt();
+            }
+        };
+
+        // The threads queue up while this holds the mutex, and must get it in that order.
+        let guard = ORDER_MUTEX.lock();
+        for index in 1..=2 {
+            crate::kassert!(scheduler::spawn(order_thread, index).is_some());
+            wait_for(&|| ORDER_MUTEX.waiters() == index as usize);
+        }
+        drop(guard);
+
+        wait_for(&|| scheduler::thread_count() == 1);
+        crate::kassert_eq!(&ORDER_MUTEX.lock()[..], &[1, 2]);
+
+        for index in 0..2 {
+            crate::kassert!(scheduler::spawn(contend_thread, index).is_some());
+        }
+
+        wait_for(&|| scheduler::thread_count() == 1);
+        crate::kassert_eq!(*COUNTER_MUTEX.lock(), 2 * CONTEND_ITERATIONS);
+
+        let semaphore = task::sync::Semaphore::new(1);
+        crate::kassert!(semaphore.try_acquire());
+        crate::kassert!(!semaphore.try_acquire());
+        semaphore.release();
+        crate::kassert!(semaphore.try_acquire());
+
+        // This thread blocks in `wait_until` on the pipe until the producer writes to it.
+        let pipe = PipeDevice::new(devfs.clone());
+        let queue = pipe.wait_queue().unwrap();
+        let wakeups = queue.wakeups();
+        crate::kassert!(scheduler::spawn(producer_thread, Arc::into_raw(pipe.clone()) as u64).is_some());
+
+        let mut buf = [0; 8];
+        let reader: Arc<dyn INode> = pipe.clone();
+        crate::kassert_eq!(reader.read_blocking(0, &mut buf), Ok(8));
+        crate::kassert_eq!(&buf, b"produced");
+        crate::kassert!(PRODUCER_SAW_WAITER.load(Ordering::SeqCst), "The consumer never blocked");
+        crate::kassert!(queue.wakeups() > wakeups);
+        crate::kassert_eq!(queue.waiters(), 0);
+
+        wait_for(&|| scheduler::thread_count() == 1);
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting the idle thread...");
+    {
+        // Sleeping only ends on a tick, so check that they arrive instead of hanging in it.
+        let start = driver::pit::ticks();
+        for _ in 0..1000 {
+            if driver::pit::ticks() != start {
+                break;
+            }
+
+            time::delay_us(100);
+        }
+
+        crate::kassert!(driver::pit::ticks() != start, "The PIT doesn't tick");
+
+        // Nothing else can run while this sleeps, so the idle thread has to halt the CPU.
+        let halts = task::idle_halts();
+        time::sleep_ms(20);
+        crate::kassert!(task::idle_halts() > halts);
+        crate::kassert_eq!(task::scheduler::current_id(), Some(0));
+    }
+
+    {
+        use interrupts::testutil::take_caught_fault;
+        use task::scheduler;
+
+        // The overflow faults on the guard page of the thread's stack. The page fault handler has a
+        // stack of its own, so it can report the overflow instead of escalating to a double and
+        // then a triple fault.
+        crate::kassert!(scheduler::spawn(overflow_thread, 0).is_some());
+
+        let mut fault = None;
+        for _ in 0..1000 {
+            fault = take_caught_fault();
+            if fault.is_some() {
+                break;
+            }
+
+            x86_64::instructions::interrupts::enable_and_hlt();
+        }
+
+        crate::kassert!(fault.map_or(false, |fault| fault.name == "Kernel Stack Overflow"));
+
+        // The overflow hit the page right below the stack, and is reported with that stack.
+        crate::kassert!(fault.map_or(false, |fault| {
+            let guard_end = fault.stack.page.start_address().as_u64() + memory::PAGE_SIZE as u64;
+            guard_end == fault.stack.stack_bottom.as_u64() && fault.stack.stack_top.as_u64() > guard_end
+        }));
+        crate::kassert!(fault.and_then(|fault| fault.handler_stack).map_or(false, |stack| {
+            stack.stack_top.as_u64() == gdt::interrupt_stack(gdt::PAGE_FAULT_IST_INDEX).as_u64()
+        }));
+    }
+
+    crate::kprintln!("\x1b[92m- \x1b[97mTesting stack guard pages...");
+    let stack = memory::with_memory(|active_table, frame_allocator| {
+        memory::alloc_kernel_stack(active_table, frame_allocator, 4)
+    }).unwrap();
+
+    // A push just below the stack faults on its guard page, which is reported as an overflow.
+    let guard_page = memory::guard_page::find(VirtualAddress::new(stack.bottom().as_u64() - 8));
+    crate::kassert!(guard_page.map_or(false, |guard_page| {
+        guard_page.stack_bottom.as_u64() == stack.bottom().as_u64() && guard_page.stack_top.as_u64() == stack.top().as_u64()
+    }));
+    crate::kassert!(memory::guard_page::find(stack.bottom()).is_none());
+}
+
+static SPIN_COUNTERS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+static SPIN_STOP: AtomicBool = AtomicBool::new(false);
+
+/// A thread of the preemption test, counts until it's told to stop.
+extern "C" fn spin_thread(index: u64) {
+    while !SPIN_STOP.load(Ordering::SeqCst) {
+        SPIN_COUNTERS[index as usize].fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// The value the thread of the address space test read from the start of its user memory.
+static ADDRESS_SPACE_VALUE: AtomicU64 = AtomicU64::new(0);
+
+/// A thread of the address space test, runs in an address space of its own.
+extern "C" fn address_space_thread(_argument: u64) {
+    let value = unsafe { core::ptr::read_volatile(address_space::USER_START.as_ptr::<u64>()) };
+    ADDRESS_SPACE_VALUE.store(value, Ordering::SeqCst);
+}
+
+static ORDER_MUTEX: task::sync::Mutex<Vec<u64>> = task::sync::Mutex::new(Vec::new());
+static COUNTER_MUTEX: task::sync::Mutex<u64> = task::sync::Mutex::new(0);
+const CONTEND_ITERATIONS: u64 = 1000;
+
+/// A thread of the blocking lock test, records when it got the mutex.
+extern "C" fn order_thread(index: u64) {
+    ORDER_MUTEX.lock().push(index);
+}
+
+/// A thread of the blocking lock test. The increments are split in a read and a write that are
+/// far apart, so they get lost if both threads are in the critical section at the same time.
+extern "C" fn contend_thread(_index: u64) {
+    for _ in 0..CONTEND_ITERATIONS {
+        let mut counter = COUNTER_MUTEX.lock();
+        let value = *counter;
+
+        for _ in 0..1000 {
+            core::sync::atomic::spin_loop_hint();
+        }
+
+        *counter = value + 1;
+    }
+}
+
+/// Set by the producer of the wait queue test if the consumer was parked before it produced.
+static PRODUCER_SAW_WAITER: AtomicBool = AtomicBool::new(false);
+
+/// A thread of the wait queue test, writes to the pipe in `pipe` once a reader waits on it.
+extern "C" fn producer_thread(pipe: u64) {
+    let pipe = unsafe { Arc::from_raw(pipe as *const PipeDevice) };
+    let queue = pipe.wait_queue().unwrap();
+
+    for _ in 0..1000 {
+        if queue.waiters() > 0 {
+            break;
+        }
+
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+
+    PRODUCER_SAW_WAITER.store(queue.waiters() > 0, Ordering::SeqCst);
+    pipe.write_at(0, b"produced").unwrap();
+}
+
+/// A thread of the stack overflow test, recurses until its stack overflows.
+extern "C" fn overflow_thread(_argument: u64) {
+    // The volatile accesses keep the recursion and the frames from being optimized away.
+    #[allow(unconditional_recursion)]
+    fn recurse(depth: u64) -> u64 {
+        let mut frame = [0u64; 16];
+        unsafe {
+            core::ptr::write_volatile(&mut frame[0], depth);
+            recurse(depth + 1) + core::ptr::read_volatile(&frame[0])
+        }
+    }
+
+    interrupts::testutil::catch_faults();
+    recurse(0);
+}
\ No newline at end of file
@@ -0,0 +1,32 @@
+use core::sync::atomic::{AtomicI64, Ordering};
+
+use driver::rtc::RTC;
+use fs::vfs::Timespec;
+use time;
+
+/// The wall-clock time at uptime zero, in milliseconds since the Unix epoch.
+static BOOT_TIME_MS: AtomicI64 = AtomicI64::new(0);
+
+/// Reads the RTC once to start the wall-clock time, which is advanced by the PIT ticks from then
+/// on. Must be called after the PIT is initialized.
+pub fn init() {
+    let rtc_time = RTC.lock().read().unix_timestamp();
+    BOOT_TIME_MS.store(rtc_time * 1000 - time::uptime_ms() as i64, Ordering::SeqCst);
+}
+
+/// Returns the current wall-clock time. This doesn't touch the RTC, so it is cheap enough to call
+/// on every file access.
+pub fn now() -> Timespec {
+    let ms = BOOT_TIME_MS.load(Ordering::SeqCst) + time::uptime_ms() as i64;
+
+    Timespec {
+        sec: ms / 1000,
+        nanosec: (ms % 1000) as i32 * 1_000_000,
+    }
+}
+
+/// Sets the wall-clock time to `time`. The RTC itself is not changed.
+pub fn set(time: Timespec) {
+    let ms = time.sec * 1000 + i64::from(time.nanosec / 1_000_000);
+    BOOT_TIME_MS.store(ms - time::uptime_ms() as i64, Ordering::SeqCst);
+}
\ No newline at end of file
@@ -0,0 +1,533 @@
+//! A stub for the GDB Remote Serial Protocol on the second serial port. Once `init` was called,
+//! breakpoint and debug exceptions hand control to GDB instead of panicking. Connect with
+//! `target remote` to the second serial port of the emulator, e.g. `-serial tcp::1234,server` as
+//! the second `-serial` option of QEMU.
+//!
+//! The stub runs inside exception handlers, so it doesn't allocate and doesn't take the memory
+//! lock.
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use driver::uart16550::{UART16550, UartError};
+use interrupts::StackFrame;
+use memory::paging::mapper::Mapper;
+use memory::paging::table::RecursivePageTable;
+use util::irq_lock::IrqLock;
+use watchdog;
+use x86_64::VirtualAddress;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::registers::rflags::RFlags;
+
+/// The second serial port, which is reserved for GDB.
+static GDB_UART: IrqLock<UART16550> = IrqLock::new(UART16550::new(0x2F8));
+
+/// The software breakpoints that are inserted right now.
+static BREAKPOINTS: IrqLock<[Option<Breakpoint>; MAX_BREAKPOINTS]> = IrqLock::new([None; MAX_BREAKPOINTS]);
+
+/// Whether exceptions are handed to GDB.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether GDB resumed the kernel and is waiting for a stop reply.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// The largest packet the stub accepts or sends, including the framing. Told to GDB in the reply
+/// to `qSupported`.
+pub const MAX_PACKET_SIZE: usize = 1024;
+
+const MAX_BREAKPOINTS: usize = 32;
+
+/// The amount of bytes sent for the registers in a `g` packet: rax to r15 and rip as 64 bit
+/// values, then eflags, cs, ss, ds, es, fs and gs as 32 bit values.
+pub const REGISTERS_SIZE: usize = 17 * 8 + 7 * 4;
+
+const BREAKPOINT_VECTOR: u64 = 0x03;
+const INT3: u8 = 0xcc;
+
+/// The stop reply for a breakpoint or a finished step: signal 5, `SIGTRAP`.
+const STOP_REPLY: &[u8] = b"S05";
+
+/// GDB error reply for an address that isn't mapped (`EFAULT`).
+const ERROR_FAULT: &[u8] = b"E0e";
+
+/// GDB error reply for a malformed packet (`EINVAL`).
+const ERROR_INVALID: &[u8] = b"E16";
+
+/// GDB error reply for a breakpoint that can't be inserted.
+const ERROR_NO_SPACE: &[u8] = b"E1c";
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    /// The byte that was replaced by `int3`.
+    original: u8,
+}
+
+/// A packet without the framing. Fixed size, so the stub never allocates.
+pub struct Packet {
+    data: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Packet {
+    pub fn new() -> Packet {
+        Packet {
+            data: [0; MAX_PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    /// Appends `byte`. Returns false if the packet is full.
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == MAX_PACKET_SIZE {
+            return false;
+        }
+
+        self.data[self.len] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) -> bool {
+        bytes.iter().all(|&byte| self.push(byte))
+    }
+
+    /// Appends `byte` as two hexadecimal digits.
+    pub fn push_hex(&mut self, byte: u8) -> bool {
+        self.push(HEX_DIGITS[(byte >> 4) as usize]) && self.push(HEX_DIGITS[(byte & 0xf) as usize])
+    }
+
+    /// Appends `value` as hexadecimal digits in target (little endian) byte order, the way GDB
+    /// expects register values.
+    pub fn push_le(&mut self, value: u64, size: usize) -> bool {
+        (0..size).all(|i| self.push_hex((value >> (i * 8)) as u8))
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Initializes the second serial port and waits for GDB to connect. From then on, breakpoints and
+/// debug exceptions are handled by GDB.
+pub fn init() -> Result<(), UartError> {
+    GDB_UART.lock().init()?;
+    ENABLED.store(true, Ordering::SeqCst);
+
+    breakpoint();
+    Ok(())
+}
+
+/// Stops at a breakpoint. Panics like any other breakpoint exception if the stub isn't enabled.
+pub fn breakpoint() {
+    unsafe { asm!("int3" :::: "volatile") }
+}
+
+/// Hands control to GDB until it resumes the kernel. Called by the breakpoint and debug exception
+/// handlers, returns false if the stub isn't enabled and the exception has to be handled as usual.
+pub fn handle_exception(stack_frame: &mut StackFrame) -> bool {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    // The instruction pointer points after the `int3` of an inserted breakpoint, GDB wants to see
+    // the address of the breakpoint itself.
+    if stack_frame.kind == BREAKPOINT_VECTOR {
+        let address = stack_frame.instruction_pointer.as_u64().wrapping_sub(1);
+        if BREAKPOINTS.lock().iter().flatten().any(|breakpoint| breakpoint.address == address) {
+            stack_frame.instruction_pointer = VirtualAddress::new(address);
+        }
+    }
+
+    let mut uart = GDB_UART.lock();
+    if RUNNING.swap(false, Ordering::SeqCst) {
+        send_packet(&mut uart, STOP_REPLY);
+    }
+
+    let mut packet = Packet::new();
+    let mut reply = Packet::new();
+
+    loop {
+        receive_packet(&mut uart, &mut packet);
+        reply.len = 0;
+
+        let (&command, arguments) = match packet.as_bytes().split_first() {
+            Some(split) => split,
+            None => {
+                send_packet(&mut uart, b"");
+                continue;
+            }
+        };
+
+        match command {
+            b'?' => {
+                reply.push_bytes(STOP_REPLY);
+            }
+            b'g' => write_registers(stack_frame, &mut reply),
+            b'G' => {
+                let result = if read_registers(stack_frame, arguments) { b"OK" as &[u8] } else { ERROR_INVALID };
+                reply.push_bytes(result);
+            }
+            b'm' => match parse_memory_range(arguments) {
+                Some((address, len, _)) => read_memory(address, len, &mut reply),
+                None => { reply.push_bytes(ERROR_INVALID); }
+            },
+            b'M' => {
+                let result = match parse_memory_range(arguments) {
+                    Some((address, len, data)) => write_memory(address, len, data),
+                    None => ERROR_INVALID,
+                };
+                reply.push_bytes(result);
+            }
+            b'c' | b's' => {
+                if let Some(address) = parse_hex(arguments) {
+                    stack_frame.instruction_pointer = VirtualAddress::new(address);
+                }
+
+                resume(stack_frame, command == b's');
+                return true;
+            }
+            b'Z' | b'z' if arguments.starts_with(b"0,") => {
+                let result = match arguments[2..].split(|&byte| byte == b',').next().and_then(parse_hex) {
+                    Some(address) if command == b'Z' => insert_breakpoint(address),
+                    Some(address) => remove_breakpoint(address),
+                    None => ERROR_INVALID,
+                };
+                reply.push_bytes(result);
+            }
+            b'D' | b'k' => {
+                send_packet(&mut uart, b"OK");
+                remove_all_breakpoints();
+                ENABLED.store(false, Ordering::SeqCst);
+                resume(stack_frame, false);
+                return true;
+            }
+            b'q' if arguments.starts_with(b"Supported") => {
+                reply.push_bytes(b"PacketSize=");
+                push_hex_number(&mut reply, MAX_PACKET_SIZE as u64);
+            }
+            // An empty reply tells GDB that the packet isn't supported.
+            _ => {}
+        }
+
+        send_packet(&mut uart, reply.as_bytes());
+    }
+}
+
+/// Returns the checksum of a packet: the sum of its bytes, modulo 256.
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Frames `data` as a packet, `$data#checksum`, and passes the bytes to `write`. The bytes `#`, `$`,
+/// `}` and `*` are escaped as `}` followed by the byte xor 0x20. The checksum covers the escaped
+/// data.
+pub fn write_packet<F>(data: &[u8], mut write: F) where F: FnMut(u8) {
+    let mut sum = 0u8;
+    let mut write_data = |byte: u8, write: &mut F| {
+        sum = sum.wrapping_add(byte);
+        write(byte);
+    };
+
+    write(b'$');
+    for &byte in data {
+        match byte {
+            b'#' | b'$' | b'}' | b'*' => {
+                write_data(b'}', &mut write);
+                write_data(byte ^ 0x20, &mut write);
+            }
+            _ => write_data(byte, &mut write),
+        }
+    }
+
+    write(b'#');
+    write(HEX_DIGITS[(sum >> 4) as usize]);
+    write(HEX_DIGITS[(sum & 0xf) as usize]);
+}
+
+/// Writes the registers in `stack_frame` to `packet` in the order of a `g` reply.
+pub fn write_registers(stack_frame: &StackFrame, packet: &mut Packet) {
+    let registers = [
+        stack_frame.rax, stack_frame.rbx, stack_frame.rcx, stack_frame.rdx,
+        stack_frame.rsi, stack_frame.rdi, stack_frame.rbp, stack_frame.stack_pointer.as_u64(),
+        stack_frame.r8, stack_frame.r9, stack_frame.r10, stack_frame.r11,
+        stack_frame.r12, stack_frame.r13, stack_frame.r14, stack_frame.r15,
+        stack_frame.instruction_pointer.as_u64(),
+    ];
+
+    for &register in registers.iter() {
+        packet.push_le(register, 8);
+    }
+
+    // The data segment registers aren't saved by the interrupt handlers. They hold the same
+    // selector as the stack segment in the kernel, fs and gs are unused.
+    let stack_segment = u64::from(stack_frame.stack_segment.0);
+    let segments = [
+        stack_frame.cpu_flags, u64::from(stack_frame.code_segment.0), stack_segment,
+        stack_segment, stack_segment, 0, 0,
+    ];
+
+    for &segment in segments.iter() {
+        packet.push_le(segment, 4);
+    }
+}
+
+/// Reads the registers of a `G` packet into `stack_frame`. The segment registers can't be changed,
+/// they are ignored. Returns false if `data` is malformed, nothing is changed in that case.
+pub fn read_registers(stack_frame: &mut StackFrame, data: &[u8]) -> bool {
+    if data.len() < (17 * 8 + 4) * 2 {
+        return false;
+    }
+
+    let mut values = [0u64; 18];
+    for (i, value) in values.iter_mut().enumerate() {
+        let size = if i < 17 { 8 } else { 4 };
+        match parse_le(&data[i * 16..i * 16 + size * 2]) {
+            Some(parsed) => *value = parsed,
+            None => return false,
+        }
+    }
+
+    {
+        let registers = [
+            &mut stack_frame.rax, &mut stack_frame.rbx, &mut stack_frame.rcx, &mut stack_frame.rdx,
+            &mut stack_frame.rsi, &mut stack_frame.rdi, &mut stack_frame.rbp,
+        ];
+
+        for (register, &value) in registers.iter_mut().zip(values[..7].iter()) {
+            **register = value;
+        }
+    }
+
+    stack_frame.stack_pointer = VirtualAddress::new(values[7]);
+
+    {
+        let registers = [
+            &mut stack_frame.r8, &mut stack_frame.r9, &mut stack_frame.r10, &mut stack_frame.r11,
+            &mut stack_frame.r12, &mut stack_frame.r13, &mut stack_frame.r14, &mut stack_frame.r15,
+        ];
+
+        for (register, &value) in registers.iter_mut().zip(values[8..16].iter()) {
+            **register = value;
+        }
+    }
+
+    stack_frame.instruction_pointer = VirtualAddress::new(values[16]);
+    stack_frame.cpu_flags = values[17];
+    true
+}
+
+/// Parses hexadecimal digits into a number, most significant digit first.
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+
+    digits.iter().try_fold(0, |value, &digit| Some(value << 4 | u64::from(hex_value(digit)?)))
+}
+
+/// Parses a value in target byte order, as sent in `G` packets.
+fn parse_le(digits: &[u8]) -> Option<u64> {
+    digits.chunks(2).enumerate().try_fold(0, |value, (i, byte)| Some(value | parse_hex(byte)? << (i * 8)))
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Appends `value` as hexadecimal digits without leading zeroes, most significant digit first.
+fn push_hex_number(packet: &mut Packet, value: u64) {
+    let digits = (64 - value.leading_zeros() as usize + 3) / 4;
+    for i in (0..digits.max(1)).rev() {
+        packet.push(HEX_DIGITS[(value >> (i * 4)) as usize & 0xf]);
+    }
+}
+
+/// Parses `address,length` of an `m` packet or `address,length:data` of an `M` packet.
+fn parse_memory_range(arguments: &[u8]) -> Option<(u64, usize, &[u8])> {
+    let comma = arguments.iter().position(|&byte| byte == b',')?;
+    let (length, data) = match arguments.iter().position(|&byte| byte == b':') {
+        Some(colon) => (&arguments[comma + 1..colon], &arguments[colon + 1..]),
+        None => (&arguments[comma + 1..], &[] as &[u8]),
+    };
+
+    Some((parse_hex(&arguments[..comma])?, parse_hex(length)? as usize, data))
+}
+
+/// Returns whether `address` is mapped in the active page table.
+fn is_mapped(address: u64) -> bool {
+    // Only reads the page tables, so the memory lock isn't needed. It could be held by the code
+    // that hit the breakpoint.
+    let mapper = Mapper::new(unsafe { RecursivePageTable::active() });
+    mapper.translate(VirtualAddress::new(address)).is_some()
+}
+
+/// Appends the contents of memory at `address` to `packet`, as far as it is mapped and fits.
+fn read_memory(address: u64, len: usize, packet: &mut Packet) {
+    for i in 0..len {
+        let address = address.wrapping_add(i as u64);
+        if !is_mapped(address) {
+            break;
+        }
+
+        let byte = unsafe { ptr::read_volatile(address as *const u8) };
+        if packet.len + 2 > MAX_PACKET_SIZE || !packet.push_hex(byte) {
+            break;
+        }
+    }
+
+    if packet.len == 0 {
+        packet.push_bytes(ERROR_FAULT);
+    }
+}
+
+/// Writes the hexadecimal `data` of an `M` packet to memory at `address`. Read-only pages, like the
+/// kernel code, are written too, GDB uses that to insert breakpoints.
+fn write_memory(address: u64, len: usize, data: &[u8]) -> &'static [u8] {
+    if data.len() != len * 2 {
+        return ERROR_INVALID;
+    }
+
+    if (0..len as u64).any(|i| !is_mapped(address.wrapping_add(i))) {
+        return ERROR_FAULT;
+    }
+
+    let mut bytes = data.chunks(2).map(parse_hex);
+    if bytes.clone().any(|byte| byte.is_none()) {
+        return ERROR_INVALID;
+    }
+
+    with_write_protect_disabled(|| {
+        for i in 0..len as u64 {
+            let byte = bytes.next().and_then(|byte| byte).unwrap_or(0) as u8;
+            unsafe { ptr::write_volatile(address.wrapping_add(i) as *mut u8, byte) };
+        }
+    });
+
+    b"OK"
+}
+
+/// Runs `f` with the write protect bit of CR0 cleared, so the kernel can write to read-only pages.
+fn with_write_protect_disabled<F>(f: F) where F: FnOnce() {
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 - Cr0Flags::WriteProtect);
+    f();
+    Cr0::write(cr0);
+}
+
+fn insert_breakpoint(address: u64) -> &'static [u8] {
+    if !is_mapped(address) {
+        return ERROR_FAULT;
+    }
+
+    let mut breakpoints = BREAKPOINTS.lock();
+    if breakpoints.iter().flatten().any(|breakpoint| breakpoint.address == address) {
+        return b"OK";
+    }
+
+    let slot = match breakpoints.iter_mut().find(|breakpoint| breakpoint.is_none()) {
+        Some(slot) => slot,
+        None => return ERROR_NO_SPACE,
+    };
+
+    let original = unsafe { ptr::read_volatile(address as *const u8) };
+    with_write_protect_disabled(|| unsafe { ptr::write_volatile(address as *mut u8, INT3) });
+
+    *slot = Some(Breakpoint { address, original });
+    b"OK"
+}
+
+fn remove_breakpoint(address: u64) -> &'static [u8] {
+    let mut breakpoints = BREAKPOINTS.lock();
+    if let Some(slot) = breakpoints.iter_mut().find(|slot| slot.map_or(false, |breakpoint| breakpoint.address == address)) {
+        restore(slot.take().unwrap());
+    }
+
+    b"OK"
+}
+
+fn remove_all_breakpoints() {
+    for slot in BREAKPOINTS.lock().iter_mut() {
+        if let Some(breakpoint) = slot.take() {
+            restore(breakpoint);
+        }
+    }
+}
+
+/// Puts back the byte that was replaced by `int3`.
+fn restore(breakpoint: Breakpoint) {
+    with_write_protect_disabled(|| unsafe {
+        ptr::write_volatile(breakpoint.address as *mut u8, breakpoint.original)
+    });
+}
+
+/// Lets the kernel continue when the exception handler returns. With `step`, the trap flag makes
+/// the CPU raise a debug exception after the next instruction.
+fn resume(stack_frame: &mut StackFrame, step: bool) {
+    stack_frame.cpu_flags = RFlags::with_trap_flag(stack_frame.cpu_flags, step);
+
+    // The kernel was stopped on purpose, that is no reason for the watchdog to bite.
+    watchdog::pet();
+    RUNNING.store(true, Ordering::SeqCst);
+}
+
+/// Waits for a byte from GDB.
+fn receive_byte(uart: &mut UART16550) -> u8 {
+    loop {
+        if let Some(byte) = uart.receive_byte() {
+            return byte;
+        }
+    }
+}
+
+/// Waits for a packet with a valid checksum and acknowledges it. Packets that are too large or
+/// corrupted are rejected, so GDB sends them again.
+fn receive_packet(uart: &mut UART16550, packet: &mut Packet) {
+    loop {
+        // Everything outside of a packet, like acknowledgements and interrupt requests, is
+        // skipped.
+        while receive_byte(uart) != b'$' {}
+
+        packet.len = 0;
+        let mut fits = true;
+        loop {
+            match receive_byte(uart) {
+                b'#' => break,
+                byte => fits &= packet.push(byte),
+            }
+        }
+
+        let high = hex_value(receive_byte(uart));
+        let low = hex_value(receive_byte(uart));
+        let valid = match (high, low) {
+            (Some(high), Some(low)) => high << 4 | low == checksum(packet.as_bytes()),
+            _ => false,
+        };
+
+        if fits && valid {
+            uart.send_byte(b'+');
+            return;
+        }
+
+        uart.send_byte(b'-');
+    }
+}
+
+/// Sends a packet to GDB and resends it until GDB acknowledges it.
+fn send_packet(uart: &mut UART16550, data: &[u8]) {
+    loop {
+        write_packet(data, |byte| uart.send_byte(byte));
+
+        match receive_byte(uart) {
+            b'+' => return,
+            _ => continue,
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1 @@
+pub mod gdbstub;
\ No newline at end of file
@@ -0,0 +1,277 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use driver::mmio::Mmio;
+use driver::pic::{IRQ_COUNT, PICS};
+use interrupts::irq::{self, InterruptController};
+use memory::paging::mmio;
+use util::irq_lock::IrqLock;
+use x86_64::{PhysicalAddress, VirtualAddress};
+use x86_64::instructions::cpuid::cpuid;
+use x86_64::registers::msr::{ApicBase, ApicBaseFlags};
+
+/// The local APIC of the CPU, once `init` enabled it.
+pub static LOCAL_APIC: IrqLock<Option<LocalApic>> = IrqLock::new(None);
+
+/// The IO APIC that routes the legacy IRQs, once `init` enabled it.
+pub static IO_APIC: IrqLock<Option<IoApic>> = IrqLock::new(None);
+
+/// The amount of times the local APIC timer fired since it was started.
+static TIMER_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Set in EDX of CPUID leaf 1 when the CPU has a local APIC.
+const CPUID_APIC: u32 = 1 << 9;
+
+/// The ISA IRQ the second PIC is cascaded into. It is never raised on its own.
+const CASCADE_IRQ: u8 = 2;
+
+/// The vector of the local APIC timer interrupt.
+pub const TIMER_VECTOR: u8 = 0x30;
+
+/// The vector of spurious local APIC interrupts. The lowest four bits have to be set on older
+/// CPUs.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+pub const REG_ID: usize = 0x20;
+pub const REG_VERSION: usize = 0x30;
+pub const REG_TASK_PRIORITY: usize = 0x80;
+pub const REG_EOI: usize = 0xb0;
+pub const REG_SPURIOUS: usize = 0xf0;
+pub const REG_LVT_TIMER: usize = 0x320;
+pub const REG_LVT_PERF: usize = 0x340;
+pub const REG_TIMER_INITIAL_COUNT: usize = 0x380;
+pub const REG_TIMER_CURRENT_COUNT: usize = 0x390;
+pub const REG_TIMER_DIVIDE: usize = 0x3e0;
+
+/// Set in the spurious interrupt vector register to enable the local APIC.
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+
+/// Set in a local vector table entry or IO APIC redirection entry to mask the interrupt.
+const MASKED: u32 = 1 << 16;
+
+/// The delivery mode of a local vector table entry that raises a non-maskable interrupt instead of
+/// its vector.
+const DELIVERY_NMI: u32 = 0b100 << 8;
+
+/// Set in the timer local vector table entry to restart the timer every time it reaches zero.
+const TIMER_PERIODIC: u32 = 1 << 17;
+
+/// The offsets of the IO APIC register select and data window registers.
+const IO_APIC_SELECT: usize = 0x00;
+const IO_APIC_WINDOW: usize = 0x10;
+
+const IO_APIC_REG_VERSION: u32 = 0x01;
+const IO_APIC_REG_REDIRECTION: u32 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicError {
+    /// The CPU has no local APIC.
+    Unsupported,
+    /// The registers of an APIC are not identity mapped, see `memory::paging::mmio`.
+    NotMapped,
+    /// There is no IO APIC at the usual address.
+    MissingIoApic,
+}
+
+/// Returns whether the CPU has a local APIC.
+pub fn is_supported() -> bool {
+    cpuid(1).edx & CPUID_APIC != 0
+}
+
+/// Enables the local APIC and the IO APIC and disables the 8259 PIC. The legacy IRQs are routed
+/// to the same vectors the PIC used, so handlers registered with `register_irq` keep working.
+/// Leaves the PIC in charge if anything is missing.
+pub fn init() -> Result<(), ApicError> {
+    if !is_supported() {
+        return Err(ApicError::Unsupported);
+    }
+
+    let (base, flags) = ApicBase::read();
+    let local_apic = LocalApic::new(mapped_address(base.as_u64())?);
+    let mut io_apic = IoApic::new(mapped_address(mmio::IO_APIC_ADDRESS)?);
+
+    // Reads of registers without a device behind them return all ones.
+    if io_apic.read(IO_APIC_REG_VERSION) == !0 {
+        return Err(ApicError::MissingIoApic);
+    }
+
+    PICS.lock().disable();
+    ApicBase::write(base, flags | ApicBaseFlags::Enable);
+
+    local_apic.write(REG_TASK_PRIORITY, 0);
+    local_apic.write(REG_SPURIOUS, SPURIOUS_ENABLE | u32::from(SPURIOUS_VECTOR));
+
+    // The GSI of the cascade is the one the PIT arrives at, routing it would overwrite the timer.
+    let apic_id = (local_apic.read(REG_ID) >> 24) as u8;
+    for irq in (0..IRQ_COUNT).filter(|&irq| irq != CASCADE_IRQ) {
+        io_apic.route(isa_irq_to_gsi(irq), irq::irq_vector(irq), apic_id);
+    }
+
+    *LOCAL_APIC.lock() = Some(local_apic);
+    *IO_APIC.lock() = Some(io_apic);
+    irq::set_controller(InterruptController::Apic);
+
+    Ok(())
+}
+
+/// Returns the virtual address of the APIC registers at `physical`, which are identity mapped if
+/// they were registered as an MMIO region.
+fn mapped_address(physical: u64) -> Result<VirtualAddress, ApicError> {
+    mmio::find(PhysicalAddress::new(physical))
+        .map(|_| VirtualAddress::new(physical))
+        .ok_or(ApicError::NotMapped)
+}
+
+/// Returns the global system interrupt ISA IRQ `irq` arrives at. The PIT is connected to input 2
+/// of the IO APIC, the other IRQs are connected one to one. Firmware reports this in the ACPI
+/// tables, but nothing parses those yet.
+pub fn isa_irq_to_gsi(irq: u8) -> u8 {
+    if irq == 0 { 2 } else { irq }
+}
+
+/// Notifies the local APIC that the current interrupt is handled.
+pub fn eoi() {
+    if let Some(local_apic) = LOCAL_APIC.lock().as_ref() {
+        local_apic.write(REG_EOI, 0);
+    }
+}
+
+/// Starts the local APIC timer, which raises `TIMER_VECTOR` every `initial_count` ticks of the
+/// bus clock divided by `divisor`. Returns false if the divisor is invalid or the APIC is not
+/// enabled.
+pub fn start_timer(initial_count: u32, divisor: u32) -> bool {
+    let divide = match timer_divide_configuration(divisor) {
+        Some(divide) => divide,
+        None => return false,
+    };
+
+    match LOCAL_APIC.lock().as_ref() {
+        Some(local_apic) => {
+            local_apic.write(REG_TIMER_DIVIDE, divide);
+            local_apic.write(REG_LVT_TIMER, TIMER_PERIODIC | u32::from(TIMER_VECTOR));
+            local_apic.write(REG_TIMER_INITIAL_COUNT, initial_count);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Stops the local APIC timer.
+pub fn stop_timer() {
+    if let Some(local_apic) = LOCAL_APIC.lock().as_ref() {
+        local_apic.write(REG_LVT_TIMER, MASKED | u32::from(TIMER_VECTOR));
+        local_apic.write(REG_TIMER_INITIAL_COUNT, 0);
+    }
+}
+
+/// Makes an overflow of a performance counter raise a non-maskable interrupt. Returns false if the
+/// local APIC is not enabled.
+pub fn set_perf_counter_nmi() -> bool {
+    match LOCAL_APIC.lock().as_ref() {
+        Some(local_apic) => {
+            local_apic.write(REG_LVT_PERF, DELIVERY_NMI);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Unmasks the performance counter entry again, the local APIC masks it every time it raises the
+/// interrupt. Takes no lock, so it can be used in the NMI handler. Only valid after
+/// `set_perf_counter_nmi` succeeded, because the registers are found through the APIC base MSR.
+pub fn unmask_perf_counter_nmi() {
+    let (base, _) = ApicBase::read();
+    LocalApic::new(VirtualAddress::new(base.as_u64())).write(REG_LVT_PERF, DELIVERY_NMI);
+}
+
+/// Returns the amount of times the local APIC timer fired.
+pub fn timer_ticks() -> u64 {
+    TIMER_TICKS.load(Ordering::SeqCst)
+}
+
+/// Called by the interrupt handler of `TIMER_VECTOR`.
+pub fn timer_interrupt() {
+    TIMER_TICKS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Encodes `divisor` for the timer divide configuration register. The divisor is a power of two
+/// from 1 to 128, encoded as its logarithm minus one in bits 0, 1 and 3, where 1 wraps around to
+/// the highest value.
+pub fn timer_divide_configuration(divisor: u32) -> Option<u32> {
+    if !divisor.is_power_of_two() || divisor > 128 {
+        return None;
+    }
+
+    let value = (divisor.trailing_zeros() + 7) % 8;
+    Some((value & 0b11) | ((value & 0b100) << 1))
+}
+
+/// Returns the address of the local APIC register at `offset`, for the registers mapped at `base`.
+/// Every register is 32 bits wide, but they are 16 byte aligned.
+pub fn register_address(base: VirtualAddress, offset: usize) -> VirtualAddress {
+    base + register_offset(offset) as u64
+}
+
+fn register_offset(offset: usize) -> usize {
+    assert_eq!(offset % 16, 0, "Invalid local APIC register: {:#x}", offset);
+    offset
+}
+
+/// The local APIC of the CPU.
+pub struct LocalApic {
+    registers: Mmio<u32>,
+}
+
+impl LocalApic {
+    fn new(base: VirtualAddress) -> LocalApic {
+        LocalApic {
+            registers: unsafe { Mmio::new(base) },
+        }
+    }
+
+    pub fn read(&self, offset: usize) -> u32 {
+        self.registers.read(register_offset(offset))
+    }
+
+    pub fn write(&self, offset: usize, value: u32) {
+        self.registers.write(register_offset(offset), value)
+    }
+}
+
+/// An IO APIC, which routes the interrupts of devices to the local APICs. Its registers are
+/// accessed indirectly, by writing the register index to the select register first.
+pub struct IoApic {
+    registers: Mmio<u32>,
+}
+
+impl IoApic {
+    fn new(base: VirtualAddress) -> IoApic {
+        IoApic {
+            registers: unsafe { Mmio::new(base) },
+        }
+    }
+
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write(IO_APIC_SELECT, register);
+        self.registers.read(IO_APIC_WINDOW)
+    }
+
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write(IO_APIC_SELECT, register);
+        self.registers.write(IO_APIC_WINDOW, value);
+    }
+
+    /// Routes input `gsi` to `vector` on the local APIC with id `apic_id`. The input starts out
+    /// masked.
+    fn route(&mut self, gsi: u8, vector: u8, apic_id: u8) {
+        let register = IO_APIC_REG_REDIRECTION + u32::from(gsi) * 2;
+        self.write(register + 1, u32::from(apic_id) << 24);
+        self.write(register, MASKED | u32::from(vector));
+    }
+
+    /// Allows input `gsi` to raise its interrupt.
+    pub fn unmask(&mut self, gsi: u8) {
+        let register = IO_APIC_REG_REDIRECTION + u32::from(gsi) * 2;
+        let entry = self.read(register);
+        self.write(register, entry & !MASKED);
+    }
+}
\ No newline at end of file
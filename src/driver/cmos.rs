@@ -0,0 +1,145 @@
+use util::irq_lock::IrqLock;
+use x86_64::port::Port;
+
+/// The CMOS of the PC. Shares its ports with the `RTC`, which only reads the time registers.
+pub static CMOS: IrqLock<Cmos<Port<u8>>> = IrqLock::new(Cmos::new(Port::new(0x70), Port::new(0x71)));
+
+/// The first register that is free to use. Registers below it belong to the RTC, or hold the BIOS
+/// configuration and the checksum over registers 0x10 to 0x2d.
+pub const NVRAM_START: u8 = 0x30;
+
+/// The register after the last register of the standard bank.
+pub const NVRAM_END: u8 = 0x80;
+
+/// Set in the address register to disable non-maskable interrupts. Every write to the address
+/// register changes it, so it is always written together with the selected register.
+const NMI_DISABLE: u8 = 0x80;
+
+/// The register the kernel settings are stored at, followed by their checksum.
+pub const SETTINGS_REGISTER: u8 = 0x40;
+
+/// The size of the kernel settings in bytes.
+pub const SETTINGS_SIZE: usize = 8;
+
+/// Reads NVRAM register `register`.
+pub fn read_nvram(register: u8) -> Result<u8, CmosError> {
+    CMOS.lock().read_nvram(register)
+}
+
+/// Writes `value` to NVRAM register `register`.
+pub fn write_nvram(register: u8, value: u8) -> Result<(), CmosError> {
+    CMOS.lock().write_nvram(register, value)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CmosError {
+    /// The register is outside `NVRAM_START..NVRAM_END`, it belongs to the RTC or the BIOS.
+    ReservedRegister(u8),
+}
+
+/// A byte wide IO port. Implemented by `Port<u8>` and by fake ports, so the access sequence can be
+/// checked without hardware.
+pub trait BytePort {
+    fn read(&self) -> u8;
+    fn write(&self, value: u8);
+}
+
+impl BytePort for Port<u8> {
+    fn read(&self) -> u8 {
+        Port::<u8>::read(self)
+    }
+
+    fn write(&self, value: u8) {
+        Port::<u8>::write(self, value)
+    }
+}
+
+/// The battery backed memory of the CMOS, accessed by selecting a register through the address
+/// port and then reading or writing the data port.
+pub struct Cmos<P> {
+    address: P,
+    data: P,
+    nmi_disabled: bool,
+}
+
+impl<P> Cmos<P> {
+    pub const fn new(address: P, data: P) -> Cmos<P> {
+        Cmos {
+            address,
+            data,
+            nmi_disabled: false,
+        }
+    }
+}
+
+impl<P: BytePort> Cmos<P> {
+    /// Sets whether non-maskable interrupts are disabled. Takes effect with the next access.
+    pub fn set_nmi_disabled(&mut self, disabled: bool) {
+        self.nmi_disabled = disabled;
+    }
+
+    /// Reads NVRAM register `register`. Fails without touching the ports for the registers of the
+    /// RTC and the BIOS.
+    pub fn read_nvram(&mut self, register: u8) -> Result<u8, CmosError> {
+        self.select(register)?;
+        Ok(self.data.read())
+    }
+
+    /// Writes `value` to NVRAM register `register`. Fails without touching the ports for the
+    /// registers of the RTC and the BIOS.
+    pub fn write_nvram(&mut self, register: u8, value: u8) -> Result<(), CmosError> {
+        self.select(register)?;
+        self.data.write(value);
+        Ok(())
+    }
+
+    /// Selects `register`, keeping non-maskable interrupts in the state set with
+    /// `set_nmi_disabled`.
+    fn select(&mut self, register: u8) -> Result<(), CmosError> {
+        if register < NVRAM_START || register >= NVRAM_END {
+            return Err(CmosError::ReservedRegister(register));
+        }
+
+        let nmi = if self.nmi_disabled { NMI_DISABLE } else { 0 };
+        self.address.write(register | nmi);
+        Ok(())
+    }
+}
+
+/// Small settings the kernel keeps across reboots. They are stored with a checksum, so an NVRAM
+/// that was cleared or is used by something else is detected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub bytes: [u8; SETTINGS_SIZE],
+}
+
+impl Settings {
+    /// Reads the settings from the NVRAM. Returns `None` if the checksum does not match.
+    pub fn load<P: BytePort>(cmos: &mut Cmos<P>) -> Option<Settings> {
+        let mut bytes = [0; SETTINGS_SIZE];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = cmos.read_nvram(SETTINGS_REGISTER + i as u8).ok()?;
+        }
+
+        if cmos.read_nvram(SETTINGS_REGISTER + SETTINGS_SIZE as u8).ok()? != checksum(&bytes) {
+            return None;
+        }
+
+        Some(Settings { bytes })
+    }
+
+    /// Writes the settings and their checksum to the NVRAM.
+    pub fn store<P: BytePort>(&self, cmos: &mut Cmos<P>) -> Result<(), CmosError> {
+        for (i, &byte) in self.bytes.iter().enumerate() {
+            cmos.write_nvram(SETTINGS_REGISTER + i as u8, byte)?;
+        }
+
+        cmos.write_nvram(SETTINGS_REGISTER + SETTINGS_SIZE as u8, checksum(&self.bytes))
+    }
+}
+
+/// Returns the checksum of `bytes`: the inverted wrapping sum, so all zeroes don't have a zero
+/// checksum.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
\ No newline at end of file
@@ -0,0 +1,51 @@
+use core::marker::PhantomData;
+use core::mem;
+
+use x86_64::VirtualAddress;
+
+/// The widths a memory mapped register can have, like `PortValue` for IO ports.
+pub trait MmioValue: Copy {}
+impl MmioValue for u8 {}
+impl MmioValue for u16 {}
+impl MmioValue for u32 {}
+impl MmioValue for u64 {}
+
+/// A block of memory mapped registers of type `T` at `base`. Every access is a single volatile
+/// read or write of the width of `T`, so the compiler neither merges, splits nor removes them.
+/// Map the registers with `Mapper::map_mmio` first, or use an identity mapped `mmio` region.
+pub struct Mmio<T: MmioValue> {
+    base: VirtualAddress,
+    phantom: PhantomData<T>,
+}
+
+impl<T: MmioValue> Mmio<T> {
+    /// # Safety
+    /// The registers at `base` have to stay mapped for as long as the `Mmio` exists, and every
+    /// offset that is accessed has to be a register of the device.
+    pub const unsafe fn new(base: VirtualAddress) -> Mmio<T> {
+        Mmio {
+            base,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn base(&self) -> VirtualAddress {
+        self.base
+    }
+
+    /// Reads the register `offset` bytes after the base, which has to be aligned to its width.
+    pub fn read(&self, offset: usize) -> T {
+        unsafe { self.address(offset).as_ptr::<T>().read_volatile() }
+    }
+
+    /// Writes `value` to the register `offset` bytes after the base, which has to be aligned to
+    /// its width.
+    pub fn write(&self, offset: usize, value: T) {
+        unsafe { self.address(offset).as_mut_ptr::<T>().write_volatile(value) }
+    }
+
+    fn address(&self, offset: usize) -> VirtualAddress {
+        assert_eq!(offset % mem::size_of::<T>(), 0, "Unaligned MMIO register: {:#x}", offset);
+        self.base + offset as u64
+    }
+}
\ No newline at end of file
@@ -1,3 +1,10 @@
 pub mod vga;
+pub mod framebuffer;
 pub mod uart16550;
-pub mod pic;
\ No newline at end of file
+pub mod pic;
+pub mod apic;
+pub mod mmio;
+pub mod pit;
+pub mod rtc;
+pub mod cmos;
+pub mod pci;
\ No newline at end of file
@@ -0,0 +1,192 @@
+use alloc::vec::Vec;
+
+use util::irq_lock::IrqLock;
+use x86_64::port::Port;
+
+/// The configuration space of the PCI bus, accessed through configuration mechanism 1.
+pub static PCI: IrqLock<Pci> = IrqLock::new(Pci::new());
+
+/// Set in the configuration address to enable the access to the configuration space.
+const CONFIG_ENABLE: u32 = 1 << 31;
+
+/// The vendor id that is read for functions that don't exist.
+const VENDOR_NONE: u16 = 0xffff;
+
+/// Set in the header type of function 0 if the device has more functions.
+const HEADER_MULTIFUNCTION: u8 = 1 << 7;
+
+/// The header type of normal devices, which have 6 BARs. Bridges have a different layout.
+const HEADER_TYPE_DEVICE: u8 = 0x00;
+
+const REG_VENDOR_DEVICE: u8 = 0x00;
+const REG_CLASS: u8 = 0x08;
+const REG_HEADER_TYPE: u8 = 0x0c;
+const REG_BAR_0: u8 = 0x10;
+
+pub const BUS_COUNT: u16 = 256;
+pub const SLOT_COUNT: u8 = 32;
+pub const FUNCTION_COUNT: u8 = 8;
+
+/// Returns the value to write to the configuration address port to access the 32 bit register
+/// at `offset` of a function. The lowest two bits of `offset` are ignored, registers are always
+/// accessed as a whole.
+pub fn config_address(bus: u8, slot: u8, function: u8, offset: u8) -> u32 {
+    assert!(slot < SLOT_COUNT && function < FUNCTION_COUNT, "Invalid PCI function {}:{}.{}", bus, slot, function);
+
+    CONFIG_ENABLE
+        | u32::from(bus) << 16
+        | u32::from(slot) << 11
+        | u32::from(function) << 8
+        | u32::from(offset & 0xfc)
+}
+
+/// Finds every PCI function on every bus.
+pub fn enumerate() -> Vec<PciDevice> {
+    PCI.lock().enumerate()
+}
+
+pub struct Pci {
+    address: Port<u32>,
+    data: Port<u32>,
+}
+
+impl Pci {
+    const fn new() -> Pci {
+        Pci {
+            address: Port::new(0xcf8),
+            data: Port::new(0xcfc),
+        }
+    }
+
+    pub fn read_config(&mut self, bus: u8, slot: u8, function: u8, offset: u8) -> u32 {
+        self.address.write(config_address(bus, slot, function, offset));
+        self.data.read()
+    }
+
+    pub fn write_config(&mut self, bus: u8, slot: u8, function: u8, offset: u8, value: u32) {
+        self.address.write(config_address(bus, slot, function, offset));
+        self.data.write(value);
+    }
+
+    /// Finds every PCI function by checking every slot of every bus. Only function 0 is checked for
+    /// slots that are not multifunction devices, because some single function devices respond to
+    /// every function number.
+    pub fn enumerate(&mut self) -> Vec<PciDevice> {
+        let mut devices = Vec::new();
+
+        for bus in 0..BUS_COUNT {
+            for slot in 0..SLOT_COUNT {
+                let first = match self.read_device(bus as u8, slot, 0) {
+                    Some(device) => device,
+                    None => continue,
+                };
+
+                let functions = if first.is_multifunction() { FUNCTION_COUNT } else { 1 };
+                devices.push(first);
+
+                for function in 1..functions {
+                    if let Some(device) = self.read_device(bus as u8, slot, function) {
+                        devices.push(device);
+                    }
+                }
+            }
+        }
+
+        devices
+    }
+
+    /// Reads the header of a function. Returns `None` if the function doesn't exist.
+    fn read_device(&mut self, bus: u8, slot: u8, function: u8) -> Option<PciDevice> {
+        let ids = self.read_config(bus, slot, function, REG_VENDOR_DEVICE);
+        let vendor_id = ids as u16;
+
+        if vendor_id == VENDOR_NONE {
+            return None;
+        }
+
+        let class = self.read_config(bus, slot, function, REG_CLASS);
+        let header_type = (self.read_config(bus, slot, function, REG_HEADER_TYPE) >> 16) as u8;
+
+        let mut bars = [0; 6];
+        if header_type & !HEADER_MULTIFUNCTION == HEADER_TYPE_DEVICE {
+            for (i, bar) in bars.iter_mut().enumerate() {
+                *bar = self.read_config(bus, slot, function, REG_BAR_0 + i as u8 * 4);
+            }
+        }
+
+        Some(PciDevice {
+            bus,
+            slot,
+            function,
+            vendor_id,
+            device_id: (ids >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            bars,
+        })
+    }
+}
+
+/// A function of a device on the PCI bus.
+#[derive(Debug, Clone)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub slot: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    /// The raw base address registers. Zero for bridges, which have a different layout.
+    pub bars: [u32; 6],
+}
+
+impl PciDevice {
+    pub fn is_multifunction(&self) -> bool {
+        self.header_type & HEADER_MULTIFUNCTION != 0
+    }
+
+    /// Decodes BAR `index`. Returns `None` if it is unused, or if it is the upper half of the
+    /// 64 bit BAR before it.
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        if index > 0 && Bar::is_64_bit(self.bars[index - 1]) {
+            return None;
+        }
+
+        let raw = self.bars[index];
+        if raw == 0 {
+            return None;
+        }
+
+        if raw & 1 != 0 {
+            return Some(Bar::Io { port: (raw & !0b11) as u16 });
+        }
+
+        let mut address = u64::from(raw & !0b1111);
+        if Bar::is_64_bit(raw) {
+            address |= u64::from(*self.bars.get(index + 1)?) << 32;
+        }
+
+        Some(Bar::Memory { address, prefetchable: raw & (1 << 3) != 0 })
+    }
+}
+
+/// A decoded base address register, which tells where the registers of a device are.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Bar {
+    Memory { address: u64, prefetchable: bool },
+    Io { port: u16 },
+}
+
+impl Bar {
+    /// Returns whether the raw BAR `raw` is a 64 bit memory BAR, which continues in the next one.
+    fn is_64_bit(raw: u32) -> bool {
+        raw & 1 == 0 && (raw >> 1) & 0b11 == 0b10
+    }
+}
\ No newline at end of file
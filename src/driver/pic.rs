@@ -1,13 +1,21 @@
 use lazy_static::lazy_static;
 
+use time;
 use util::irq_lock::IrqLock;
 use x86_64::port::Port;
 
 /// The IRQ index for the first PIC
-const PIC_1_OFFSET: u8 = 0x20;
+pub const PIC_1_OFFSET: u8 = 0x20;
 
 /// The IRQ index for the second PIC
-const PIC_2_OFFSET: u8 = 0x28;
+pub const PIC_2_OFFSET: u8 = 0x28;
+
+/// The amount of IRQs of both PICs together
+pub const IRQ_COUNT: u8 = 16;
+
+/// The IRQ index for the first PIC once it is disabled, out of the way of the APIC vectors. Only
+/// its spurious interrupts can still arrive there.
+pub const DISABLED_PIC_OFFSET: u8 = 0xe0;
 
 lazy_static! {
     pub static ref PICS: IrqLock<ChainedPics> = IrqLock::new(ChainedPics::new());
@@ -30,6 +38,19 @@ impl Pic {
     fn end_of_interrupt(&mut self) {
         self.command.write(0x20);
     }
+
+    /// Allows the PIC to raise the interrupt on line 'line'.
+    fn unmask(&mut self, line: u8) {
+        let mask = self.data.read();
+        self.data.write(mask & !(1 << line));
+    }
+
+    /// Reads the in-service register, which has a bit set for every interrupt that was sent to the
+    /// CPU but has not been acknowledged yet.
+    fn read_isr(&mut self) -> u8 {
+        self.command.write(0x0b);
+        self.command.read()
+    }
 }
 
 /// A representation of how the PIC's are set up on x86 hardware
@@ -40,7 +61,7 @@ pub struct ChainedPics {
 impl ChainedPics {
     /// Creates and initializes 'ChainedPics'
     pub fn new() -> ChainedPics {
-        let chained_pics = ChainedPics {
+        let mut chained_pics = ChainedPics {
             pics: [
                 Pic {
                     offset: PIC_1_OFFSET,
@@ -55,38 +76,52 @@ impl ChainedPics {
             ]
         };
 
-        let wait_port: Port<u8> = Port::new(0x80);
-        let wait = || wait_port.write(0);
+        chained_pics.remap(PIC_1_OFFSET);
+        chained_pics
+    }
+
+    /// Reinitializes both PICs so their IRQs arrive at the 16 vectors starting at 'offset'. The
+    /// masks are kept.
+    fn remap(&mut self, offset: u8) {
+        let wait = || time::delay_us(1);
 
         let saved_masks = (
-            chained_pics.pics[0].data.read(),
-            chained_pics.pics[1].data.read()
+            self.pics[0].data.read(),
+            self.pics[1].data.read()
         );
 
-        chained_pics.pics[0].command.write(0x11);
+        self.pics[0].offset = offset;
+        self.pics[1].offset = offset + 8;
+
+        self.pics[0].command.write(0x11);
         wait();
-        chained_pics.pics[1].command.write(0x11);
+        self.pics[1].command.write(0x11);
         wait();
 
-        chained_pics.pics[0].data.write(PIC_1_OFFSET);
+        self.pics[0].data.write(self.pics[0].offset);
         wait();
-        chained_pics.pics[1].data.write(PIC_2_OFFSET);
+        self.pics[1].data.write(self.pics[1].offset);
         wait();
 
-        chained_pics.pics[0].data.write(4);
+        self.pics[0].data.write(4);
         wait();
-        chained_pics.pics[1].data.write(2);
+        self.pics[1].data.write(2);
         wait();
 
-        chained_pics.pics[0].data.write(1);
+        self.pics[0].data.write(1);
         wait();
-        chained_pics.pics[1].data.write(1);
+        self.pics[1].data.write(1);
         wait();
 
-        chained_pics.pics[0].data.write(saved_masks.0);
-        chained_pics.pics[1].data.write(saved_masks.1);
+        self.pics[0].data.write(saved_masks.0);
+        self.pics[1].data.write(saved_masks.1);
+    }
 
-        chained_pics
+    /// Masks every IRQ and moves the PICs to `DISABLED_PIC_OFFSET`, for when the APIC takes over.
+    pub fn disable(&mut self) {
+        self.pics[0].data.write(0xff);
+        self.pics[1].data.write(0xff);
+        self.remap(DISABLED_PIC_OFFSET);
     }
 
     /// Checks if either of the PIC's contained in this 'ChainedPic' handles interrupt with id 'id'
@@ -95,7 +130,7 @@ impl ChainedPics {
     }
 
     /// Notify the correct PIC that the current interrupt with id 'id' is handled.
-    fn end_of_interrupt(&mut self, id: u8) {
+    pub fn end_of_interrupt(&mut self, id: u8) {
         if self.handles_interrupt(id) {
             if self.pics[1].handles_interrupt(id) {
                 self.pics[1].end_of_interrupt();
@@ -104,4 +139,45 @@ impl ChainedPics {
             self.pics[0].end_of_interrupt();
         }
     }
+
+    /// Reads the in-service registers of both PICs. The lower byte is the register of the first
+    /// PIC, so bit 'n' is set when IRQ 'n' is in service.
+    pub fn read_isr(&mut self) -> u16 {
+        u16::from(self.pics[0].read_isr()) | u16::from(self.pics[1].read_isr()) << 8
+    }
+
+    /// Allows IRQ 'irq' to be raised. IRQs of the second PIC also unmask the cascade line on the
+    /// first PIC.
+    pub fn unmask(&mut self, irq: u8) {
+        if irq < 8 {
+            self.pics[0].unmask(irq);
+        } else {
+            self.pics[1].unmask(irq - 8);
+            self.pics[0].unmask(2);
+        }
+    }
+
+    /// Checks if the interrupt with id 'id' is spurious, which happens for the lowest priority IRQ
+    /// of a PIC (7 or 15) when the device stops signalling it before the CPU accepts it. A
+    /// spurious interrupt is not in service, so it must not get an end of interrupt. For a
+    /// spurious IRQ 15 the first PIC did receive a real interrupt on the cascade line, so that one
+    /// is acknowledged here.
+    pub fn check_spurious(&mut self, id: u8) -> bool {
+        let irq = id - self.pics[0].offset;
+
+        if (irq != 7 && irq != 15) || !is_spurious(self.read_isr(), irq) {
+            return false;
+        }
+
+        if self.pics[1].handles_interrupt(id) {
+            self.pics[0].end_of_interrupt();
+        }
+
+        true
+    }
+}
+
+/// Checks if IRQ 'irq' is spurious, given the combined in-service registers 'isr' of both PICs.
+pub fn is_spurious(isr: u16, irq: u8) -> bool {
+    isr & (1 << irq) == 0
 }
\ No newline at end of file
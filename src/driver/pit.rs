@@ -0,0 +1,119 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use interrupts;
+use task::wait_queue::WaitQueue;
+use util::preempt_lock::PreemptMutex;
+use x86_64::port::Port;
+
+/// The programmable interval timer. This is a `PreemptMutex` instead of an `IrqLock`, because it
+/// is never used by interrupt handlers. Delays only take it to start channel 2 and to poll it, so
+/// other callers can get to it while a delay runs.
+pub static PIT: PreemptMutex<Pit> = PreemptMutex::new(Pit::new());
+
+/// The frequency every channel of the PIT counts down at, in Hz.
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// The frequency channel 0 raises the tick interrupt at, in Hz.
+pub const TICK_FREQUENCY: u64 = 1000;
+
+/// The IRQ of channel 0.
+const PIT_IRQ: u8 = 0;
+
+/// Set in the gate register to let channel 2 count.
+const GATE_CHANNEL_2: u8 = 1;
+
+/// Set in the gate register to connect channel 2 to the PC speaker.
+const GATE_SPEAKER: u8 = 1 << 1;
+
+/// Set in the gate register while the output of channel 2 is high.
+const GATE_OUTPUT_2: u8 = 1 << 5;
+
+/// The amount of tick interrupts since `init`.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Woken by every tick, for threads that wait for a point in time.
+pub static TICK_QUEUE: WaitQueue = WaitQueue::new();
+
+/// Starts the tick interrupt on channel 0.
+pub fn init() {
+    PIT.lock().set_tick_frequency(TICK_FREQUENCY);
+    interrupts::register_irq(PIT_IRQ, tick);
+}
+
+/// Returns the amount of tick interrupts since `init`.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+fn tick() {
+    TICKS.fetch_add(1, Ordering::SeqCst);
+
+    if TICK_QUEUE.waiters() > 0 {
+        TICK_QUEUE.wake_all();
+    }
+}
+
+pub struct Pit {
+    command: Port<u8>,
+    channel_0: Port<u8>,
+    channel_2: Port<u8>,
+    gate: Port<u8>,
+    one_shot_running: bool,
+}
+
+impl Pit {
+    const fn new() -> Pit {
+        Pit {
+            command: Port::new(0x43),
+            channel_0: Port::new(0x40),
+            channel_2: Port::new(0x42),
+            gate: Port::new(0x61),
+            one_shot_running: false,
+        }
+    }
+
+    /// Makes channel 0 raise its interrupt `frequency` times per second, in square wave mode.
+    fn set_tick_frequency(&mut self, frequency: u64) {
+        let divisor = (PIT_FREQUENCY / frequency) as u16;
+
+        self.command.write(0x36);
+        self.channel_0.write(divisor as u8);
+        self.channel_0.write((divisor >> 8) as u8);
+    }
+
+    /// Starts counting down `cycles` cycles on channel 2, in one-shot mode. Returns false without
+    /// doing anything if another one-shot count is still running, it has to be polled to
+    /// completion with `one_shot_done` first.
+    pub fn start_one_shot(&mut self, cycles: u16) -> bool {
+        if self.one_shot_running {
+            return false;
+        }
+
+        let gate = self.gate.read() & !(GATE_CHANNEL_2 | GATE_SPEAKER);
+        self.gate.write(gate);
+
+        // Interrupt on terminal count mode, which sets the output once the count reaches zero.
+        // Counting starts when the gate goes high.
+        self.command.write(0xb0);
+        self.channel_2.write(cycles as u8);
+        self.channel_2.write((cycles >> 8) as u8);
+        self.gate.write(gate | GATE_CHANNEL_2);
+
+        self.one_shot_running = true;
+        true
+    }
+
+    /// Returns whether the count started by `start_one_shot` reached zero, and stops channel 2 if
+    /// it did. Channel 2 is not connected to an interrupt, its output is polled through the gate
+    /// register instead.
+    pub fn one_shot_done(&mut self) -> bool {
+        let gate = self.gate.read();
+        if gate & GATE_OUTPUT_2 == 0 {
+            return false;
+        }
+
+        self.gate.write(gate & !GATE_CHANNEL_2);
+        self.one_shot_running = false;
+        true
+    }
+}
\ No newline at end of file
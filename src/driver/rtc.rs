@@ -0,0 +1,136 @@
+use util::irq_lock::IrqLock;
+use x86_64::port::Port;
+
+pub static RTC: IrqLock<Rtc> = IrqLock::new(Rtc::new());
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+/// Set in status register A while the RTC is updating its registers.
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 1 << 7;
+
+/// Set in status register B if the hours are in 24 hour format.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+
+/// Set in status register B if the registers are binary instead of BCD.
+const STATUS_B_BINARY: u8 = 1 << 2;
+
+/// Set in the hours register for PM times in 12 hour format.
+const HOURS_PM: u8 = 1 << 7;
+
+/// A calendar date and time in UTC, as read from the RTC.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Returns the amount of seconds since the Unix epoch.
+    pub fn unix_timestamp(&self) -> i64 {
+        let days = days_since_epoch(i64::from(self.year), u32::from(self.month), u32::from(self.day));
+        days * 86400 + i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second)
+    }
+}
+
+/// The real time clock in the CMOS.
+pub struct Rtc {
+    address: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Rtc {
+    pub const fn new() -> Rtc {
+        Rtc {
+            address: Port::new(0x70),
+            data: Port::new(0x71),
+        }
+    }
+
+    /// Reads the current date and time. The registers are read until two consecutive reads outside
+    /// of an update match, so a read can't be torn by an update of the RTC.
+    pub fn read(&mut self) -> DateTime {
+        let mut last = self.read_registers();
+
+        loop {
+            let current = self.read_registers();
+            if current == last {
+                break;
+            }
+
+            last = current;
+        }
+
+        let status_b = self.read_register(REG_STATUS_B);
+        let convert = |value: u8| if status_b & STATUS_B_BINARY != 0 { value } else { bcd_to_binary(value) };
+
+        let [second, minute, hours, day, month, year] = last;
+
+        let mut hour = convert(hours & !HOURS_PM);
+        if status_b & STATUS_B_24_HOUR == 0 {
+            hour %= 12;
+
+            if hours & HOURS_PM != 0 {
+                hour += 12;
+            }
+        }
+
+        DateTime {
+            // The century register is not reliably available, so assume the 21st century.
+            year: 2000 + u16::from(convert(year)),
+            month: convert(month),
+            day: convert(day),
+            hour,
+            minute: convert(minute),
+            second: convert(second),
+        }
+    }
+
+    /// Reads the raw time registers after waiting for a running update to finish.
+    fn read_registers(&mut self) -> [u8; 6] {
+        while self.read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {}
+
+        [
+            self.read_register(REG_SECONDS),
+            self.read_register(REG_MINUTES),
+            self.read_register(REG_HOURS),
+            self.read_register(REG_DAY),
+            self.read_register(REG_MONTH),
+            self.read_register(REG_YEAR),
+        ]
+    }
+
+    fn read_register(&mut self, register: u8) -> u8 {
+        self.address.write(register);
+        self.data.read()
+    }
+}
+
+/// Converts a two digit binary coded decimal value to binary.
+pub fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0f)
+}
+
+/// Returns the amount of days between the Unix epoch and the given date in the proleptic
+/// Gregorian calendar.
+pub fn days_since_epoch(year: i64, month: u32, day: u32) -> i64 {
+    // Count years from March, so the leap day is at the end of the year.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
\ No newline at end of file
@@ -2,11 +2,34 @@ use core::fmt::{Error, Write};
 
 use flagset::{flags, FlagSet};
 
+use driver::cmos::BytePort;
+use interrupts;
+use task::wait_queue::WaitQueue;
 use x86_64::port::Port;
 use util::irq_lock::IrqLock;
 
+/// The first serial port. This is a `static`, so every use locks the same instance.
 pub static UART: IrqLock<UART16550> = IrqLock::new(UART16550::new(0x3F8));
 
+/// Woken by the interrupt of the first serial port, when it received data.
+pub static RECEIVE_QUEUE: WaitQueue = WaitQueue::new();
+
+/// The IRQ of the first serial port.
+const COM1_IRQ: u8 = 4;
+
+/// Initializes the first serial port and registers its interrupt handler.
+pub fn init() -> Result<(), UartError> {
+    UART.lock().init()?;
+    interrupts::register_irq(COM1_IRQ, receive_interrupt);
+
+    Ok(())
+}
+
+/// Called when the first serial port received data. The data stays in the UART until it is read.
+fn receive_interrupt() {
+    RECEIVE_QUEUE.wake_all();
+}
+
 flags! {
     enum LineStsFlags: u8 {
         InputFull = 1,
@@ -14,71 +37,220 @@ flags! {
     }
 }
 
+/// The frequency the divisor of the UART divides to get the baud rate.
+const UART_CLOCK: u32 = 115_200;
+
+/// The amount of times the line status is polled while waiting to send a byte, before the byte is
+/// dropped. Keeps a missing or wedged port from hanging the kernel, including the panic handler.
+const SEND_SPIN_LIMIT: usize = 100_000;
+
+/// The value written to the scratch register by `probe` and sent by `self_test`.
+const TEST_VALUE: u8 = 0xAE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    /// There is no UART at the port.
+    Missing,
+    /// A byte sent in loopback mode did not come back.
+    SelfTestFailed,
+}
+
 pub struct UART16550 {
+    initialized: bool,
+    /// False once `init` found the port missing or faulty. Output is dropped from then on.
+    present: bool,
+    divisor: u16,
     data: Port<u8>,
     int_en: Port<u8>,
     fifo_ctrl: Port<u8>,
     line_ctrl: Port<u8>,
     modem_ctrl: Port<u8>,
     line_sts: Port<u8>,
+    scratch: Port<u8>,
 }
 
 impl UART16550 {
     pub const fn new(base: u16) -> UART16550 {
         UART16550 {
+            initialized: false,
+            present: true,
+            divisor: 3,
             data: Port::new(base),
             int_en: Port::new(base + 1),
             fifo_ctrl: Port::new(base + 2),
             line_ctrl: Port::new(base + 3),
             modem_ctrl: Port::new(base + 4),
             line_sts: Port::new(base + 5),
+            scratch: Port::new(base + 7),
         }
     }
 
-    pub fn init(&mut self) {
-        // Disable interrupts
-        self.int_en.write(0x00);
+    /// Initializes the UART. Only the first call has an effect, so a later call can't reset a baud
+    /// rate that was changed in the meantime. Returns an error if the port is missing or fails the
+    /// loopback test, output is dropped from then on.
+    pub fn init(&mut self) -> Result<(), UartError> {
+        if self.initialized {
+            return if self.present { Ok(()) } else { Err(UartError::Missing) };
+        }
 
-        // Enable DLAB
-        self.line_ctrl.write(0x80);
+        self.initialized = true;
+        self.present = false;
+
+        if !self.probe() {
+            return Err(UartError::Missing);
+        }
 
-        // Set maximum speed to 384000 bps
-        self.data.write(0x03);
+        // Disable interrupts
         self.int_en.write(0x00);
 
-        // Disable DLAB
-        self.line_ctrl.write(0x03);
+        // Set speed to 38400 bps
+        self.set_divisor(self.divisor);
 
         // Enable FIFO
         self.fifo_ctrl.write(0xC7);
 
+        if !self.self_test() {
+            return Err(UartError::SelfTestFailed);
+        }
+
         // Mark data terminal ready, enable auxiliary output #2
         self.modem_ctrl.write(0x0B);
 
         // Enable interrupts
         self.int_en.write(0x01);
+
+        self.present = true;
+        Ok(())
+    }
+
+    /// Runs the loopback test on this port, see `loopback_test`.
+    pub fn self_test(&mut self) -> bool {
+        loopback_test(&self.modem_ctrl, &self.line_sts, &self.data)
+    }
+
+    /// Programs the baud rate divisor. The resulting baud rate is `115200 / divisor`.
+    pub fn set_divisor(&mut self, divisor: u16) {
+        self.divisor = divisor;
+
+        // Enable DLAB
+        self.line_ctrl.write(0x80);
+
+        self.data.write(divisor as u8);
+        self.int_en.write((divisor >> 8) as u8);
+
+        // Disable DLAB, 8 bits, no parity, one stop bit
+        self.line_ctrl.write(0x03);
+    }
+
+    /// Sets the baud rate. Returns false if the baud rate can't be reached exactly.
+    pub fn set_baud_rate(&mut self, baud: u32) -> bool {
+        if baud == 0 || UART_CLOCK % baud != 0 || UART_CLOCK / baud > u32::from(u16::max_value()) {
+            return false;
+        }
+
+        self.set_divisor((UART_CLOCK / baud) as u16);
+        true
+    }
+
+    /// Returns the current baud rate.
+    pub fn baud_rate(&self) -> u32 {
+        UART_CLOCK / u32::from(self.divisor)
+    }
+
+    /// Checks if there is a UART at this port, see `probe_scratch`.
+    pub fn probe(&mut self) -> bool {
+        probe_scratch(&self.scratch)
+    }
+
+    /// Returns whether the port was found and passed its self test in `init`. Before `init`, the port is assumed present.
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Returns true if a received byte is waiting to be read.
+    pub fn has_received(&mut self) -> bool {
+        self.present && self.line_sts().contains(LineStsFlags::InputFull)
+    }
+
+    /// Reads a received byte, if there is one.
+    pub fn receive_byte(&mut self) -> Option<u8> {
+        if self.has_received() {
+            Some(self.data.read())
+        } else {
+            None
+        }
     }
 
     fn line_sts(&mut self) -> FlagSet<LineStsFlags> {
         FlagSet::new_truncated(self.line_sts.read())
     }
 
+    /// Waits until the transmitter can take another byte. Returns false if it didn't become empty
+    /// within `SEND_SPIN_LIMIT` polls.
+    fn wait_output_empty(&mut self) -> bool {
+        for _ in 0..SEND_SPIN_LIMIT {
+            if self.line_sts().contains(LineStsFlags::OutputEmpty) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Writes a byte to the data register once the transmitter is empty. The byte is dropped if
+    /// the transmitter stays full.
+    fn write_data(&mut self, data: u8) {
+        if self.wait_output_empty() {
+            self.data.write(data);
+        }
+    }
+
     pub fn send_byte(&mut self, data: u8) {
+        if !self.present {
+            return;
+        }
+
         match data {
             8 | 0x7F => {
-                while !self.line_sts().contains(LineStsFlags::OutputEmpty) {}
-                self.data.write(8);
-                while !self.line_sts().contains(LineStsFlags::OutputEmpty) {}
-                self.data.write(b' ');
-                while !self.line_sts().contains(LineStsFlags::OutputEmpty) {}
-                self.data.write(8);
-            }
-            _ => {
-                while !self.line_sts().contains(LineStsFlags::OutputEmpty) {}
-                self.data.write(data);
+                self.write_data(8);
+                self.write_data(b' ');
+                self.write_data(8);
             }
+            _ => self.write_data(data),
+        }
+    }
+}
+
+/// Checks if there is a UART behind `scratch`, by writing a value to its scratch register and
+/// reading it back. Without a device, reads return the value of a floating bus instead.
+pub fn probe_scratch<P: BytePort>(scratch: &P) -> bool {
+    scratch.write(TEST_VALUE);
+    scratch.read() == TEST_VALUE
+}
+
+/// Sends a byte in loopback mode and checks that it is received again, through the modem control,
+/// line status and data registers of a UART. The modem control register is left in loopback mode,
+/// `UART16550::init` sets it back afterwards.
+pub fn loopback_test<P: BytePort>(modem_ctrl: &P, line_sts: &P, data: &P) -> bool {
+    let input_full = || FlagSet::<LineStsFlags>::new_truncated(line_sts.read()).contains(LineStsFlags::InputFull);
+
+    // Loopback mode, request to send, auxiliary outputs #1 and #2
+    modem_ctrl.write(0x1E);
+
+    // Drain a byte that was received before loopback mode was entered
+    if input_full() {
+        data.read();
+    }
+
+    data.write(TEST_VALUE);
+
+    for _ in 0..SEND_SPIN_LIMIT {
+        if input_full() {
+            return data.read() == TEST_VALUE;
         }
     }
+
+    false
 }
 
 impl Write for UART16550 {
@@ -7,7 +7,8 @@ enum AnsiParserState {
     /// No characters of significance have been found yet.
     None,
 
-    /// Currently looking for a bracket to start the escape.
+    /// Currently looking for a bracket to start the escape, or the character of an escape
+    /// without one, like `ESC 7`.
     Bracket,
 
     /// ANSI escape prefix has been found and the parser is reading the escape codes.
@@ -129,6 +130,16 @@ impl <'a> Iterator for AnsiParseIterator<'a> {
                                 self.index += 1;
                                 self.attr_stack[0] = AttrStackEntry::Initialized;
                             },
+                            '7' | '8' | 'c' => {
+                                self.state = AnsiParserState::None;
+                                self.data = &self.data[self.index + 1..];
+
+                                return Some(match c {
+                                    '7' => AnsiSequencePart::SaveCursor,
+                                    '8' => AnsiSequencePart::RestoreCursor,
+                                    _ => AnsiSequencePart::Reset,
+                                });
+                            },
                             _ => self.state = AnsiParserState::None,
                         }
                     } else {
@@ -152,8 +163,10 @@ impl <'a> Iterator for AnsiParseIterator<'a> {
                                             _ => unreachable!(),
                                         };
 
+                                        // Values that don't fit saturate, `ESC[999D` still moves as far as possible.
                                         let addition = c.to_digit(10).unwrap() as u8;
-                                        self.attr_stack[current_index] = AttrStackEntry::Value(current_value * 10 + addition);
+                                        let value = current_value.saturating_mul(10).saturating_add(addition);
+                                        self.attr_stack[current_index] = AttrStackEntry::Value(value);
                                     }
                                 }
 
@@ -163,6 +176,24 @@ impl <'a> Iterator for AnsiParseIterator<'a> {
                                 self.attr_stack[self.current_stack_index() + 1] = AttrStackEntry::Initialized;
                                 self.index += 1;
                             },
+                            'A' | 'B' | 'C' | 'D' => {
+                                // A missing count or a count of 0 moves by one.
+                                let count = match self.attr_stack[0] {
+                                    AttrStackEntry::Value(value) if value > 0 => value,
+                                    _ => 1,
+                                };
+
+                                self.state = AnsiParserState::None;
+                                self.attr_stack = [AttrStackEntry::Missing; ATTR_STACK_SIZE];
+                                self.data = &self.data[self.index + 1..];
+
+                                return Some(match c {
+                                    'A' => AnsiSequencePart::CursorUp(count),
+                                    'B' => AnsiSequencePart::CursorDown(count),
+                                    'C' => AnsiSequencePart::CursorForward(count),
+                                    _ => AnsiSequencePart::CursorBack(count),
+                                });
+                            },
                             'm' => {
                                 self.state = AnsiParserState::None;
                                 if self.attr_stack.contains(&AttrStackEntry::Initialized) |
@@ -189,7 +220,7 @@ impl <'a> Iterator for AnsiParseIterator<'a> {
 }
 
 /// A parsed ANSI escape code that gets returned by `AnsiEscapeParser`
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum AnsiSequencePart<'a> {
     /// No escape code, just text in between escape codes
     Text(&'a str),
@@ -199,4 +230,25 @@ pub enum AnsiSequencePart<'a> {
     /// See https://en.wikipedia.org/wiki/ANSI_escape_code#SGR_parameters for more specific
     /// information and examples
     SGR(u8),
+
+    /// `ESC 7`, saves the cursor position and the text attributes.
+    SaveCursor,
+
+    /// `ESC 8`, restores what the last `SaveCursor` saved.
+    RestoreCursor,
+
+    /// `ESC c`, resets the terminal to its initial state.
+    Reset,
+
+    /// `ESC [ n A`, moves the cursor up `n` rows, stopping at the edge of the screen.
+    CursorUp(u8),
+
+    /// `ESC [ n B`, moves the cursor down `n` rows.
+    CursorDown(u8),
+
+    /// `ESC [ n C`, moves the cursor right `n` columns.
+    CursorForward(u8),
+
+    /// `ESC [ n D`, moves the cursor left `n` columns.
+    CursorBack(u8),
 }
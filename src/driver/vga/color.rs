@@ -1,5 +1,5 @@
 #[allow(dead_code)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(u8)]
 pub enum Color {
     Black = 0x0,
@@ -48,9 +48,41 @@ impl Color {
             }
         }
     }
+
+    /// Converts the lower 4 bits of `value` to a color.
+    pub fn from_u8(value: u8) -> Color {
+        match value & 0xf {
+            0x0 => Color::Black,
+            0x1 => Color::Blue,
+            0x2 => Color::Green,
+            0x3 => Color::Cyan,
+            0x4 => Color::Red,
+            0x5 => Color::Magenta,
+            0x6 => Color::Brown,
+            0x7 => Color::LightGray,
+            0x8 => Color::DarkGray,
+            0x9 => Color::LightBlue,
+            0xa => Color::LightGreen,
+            0xb => Color::LightCyan,
+            0xc => Color::LightRed,
+            0xd => Color::Pink,
+            0xe => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+
+    /// Returns the bright variant of this color. Bright colors are returned as is.
+    pub fn brighten(self) -> Color {
+        Color::from_u8(self as u8 | 0x8)
+    }
+
+    /// Returns the dark variant of this color. Dark colors are returned as is.
+    pub fn darken(self) -> Color {
+        Color::from_u8(self as u8 & 0x7)
+    }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 #[repr(transparent)]
 pub struct ColorCode(u8);
 
@@ -66,4 +98,12 @@ impl ColorCode {
     pub fn set_foreground(&mut self, foreground: Color) {
         self.0 = self.0 & 0xf0 | (foreground as u8);
     }
+
+    pub fn foreground(self) -> Color {
+        Color::from_u8(self.0)
+    }
+
+    pub fn background(self) -> Color {
+        Color::from_u8(self.0 >> 4)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,38 @@
+/// The characters of code page 437 from 0x80 to 0xff, which is the character set of the vga text
+/// mode font.
+const UPPER_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The graphical characters of code page 437 below 0x20 that `ScreenWriter::write_byte` doesn't
+/// interpret as control characters.
+const GLYPHS: [(char, u8); 18] = [
+    ('☺', 0x01), ('☻', 0x02), ('♥', 0x03), ('♦', 0x04), ('♣', 0x05), ('♠', 0x06),
+    ('►', 0x10), ('◄', 0x11), ('↕', 0x12), ('‼', 0x13), ('¶', 0x14), ('§', 0x15),
+    ('↑', 0x18), ('↓', 0x19), ('→', 0x1a), ('←', 0x1b), ('▲', 0x1e), ('▼', 0x1f),
+];
+
+/// The byte written for characters that have no glyph in code page 437.
+pub const REPLACEMENT: u8 = b'?';
+
+/// Returns the code page 437 byte that shows `character`, or `REPLACEMENT` if there is none.
+pub fn from_char(character: char) -> u8 {
+    if character.is_ascii() {
+        return character as u8;
+    }
+
+    if let Some(index) = UPPER_HALF.iter().position(|&c| c == character) {
+        return 0x80 + index as u8;
+    }
+
+    GLYPHS.iter()
+        .find(|&&(c, _)| c == character)
+        .map_or(REPLACEMENT, |&(_, byte)| byte)
+}
\ No newline at end of file
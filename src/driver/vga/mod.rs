@@ -1,15 +1,22 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use core::cmp;
 use core::fmt;
 use core::fmt::Error;
+use core::ops::{Deref, DerefMut};
 
 use lazy_static::lazy_static;
 use volatile::Volatile;
 
 use driver::vga::ansi::{AnsiParseIterator, AnsiSequencePart};
 use driver::vga::color::{Color, ColorCode};
+use driver::vga::mode::{MAX_HEIGHT, MAX_WIDTH, TextMode};
 use util::irq_lock::IrqLock;
 
 pub mod color;
 pub mod ansi;
+pub mod cp437;
+pub mod mode;
 
 lazy_static! {
     /// A locked instance of `ScreenWriter` to be used by the kernel. This is so you can safely
@@ -19,7 +26,7 @@ lazy_static! {
 
 /// A memory aligned struct to represent a character on the vga buffer. Contains the byte
 /// representation of the character and the color.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq)]
 #[repr(C)]
 struct ScreenChar {
     character: u8,
@@ -37,47 +44,149 @@ impl ScreenChar {
 }
 
 /// A memory aligned struct to access the vga buffer safely. Protects the buffer from various
-/// illegal actions, such as overflowing or corrupting the buffer.
+/// illegal actions, such as overflowing or corrupting the buffer. It is as large as the highest
+/// text mode, in lower modes only the first rows are on the screen.
 #[repr(transparent)]
-struct ScreenBuffer([[Volatile<ScreenChar>; 80]; 25]);
+pub struct ScreenBuffer([[Volatile<ScreenChar>; MAX_WIDTH]; MAX_HEIGHT]);
 
 impl ScreenBuffer {
+    /// Returns the vga text buffer at `0xb8000`.
+    ///
+    /// # Safety
+    /// The buffer is shared by every caller, so only one writer should use it at a time.
+    pub unsafe fn vga() -> &'static mut ScreenBuffer {
+        &mut *(0xb8000 as *mut ScreenBuffer)
+    }
+
+    /// Returns a new buffer on the heap instead of the screen, so the logic of `ScreenWriter` can
+    /// be tested without changing what is on the screen. The buffer is leaked.
+    #[cfg(feature = "testutil")]
+    pub fn mock() -> &'static mut ScreenBuffer {
+        // A character of 0 in black on black is a valid `ScreenChar`.
+        alloc::boxed::Box::leak(alloc::boxed::Box::new(unsafe { core::mem::zeroed() }))
+    }
+
     /// Utility function to set a character in the vga buffer using an x and y coordinate.
-    pub fn set(&mut self, x: u8, y: u8, character: ScreenChar) {
+    fn set(&mut self, x: u8, y: u8, character: ScreenChar) {
         self.0[y as usize][x as usize].write(character);
     }
 
     /// Utility function to get a character in the vga buffer using an x and y coordinate.
-    pub fn get(&self, x: u8, y: u8) -> ScreenChar {
+    fn get(&self, x: u8, y: u8) -> ScreenChar {
         self.0[y as usize][x as usize].read()
     }
 }
 
+/// An off-screen copy of the vga buffer that writes go to while batching, `MAX_HEIGHT` rows. It
+/// lives on the heap, so a `ScreenWriter` stays small enough for the interrupt stacks the panic
+/// path builds one on.
+type ShadowBuffer = Box<[[ScreenChar; MAX_WIDTH]]>;
+
 /// An utility struct to write to the vga buffer without handling every byte manually. Also handles
 /// ANSI escape code parsing through `driver::vga::ansi::AnsiParseIterator`.
 pub struct ScreenWriter {
     buffer: &'static mut ScreenBuffer,
+    /// Whether `buffer` is the vga buffer, so `set_mode` should also switch the hardware.
+    hardware: bool,
+    width: u8,
+    height: u8,
+    /// The buffer that is written to instead of `buffer` between `begin_batch` and `flush`.
+    shadow: Option<ShadowBuffer>,
     cursor_position: (u8, u8),
     current_color: ColorCode,
+    bold: bool,
+    /// The cursor position and text attributes stored by `save_cursor`.
+    saved_cursor: ((u8, u8), ColorCode, bool),
 }
 
 impl ScreenWriter {
     // TODO: Mark unsafe?
-    /// Creates a new instance of `ScreenWriter`. This internally also creates a new instance of
-    /// `ScreenBuffer`. To avoid data races, this function should only be called once.
+    /// Creates a new instance of `ScreenWriter` on the vga buffer. To avoid data races, this
+    /// function should only be called once.
     pub fn new() -> ScreenWriter {
+        let mut writer = ScreenWriter::with_buffer(unsafe { ScreenBuffer::vga() });
+        writer.hardware = true;
+        writer.resize(mode::current());
+        writer
+    }
+
+    /// Creates a `ScreenWriter` that writes to `buffer`, like a `ScreenBuffer::mock` in tests. The
+    /// writer starts in 80x25 mode.
+    pub fn with_buffer(buffer: &'static mut ScreenBuffer) -> ScreenWriter {
         ScreenWriter {
-            buffer: unsafe { &mut *(0xb8000 as *mut ScreenBuffer) },
+            buffer,
+            hardware: false,
+            width: TextMode::Mode80x25.width(),
+            height: TextMode::Mode80x25.height(),
+            shadow: None,
             cursor_position: (0, 0),
             current_color: ColorCode::new(Color::LightGray, Color::Black),
+            bold: false,
+            saved_cursor: ((0, 0), ColorCode::new(Color::LightGray, Color::Black), false),
+        }
+    }
+
+    /// Creates a temporary `ScreenWriter` directly on the vga buffer, without going through the
+    /// lock of `WRITER`. Output starts on a fresh line at the bottom of the screen, so it doesn't
+    /// depend on the cursor state of `WRITER`. This is only meant for the panic path, where
+    /// `WRITER` might be locked by the code that panicked.
+    pub fn raw() -> ScreenWriter {
+        let mut writer = ScreenWriter::new();
+        writer.cursor_position = (0, writer.height - 1);
+        writer.write_byte(b'\n');
+        writer
+    }
+
+    /// Writes a string to the screen, like `write_string`, but without touching the hardware
+    /// cursor. Used together with `ScreenWriter::raw`.
+    pub fn write_str_raw(&mut self, string: &str) {
+        for part in AnsiParseIterator::new(string) {
+            self.write_part(part);
+        }
+    }
+
+    /// Starts buffering writes in an off-screen copy of the screen, until `flush` is called. This
+    /// avoids flicker when large parts of the screen are redrawn.
+    pub fn begin_batch(&mut self) {
+        if self.shadow.is_some() {
+            return;
+        }
+
+        let mut shadow = vec![[ScreenChar::new(b' ', self.current_color); MAX_WIDTH]; MAX_HEIGHT].into_boxed_slice();
+
+        for (y, row) in shadow.iter_mut().enumerate().take(self.height as usize) {
+            for (x, character) in row.iter_mut().enumerate().take(self.width as usize) {
+                *character = self.buffer.get(x as u8, y as u8);
+            }
+        }
+
+        self.shadow = Some(shadow);
+    }
+
+    /// Copies everything written since `begin_batch` to the screen and stops batching. Only the
+    /// characters that changed are written to the vga buffer.
+    pub fn flush(&mut self) {
+        let shadow = match self.shadow.take() {
+            Some(shadow) => shadow,
+            None => return,
+        };
+
+        for (y, row) in shadow.iter().enumerate().take(self.height as usize) {
+            for (x, &character) in row.iter().enumerate().take(self.width as usize) {
+                if self.buffer.get(x as u8, y as u8) != character {
+                    self.buffer.set(x as u8, y as u8, character);
+                }
+            }
         }
+
+        self.update_cursor_position();
     }
 
     /// Clears the screen using the current color and resets the cursor position to `(0, 0)`
     pub fn clear_screen(&mut self) {
-        for x in 0..80 {
-            for y in 0..25 {
-                self.buffer.set(x, y, ScreenChar::new(b' ', self.current_color));
+        for x in 0..self.width {
+            for y in 0..self.height {
+                self.set(x, y, ScreenChar::new(b' ', self.current_color));
             }
         }
 
@@ -85,11 +194,139 @@ impl ScreenWriter {
         self.update_cursor_position();
     }
 
-    /// Writes a single byte to the screen. Also handles special escaped codes such as `\n` and
-    /// `\r`. Does not handle ANSI escape codes.
+    /// Switches to the text mode `mode` and clears the screen. On the vga buffer this also switches
+    /// the mode of the hardware, other buffers only change the size the writer uses.
+    pub fn set_mode(&mut self, mode: TextMode) {
+        self.flush();
+
+        if self.hardware {
+            unsafe { mode::set(mode) };
+        }
+
+        self.resize(mode);
+        self.clear_screen();
+    }
+
+    /// Returns the size of the screen as `(width, height)`.
+    pub fn size(&self) -> (u8, u8) {
+        (self.width, self.height)
+    }
+
+    fn resize(&mut self, mode: TextMode) {
+        self.width = mode.width();
+        self.height = mode.height();
+        self.clamp_cursor();
+    }
+
+    /// Resets the colors and the saved cursor and clears the screen, like `ESC c`.
+    pub fn reset(&mut self) {
+        self.reset_color();
+        self.saved_cursor = ((0, 0), self.current_color, false);
+        self.clear_screen();
+    }
+
+    /// Sets the colors that following text is written in.
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.current_color = ColorCode::new(foreground, background);
+    }
+
+    /// Sets the color that following text is written in, keeping the background.
+    pub fn fg(&mut self, foreground: Color) {
+        self.current_color.set_foreground(foreground);
+    }
+
+    /// Sets the background that following text is written on, keeping the foreground.
+    pub fn bg(&mut self, background: Color) {
+        self.current_color.set_background(background);
+    }
+
+    /// Resets the colors to light gray on black, like the ANSI reset code.
+    pub fn reset_color(&mut self) {
+        self.current_color = ColorCode::new(Color::LightGray, Color::Black);
+        self.bold = false;
+    }
+
+    /// Returns the colors that following text is written in.
+    pub fn color(&self) -> ColorCode {
+        self.current_color
+    }
+
+    /// Returns a guard that restores the current colors when it is dropped. Colors can be changed
+    /// through the guard in the meantime.
+    pub fn color_guard(&mut self) -> ColorGuard {
+        let previous = (self.current_color, self.bold);
+        ColorGuard {
+            writer: self,
+            previous,
+        }
+    }
+
+    /// Returns the cursor position as `(x, y)`.
+    pub fn cursor_position(&self) -> (u8, u8) {
+        self.cursor_position
+    }
+
+    /// Moves the cursor to `(x, y)`, clamped to the screen.
+    pub fn set_cursor(&mut self, x: u8, y: u8) {
+        self.cursor_position = (x, y);
+        self.clamp_cursor();
+        self.update_cursor_position();
+    }
+
+    /// Moves the cursor by `dx` columns and `dy` rows, stopping at the edges of the screen.
+    pub fn move_cursor(&mut self, dx: i16, dy: i16) {
+        let (x, y) = self.cursor_position;
+        let x = cmp::max(i16::from(x) + dx, 0);
+        let y = cmp::max(i16::from(y) + dy, 0);
+
+        self.cursor_position = (cmp::min(x, 255) as u8, cmp::min(y, 255) as u8);
+        self.clamp_cursor();
+        self.update_cursor_position();
+    }
+
+    /// Keeps the cursor on the screen. Every change of the cursor position ends with this, so the
+    /// position is always a valid cell for `ScreenBuffer::set`.
+    fn clamp_cursor(&mut self) {
+        let (x, y) = self.cursor_position;
+        self.cursor_position = (cmp::min(x, self.width - 1), cmp::min(y, self.height - 1));
+    }
+
+    /// Stores the cursor position and the text attributes, like `ESC 7`.
+    pub fn save_cursor(&mut self) {
+        self.saved_cursor = (self.cursor_position, self.current_color, self.bold);
+    }
+
+    /// Moves the cursor back to where `save_cursor` was called and restores the text attributes,
+    /// like `ESC 8`. Without a saved cursor, this moves to `(0, 0)` with the default colors.
+    pub fn restore_cursor(&mut self) {
+        let (position, color, bold) = self.saved_cursor;
+        self.cursor_position = position;
+        self.current_color = color;
+        self.bold = bold;
+        self.clamp_cursor();
+        self.update_cursor_position();
+    }
+
+    /// Returns the character and the colors of the cell at `(x, y)`.
+    pub fn cell(&self, x: u8, y: u8) -> (u8, ColorCode) {
+        let character = self.get(x, y);
+        (character.character, character.color)
+    }
+
+    /// Writes a single byte to the screen. Also handles special escaped codes such as `\n`, `\r`
+    /// and backspace, which erases the previous character on the line. Does not handle ANSI escape
+    /// codes.
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\r' => self.cursor_position.0 = 0,
+            0x08 | 0x7f => {
+                if self.cursor_position.0 > 0 {
+                    self.cursor_position.0 -= 1;
+
+                    let blank = ScreenChar::new(b' ', self.current_color);
+                    self.set(self.cursor_position.0, self.cursor_position.1, blank);
+                }
+            },
             b'\n' => {
                 self.cursor_position.0 = 0;
                 self.cursor_position.1 += 1;
@@ -98,76 +335,125 @@ impl ScreenWriter {
             },
             _ => {
                 let character = ScreenChar::new(byte, self.current_color);
-                self.buffer.set(self.cursor_position.0, self.cursor_position.1, character);
+                self.set(self.cursor_position.0, self.cursor_position.1, character);
                 self.cursor_position.0 += 1;
 
                 self.check_scroll_position();
             }
         }
+
+        self.clamp_cursor();
     }
 
     /// Writes a string to the screen. A newline character is not automatically appended. This also
     /// handles any and all ANSI escape codes that might be present in the string.
     pub fn write_string(&mut self, string: &str) {
-        let ansi_parse_iter = AnsiParseIterator::new(string);
+        self.write_str_raw(string);
+        self.update_cursor_position();
+    }
 
-        for part in ansi_parse_iter {
-            match part {
-                AnsiSequencePart::Text(text) => {
+    /// Internal function to write a single part of a parsed string to the screen.
+    fn write_part(&mut self, part: AnsiSequencePart) {
+        match part {
+            AnsiSequencePart::Text(text) => {
+                if text.is_ascii() {
                     for byte in text.bytes() {
                         self.write_byte(byte);
                     }
-                },
-                AnsiSequencePart::SGR(sgr) => {
-                    match sgr {
-                        0 => self.current_color = ColorCode::new(Color::LightGray, Color::Black),
-                        30..=37 => {
-                            let color = Color::from_ansi(sgr - 30, false).unwrap();
-                            self.current_color.set_foreground(color);
-                        },
-                        40..=47 => {
-                            let color = Color::from_ansi(sgr - 40, false).unwrap();
-                            self.current_color.set_background(color);
-                        },
-                        90..=97 => {
-                            let color = Color::from_ansi(sgr - 90, true).unwrap();
-                            self.current_color.set_foreground(color);
-                        },
-                        _ => (),
+                } else {
+                    for character in text.chars() {
+                        self.write_byte(cp437::from_char(character));
                     }
                 }
-            }
+            },
+            AnsiSequencePart::SGR(sgr) => {
+                match sgr {
+                    0 => {
+                        self.current_color = ColorCode::new(Color::LightGray, Color::Black);
+                        self.bold = false;
+                    },
+                    1 => {
+                        self.bold = true;
+                        let color = self.current_color.foreground().brighten();
+                        self.current_color.set_foreground(color);
+                    },
+                    2 | 22 => {
+                        // Only undo what bold did, bright colors set with 90 to 97 stay bright.
+                        if self.bold {
+                            self.bold = false;
+                            let color = self.current_color.foreground().darken();
+                            self.current_color.set_foreground(color);
+                        }
+                    },
+                    30..=37 => {
+                        let color = Color::from_ansi(sgr - 30, self.bold).unwrap();
+                        self.current_color.set_foreground(color);
+                    },
+                    40..=47 => {
+                        let color = Color::from_ansi(sgr - 40, false).unwrap();
+                        self.current_color.set_background(color);
+                    },
+                    90..=97 => {
+                        let color = Color::from_ansi(sgr - 90, true).unwrap();
+                        self.current_color.set_foreground(color);
+                    },
+                    _ => (),
+                }
+            },
+            AnsiSequencePart::SaveCursor => self.save_cursor(),
+            AnsiSequencePart::RestoreCursor => self.restore_cursor(),
+            AnsiSequencePart::Reset => self.reset(),
+            AnsiSequencePart::CursorUp(count) => self.move_cursor(0, -i16::from(count)),
+            AnsiSequencePart::CursorDown(count) => self.move_cursor(0, i16::from(count)),
+            AnsiSequencePart::CursorForward(count) => self.move_cursor(i16::from(count), 0),
+            AnsiSequencePart::CursorBack(count) => self.move_cursor(-i16::from(count), 0),
         }
-
-        self.update_cursor_position();
     }
 
     /// Internal function to check and update the scroll position if necessary. Resets the x
     /// position and increases the y position when the right edge of the buffer is reached. Also
     /// scrolls the screen up when the bottom of the buffer is reached.
     fn check_scroll_position(&mut self) {
-        if self.cursor_position.0 >= 80 {
+        if self.cursor_position.0 >= self.width {
             self.cursor_position.0 = 0;
             self.cursor_position.1 += 1;
         }
 
-        if self.cursor_position.1 >= 25 {
-            for y in 0..24 {
-                for x in 0..80 {
-                    self.buffer.set(x, y, self.buffer.get(x, y + 1));
+        if self.cursor_position.1 >= self.height {
+            for y in 0..self.height - 1 {
+                for x in 0..self.width {
+                    let character = self.get(x, y + 1);
+                    self.set(x, y, character);
                 }
             }
 
             let blank = ScreenChar::new(b' ', self.current_color);
 
-            for x in 0..80 {
-                self.buffer.set(x, 24, blank);
+            for x in 0..self.width {
+                self.set(x, self.height - 1, blank);
             }
 
             self.cursor_position.1 -= 1;
         }
     }
 
+    /// Internal function to set a character on the screen, or in the shadow buffer while batching.
+    fn set(&mut self, x: u8, y: u8, character: ScreenChar) {
+        match self.shadow {
+            Some(ref mut shadow) => shadow[y as usize][x as usize] = character,
+            None => self.buffer.set(x, y, character),
+        }
+    }
+
+    /// Internal function to get a character from the screen, or from the shadow buffer while
+    /// batching.
+    fn get(&self, x: u8, y: u8) -> ScreenChar {
+        match self.shadow {
+            Some(ref shadow) => shadow[y as usize][x as usize],
+            None => self.buffer.get(x, y),
+        }
+    }
+
     // TODO: Implement cursor (also need port io api)
     /// Updates VGA cursor position using ports on the cpu.
     fn update_cursor_position(&mut self) {
@@ -175,6 +461,34 @@ impl ScreenWriter {
     }
 }
 
+/// Restores the colors of a `ScreenWriter` when it is dropped, see `ScreenWriter::color_guard`.
+pub struct ColorGuard<'a> {
+    writer: &'a mut ScreenWriter,
+    previous: (ColorCode, bool),
+}
+
+impl<'a> Deref for ColorGuard<'a> {
+    type Target = ScreenWriter;
+
+    fn deref(&self) -> &ScreenWriter {
+        self.writer
+    }
+}
+
+impl<'a> DerefMut for ColorGuard<'a> {
+    fn deref_mut(&mut self) -> &mut ScreenWriter {
+        self.writer
+    }
+}
+
+impl<'a> Drop for ColorGuard<'a> {
+    fn drop(&mut self) {
+        let (color, bold) = self.previous;
+        self.writer.current_color = color;
+        self.writer.bold = bold;
+    }
+}
+
 impl fmt::Write for ScreenWriter {
     fn write_str(&mut self, s: &str) -> Result<(), Error> {
         self.write_string(s);
@@ -0,0 +1,148 @@
+//! Switches the vga text mode between 25 and 50 rows. The 50 row mode uses 8 scan lines per row
+//! instead of 16, so it also needs a font that is half as high.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use x86_64::port::Port;
+
+/// The widest text mode, which is the row stride of the vga buffer in every mode.
+pub const MAX_WIDTH: usize = 80;
+
+/// The highest text mode, which decides the size of `ScreenBuffer`.
+pub const MAX_HEIGHT: usize = 50;
+
+const SEQUENCER_INDEX: u16 = 0x3c4;
+const GRAPHICS_INDEX: u16 = 0x3ce;
+const CRTC_INDEX: u16 = 0x3d4;
+
+const SEQUENCER_MAP_MASK: u8 = 0x02;
+const SEQUENCER_CHARACTER_MAP: u8 = 0x03;
+const SEQUENCER_MEMORY_MODE: u8 = 0x04;
+const GRAPHICS_READ_MAP: u8 = 0x04;
+const GRAPHICS_MODE: u8 = 0x05;
+const GRAPHICS_MISC: u8 = 0x06;
+const CRTC_MAX_SCAN_LINE: u8 = 0x09;
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+
+/// Plane 2 of the vga memory, which holds the fonts, while it is mapped at `0xa0000`.
+const FONT_MEMORY: usize = 0xa0000;
+
+/// Every glyph takes 32 bytes in the font memory, of which the 16 line font uses the first 16.
+const GLYPH_STRIDE: usize = 32;
+
+/// The offset of font block 1, where the 8 line font is stored. The BIOS font in block 0 is kept,
+/// so switching back to 25 rows doesn't need a copy of it.
+const HALF_FONT_OFFSET: usize = 0x4000;
+
+/// The values of the character map select register that select font block 0 and 1.
+const FONT_BLOCK_0: u8 = 0x00;
+const FONT_BLOCK_1: u8 = 0x05;
+
+static CURRENT: AtomicU8 = AtomicU8::new(TextMode::Mode80x25 as u8);
+
+/// A text mode of the vga, named after its columns and rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TextMode {
+    Mode80x25,
+    Mode80x50,
+}
+
+impl TextMode {
+    pub fn width(self) -> u8 {
+        80
+    }
+
+    pub fn height(self) -> u8 {
+        match self {
+            TextMode::Mode80x25 => 25,
+            TextMode::Mode80x50 => 50,
+        }
+    }
+
+    /// The amount of scan lines of a character, which is also the height of the font.
+    fn scan_lines(self) -> u8 {
+        match self {
+            TextMode::Mode80x25 => 16,
+            TextMode::Mode80x50 => 8,
+        }
+    }
+}
+
+/// Returns the text mode the vga was last switched to with `set`.
+pub fn current() -> TextMode {
+    match CURRENT.load(Ordering::SeqCst) {
+        0 => TextMode::Mode80x25,
+        _ => TextMode::Mode80x50,
+    }
+}
+
+/// Switches the vga to `mode`. The contents of the vga buffer are left as they are.
+///
+/// # Safety
+/// The font memory at `0xa0000` must be identity mapped, and nothing else may access the vga
+/// registers at the same time.
+pub unsafe fn set(mode: TextMode) {
+    if mode == TextMode::Mode80x50 {
+        load_half_font();
+    }
+
+    let font_block = match mode {
+        TextMode::Mode80x25 => FONT_BLOCK_0,
+        TextMode::Mode80x50 => FONT_BLOCK_1,
+    };
+
+    write_register(SEQUENCER_INDEX, SEQUENCER_CHARACTER_MAP, font_block);
+
+    let last_line = mode.scan_lines() - 1;
+    update_register(CRTC_INDEX, CRTC_MAX_SCAN_LINE, 0xe0, last_line);
+    update_register(CRTC_INDEX, CRTC_CURSOR_START, 0xc0, last_line - 1);
+    update_register(CRTC_INDEX, CRTC_CURSOR_END, 0xe0, last_line);
+
+    CURRENT.store(mode as u8, Ordering::SeqCst);
+}
+
+/// Writes an 8 line version of the BIOS font to font block 1. Every line of the new font is the
+/// combination of two lines of the 16 line font, so thin strokes don't disappear.
+unsafe fn load_half_font() {
+    // Map plane 2 at 0xa0000 without odd/even addressing, so the font can be read and written.
+    write_register(SEQUENCER_INDEX, SEQUENCER_MAP_MASK, 0x04);
+    write_register(SEQUENCER_INDEX, SEQUENCER_MEMORY_MODE, 0x07);
+    write_register(GRAPHICS_INDEX, GRAPHICS_READ_MAP, 0x02);
+    write_register(GRAPHICS_INDEX, GRAPHICS_MODE, 0x00);
+    write_register(GRAPHICS_INDEX, GRAPHICS_MISC, 0x04);
+
+    let font = FONT_MEMORY as *mut u8;
+
+    for glyph in 0..256 {
+        let source = font.add(glyph * GLYPH_STRIDE);
+        let destination = font.add(HALF_FONT_OFFSET + glyph * GLYPH_STRIDE);
+
+        for line in 0..8 {
+            let value = source.add(line * 2).read_volatile() | source.add(line * 2 + 1).read_volatile();
+            destination.add(line).write_volatile(value);
+        }
+    }
+
+    // Back to the text mode memory layout at 0xb8000.
+    write_register(SEQUENCER_INDEX, SEQUENCER_MAP_MASK, 0x03);
+    write_register(SEQUENCER_INDEX, SEQUENCER_MEMORY_MODE, 0x03);
+    write_register(GRAPHICS_INDEX, GRAPHICS_READ_MAP, 0x00);
+    write_register(GRAPHICS_INDEX, GRAPHICS_MODE, 0x10);
+    write_register(GRAPHICS_INDEX, GRAPHICS_MISC, 0x0e);
+}
+
+/// Writes `value` to the register `register` of the index/data port pair at `index`.
+fn write_register(index: u16, register: u8, value: u8) {
+    Port::<u8>::new(index).write(register);
+    Port::<u8>::new(index + 1).write(value);
+}
+
+/// Replaces the bits of a register outside of `keep` with `value`.
+fn update_register(index: u16, register: u8, keep: u8, value: u8) {
+    Port::<u8>::new(index).write(register);
+    let data = Port::<u8>::new(index + 1);
+    let old = data.read();
+    data.write((old & keep) | (value & !keep));
+}
\ No newline at end of file
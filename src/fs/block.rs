@@ -0,0 +1,212 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp;
+
+use fs::vfs::{FsError, Result};
+use util::preempt_lock::{PreemptMutex, PreemptRwLock};
+
+/// A device that stores data in blocks of a fixed size, like a disk. Filesystems that live on a
+/// disk, like `Ext2Fs`, read and write it through this trait.
+pub trait BlockDevice {
+    /// Returns the size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Returns the amount of blocks on the device.
+    fn block_count(&self) -> usize;
+
+    /// Reads block `index` into `buf`, which has to be exactly one block long.
+    fn read_block(&self, index: usize, buf: &mut [u8]) -> Result<()>;
+
+    /// Writes `buf`, which has to be exactly one block long, to block `index`.
+    fn write_block(&self, index: usize, buf: &[u8]) -> Result<()>;
+
+    /// Makes sure a write to block `index` reached the storage. Devices that write through have
+    /// nothing to do.
+    fn flush_block(&self, _index: usize) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes sure every write reached the storage.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl dyn BlockDevice {
+    /// Reads `buf.len()` bytes starting at byte `offset`, which don't have to be aligned to blocks.
+    pub fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let mut block = vec![0; block_size];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done;
+            let start = position % block_size;
+            let len = cmp::min(block_size - start, buf.len() - done);
+
+            self.read_block(position / block_size, &mut block)?;
+            buf[done..done + len].copy_from_slice(&block[start..start + len]);
+            done += len;
+        }
+
+        Ok(())
+    }
+
+    /// Writes `buf` starting at byte `offset`, which don't have to be aligned to blocks. Partially
+    /// written blocks are read first.
+    pub fn write_bytes(&self, offset: usize, buf: &[u8]) -> Result<()> {
+        let block_size = self.block_size();
+        let mut block = vec![0; block_size];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done;
+            let start = position % block_size;
+            let len = cmp::min(block_size - start, buf.len() - done);
+
+            if len < block_size {
+                self.read_block(position / block_size, &mut block)?;
+            }
+
+            block[start..start + len].copy_from_slice(&buf[done..done + len]);
+            self.write_block(position / block_size, &block)?;
+            done += len;
+        }
+
+        Ok(())
+    }
+}
+
+/// A block device that keeps written blocks in memory until they are flushed, so a block that is
+/// written repeatedly only reaches the inner device once. Reads see the buffered writes.
+pub struct CachedBlockDevice {
+    inner: Arc<dyn BlockDevice>,
+    dirty: PreemptMutex<BTreeMap<usize, Vec<u8>>>,
+}
+
+impl CachedBlockDevice {
+    pub fn new(inner: Arc<dyn BlockDevice>) -> CachedBlockDevice {
+        CachedBlockDevice {
+            inner,
+            dirty: PreemptMutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns whether block `index` was written without being flushed since.
+    pub fn is_dirty(&self, index: usize) -> bool {
+        self.dirty.lock().contains_key(&index)
+    }
+
+    /// Returns the amount of blocks that were written without being flushed since.
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.lock().len()
+    }
+}
+
+impl BlockDevice for CachedBlockDevice {
+    fn block_size(&self) -> usize {
+        self.inner.block_size()
+    }
+
+    fn block_count(&self) -> usize {
+        self.inner.block_count()
+    }
+
+    fn read_block(&self, index: usize, buf: &mut [u8]) -> Result<()> {
+        match self.dirty.lock().get(&index) {
+            Some(data) if data.len() == buf.len() => {
+                buf.copy_from_slice(data);
+                Ok(())
+            },
+            Some(_) => Err(FsError::InvalidArgument),
+            None => self.inner.read_block(index, buf),
+        }
+    }
+
+    fn write_block(&self, index: usize, buf: &[u8]) -> Result<()> {
+        if buf.len() != self.block_size() || index >= self.block_count() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        self.dirty.lock().insert(index, Vec::from(buf));
+        Ok(())
+    }
+
+    fn flush_block(&self, index: usize) -> Result<()> {
+        let mut dirty = self.dirty.lock();
+
+        if let Some(data) = dirty.get(&index) {
+            self.inner.write_block(index, data)?;
+            dirty.remove(&index);
+        }
+
+        self.inner.flush_block(index)
+    }
+
+    fn flush(&self) -> Result<()> {
+        let mut dirty = self.dirty.lock();
+
+        while let Some((&index, data)) = dirty.iter().next() {
+            self.inner.write_block(index, data)?;
+            dirty.remove(&index);
+        }
+
+        self.inner.flush()
+    }
+}
+
+/// A block device that is stored in RAM, for example a disk image that was loaded with the
+/// kernel.
+pub struct MemBlockDevice {
+    data: PreemptRwLock<Vec<u8>>,
+    block_size: usize,
+}
+
+impl MemBlockDevice {
+    /// Creates a device containing `data`, which is cut into blocks of `block_size` bytes. A
+    /// partial block at the end is not accessible.
+    pub fn new(data: Vec<u8>, block_size: usize) -> MemBlockDevice {
+        assert!(block_size > 0, "Block size can't be zero");
+
+        MemBlockDevice {
+            data: PreemptRwLock::new(data),
+            block_size,
+        }
+    }
+
+    /// Returns the byte range of block `index`, or an error if the block does not exist or `len`
+    /// is not the block size.
+    fn range(&self, index: usize, len: usize) -> Result<(usize, usize)> {
+        if len != self.block_size || index >= self.block_count() {
+            return Err(FsError::InvalidArgument);
+        }
+
+        Ok((index * self.block_size, (index + 1) * self.block_size))
+    }
+}
+
+impl BlockDevice for MemBlockDevice {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn block_count(&self) -> usize {
+        self.data.read().len() / self.block_size
+    }
+
+    fn read_block(&self, index: usize, buf: &mut [u8]) -> Result<()> {
+        let (start, end) = self.range(index, buf.len())?;
+        buf.copy_from_slice(&self.data.read()[start..end]);
+
+        Ok(())
+    }
+
+    fn write_block(&self, index: usize, buf: &[u8]) -> Result<()> {
+        let (start, end) = self.range(index, buf.len())?;
+        self.data.write()[start..end].copy_from_slice(buf);
+
+        Ok(())
+    }
+}
\ No newline at end of file
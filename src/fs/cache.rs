@@ -0,0 +1,346 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+use fs::vfs::{FileSystem, FileSystemMetadata, FileType, FsError, INode, INodeMetadata, PollStatus, Result};
+use task::wait_queue::WaitQueue;
+use util::preempt_lock::PreemptMutex;
+
+/// The size of the blocks file contents are cached in.
+pub const CACHE_BLOCK_SIZE: usize = 512;
+
+/// The amount of blocks a `CachedFs` created with `CachedFs::new` keeps.
+pub const DEFAULT_CACHE_BLOCKS: usize = 64;
+
+/// Counts how often reads were answered from the cache.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// A cached block of file contents. Blocks at the end of a file are shorter than
+/// `CACHE_BLOCK_SIZE`.
+struct CachedBlock {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+/// The cached blocks and metadata of all inodes of a `CachedFs`. Blocks are keyed by inode id and
+/// block index, the least recently used block is evicted once `capacity` blocks are cached.
+struct Cache {
+    blocks: BTreeMap<(usize, usize), CachedBlock>,
+    metadata: BTreeMap<usize, INodeMetadata>,
+    capacity: usize,
+    time: u64,
+    stats: CacheStats,
+}
+
+impl Cache {
+    /// Returns a copy of a cached block and marks it as used, or `None` on a miss.
+    fn block(&mut self, key: (usize, usize)) -> Option<Vec<u8>> {
+        self.time += 1;
+
+        match self.blocks.get_mut(&key) {
+            Some(block) => {
+                block.last_used = self.time;
+                self.stats.hits += 1;
+                Some(block.data.clone())
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert_block(&mut self, key: (usize, usize), data: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.blocks.len() >= self.capacity && !self.blocks.contains_key(&key) {
+            let oldest = self.blocks.iter()
+                .min_by_key(|(_, block)| block.last_used)
+                .map(|(&key, _)| key);
+
+            if let Some(oldest) = oldest {
+                self.blocks.remove(&oldest);
+            }
+        }
+
+        self.blocks.insert(key, CachedBlock { data, last_used: self.time });
+    }
+
+    /// Forgets everything cached for inode `inode`.
+    fn invalidate(&mut self, inode: usize) {
+        let keys: Vec<_> = self.blocks.range((inode, 0)..=(inode, usize::max_value()))
+            .map(|(&key, _)| key)
+            .collect();
+
+        for key in keys {
+            self.blocks.remove(&key);
+        }
+
+        self.metadata.remove(&inode);
+    }
+}
+
+/// A wrapper for another filesystem that caches recently read blocks of file contents and the
+/// metadata of inodes. Writes go straight to the inner filesystem and invalidate what is cached
+/// for the written inode. Meant for filesystems that are slow to read, like ones on a disk.
+pub struct CachedFs {
+    inner: Arc<dyn FileSystem>,
+    cache: PreemptMutex<Cache>,
+    self_ref: Weak<CachedFs>,
+}
+
+impl CachedFs {
+    /// Creates a new instance of `CachedFs` that caches at most `DEFAULT_CACHE_BLOCKS` blocks
+    pub fn new(inner: Arc<dyn FileSystem>) -> Arc<CachedFs> {
+        CachedFs::with_capacity(inner, DEFAULT_CACHE_BLOCKS)
+    }
+
+    /// Creates a new instance of `CachedFs` that caches at most `blocks` blocks
+    pub fn with_capacity(inner: Arc<dyn FileSystem>, blocks: usize) -> Arc<CachedFs> {
+        CachedFs {
+            inner,
+            cache: PreemptMutex::new(Cache {
+                blocks: BTreeMap::new(),
+                metadata: BTreeMap::new(),
+                capacity: blocks,
+                time: 0,
+                stats: CacheStats::default(),
+            }),
+            self_ref: Weak::default(),
+        }.wrap()
+    }
+
+    /// Returns how many block reads were hits and misses so far.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().stats
+    }
+
+    /// Wraps an inode of the inner filesystem in a `CachedINode`.
+    fn wrap_inode(&self, inode: Arc<dyn INode>) -> Result<Arc<dyn INode>> {
+        let metadata = inode.metadata()?;
+        self.cache.lock().metadata.insert(metadata.inode, metadata);
+
+        Ok(Arc::new(CachedINode {
+            inode,
+            id: metadata.inode,
+            type_: metadata.type_,
+            fs: self.self_ref.upgrade().unwrap(),
+        }))
+    }
+
+    /// Wraps the `CachedFs` in an `Arc` and sets the `self_ref` variable
+    fn wrap(self) -> Arc<CachedFs> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut CachedFs;
+
+        unsafe {
+            (*ptr).self_ref = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+}
+
+impl FileSystem for CachedFs {
+    fn sync(&self) -> Result<()> {
+        self.inner.sync()
+    }
+
+    fn root(&self) -> Arc<dyn INode> {
+        self.wrap_inode(self.inner.root()).expect("Can't read the metadata of the root inode!")
+    }
+
+    fn metadata(&self) -> FileSystemMetadata {
+        self.inner.metadata()
+    }
+}
+
+/// An inode implementation for `CachedFs` that caches reads of the inner inode
+pub struct CachedINode {
+    inode: Arc<dyn INode>,
+    id: usize,
+    type_: FileType,
+    fs: Arc<CachedFs>,
+}
+
+impl CachedINode {
+    /// Returns block `index` of the contents of this inode, from the cache if possible.
+    fn block(&self, index: usize) -> Result<Vec<u8>> {
+        if let Some(data) = self.fs.cache.lock().block((self.id, index)) {
+            return Ok(data);
+        }
+
+        let mut data = vec![0; CACHE_BLOCK_SIZE];
+        let len = self.inode.read_at(index * CACHE_BLOCK_SIZE, &mut data)?;
+        data.truncate(len);
+
+        self.fs.cache.lock().insert_block((self.id, index), data.clone());
+        Ok(data)
+    }
+
+    /// Forgets everything cached for this inode, after it was changed.
+    fn invalidate(&self) {
+        self.fs.cache.lock().invalidate(self.id);
+    }
+
+    /// Forgets all cached metadata, after a change to a directory that can change the link count
+    /// of other inodes.
+    fn invalidate_metadata(&self) {
+        self.fs.cache.lock().metadata.clear();
+    }
+
+    /// Returns the inner inode of `inode`, which has to belong to the same `CachedFs`.
+    fn inner_of<'a>(&self, inode: &'a Arc<dyn INode>) -> Result<&'a Arc<dyn INode>> {
+        let inode = inode.downcast_ref::<CachedINode>().ok_or(FsError::NotSameFileSystem)?;
+
+        if !Arc::ptr_eq(&inode.fs, &self.fs) {
+            return Err(FsError::NotSameFileSystem);
+        }
+
+        Ok(&inode.inode)
+    }
+}
+
+impl INode for CachedINode {
+    /// Reads regular files through the cache. Other inodes, like devices, are read directly.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if self.type_ != FileType::File {
+            return self.inode.read_at(offset, buf);
+        }
+
+        let mut read = 0;
+
+        while read < buf.len() {
+            let position = offset.checked_add(read).ok_or(FsError::InvalidArgument)?;
+            let block = self.block(position / CACHE_BLOCK_SIZE)?;
+            let start = position % CACHE_BLOCK_SIZE;
+
+            if start >= block.len() {
+                break;
+            }
+
+            let len = (block.len() - start).min(buf.len() - read);
+            buf[read..read + len].copy_from_slice(&block[start..start + len]);
+            read += len;
+
+            // A short block is the last block of the file.
+            if block.len() < CACHE_BLOCK_SIZE {
+                break;
+            }
+        }
+
+        Ok(read)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let result = self.inode.write_at(offset, buf);
+        self.invalidate();
+        result
+    }
+
+    fn append(&self, buf: &[u8]) -> Result<usize> {
+        let result = self.inode.append(buf);
+        self.invalidate();
+        result
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        self.inode.poll()
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        self.inode.wait_queue()
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        if let Some(&metadata) = self.fs.cache.lock().metadata.get(&self.id) {
+            return Ok(metadata);
+        }
+
+        let metadata = self.inode.metadata()?;
+        self.fs.cache.lock().metadata.insert(self.id, metadata);
+        Ok(metadata)
+    }
+
+    fn set_metadata(&self, metadata: INodeMetadata) -> Result<()> {
+        let result = self.inode.set_metadata(metadata);
+        self.invalidate();
+        result
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        self.inode.sync_all()
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        self.inode.sync_data()
+    }
+
+    fn resize(&self, new_len: usize) -> Result<()> {
+        let result = self.inode.resize(new_len);
+        self.invalidate();
+        result
+    }
+
+    fn create(&self, name: &str, type_: FileType, permissions: u32) -> Result<Arc<dyn INode>> {
+        let inode = self.inode.create(name, type_, permissions);
+        self.invalidate_metadata();
+        self.fs.wrap_inode(inode?)
+    }
+
+    fn link(&self, name: &str, other: &Arc<dyn INode>) -> Result<()> {
+        let result = self.inode.link(name, self.inner_of(other)?);
+        self.invalidate_metadata();
+        result
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        // The id of the removed inode can be reused, so nothing cached for it may stay around.
+        if let Ok(metadata) = self.inode.find(name).and_then(|child| child.metadata()) {
+            self.fs.cache.lock().invalidate(metadata.inode);
+        }
+
+        let result = self.inode.unlink(name);
+        self.invalidate_metadata();
+        result
+    }
+
+    fn move_(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
+        let result = self.inode.move_(old_name, self.inner_of(target)?, new_name);
+
+        // A replaced file is gone, so its cached contents must not be found by a new inode that
+        // reuses its id.
+        self.fs.cache.lock().blocks.clear();
+        self.invalidate_metadata();
+        result
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        self.fs.wrap_inode(self.inode.find(name)?)
+    }
+
+    fn get_entry(&self, index: usize) -> Result<String> {
+        self.inode.get_entry(index)
+    }
+
+    fn io_control(&self, cmd: u32, arg: usize) -> Result<usize> {
+        self.inode.io_control(cmd, arg)
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
\ No newline at end of file
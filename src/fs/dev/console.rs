@@ -0,0 +1,243 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+
+use driver::uart16550::{RECEIVE_QUEUE, UART};
+use fs::dev::DevFS;
+use fs::ioctl::{TCGCOOKED, TCSCOOKED};
+use fs::vfs::{INode, INodeMetadata, FileType, FileSystem, FsError, PollStatus, Result, Timespec};
+use task::wait_queue::WaitQueue;
+use util::preempt_lock::PreemptMutex;
+
+/// The byte that erases the whole line being edited in cooked mode, Ctrl+U.
+pub const LINE_KILL: u8 = 0x15;
+
+/// Buffers console input. In cooked mode, input is echoed and edited per line and only handed out
+/// once a full line has been entered. In raw mode, input is handed out as is.
+pub struct LineDiscipline {
+    cooked: bool,
+    line: Vec<u8>,
+    ready: VecDeque<u8>,
+}
+
+impl LineDiscipline {
+    /// Creates a new `LineDiscipline` in cooked mode.
+    pub fn new() -> LineDiscipline {
+        LineDiscipline {
+            cooked: true,
+            line: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Switch between cooked and raw mode. Any partially edited line is handed out as is when
+    /// switching to raw mode.
+    pub fn set_cooked(&mut self, cooked: bool) {
+        if !cooked {
+            self.ready.extend(self.line.drain(..));
+        }
+
+        self.cooked = cooked;
+    }
+
+    pub fn is_cooked(&self) -> bool {
+        self.cooked
+    }
+
+    /// Handles a single byte of input. In cooked mode the byte is echoed through `echo`.
+    pub fn input(&mut self, byte: u8, echo: impl Fn(u8)) {
+        if !self.cooked {
+            self.ready.push_back(byte);
+            return;
+        }
+
+        match byte {
+            0x08 | 0x7f => {
+                if self.line.pop().is_some() {
+                    echo(0x08);
+                }
+            },
+            LINE_KILL => {
+                for _ in self.line.drain(..) {
+                    echo(0x08);
+                }
+            },
+            b'\r' | b'\n' => {
+                self.ready.extend(self.line.drain(..));
+                self.ready.push_back(b'\n');
+                echo(b'\n');
+            },
+            _ => {
+                self.line.push(byte);
+                echo(byte);
+            }
+        }
+    }
+
+    /// Returns true if a read would return data.
+    pub fn has_data(&self) -> bool {
+        if self.cooked {
+            self.ready.contains(&b'\n')
+        } else {
+            !self.ready.is_empty()
+        }
+    }
+
+    /// Reads input into `buf`, returns the amount of bytes read. In cooked mode this reads at most
+    /// one line, without the line terminator, and nothing until a full line has been entered.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        if !self.has_data() {
+            return 0;
+        }
+
+        let mut count = 0;
+        while count < buf.len() {
+            match self.ready.pop_front() {
+                Some(b'\n') if self.cooked => break,
+                Some(byte) => {
+                    buf[count] = byte;
+                    count += 1;
+                },
+                None => break,
+            }
+        }
+
+        count
+    }
+}
+
+/// The console device, usually mounted at `/dev/console`. Output goes to the screen and the serial
+/// port, input is read from the serial port through a `LineDiscipline`.
+pub struct ConsoleDevice {
+    discipline: PreemptMutex<LineDiscipline>,
+    fs: Arc<DevFS>,
+}
+
+impl ConsoleDevice {
+    pub fn new(fs: Arc<DevFS>) -> Arc<ConsoleDevice> {
+        Arc::new(ConsoleDevice {
+            discipline: PreemptMutex::new(LineDiscipline::new()),
+            fs,
+        })
+    }
+
+    /// Moves all received input from the serial port into the line discipline.
+    fn pump_input(&self, discipline: &mut LineDiscipline) {
+        loop {
+            let byte = UART.lock().receive_byte();
+
+            match byte {
+                Some(byte) => discipline.input(byte, |byte| crate::kprint!("{}", byte as char)),
+                None => break,
+            }
+        }
+    }
+}
+
+impl INode for ConsoleDevice {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut discipline = self.discipline.lock();
+        self.pump_input(&mut discipline);
+
+        Ok(discipline.read(buf))
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        crate::kprint!("{}", String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&RECEIVE_QUEUE)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let mut discipline = self.discipline.lock();
+        self.pump_input(&mut discipline);
+
+        Ok(PollStatus {
+            readable: discipline.has_data(),
+            writable: true,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        Ok(INodeMetadata {
+            inode: 0,
+            size: 0,
+            access_time: Timespec { sec: 0, nanosec: 0 },
+            modification_time: Timespec { sec: 0, nanosec: 0 },
+            change_time: Timespec { sec: 0, nanosec: 0 },
+            type_: FileType::CharDevice,
+            permissions: 0o666,
+            links: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn set_metadata(&self, _metadata: INodeMetadata) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, _new_len: usize) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn create(&self, _name: &str, _type_: FileType, _permissions: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn move_(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn find(&self, _name: &str) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn get_entry(&self, _index: usize) -> Result<String> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn io_control(&self, cmd: u32, arg: usize) -> Result<usize> {
+        match cmd {
+            TCGCOOKED => Ok(self.discipline.lock().is_cooked() as usize),
+            TCSCOOKED => match arg {
+                0 | 1 => {
+                    self.discipline.lock().set_cooked(arg == 1);
+                    Ok(0)
+                },
+                _ => Err(FsError::InvalidArgument),
+            },
+            _ => Err(FsError::Unsupported),
+        }
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
\ No newline at end of file
@@ -0,0 +1,171 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+
+use boot;
+use fs::dev::DevFS;
+use fs::vfs::{FileSystem, FileType, FsError, INode, INodeMetadata, Result, Timespec};
+use memory;
+use memory::frame::Frame;
+use memory::PAGE_SIZE;
+use task::scheduler;
+use x86_64::PhysicalAddress;
+
+/// The legacy VGA memory, which is not part of the memory map but is always safe to access.
+const VGA_MEMORY: (u64, u64) = (0xa_0000, 0xc_0000);
+
+/// A device that gives access to physical memory, the offset is used as physical address. Only
+/// root can use it, and only usable RAM, the VGA memory and the framebuffer can be accessed, so
+/// reserved regions that could fault or have side effects on access are off limits.
+pub struct MemDevice {
+    fs: Arc<DevFS>,
+}
+
+impl MemDevice {
+    pub fn new(fs: Arc<DevFS>) -> Arc<MemDevice> {
+        Arc::new(MemDevice { fs })
+    }
+
+    /// Checks if the current user is root, and if the `len` bytes at physical address `start` are
+    /// in a region that may be accessed.
+    fn check_access(start: usize, len: usize) -> Result<()> {
+        if scheduler::current_uid() != 0 {
+            return Err(FsError::PermissionDenied);
+        }
+
+        let start = start as u64;
+        let end = start.checked_add(len as u64).ok_or(FsError::InvalidArgument)?;
+        let contains = |(region_start, region_end): (u64, u64)| start >= region_start && end <= region_end;
+
+        if contains(VGA_MEMORY) {
+            return Ok(());
+        }
+
+        let boot_info = boot::boot_info().ok_or(FsError::Unsupported)?;
+
+        if let Some(framebuffer) = boot_info.framebuffer() {
+            let framebuffer_start = framebuffer.address.as_u64();
+            let framebuffer_size = u64::from(framebuffer.pitch) * u64::from(framebuffer.height);
+
+            if contains((framebuffer_start, framebuffer_start + framebuffer_size)) {
+                return Ok(());
+            }
+        }
+
+        let usable = boot_info.memory_map().iter()
+            .filter(|region| region.is_usable())
+            .any(|region| contains((region.start_address(), region.end_address())));
+
+        if usable {
+            Ok(())
+        } else {
+            Err(FsError::InvalidArgument)
+        }
+    }
+
+    /// Calls `f` for every chunk of the physical range `offset..offset + len` that lies within a
+    /// single frame, with the mapped chunk and the offset of the chunk in the range.
+    fn for_each_chunk<F>(offset: usize, len: usize, mut f: F) where F: FnMut(&mut [u8], usize) {
+        let mut done = 0;
+
+        while done < len {
+            let address = offset + done;
+            let page_offset = address % PAGE_SIZE;
+            let chunk_len = (PAGE_SIZE - page_offset).min(len - done);
+            let frame = Frame::containing_address(PhysicalAddress::new(address as u64));
+
+            memory::with_memory(|active_table, allocator| {
+                active_table.with_frame_mapped(frame, allocator, |contents| {
+                    f(&mut contents[page_offset..page_offset + chunk_len], done);
+                });
+            });
+
+            done += chunk_len;
+        }
+    }
+}
+
+impl INode for MemDevice {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        MemDevice::check_access(offset, buf.len())?;
+
+        MemDevice::for_each_chunk(offset, buf.len(), |chunk, done| {
+            buf[done..done + chunk.len()].copy_from_slice(chunk);
+        });
+
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        MemDevice::check_access(offset, buf.len())?;
+
+        MemDevice::for_each_chunk(offset, buf.len(), |chunk, done| {
+            chunk.copy_from_slice(&buf[done..done + chunk.len()]);
+        });
+
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        Ok(INodeMetadata {
+            inode: 0,
+            size: 0,
+            access_time: Timespec { sec: 0, nanosec: 0 },
+            modification_time: Timespec { sec: 0, nanosec: 0 },
+            change_time: Timespec { sec: 0, nanosec: 0 },
+            type_: FileType::CharDevice,
+            permissions: 0o600,
+            links: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn set_metadata(&self, _metadata: INodeMetadata) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, _new_len: usize) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn create(&self, _name: &str, _type_: FileType, _permissions: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn move_(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn find(&self, _name: &str) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn get_entry(&self, _index: usize) -> Result<String> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
\ No newline at end of file
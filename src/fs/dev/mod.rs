@@ -3,15 +3,19 @@ use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use core::any::Any;
 
-use spin::RwLock;
-
 use fs::vfs::{FileSystem, FileSystemMetadata, FileType, FsError, INode, INodeMetadata, Result, Timespec};
+use util::preempt_lock::PreemptRwLock;
 
+pub mod console;
+pub mod mem;
+pub mod pipe;
+pub mod serial;
 pub mod zeronull;
+pub mod full;
 
 /// The device file system usually mounted at `/dev/`
 pub struct DevFS {
-    devices: RwLock<BTreeMap<String, Arc<dyn INode>>>,
+    devices: PreemptRwLock<BTreeMap<String, Arc<dyn INode>>>,
     self_ref: Weak<DevFS>,
 }
 
@@ -39,29 +43,56 @@ impl DevFS {
     /// Creates a new instance of `DevFS`
     pub fn new() -> Arc<DevFS> {
         DevFS {
-            devices: RwLock::new(BTreeMap::new()),
+            devices: PreemptRwLock::new(BTreeMap::new()),
             self_ref: Weak::default(),
         }.wrap()
     }
 
-    /// Add a new device with name `name` (probably mounted at /dev/name)
-    pub fn add(&self, name: &str, device: Arc<dyn INode>) -> Result<()> {
-        let mut devices = self.devices.write();
-        if devices.contains_key(name) {
-            return Err(FsError::EntryExists);
-        }
+    /// Add a new device at `path` (probably mounted at /dev/path). Every directory in `path`
+    /// has to be created first, for example with `create` on the root inode.
+    pub fn add(&self, path: &str, device: Arc<dyn INode>) -> Result<()> {
+        let directory;
+        let (devices, name) = match path.rfind('/') {
+            None => (&self.devices, path),
+            Some(pos) => {
+                directory = self.directory(&path[..pos])?;
+                (&directory.downcast_ref::<DevFSDirINode>().unwrap().devices, &path[pos + 1..])
+            }
+        };
 
-        devices.insert(String::from(name), device);
-        Ok(())
+        add_device(devices, name, device)
     }
 
-    /// Remove a device with name `name`
-    pub fn remove(&self, name: &str) -> Result<()> {
-        let mut devices = self.devices.write();
-        devices.remove(name).ok_or(FsError::EntryNotFound)?;
+    /// Remove the device at `path`
+    pub fn remove(&self, path: &str) -> Result<()> {
+        let directory;
+        let (devices, name) = match path.rfind('/') {
+            None => (&self.devices, path),
+            Some(pos) => {
+                directory = self.directory(&path[..pos])?;
+                (&directory.downcast_ref::<DevFSDirINode>().unwrap().devices, &path[pos + 1..])
+            }
+        };
+
+        devices.write().remove(name).ok_or(FsError::EntryNotFound)?;
         Ok(())
     }
 
+    /// Finds the directory at `path`, relative to the root of this filesystem.
+    fn directory(&self, path: &str) -> Result<Arc<dyn INode>> {
+        let mut current = self.root();
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            current = current.find(name)?;
+
+            if current.downcast_ref::<DevFSDirINode>().is_none() {
+                return Err(FsError::NotDirectory);
+            }
+        }
+
+        Ok(current)
+    }
+
     /// Wraps the `DevFS` in an `Arc` and sets the `self_ref` variable
     fn wrap(self) -> Arc<DevFS> {
         let fs = Arc::new(self);
@@ -120,8 +151,9 @@ impl INode for DevFSRootINode {
         Err(FsError::IsDirectory)
     }
 
-    fn create(&self, _name: &str, _type_: FileType, _permissions: u32) -> Result<Arc<dyn INode>> {
-        Err(FsError::Unsupported)
+    /// Creates a directory. Device files can't be created, they are added with `DevFS::add`.
+    fn create(&self, name: &str, type_: FileType, permissions: u32) -> Result<Arc<dyn INode>> {
+        create_directory(&self.fs, &self.fs.devices, None, name, type_, permissions)
     }
 
     fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
@@ -158,4 +190,143 @@ impl INode for DevFSRootINode {
     fn as_any_ref(&self) -> &dyn Any {
         self
     }
+}
+
+/// A directory below the root of `DevFS`, which holds its own devices.
+struct DevFSDirINode {
+    fs: Arc<DevFS>,
+    devices: PreemptRwLock<BTreeMap<String, Arc<dyn INode>>>,
+    /// The directory this directory is in, or `None` if it is in the root directory.
+    parent: Option<Weak<DevFSDirINode>>,
+    self_ref: Weak<DevFSDirINode>,
+    permissions: u16,
+}
+
+impl DevFSDirINode {
+    /// Wraps the `DevFSDirINode` in an `Arc` and sets the `self_ref` variable
+    fn wrap(self) -> Arc<DevFSDirINode> {
+        let directory = Arc::new(self);
+        let weak = Arc::downgrade(&directory);
+        let ptr = Arc::into_raw(directory) as *mut DevFSDirINode;
+
+        unsafe {
+            (*ptr).self_ref = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+}
+
+impl INode for DevFSDirINode {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDirectory)
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        Ok(INodeMetadata {
+            inode: 0,
+            size: self.devices.read().len(),
+            access_time: Timespec { sec: 0, nanosec: 0 },
+            modification_time: Timespec { sec: 0, nanosec: 0 },
+            change_time: Timespec { sec: 0, nanosec: 0 },
+            type_: FileType::Directory,
+            permissions: self.permissions,
+            links: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn set_metadata(&self, _metadata: INodeMetadata) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, _new_len: usize) -> Result<()> {
+        Err(FsError::IsDirectory)
+    }
+
+    /// Creates a directory. Device files can't be created, they are added with `DevFS::add`.
+    fn create(&self, name: &str, type_: FileType, permissions: u32) -> Result<Arc<dyn INode>> {
+        create_directory(&self.fs, &self.devices, Some(self.self_ref.clone()), name, type_, permissions)
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn move_(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        match name {
+            "." => Ok(self.self_ref.upgrade().ok_or(FsError::EntryNotFound)?),
+            ".." => match self.parent {
+                Some(ref parent) => Ok(parent.upgrade().ok_or(FsError::EntryNotFound)?),
+                None => Ok(self.fs.root()),
+            },
+            name => self.devices.read().get(name).cloned().ok_or(FsError::EntryNotFound)
+        }
+    }
+
+    fn get_entry(&self, index: usize) -> Result<String> {
+        match index {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            i => self.devices.read().keys().nth(i - 2).cloned().ok_or(FsError::EntryNotFound)
+        }
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Adds `device` to `devices` under `name`, unless that name is taken.
+fn add_device(devices: &PreemptRwLock<BTreeMap<String, Arc<dyn INode>>>, name: &str, device: Arc<dyn INode>) -> Result<()> {
+    let mut devices = devices.write();
+    if devices.contains_key(name) {
+        return Err(FsError::EntryExists);
+    }
+
+    devices.insert(String::from(name), device);
+    Ok(())
+}
+
+/// Creates a directory named `name` in the directory that holds `devices`.
+fn create_directory(fs: &Arc<DevFS>, devices: &PreemptRwLock<BTreeMap<String, Arc<dyn INode>>>, parent: Option<Weak<DevFSDirINode>>,
+                    name: &str, type_: FileType, permissions: u32) -> Result<Arc<dyn INode>> {
+    if type_ != FileType::Directory {
+        return Err(FsError::Unsupported);
+    }
+
+    let directory = DevFSDirINode {
+        fs: fs.clone(),
+        devices: PreemptRwLock::new(BTreeMap::new()),
+        parent,
+        self_ref: Weak::default(),
+        permissions: permissions as u16,
+    }.wrap();
+
+    add_device(devices, name, directory.clone())?;
+    Ok(directory)
 }
\ No newline at end of file
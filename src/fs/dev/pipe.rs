@@ -0,0 +1,136 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+
+use fs::dev::DevFS;
+use fs::vfs::{INode, INodeMetadata, FileType, FileSystem, FsError, PollStatus, Result, Timespec};
+use task::wait_queue::WaitQueue;
+use util::preempt_lock::PreemptMutex;
+
+/// The amount of bytes a pipe buffers before writes stop accepting data.
+pub const PIPE_CAPACITY: usize = 4096;
+
+/// A pipe, bytes written to it can be read back once, in the order they were written. Reads from
+/// an empty pipe and writes to a full pipe transfer nothing instead of blocking.
+pub struct PipeDevice {
+    fs: Arc<DevFS>,
+    buffer: PreemptMutex<VecDeque<u8>>,
+    /// Woken whenever data is written, see `read_blocking`.
+    readers: WaitQueue,
+}
+
+impl PipeDevice {
+    pub fn new(fs: Arc<DevFS>) -> Arc<PipeDevice> {
+        Arc::new(PipeDevice {
+            fs,
+            buffer: PreemptMutex::new(VecDeque::with_capacity(PIPE_CAPACITY)),
+            readers: WaitQueue::new(),
+        })
+    }
+}
+
+impl INode for PipeDevice {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut buffer = self.buffer.lock();
+        let len = buf.len().min(buffer.len());
+
+        for (x, byte) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *x = byte;
+        }
+
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        let len = {
+            let mut buffer = self.buffer.lock();
+            let len = buf.len().min(PIPE_CAPACITY - buffer.len());
+            buffer.extend(&buf[..len]);
+            len
+        };
+
+        if len > 0 {
+            self.readers.wake_all();
+        }
+
+        Ok(len)
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&self.readers)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        let buffer = self.buffer.lock();
+
+        Ok(PollStatus {
+            readable: !buffer.is_empty(),
+            writable: buffer.len() < PIPE_CAPACITY,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        Ok(INodeMetadata {
+            inode: 0,
+            size: self.buffer.lock().len(),
+            access_time: Timespec { sec: 0, nanosec: 0 },
+            modification_time: Timespec { sec: 0, nanosec: 0 },
+            change_time: Timespec { sec: 0, nanosec: 0 },
+            type_: FileType::CharDevice,
+            permissions: 0o666,
+            links: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn set_metadata(&self, _metadata: INodeMetadata) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, _new_len: usize) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn create(&self, _name: &str, _type_: FileType, _permissions: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn move_(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn find(&self, _name: &str) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn get_entry(&self, _index: usize) -> Result<String> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
\ No newline at end of file
@@ -0,0 +1,134 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+
+use driver::uart16550::{RECEIVE_QUEUE, UART};
+use fs::dev::DevFS;
+use fs::ioctl::{TIOCGBAUD, TIOCSBAUD};
+use fs::vfs::{INode, INodeMetadata, FileType, FileSystem, FsError, PollStatus, Result, Timespec};
+use task::wait_queue::WaitQueue;
+
+/// A character device for the serial port, usually mounted at `/dev/ttyS0`
+pub struct SerialDevice {
+    fs: Arc<DevFS>,
+}
+
+impl SerialDevice {
+    pub fn new(fs: Arc<DevFS>) -> Arc<SerialDevice> {
+        Arc::new(SerialDevice { fs })
+    }
+}
+
+impl INode for SerialDevice {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut uart = UART.lock();
+
+        for (i, x) in buf.iter_mut().enumerate() {
+            match uart.receive_byte() {
+                Some(byte) => *x = byte,
+                None => return Ok(i),
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut uart = UART.lock();
+
+        for byte in buf {
+            uart.send_byte(*byte);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        Some(&RECEIVE_QUEUE)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            readable: UART.lock().has_received(),
+            writable: true,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        Ok(INodeMetadata {
+            inode: 0,
+            size: 0,
+            access_time: Timespec { sec: 0, nanosec: 0 },
+            modification_time: Timespec { sec: 0, nanosec: 0 },
+            change_time: Timespec { sec: 0, nanosec: 0 },
+            type_: FileType::CharDevice,
+            permissions: 0o666,
+            links: 1,
+            uid: 0,
+            gid: 0,
+        })
+    }
+
+    fn set_metadata(&self, _metadata: INodeMetadata) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn resize(&self, _new_len: usize) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn create(&self, _name: &str, _type_: FileType, _permissions: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn move_(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn find(&self, _name: &str) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn get_entry(&self, _index: usize) -> Result<String> {
+        Err(FsError::NotDirectory)
+    }
+
+    fn io_control(&self, cmd: u32, arg: usize) -> Result<usize> {
+        match cmd {
+            TIOCGBAUD => Ok(UART.lock().baud_rate() as usize),
+            TIOCSBAUD => {
+                if UART.lock().set_baud_rate(arg as u32) {
+                    Ok(0)
+                } else {
+                    Err(FsError::InvalidArgument)
+                }
+            },
+            _ => Err(FsError::Unsupported),
+        }
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
\ No newline at end of file
@@ -0,0 +1,584 @@
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::cmp;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use clock;
+use fs::block::BlockDevice;
+use fs::vfs::{FileSystem, FileSystemMetadata, FileType, FsError, INode, INodeMetadata, Result, Timespec};
+
+/// The byte offset of the superblock, regardless of the block size.
+const SUPERBLOCK_OFFSET: usize = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+
+/// The offset of the time of the last write in the superblock.
+const SUPERBLOCK_WRITE_TIME: usize = 48;
+
+const EXT2_MAGIC: u16 = 0xef53;
+
+/// The inode number of the root directory.
+const ROOT_INODE: u32 = 2;
+
+/// The size of an inode in revision 0 filesystems. Later revisions can have larger inodes, but
+/// the fields this implementation reads are all in the first 128 bytes.
+const GOOD_OLD_INODE_SIZE: usize = 128;
+
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+/// The amount of block pointers in an inode that point directly to data. They are followed by a
+/// singly, a doubly and a triply indirect pointer.
+const DIRECT_BLOCKS: usize = 12;
+
+/// The size of the block pointers of an inode in bytes. Symbolic links with a target shorter
+/// than this store the target in the pointers instead of in a block.
+const INODE_BLOCK_BYTES: usize = 60;
+
+/// Incompatible feature: directory entries contain the type of the file. This is the only
+/// incompatible feature that is supported.
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+
+const MAX_NAME_LEN: usize = 255;
+
+const MODE_TYPE_MASK: u16 = 0xf000;
+const MODE_CHAR_DEVICE: u16 = 0x2000;
+const MODE_DIRECTORY: u16 = 0x4000;
+const MODE_SYMBOLIC_LINK: u16 = 0xa000;
+
+/// An ext2 filesystem on a block device. The contents of files can be overwritten, but nothing
+/// can be created, removed or grown, since blocks and inodes can't be allocated.
+///
+/// Writes go to the device right away, syncing flushes them out of a device that buffers them,
+/// like a `CachedBlockDevice`.
+pub struct Ext2Fs {
+    device: Arc<dyn BlockDevice>,
+    superblock: Superblock,
+    /// The first block of the inode table of every block group.
+    inode_tables: Vec<u32>,
+    /// Set by writes, the next sync updates the write time in the superblock.
+    written: AtomicBool,
+    self_ref: Weak<Ext2Fs>,
+}
+
+/// The fields of the superblock that are needed to find inodes and their data.
+struct Superblock {
+    inodes_count: u32,
+    free_inodes_count: u32,
+    block_size: usize,
+    inodes_per_group: u32,
+    inode_size: usize,
+    /// Whether directory entries contain the type of the file, which makes the name length a
+    /// single byte.
+    has_file_type: bool,
+}
+
+impl Ext2Fs {
+    /// Reads the superblock and block group descriptors from `device`. Fails with
+    /// `FsError::InvalidArgument` if there is no ext2 filesystem on the device, and with
+    /// `FsError::Unsupported` if it uses features that are not implemented.
+    pub fn new(device: Arc<dyn BlockDevice>) -> Result<Arc<Ext2Fs>> {
+        let mut raw = vec![0; SUPERBLOCK_SIZE];
+        device.read_bytes(SUPERBLOCK_OFFSET, &mut raw)?;
+
+        if read_u16(&raw, 56)? != EXT2_MAGIC {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let revision = read_u32(&raw, 76)?;
+        let (inode_size, incompatible_features) = if revision == 0 {
+            (GOOD_OLD_INODE_SIZE, 0)
+        } else {
+            (read_u16(&raw, 88)? as usize, read_u32(&raw, 96)?)
+        };
+
+        if incompatible_features & !INCOMPAT_FILETYPE != 0 || inode_size < GOOD_OLD_INODE_SIZE {
+            return Err(FsError::Unsupported);
+        }
+
+        let blocks_count = read_u32(&raw, 4)? as usize;
+        let first_data_block = read_u32(&raw, 20)? as usize;
+        let blocks_per_group = read_u32(&raw, 32)? as usize;
+        let block_size = 1024usize.checked_shl(read_u32(&raw, 24)?).ok_or(FsError::InvalidArgument)?;
+
+        if blocks_per_group == 0 || blocks_count <= first_data_block {
+            return Err(FsError::InvalidArgument);
+        }
+
+        let superblock = Superblock {
+            inodes_count: read_u32(&raw, 0)?,
+            free_inodes_count: read_u32(&raw, 16)?,
+            block_size,
+            inodes_per_group: read_u32(&raw, 40)?,
+            inode_size,
+            has_file_type: incompatible_features & INCOMPAT_FILETYPE != 0,
+        };
+
+        // The block group descriptor table starts in the block after the superblock.
+        let group_count = (blocks_count - first_data_block + blocks_per_group - 1) / blocks_per_group;
+        let mut descriptors = vec![0; group_count * GROUP_DESCRIPTOR_SIZE];
+        device.read_bytes((first_data_block + 1) * block_size, &mut descriptors)?;
+
+        let inode_tables = (0..group_count)
+            .map(|group| read_u32(&descriptors, group * GROUP_DESCRIPTOR_SIZE + 8))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Ext2Fs {
+            device,
+            superblock,
+            inode_tables,
+            written: AtomicBool::new(false),
+            self_ref: Weak::default(),
+        }.wrap())
+    }
+
+    /// Returns the block of the inode table that inode `id` is stored in, and its offset in that
+    /// block.
+    fn inode_location(&self, id: u32) -> Result<(u32, usize)> {
+        if id == 0 || id > self.superblock.inodes_count {
+            return Err(FsError::EntryNotFound);
+        }
+
+        let group = ((id - 1) / self.superblock.inodes_per_group) as usize;
+        let index = ((id - 1) % self.superblock.inodes_per_group) as usize;
+        let table = *self.inode_tables.get(group).ok_or(FsError::InvalidArgument)?;
+
+        let offset = index * self.superblock.inode_size;
+        let block_size = self.superblock.block_size;
+        Ok((table + (offset / block_size) as u32, offset % block_size))
+    }
+
+    /// Reads inode `id` from the inode table.
+    fn inode(&self, id: u32) -> Result<Arc<Ext2INode>> {
+        let (block, offset) = self.inode_location(id)?;
+
+        let mut raw = [0; GOOD_OLD_INODE_SIZE];
+        self.read_bytes(block, offset, &mut raw)?;
+
+        Ok(Arc::new(Ext2INode {
+            id,
+            raw: RawINode::parse(&raw)?,
+            fs: self.self_ref.upgrade().unwrap(),
+        }))
+    }
+
+    /// Reads `buf.len()` bytes starting `offset` bytes into block `block`.
+    fn read_bytes(&self, block: u32, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.device.read_bytes(block as usize * self.superblock.block_size + offset, buf)
+    }
+
+    /// Writes `buf` starting `offset` bytes into block `block`.
+    fn write_bytes(&self, block: u32, offset: usize, buf: &[u8]) -> Result<()> {
+        self.written.store(true, Ordering::SeqCst);
+        self.device.write_bytes(block as usize * self.superblock.block_size + offset, buf)
+    }
+
+    /// Flushes the device blocks that hold block `block`.
+    fn flush_block(&self, block: u32) -> Result<()> {
+        let device_block_size = self.device.block_size();
+        let first = block as usize * self.superblock.block_size / device_block_size;
+        let count = cmp::max(self.superblock.block_size / device_block_size, 1);
+
+        for index in first..first + count {
+            self.device.flush_block(index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads entry `index` of the block pointer table in block `block`.
+    fn read_pointer(&self, block: u32, index: usize) -> Result<u32> {
+        let mut raw = [0; 4];
+        self.read_bytes(block, index * 4, &mut raw)?;
+        Ok(u32::from_le_bytes(raw))
+    }
+
+    /// Wraps the `Ext2Fs` in an `Arc` and sets the `self_ref` variable
+    fn wrap(self) -> Arc<Ext2Fs> {
+        let fs = Arc::new(self);
+        let weak = Arc::downgrade(&fs);
+        let ptr = Arc::into_raw(fs) as *mut Ext2Fs;
+
+        unsafe {
+            (*ptr).self_ref = weak;
+            Arc::from_raw(ptr)
+        }
+    }
+}
+
+impl FileSystem for Ext2Fs {
+    fn sync(&self) -> Result<()> {
+        if self.written.swap(false, Ordering::SeqCst) {
+            let time = clock::now().sec as u32;
+            self.device.write_bytes(SUPERBLOCK_OFFSET + SUPERBLOCK_WRITE_TIME, &time.to_le_bytes())?;
+        }
+
+        self.device.flush()
+    }
+
+    fn root(&self) -> Arc<dyn INode> {
+        self.inode(ROOT_INODE).expect("Can't read the root inode!")
+    }
+
+    fn metadata(&self) -> FileSystemMetadata {
+        FileSystemMetadata {
+            files: (self.superblock.inodes_count - self.superblock.free_inodes_count) as usize,
+            files_free: self.superblock.free_inodes_count as usize,
+            max_name_len: MAX_NAME_LEN,
+        }
+    }
+}
+
+/// The fields of an inode as they are stored on the disk.
+struct RawINode {
+    mode: u16,
+    uid: u16,
+    size: u64,
+    access_time: u32,
+    change_time: u32,
+    modification_time: u32,
+    gid: u16,
+    links: u16,
+    /// The amount of 512 byte sectors used by the inode, including indirect blocks and the
+    /// extended attribute block.
+    sectors: u32,
+    extended_attributes: u32,
+    blocks: [u8; INODE_BLOCK_BYTES],
+}
+
+impl RawINode {
+    fn parse(raw: &[u8]) -> Result<RawINode> {
+        let mode = read_u16(raw, 0)?;
+
+        // The upper half of the size is only used for files, directories use it for an ACL.
+        let mut size = u64::from(read_u32(raw, 4)?);
+        if mode & MODE_TYPE_MASK != MODE_DIRECTORY {
+            size |= u64::from(read_u32(raw, 108)?) << 32;
+        }
+
+        let mut blocks = [0; INODE_BLOCK_BYTES];
+        blocks.copy_from_slice(raw.get(40..40 + INODE_BLOCK_BYTES).ok_or(FsError::InvalidArgument)?);
+
+        Ok(RawINode {
+            mode,
+            uid: read_u16(raw, 2)?,
+            size,
+            access_time: read_u32(raw, 8)?,
+            change_time: read_u32(raw, 12)?,
+            modification_time: read_u32(raw, 16)?,
+            gid: read_u16(raw, 24)?,
+            links: read_u16(raw, 26)?,
+            sectors: read_u32(raw, 28)?,
+            extended_attributes: read_u32(raw, 104)?,
+            blocks,
+        })
+    }
+
+    fn block_pointer(&self, index: usize) -> u32 {
+        u32::from_le_bytes([
+            self.blocks[index * 4],
+            self.blocks[index * 4 + 1],
+            self.blocks[index * 4 + 2],
+            self.blocks[index * 4 + 3],
+        ])
+    }
+}
+
+/// An inode implementation for `Ext2Fs`. The inode is read once when it is looked up.
+pub struct Ext2INode {
+    id: u32,
+    raw: RawINode,
+    fs: Arc<Ext2Fs>,
+}
+
+impl Ext2INode {
+    fn type_(&self) -> FileType {
+        match self.raw.mode & MODE_TYPE_MASK {
+            MODE_DIRECTORY => FileType::Directory,
+            MODE_SYMBOLIC_LINK => FileType::SymbolicLink,
+            MODE_CHAR_DEVICE => FileType::CharDevice,
+            _ => FileType::File,
+        }
+    }
+
+    /// Returns whether this is a symbolic link that stores its target in the block pointers. That
+    /// is the case when no blocks besides the extended attribute block are used.
+    fn is_fast_symlink(&self) -> bool {
+        let attribute_sectors = if self.raw.extended_attributes != 0 {
+            (self.fs.superblock.block_size / 512) as u32
+        } else {
+            0
+        };
+
+        self.type_() == FileType::SymbolicLink && self.raw.sectors == attribute_sectors
+    }
+
+    /// Returns the block that holds block `index` of the contents, or 0 for a hole.
+    fn block_address(&self, index: usize) -> Result<u32> {
+        if index < DIRECT_BLOCKS {
+            return Ok(self.raw.block_pointer(index));
+        }
+
+        let pointers_per_block = self.fs.superblock.block_size / 4;
+        let mut index = index - DIRECT_BLOCKS;
+        let mut span = 1;
+
+        // Level 0 is the singly indirect block, which covers `pointers_per_block` blocks. Every
+        // level of indirection multiplies that by `pointers_per_block`.
+        for level in 0..3 {
+            span *= pointers_per_block;
+
+            if index < span {
+                let mut block = self.raw.block_pointer(DIRECT_BLOCKS + level);
+                let mut span_below = span / pointers_per_block;
+
+                for _ in 0..=level {
+                    if block == 0 {
+                        return Ok(0);
+                    }
+
+                    block = self.fs.read_pointer(block, index / span_below)?;
+                    index %= span_below;
+                    span_below /= pointers_per_block;
+                }
+
+                return Ok(block);
+            }
+
+            index -= span;
+        }
+
+        Err(FsError::FileTooLarge)
+    }
+
+    /// Reads the contents of the inode from its blocks. Holes read as zeroes.
+    fn read_data(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = self.raw.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+
+        let block_size = self.fs.superblock.block_size;
+        let len = cmp::min(buf.len(), size - offset);
+        let mut done = 0;
+
+        while done < len {
+            let position = offset + done;
+            let start = position % block_size;
+            let chunk = cmp::min(block_size - start, len - done);
+
+            match self.block_address(position / block_size)? {
+                0 => {
+                    for byte in &mut buf[done..done + chunk] {
+                        *byte = 0;
+                    }
+                },
+                block => self.fs.read_bytes(block, start, &mut buf[done..done + chunk])?,
+            }
+
+            done += chunk;
+        }
+
+        Ok(len)
+    }
+
+    /// Overwrites the contents of the inode. Fails with `FsError::Unsupported` if that would need
+    /// a block to be allocated, because the write goes past the end or into a hole.
+    fn write_data(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let end = offset.checked_add(buf.len()).ok_or(FsError::InvalidArgument)?;
+        if end > self.raw.size as usize {
+            return Err(FsError::Unsupported);
+        }
+
+        let block_size = self.fs.superblock.block_size;
+        let mut done = 0;
+
+        while done < buf.len() {
+            let position = offset + done;
+            let start = position % block_size;
+            let chunk = cmp::min(block_size - start, buf.len() - done);
+
+            match self.block_address(position / block_size)? {
+                0 => return Err(FsError::Unsupported),
+                block => self.fs.write_bytes(block, start, &buf[done..done + chunk])?,
+            }
+
+            done += chunk;
+        }
+
+        Ok(done)
+    }
+
+    /// Returns the blocks that hold the contents of the inode, without holes.
+    fn data_blocks(&self) -> Result<Vec<u32>> {
+        let block_size = self.fs.superblock.block_size;
+        let count = (self.raw.size as usize + block_size - 1) / block_size;
+
+        let mut blocks = Vec::new();
+        for index in 0..count {
+            match self.block_address(index)? {
+                0 => {},
+                block => blocks.push(block),
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    /// Reads the names and inode numbers of all entries of this directory, including `.` and `..`.
+    fn entries(&self) -> Result<Vec<(String, u32)>> {
+        if self.type_() != FileType::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        let mut content = vec![0; self.raw.size as usize];
+        self.read_data(0, &mut content)?;
+
+        let mut entries = Vec::new();
+        let mut offset = 0;
+
+        while offset + 8 <= content.len() {
+            let inode = read_u32(&content, offset)?;
+            let record_len = read_u16(&content, offset + 4)? as usize;
+            let name_len = if self.fs.superblock.has_file_type {
+                content[offset + 6] as usize
+            } else {
+                read_u16(&content, offset + 6)? as usize
+            };
+
+            if record_len < 8 {
+                return Err(FsError::InvalidArgument);
+            }
+
+            // Deleted entries and the padding at the end of a block have inode 0.
+            if inode != 0 {
+                let name = content.get(offset + 8..offset + 8 + name_len).ok_or(FsError::InvalidArgument)?;
+                entries.push((String::from_utf8_lossy(name).into_owned(), inode));
+            }
+
+            offset += record_len;
+        }
+
+        Ok(entries)
+    }
+}
+
+impl INode for Ext2INode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        match self.type_() {
+            FileType::Directory => Err(FsError::IsDirectory),
+            FileType::SymbolicLink if self.is_fast_symlink() => {
+                let target = &self.raw.blocks[..cmp::min(self.raw.size as usize, INODE_BLOCK_BYTES)];
+                let start = cmp::min(offset, target.len());
+                let len = cmp::min(buf.len(), target.len() - start);
+
+                buf[..len].copy_from_slice(&target[start..start + len]);
+                Ok(len)
+            },
+            _ => self.read_data(offset, buf),
+        }
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        match self.type_() {
+            FileType::Directory => Err(FsError::IsDirectory),
+            FileType::File => self.write_data(offset, buf),
+            _ => Err(FsError::Unsupported),
+        }
+    }
+
+    fn metadata(&self) -> Result<INodeMetadata> {
+        let time = |sec: u32| Timespec { sec: i64::from(sec), nanosec: 0 };
+
+        Ok(INodeMetadata {
+            inode: self.id as usize,
+            size: self.raw.size as usize,
+            access_time: time(self.raw.access_time),
+            modification_time: time(self.raw.modification_time),
+            change_time: time(self.raw.change_time),
+            type_: self.type_(),
+            permissions: self.raw.mode & 0o7777,
+            links: self.raw.links as usize,
+            uid: self.raw.uid as usize,
+            gid: self.raw.gid as usize,
+        })
+    }
+
+    fn set_metadata(&self, _metadata: INodeMetadata) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        self.sync_data()?;
+
+        let (block, _) = self.fs.inode_location(self.id)?;
+        self.fs.flush_block(block)
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        if self.is_fast_symlink() {
+            return Ok(());
+        }
+
+        for block in self.data_blocks()? {
+            self.fs.flush_block(block)?;
+        }
+
+        Ok(())
+    }
+
+    fn resize(&self, _new_len: usize) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn create(&self, _name: &str, _type_: FileType, _permissions: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::Unsupported)
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn move_(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::Unsupported)
+    }
+
+    fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let id = self.entries()?.into_iter()
+            .find(|(entry, _)| entry == name)
+            .map(|(_, id)| id)
+            .ok_or(FsError::EntryNotFound)?;
+
+        self.fs.inode(id).map(|inode| inode as Arc<dyn INode>)
+    }
+
+    fn get_entry(&self, index: usize) -> Result<String> {
+        self.entries()?.into_iter()
+            .nth(index)
+            .map(|(name, _)| name)
+            .ok_or(FsError::EntryNotFound)
+    }
+
+    fn filesystem(&self) -> Arc<dyn FileSystem> {
+        self.fs.clone()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Result<u16> {
+    let b = bytes.get(offset..offset + 2).ok_or(FsError::InvalidArgument)?;
+    Ok(u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32> {
+    let b = bytes.get(offset..offset + 4).ok_or(FsError::InvalidArgument)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
\ No newline at end of file
@@ -0,0 +1,121 @@
+use alloc::sync::Arc;
+
+use flagset::{flags, FlagSet};
+
+use fs::vfs::{FileType, FsError, INode, Result};
+
+/// The maximum amount of symbolic links followed when opening a path.
+const MAX_FOLLOW: usize = 8;
+
+flags! {
+    /// Flags that change how a file is opened and how a `File` accesses its inode.
+    pub enum OpenFlags: u32 {
+        /// Every write goes to the end of the file, regardless of the offset
+        Append,
+
+        /// Create the file if it doesn't exist
+        Create,
+
+        /// Truncate the file to zero bytes when it is opened
+        Truncate,
+
+        /// Together with `Create`, fail if the file already exists
+        Exclusive,
+    }
+}
+
+/// Opens the file at `path`, resolved from `root`. Depending on `flags`, the file is created with
+/// `permissions` if it doesn't exist and truncated if it does. With `OpenFlags::Append`, the
+/// offset of the returned file starts at the end of the file.
+pub fn open(root: &Arc<dyn INode>, path: &str, flags: impl Into<FlagSet<OpenFlags>>, permissions: u32) -> Result<File> {
+    let flags = flags.into();
+
+    let inode = match root.resolve_follow(path, MAX_FOLLOW) {
+        Ok(_) if flags.contains(OpenFlags::Create | OpenFlags::Exclusive) => return Err(FsError::EntryExists),
+        Ok(inode) => inode,
+        Err(FsError::EntryNotFound) if flags.contains(OpenFlags::Create) => {
+            let path = path.trim_end_matches('/');
+            let (parent, name) = match path.rfind('/') {
+                Some(pos) => (root.resolve_follow(&path[..=pos], MAX_FOLLOW)?, &path[pos + 1..]),
+                None => (root.clone(), path),
+            };
+
+            parent.create(name, FileType::File, permissions)?
+        },
+        Err(err) => return Err(err),
+    };
+
+    if flags.contains(OpenFlags::Truncate) {
+        if inode.metadata()?.type_ == FileType::Directory {
+            return Err(FsError::IsDirectory);
+        }
+
+        inode.resize(0)?;
+    }
+
+    let mut file = File::new(inode, flags);
+    if flags.contains(OpenFlags::Append) {
+        let size = file.inode.metadata()?.size;
+        file.seek(size);
+    }
+
+    Ok(file)
+}
+
+/// An open file: an inode together with the offset the next read or write happens at.
+pub struct File {
+    inode: Arc<dyn INode>,
+    offset: usize,
+    flags: FlagSet<OpenFlags>,
+}
+
+impl File {
+    /// Opens `inode` with the offset at the start of the file.
+    pub fn new(inode: Arc<dyn INode>, flags: impl Into<FlagSet<OpenFlags>>) -> File {
+        File {
+            inode,
+            offset: 0,
+            flags: flags.into(),
+        }
+    }
+
+    /// Reads from the current offset into `buf` and advances the offset. Returns the amount of
+    /// bytes read.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let len = self.inode.read_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    /// Writes `buf` at the current offset and advances the offset. With `OpenFlags::Append`, the
+    /// data is written at the end of the file and the offset is moved to the new end. Returns the
+    /// amount of bytes written.
+    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.flags.contains(OpenFlags::Append) {
+            let len = self.inode.append(buf)?;
+            self.offset = self.inode.metadata()?.size;
+            return Ok(len);
+        }
+
+        let len = self.inode.write_at(self.offset, buf)?;
+        self.offset += len;
+        Ok(len)
+    }
+
+    /// Moves the offset to `offset` bytes from the start of the file.
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn flags(&self) -> FlagSet<OpenFlags> {
+        self.flags
+    }
+
+    pub fn inode(&self) -> &Arc<dyn INode> {
+        &self.inode
+    }
+}
\ No newline at end of file
@@ -0,0 +1,58 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use util::preempt_lock::PreemptMutex;
+
+/// Hands out inode ids that are unique among the live inodes of a filesystem. Freed ids are kept in
+/// a free list and handed out again before new ids are taken from the counter.
+pub struct INodeIdAllocator {
+    next: AtomicUsize,
+    free_count: AtomicUsize,
+    free: PreemptMutex<Vec<usize>>,
+}
+
+impl INodeIdAllocator {
+    /// Creates a new allocator. Ids start at 1.
+    pub fn new() -> INodeIdAllocator {
+        INodeIdAllocator {
+            next: AtomicUsize::new(1),
+            free_count: AtomicUsize::new(0),
+            free: PreemptMutex::new(Vec::new()),
+        }
+    }
+
+    /// Allocates an id. Panics if every id is in use.
+    pub fn alloc(&self) -> usize {
+        // Only take the lock if there might be a freed id to reuse.
+        if self.free_count.load(Ordering::SeqCst) > 0 {
+            if let Some(id) = self.free.lock().pop() {
+                self.free_count.fetch_sub(1, Ordering::SeqCst);
+                return id;
+            }
+        }
+
+        let mut current = self.next.load(Ordering::SeqCst);
+        loop {
+            assert_ne!(current, usize::max_value(), "Out of inode ids!");
+
+            match self.next.compare_exchange(current, current + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(id) => return id,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Returns `id` to the allocator, so it can be handed out again. The inode that used it must
+    /// no longer be reachable.
+    pub fn free(&self, id: usize) {
+        debug_assert!(id != 0 && id < self.next.load(Ordering::SeqCst), "Freeing unallocated inode id {}", id);
+
+        self.free.lock().push(id);
+        self.free_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Returns the amount of ids that are currently in use.
+    pub fn live_count(&self) -> usize {
+        self.next.load(Ordering::SeqCst) - 1 - self.free_count.load(Ordering::SeqCst)
+    }
+}
\ No newline at end of file
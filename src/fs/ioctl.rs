@@ -0,0 +1,15 @@
+//! Command numbers for `INode::io_control`.
+
+/// Get the baud rate of a serial device. Returns the baud rate.
+pub const TIOCGBAUD: u32 = 0x5401;
+
+/// Set the baud rate of a serial device to `arg`. Fails with `FsError::InvalidArgument` if the
+/// baud rate can't be reached exactly.
+pub const TIOCSBAUD: u32 = 0x5402;
+
+/// Get the line discipline mode of the console. Returns 1 when cooked, 0 when raw.
+pub const TCGCOOKED: u32 = 0x5403;
+
+/// Switch the console to cooked (`arg` is 1) or raw (`arg` is 0) mode. In cooked mode input is
+/// echoed and can be edited, and reads return one line at a time.
+pub const TCSCOOKED: u32 = 0x5404;
\ No newline at end of file
@@ -1,4 +1,16 @@
 pub mod vfs;
+pub mod cache;
+pub mod block;
+pub mod ext2;
+pub mod file;
+pub mod inode_id;
+pub mod path;
+pub mod ioctl;
 pub mod ramdisk;
+pub mod tar;
 pub mod mount;
-pub mod dev;
\ No newline at end of file
+pub mod dev;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+
+pub use fs::file::open;
\ No newline at end of file
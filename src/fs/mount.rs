@@ -1,17 +1,29 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 
-use spin::RwLock;
-
-use fs::vfs::{FileSystem, FileType, FsError, INode, Result, FileSystemMetadata, INodeMetadata};
+use fs::vfs::{FileSystem, FileType, FsError, INode, Result, FileSystemMetadata, INodeMetadata, PollStatus};
 use alloc::string::String;
 use core::any::Any;
 
+use task::wait_queue::WaitQueue;
+use util::preempt_lock::PreemptRwLock;
+
+/// Identifies a mountpoint by the address of the filesystem the inode belongs to and the inode id.
+/// Inode ids are only unique within a single filesystem, so the id alone is not enough.
+type MountKey = (usize, usize);
+
+/// Returns the key of `inode` in the mountpoints map.
+fn mount_key(inode: &Arc<dyn INode>) -> Result<MountKey> {
+    let filesystem = inode.filesystem();
+    let filesystem_address = &*filesystem as *const dyn FileSystem as *const () as usize;
+
+    Ok((filesystem_address, inode.metadata()?.inode))
+}
+
 /// A wrapper for another filesystem that allows you to mount another file system to any inode.
 pub struct MountFS {
     inner: Arc<dyn FileSystem>,
-    // TODO: Maybe some filesystems use multiple Arc's for a single inode
-    mountpoints: RwLock<BTreeMap<usize, Arc<MountFS>>>,
+    mountpoints: PreemptRwLock<BTreeMap<MountKey, Arc<MountFS>>>,
     self_mountpoint: Option<Arc<MountedNode>>,
     self_ref: Weak<MountFS>,
 }
@@ -21,7 +33,7 @@ impl MountFS {
     pub fn new(fs: Arc<dyn FileSystem>) -> Arc<MountFS> {
         MountFS {
             inner: fs,
-            mountpoints: RwLock::new(BTreeMap::new()),
+            mountpoints: PreemptRwLock::new(BTreeMap::new()),
             self_mountpoint: None,
             self_ref: Weak::default(),
         }.wrap()
@@ -85,13 +97,13 @@ impl MountedNode {
 
         let mounted_fs = MountFS {
             inner: fs,
-            mountpoints: RwLock::new(BTreeMap::new()),
+            mountpoints: PreemptRwLock::new(BTreeMap::new()),
             self_mountpoint: Some(self.self_ref.upgrade().unwrap()),
             self_ref: Weak::default(),
         }.wrap();
 
         self.fs.mountpoints.write()
-            .insert(self.inode.metadata()?.inode, mounted_fs.clone());
+            .insert(mount_key(&self.inode)?, mounted_fs.clone());
 
         Ok(mounted_fs)
     }
@@ -99,9 +111,9 @@ impl MountedNode {
     /// If a filesystem is mounted here, it returns the root inode of that filesystem. Else it
     /// returns self
     fn overlaid_inode(&self) -> Arc<MountedNode> {
-        let inode = self.inode.metadata().unwrap().inode;
+        let key = mount_key(&self.inode).unwrap();
 
-        if let Some(sub_fs) = self.fs.mountpoints.read().get(&inode) {
+        if let Some(sub_fs) = self.fs.mountpoints.read().get(&key) {
             sub_fs.root()
         } else {
             self.self_ref.upgrade().unwrap()
@@ -146,9 +158,12 @@ impl MountedNode {
                 }
             },
             _ => {
+                // If a filesystem is mounted here, the child belongs to that filesystem's mount.
+                let overlaid = self.overlaid_inode();
+
                 Ok(MountedNode {
-                    inode: self.overlaid_inode().inode.find(name)?,
-                    fs: self.fs.clone(),
+                    inode: overlaid.inode.find(name)?,
+                    fs: overlaid.fs.clone(),
                     self_ref: Weak::default(),
                 }.wrap().overlaid_inode())
             }
@@ -177,6 +192,18 @@ impl INode for MountedNode {
         self.inode.write_at(offset, buf)
     }
 
+    fn append(&self, buf: &[u8]) -> Result<usize> {
+        self.inode.append(buf)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        self.inode.poll()
+    }
+
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        self.inode.wait_queue()
+    }
+
     fn metadata(&self) -> Result<INodeMetadata> {
         self.inode.metadata()
     }
@@ -207,7 +234,8 @@ impl INode for MountedNode {
     }
 
     fn unlink(&self, name: &str) -> Result<()> {
-        if self.fs.mountpoints.read().contains_key(&self.inode.metadata()?.inode) {
+        let child = self.inode.find(name)?;
+        if self.fs.mountpoints.read().contains_key(&mount_key(&child)?) {
             return Err(FsError::Busy);
         }
 
@@ -227,6 +255,10 @@ impl INode for MountedNode {
         self.inode.get_entry(index)
     }
 
+    fn io_control(&self, cmd: u32, arg: usize) -> Result<usize> {
+        self.inode.io_control(cmd, arg)
+    }
+
     fn filesystem(&self) -> Arc<dyn FileSystem> {
         self.fs.clone()
     }
@@ -0,0 +1,57 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// A normalized absolute path, like `/tmp/file`. It never contains `.` or `..` components, empty
+/// components or a trailing slash, except for the root directory `/` itself.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Path {
+    path: String,
+}
+
+impl Path {
+    /// Returns the path of the root directory.
+    pub fn root() -> Path {
+        Path {
+            path: String::from("/"),
+        }
+    }
+
+    /// Resolves `path` relative to this path, or returns it normalized if it is absolute. `..` in
+    /// the root directory stays in the root directory.
+    pub fn join(&self, path: &str) -> Path {
+        let mut components: Vec<&str> = Vec::new();
+
+        if !path.starts_with('/') {
+            components.extend(self.components());
+        }
+
+        for component in path.split('/') {
+            match component {
+                "" | "." => (),
+                ".." => {
+                    components.pop();
+                },
+                component => components.push(component),
+            }
+        }
+
+        let mut path = String::from("/");
+        path.push_str(&components.join("/"));
+        Path { path }
+    }
+
+    /// Returns the components of the path, the root directory has none.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.path.split('/').filter(|component| !component.is_empty())
+    }
+
+    /// Returns the path without the leading slash, which is how `INode::resolve_follow` expects
+    /// paths relative to the root directory.
+    pub fn relative(&self) -> &str {
+        &self.path[1..]
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+}
\ No newline at end of file
@@ -1,42 +1,65 @@
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
+use alloc::format;
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
+use alloc::vec;
 use alloc::vec::Vec;
 use core::any::Any;
-use core::sync::atomic::{AtomicUsize, Ordering};
-
-use spin::RwLock;
+use core::cmp;
 
+use clock;
+use fs::inode_id::INodeIdAllocator;
 use fs::vfs::{FileSystem, FileSystemMetadata, FileType, FsError, INode, INodeMetadata, Result, Timespec};
+use util::preempt_lock::{PreemptMutex, PreemptRwLock, PreemptRwLockWriteGuard};
+
+/// The largest size a file on a `Ramdisk` created with `Ramdisk::new` can grow to.
+pub const DEFAULT_MAX_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The size of the chunks the content of a file grows in. Appending in small writes only allocates
+/// once per chunk, and holes in sparse files use no chunks at all.
+pub const CONTENT_CHUNK_SIZE: usize = 4096;
 
 /// A basic filesystem implementation that is stored in RAM.
 pub struct Ramdisk {
     root: Arc<LockedRamdiskINode>,
+    inode_ids: INodeIdAllocator,
+    max_file_size: usize,
 }
 
 impl Ramdisk {
     pub fn new() -> Arc<Ramdisk> {
+        Ramdisk::with_max_file_size(DEFAULT_MAX_FILE_SIZE)
+    }
+
+    /// Creates a ramdisk on which files can't grow larger than `max_file_size` bytes. Writes and
+    /// resizes past that size fail with `FsError::FileTooLarge`.
+    pub fn with_max_file_size(max_file_size: usize) -> Arc<Ramdisk> {
+        let inode_ids = INodeIdAllocator::new();
+        let now = clock::now();
+
         let root = Arc::new(LockedRamdiskINode::new(RamdiskINode {
             parent_ref: Weak::default(),
             self_ref: Weak::default(),
             children: BTreeMap::new(),
             metadata: INodeMetadata {
-                inode: next_inode(),
+                inode: inode_ids.alloc(),
                 size: 0,
-                access_time: Timespec { sec: 0, nanosec: 0 },
-                modification_time: Timespec { sec: 0, nanosec: 0 },
-                change_time: Timespec { sec: 0, nanosec: 0 },
+                access_time: now,
+                modification_time: now,
+                change_time: now,
                 type_: FileType::Directory,
                 permissions: 0o777,
                 links: 1,
                 uid: 0,
                 gid: 0,
             },
-            content: Vec::new(),
+            access_time: PreemptMutex::new(now),
+            content: SparseContent::new(),
             filesystem: Weak::new(),
         }));
 
-        let filesystem = Arc::new(Ramdisk { root });
+        let filesystem = Arc::new(Ramdisk { root, inode_ids, max_file_size });
         let mut root = filesystem.root.write();
         root.parent_ref = Arc::downgrade(&filesystem.root);
         root.self_ref = Arc::downgrade(&filesystem.root);
@@ -45,6 +68,212 @@ impl Ramdisk {
 
         filesystem
     }
+
+    /// Walks the whole tree and checks that it is consistent: every inode can reach itself through
+    /// `self_ref`, every directory entry that is a directory points back to its parent, every
+    /// directory is only reachable once and the link count of every inode matches the amount of
+    /// entries referencing it, including the `..` entries of subdirectories. Returns a description
+    /// of the first problem found.
+    pub fn check(&self) -> core::result::Result<(), String> {
+        // The amount of entries referencing every inode, by the address of the inode.
+        let mut references: BTreeMap<usize, (Arc<LockedRamdiskINode>, String, usize)> = BTreeMap::new();
+        let mut stack = vec![(self.root.clone(), String::from("/"))];
+
+        references.insert(inode_address(&self.root), (self.root.clone(), String::from("/"), 1));
+
+        while let Some((directory, path)) = stack.pop() {
+            let directory_lock = directory.read();
+
+            match directory_lock.self_ref.upgrade() {
+                Some(ref self_ref) if Arc::ptr_eq(self_ref, &directory) => (),
+                _ => return Err(format!("{}: self reference does not point to the inode", path)),
+            }
+
+            let mut subdirectories = 0;
+
+            for (name, child) in directory_lock.children.iter() {
+                let child_path = format!("{}{}", path, name);
+                let child_lock = child.read();
+
+                match child_lock.self_ref.upgrade() {
+                    Some(ref self_ref) if Arc::ptr_eq(self_ref, child) => (),
+                    _ => return Err(format!("{}: self reference does not point to the inode", child_path)),
+                }
+
+                let entry = references.entry(inode_address(child))
+                    .or_insert_with(|| (child.clone(), child_path.clone(), 0));
+                entry.2 += 1;
+
+                if child_lock.metadata.type_ != FileType::Directory {
+                    continue;
+                }
+
+                if entry.2 > 1 {
+                    return Err(format!("{}: directory is also reachable as {}", child_path, entry.1));
+                }
+
+                match child_lock.parent_ref.upgrade() {
+                    Some(ref parent) if Arc::ptr_eq(parent, &directory) => (),
+                    _ => return Err(format!("{}: parent reference does not point to {}", child_path, path)),
+                }
+
+                stack.push((child.clone(), child_path + "/"));
+                subdirectories += 1;
+            }
+
+            references.get_mut(&inode_address(&directory)).unwrap().2 += subdirectories;
+        }
+
+        for (inode, path, count) in references.values() {
+            let links = inode.read().metadata.links;
+
+            if links != *count {
+                return Err(format!("{}: has {} links, but is referenced {} times", path, links, count));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Moves entry `old_name` of `source` to `new_name` in `target`, or in `source` itself if `target`
+/// is `None`. An existing file at `new_name` is replaced, or an existing empty directory if a
+/// directory is moved. A moved directory gets `target` as its new parent. Everything is checked
+/// before anything is changed, so a failed move leaves both directories untouched.
+fn move_entry(source: &mut RamdiskINode, target: Option<&mut RamdiskINode>, old_name: &str, new_name: &str) -> Result<()> {
+    let target_type = target.as_ref().map_or(source.metadata.type_, |target| target.metadata.type_);
+    if source.metadata.type_ != FileType::Directory || target_type != FileType::Directory {
+        return Err(FsError::NotDirectory);
+    }
+
+    if [old_name, new_name].iter().any(|&name| name == "." || name == "..") {
+        return Err(FsError::InvalidArgument);
+    }
+
+    let file = source.children.get(old_name).ok_or(FsError::EntryNotFound)?.clone();
+
+    // A directory can't be moved into itself or one of its descendants.
+    if let Some(ref target) = target {
+        if is_inode(&file, target) || is_ancestor(&file, target, source) {
+            return Err(FsError::InvalidArgument);
+        }
+    }
+
+    let is_directory = file.read().metadata.type_ == FileType::Directory;
+
+    let target_children = target.as_ref().map_or(&source.children, |target| &target.children);
+    let mut replaces_directory = false;
+
+    if let Some(existing) = target_children.get(new_name) {
+        if Arc::ptr_eq(existing, &file) {
+            return Ok(());
+        }
+
+        if is_inode(existing, source) {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+
+        let existing = existing.read();
+        replaces_directory = existing.metadata.type_ == FileType::Directory;
+
+        if replaces_directory && !is_directory {
+            return Err(FsError::IsDirectory);
+        } else if !replaces_directory && is_directory {
+            return Err(FsError::NotDirectory);
+        } else if !existing.children.is_empty() {
+            return Err(FsError::DirectoryNotEmpty);
+        }
+    }
+
+    let now = clock::now();
+    source.children.remove(old_name);
+    source.touch_modified(now);
+
+    let moves_parent = target.is_some() && is_directory;
+    if moves_parent {
+        source.metadata.links -= 1;
+    }
+
+    let target = match target {
+        Some(target) => target,
+        None => source,
+    };
+
+    if let Some(replaced) = target.children.insert(String::from(new_name), file.clone()) {
+        let mut replaced = replaced.write();
+        replaced.metadata.links -= 1;
+        replaced.metadata.change_time = now;
+    }
+
+    if replaces_directory {
+        target.metadata.links -= 1;
+    }
+
+    if moves_parent {
+        target.metadata.links += 1;
+    }
+
+    target.touch_modified(now);
+
+    let mut file = file.write();
+    if moves_parent {
+        file.parent_ref = target.self_ref.clone();
+    }
+
+    file.metadata.change_time = now;
+
+    Ok(())
+}
+
+/// Returns whether `inode` is an ancestor of the already locked `directory`. `source` is also
+/// locked, so its parent is read from the guard instead of locking it again.
+fn is_ancestor(inode: &Arc<LockedRamdiskINode>, directory: &RamdiskINode, source: &RamdiskINode) -> bool {
+    let mut current = match directory.parent_ref.upgrade() {
+        Some(parent) => parent,
+        None => return false,
+    };
+
+    loop {
+        if Arc::ptr_eq(&current, inode) {
+            return true;
+        }
+
+        let parent = if is_inode(&current, source) {
+            source.parent_ref.upgrade()
+        } else {
+            current.read().parent_ref.upgrade()
+        };
+
+        match parent {
+            // The root directory is its own parent.
+            Some(ref parent) if Arc::ptr_eq(parent, &current) => return false,
+            Some(parent) => current = parent,
+            None => return false,
+        }
+    }
+}
+
+/// Write locks two different inodes. The inode with the lowest address is always locked first,
+/// so two moves between the same directories in opposite directions can't deadlock.
+fn write_both<'a>(first: &'a LockedRamdiskINode, second: &'a LockedRamdiskINode)
+    -> (PreemptRwLockWriteGuard<'a, RamdiskINode>, PreemptRwLockWriteGuard<'a, RamdiskINode>) {
+    if (first as *const LockedRamdiskINode) < (second as *const LockedRamdiskINode) {
+        let first = first.write();
+        (first, second.write())
+    } else {
+        let second = second.write();
+        (first.write(), second)
+    }
+}
+
+/// Returns whether `inode` is the inode behind the already locked `locked`.
+fn is_inode(inode: &Arc<LockedRamdiskINode>, locked: &RamdiskINode) -> bool {
+    locked.self_ref.upgrade().map_or(false, |self_ref| Arc::ptr_eq(inode, &self_ref))
+}
+
+/// Returns the address of `inode`, which identifies it while walking the tree.
+fn inode_address(inode: &Arc<LockedRamdiskINode>) -> usize {
+    &**inode as *const LockedRamdiskINode as usize
 }
 
 impl FileSystem for Ramdisk {
@@ -58,8 +287,7 @@ impl FileSystem for Ramdisk {
 
     fn metadata(&self) -> FileSystemMetadata {
         FileSystemMetadata {
-            // TODO: Inaccurate file counting
-            files: self.root.read().children.len(),
+            files: self.inode_ids.live_count(),
             files_free: 0,
             max_name_len: 0,
         }
@@ -67,34 +295,175 @@ impl FileSystem for Ramdisk {
 }
 
 /// A locked version of `RamdiskINode` so it can be written to without mutability.
-pub type LockedRamdiskINode = RwLock<RamdiskINode>;
+pub type LockedRamdiskINode = PreemptRwLock<RamdiskINode>;
 
 /// An inode implementation for `Ramdisk`
 pub struct RamdiskINode {
     parent_ref: Weak<LockedRamdiskINode>,
     self_ref: Weak<LockedRamdiskINode>,
     children: BTreeMap<String, Arc<LockedRamdiskINode>>,
+    /// The metadata, except for the access time, which is in `access_time`.
     metadata: INodeMetadata,
-    content: Vec<u8>,
+    /// The access time, kept apart from `metadata` so a read can update it while holding only the
+    /// read lock of the inode.
+    access_time: PreemptMutex<Timespec>,
+    content: SparseContent,
     filesystem: Weak<Ramdisk>,
 }
 
+/// The content of a file, stored as chunks of `CONTENT_CHUNK_SIZE` bytes. Chunks that were never
+/// written are not stored and read as zeroes, so a sparse file only uses memory for the chunks
+/// that were written to, not for the holes in between.
+struct SparseContent {
+    chunks: BTreeMap<usize, Box<[u8]>>,
+    len: usize,
+}
+
+impl SparseContent {
+    fn new() -> SparseContent {
+        SparseContent {
+            chunks: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Reads at most `buf.len()` bytes at `offset`, returns the amount of bytes read.
+    fn read(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let start = self.len.min(offset);
+        let end = self.len.min(offset.saturating_add(buf.len()));
+        let mut position = start;
+
+        while position < end {
+            let chunk_offset = position % CONTENT_CHUNK_SIZE;
+            let len = cmp::min(CONTENT_CHUNK_SIZE - chunk_offset, end - position);
+            let dst = &mut buf[position - start..position - start + len];
+
+            match self.chunks.get(&(position / CONTENT_CHUNK_SIZE)) {
+                Some(chunk) => dst.copy_from_slice(&chunk[chunk_offset..chunk_offset + len]),
+                None => {
+                    for byte in dst {
+                        *byte = 0;
+                    }
+                }
+            }
+
+            position += len;
+        }
+
+        end - start
+    }
+
+    /// Writes `buf` at `offset`, allocating the chunks it touches. The caller checks that the end
+    /// fits in a `usize`.
+    fn write(&mut self, offset: usize, buf: &[u8]) {
+        let mut written = 0;
+
+        while written < buf.len() {
+            let position = offset + written;
+            let chunk_offset = position % CONTENT_CHUNK_SIZE;
+            let len = cmp::min(CONTENT_CHUNK_SIZE - chunk_offset, buf.len() - written);
+
+            let chunk = self.chunks.entry(position / CONTENT_CHUNK_SIZE)
+                .or_insert_with(|| vec![0; CONTENT_CHUNK_SIZE].into_boxed_slice());
+            chunk[chunk_offset..chunk_offset + len].copy_from_slice(&buf[written..written + len]);
+
+            written += len;
+        }
+
+        self.len = cmp::max(self.len, offset + buf.len());
+    }
+
+    /// Changes the length to `len`. Growing adds a hole, shrinking frees the chunks past the end
+    /// and zeroes the rest of the last chunk, so growing again reads zeroes there.
+    fn resize(&mut self, len: usize) {
+        if len < self.len {
+            let first_unused = (len + CONTENT_CHUNK_SIZE - 1) / CONTENT_CHUNK_SIZE;
+            let unused: Vec<usize> = self.chunks.range(first_unused..).map(|(&index, _)| index).collect();
+            for index in unused {
+                self.chunks.remove(&index);
+            }
+
+            if let Some(chunk) = self.chunks.get_mut(&(len / CONTENT_CHUNK_SIZE)) {
+                for byte in &mut chunk[len % CONTENT_CHUNK_SIZE..] {
+                    *byte = 0;
+                }
+            }
+        }
+
+        self.len = len;
+    }
+}
+
+impl RamdiskINode {
+    /// Writes `buf` to the content at `offset`, growing the content if needed. Fails if the end of
+    /// the write doesn't fit in a `usize` or lies past the maximum file size.
+    fn write_content(&mut self, offset: usize, buf: &[u8]) -> Result<()> {
+        let end = offset.checked_add(buf.len()).ok_or(FsError::InvalidArgument)?;
+        self.check_size(end)?;
+
+        self.content.write(offset, buf);
+        self.touch_modified(clock::now());
+
+        Ok(())
+    }
+
+    /// Returns the amount of bytes allocated for the content, a multiple of `CONTENT_CHUNK_SIZE`.
+    /// Holes in sparse files are not allocated.
+    pub fn capacity(&self) -> usize {
+        self.content.chunks.len() * CONTENT_CHUNK_SIZE
+    }
+
+    /// Overwrites the link count without changing any directory entry, so tests can check that
+    /// `Ramdisk::check` notices the mismatch.
+    #[cfg(feature = "testutil")]
+    pub fn set_links(&mut self, links: usize) {
+        self.metadata.links = links;
+    }
+
+    /// Checks that the content of this inode may grow to `size` bytes.
+    fn check_size(&self, size: usize) -> Result<()> {
+        if size > self.max_file_size() {
+            return Err(FsError::FileTooLarge);
+        }
+
+        Ok(())
+    }
+
+    fn max_file_size(&self) -> usize {
+        self.filesystem.upgrade()
+            .map_or(DEFAULT_MAX_FILE_SIZE, |filesystem| filesystem.max_file_size)
+    }
+
+    /// Updates the modification and change time after the content of the inode has changed.
+    fn touch_modified(&mut self, now: Timespec) {
+        self.metadata.modification_time = now;
+        self.metadata.change_time = now;
+    }
+}
+
+impl Drop for RamdiskINode {
+    /// Returns the inode id to the filesystem once the last reference to the inode is gone.
+    fn drop(&mut self) {
+        if let Some(filesystem) = self.filesystem.upgrade() {
+            filesystem.inode_ids.free(self.metadata.inode);
+        }
+    }
+}
+
 impl INode for LockedRamdiskINode {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        // Reads only share the lock, so they don't wait for each other.
         let file = self.read();
 
         if file.metadata.type_ == FileType::Directory {
             return Err(FsError::IsDirectory)
         }
 
-        let start = file.content.len().min(offset);
-        let end = file.content.len().min(offset + buf.len());
+        let len = file.content.read(offset, buf);
 
-        let src = &file.content[start..end];
+        *file.access_time.lock() = clock::now();
 
-        buf[0..src.len()].copy_from_slice(src);
-
-        Ok(src.len())
+        Ok(len)
     }
 
     fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
@@ -104,12 +473,21 @@ impl INode for LockedRamdiskINode {
             return Err(FsError::IsDirectory);
         }
 
-        let content = &mut file.content;
-        if offset + buf.len() > content.len() {
-            content.resize(offset + buf.len(), 0);
+        file.write_content(offset, buf)?;
+
+        Ok(buf.len())
+    }
+
+    fn append(&self, buf: &[u8]) -> Result<usize> {
+        // The size is read under the same write lock, so no other write can get in between.
+        let mut file = self.write();
+
+        if file.metadata.type_ == FileType::Directory {
+            return Err(FsError::IsDirectory);
         }
 
-        content[offset..offset + buf.len()].copy_from_slice(buf);
+        let offset = file.content.len;
+        file.write_content(offset, buf)?;
 
         Ok(buf.len())
     }
@@ -117,19 +495,20 @@ impl INode for LockedRamdiskINode {
     fn metadata(&self) -> Result<INodeMetadata> {
         let file = self.read();
         let mut metadata = file.metadata;
-        metadata.size = file.content.len();
+        metadata.size = file.content.len;
+        metadata.access_time = *file.access_time.lock();
         Ok(metadata)
     }
 
     fn set_metadata(&self, metadata: INodeMetadata) -> Result<()> {
         let mut file = self.write();
 
-        file.metadata.access_time = metadata.access_time;
+        *file.access_time.lock() = metadata.access_time;
         file.metadata.modification_time = metadata.modification_time;
-        file.metadata.change_time = metadata.change_time;
         file.metadata.permissions = metadata.permissions;
         file.metadata.uid = metadata.uid;
         file.metadata.gid = metadata.gid;
+        file.metadata.change_time = clock::now();
 
         Ok(())
     }
@@ -149,7 +528,9 @@ impl INode for LockedRamdiskINode {
             return Err(FsError::NotFile);
         }
 
-        file.content.resize(new_len, 0);
+        file.check_size(new_len)?;
+        file.content.resize(new_len);
+        file.touch_modified(clock::now());
 
         Ok(())
     }
@@ -161,28 +542,38 @@ impl INode for LockedRamdiskINode {
             return Err(FsError::NotDirectory);
         }
 
+        let filesystem = file.filesystem.upgrade().ok_or(FsError::EntryNotFound)?;
+        let now = clock::now();
+
         let new_file = Arc::new(LockedRamdiskINode::new(RamdiskINode {
             parent_ref: file.self_ref.clone(),
             self_ref: Weak::default(),
             children: BTreeMap::new(),
             metadata: INodeMetadata {
-                inode: next_inode(),
+                inode: filesystem.inode_ids.alloc(),
                 size: 0,
-                access_time: Timespec { sec: 0, nanosec: 0 },
-                modification_time: Timespec { sec: 0, nanosec: 0 },
-                change_time: Timespec { sec: 0, nanosec: 0 },
+                access_time: now,
+                modification_time: now,
+                change_time: now,
                 type_,
                 permissions: permissions as u16,
                 links: 1,
                 uid: 0,
                 gid: 0,
             },
-            content: Vec::new(),
+            access_time: PreemptMutex::new(now),
+            content: SparseContent::new(),
             filesystem: file.filesystem.clone(),
         }));
 
         new_file.write().self_ref = Arc::downgrade(&new_file);
         file.children.insert(String::from(name), new_file.clone());
+        file.touch_modified(now);
+
+        // The `..` entry of a new directory links back to this directory.
+        if type_ == FileType::Directory {
+            file.metadata.links += 1;
+        }
 
         Ok(new_file)
     }
@@ -204,8 +595,11 @@ impl INode for LockedRamdiskINode {
             return Err(FsError::EntryExists);
         }
 
+        let now = clock::now();
         file.children.insert(String::from(name), other.self_ref.upgrade().unwrap());
+        file.touch_modified(now);
         other.metadata.links += 1;
+        other.metadata.change_time = now;
 
         Ok(())
     }
@@ -228,25 +622,36 @@ impl INode for LockedRamdiskINode {
             return Err(FsError::DirectoryNotEmpty);
         }
 
-        other.write().metadata.links -= 1;
+        let now = clock::now();
+        let is_directory = {
+            let mut other = other.write();
+            other.metadata.links -= 1;
+            other.metadata.change_time = now;
+            other.metadata.type_ == FileType::Directory
+        };
+
+        if is_directory {
+            file.metadata.links -= 1;
+        }
+
         file.children.remove(name);
+        file.touch_modified(now);
 
         Ok(())
     }
 
+    /// Moves `old_name` to `target/new_name`, replacing an existing file or empty directory at the
+    /// new location. Both directories stay locked during the move, so it happens atomically.
     fn move_(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
-        let file = self.find(old_name)?;
-        target.link(new_name, &file)?;
+        let target = target.downcast_ref::<LockedRamdiskINode>()
+            .ok_or(FsError::NotSameFileSystem)?;
 
-        // If unlinking the original file goes wrong, we need to revert changes by unlinking the new
-        // location.
-        if let Err(err) = self.unlink(old_name) {
-            target.unlink(new_name)?;
-
-            Err(err)
-        } else {
-            Ok(())
+        if core::ptr::eq(self, target) {
+            return move_entry(&mut self.write(), None, old_name, new_name);
         }
+
+        let (mut source, mut target) = write_both(self, target);
+        move_entry(&mut source, Some(&mut *target), old_name, new_name)
     }
 
     fn find(&self, name: &str) -> Result<Arc<dyn INode>> {
@@ -291,8 +696,3 @@ impl INode for LockedRamdiskINode {
         self
     }
 }
-
-fn next_inode() -> usize {
-    static NEXT_INODE: AtomicUsize = AtomicUsize::new(1);
-    NEXT_INODE.fetch_add(1, Ordering::SeqCst)
-}
\ No newline at end of file
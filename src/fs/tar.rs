@@ -0,0 +1,273 @@
+//! Unpacks ustar archives, which is the format of the initial ramdisk.
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::str;
+
+use fs::vfs::{FileType, FsError, INode};
+use util::math::align_up_to;
+
+const BLOCK_SIZE: usize = 512;
+
+const NAME: usize = 0;
+const NAME_SIZE: usize = 100;
+const MODE: usize = 100;
+const SIZE: usize = 124;
+const CHECKSUM: usize = 148;
+const TYPE: usize = 156;
+const LINK_NAME: usize = 157;
+const MAGIC: usize = 257;
+const PREFIX: usize = 345;
+const PREFIX_SIZE: usize = 155;
+
+const TYPE_FILE: u8 = b'0';
+/// Old archivers mark regular files with a null byte instead of `'0'`.
+const TYPE_FILE_OLD: u8 = 0;
+const TYPE_SYMLINK: u8 = b'2';
+const TYPE_DIRECTORY: u8 = b'5';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TarError {
+    /// The archive ends in the middle of a header or file.
+    Truncated,
+    /// A header is not a ustar header, or its checksum does not match.
+    InvalidHeader,
+    /// A path or link target is not valid UTF-8, or a path contains a `..` component.
+    InvalidName,
+    Fs(FsError),
+}
+
+impl From<FsError> for TarError {
+    fn from(error: FsError) -> TarError {
+        TarError::Fs(error)
+    }
+}
+
+/// The type of an archive entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind<'a> {
+    File,
+    Directory,
+    SymbolicLink(&'a str),
+    /// Hard links, devices, fifos and other types that are not supported.
+    Other(u8),
+}
+
+/// An entry of a ustar archive, which borrows its contents from the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub path: String,
+    pub kind: EntryKind<'a>,
+    pub mode: u32,
+    pub data: &'a [u8],
+}
+
+/// A ustar archive in memory. Iterating over it returns its entries in order, stopping at the
+/// end-of-archive marker or the first error.
+pub struct Archive<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Archive<'a> {
+    pub fn new(data: &'a [u8]) -> Archive<'a> {
+        Archive {
+            data,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry<'a>>, TarError> {
+        let header = self.block(self.offset)?;
+
+        // The archive ends with two blocks of zeros. Some archivers stop after the first one.
+        if is_zero(header) {
+            let at_end = self.data.len() == self.offset + BLOCK_SIZE;
+            if at_end || is_zero(self.block(self.offset + BLOCK_SIZE)?) {
+                return Ok(None);
+            }
+
+            return Err(TarError::InvalidHeader);
+        }
+
+        if &header[MAGIC..MAGIC + 5] != b"ustar" || parse_octal(&header[CHECKSUM..CHECKSUM + 8])? != checksum(header) {
+            return Err(TarError::InvalidHeader);
+        }
+
+        let size = parse_octal(&header[SIZE..SIZE + 12])?;
+        let data_start = self.offset + BLOCK_SIZE;
+        let data = self.data.get(data_start..data_start + size).ok_or(TarError::Truncated)?;
+
+        let kind = match header[TYPE] {
+            TYPE_FILE | TYPE_FILE_OLD => EntryKind::File,
+            TYPE_DIRECTORY => EntryKind::Directory,
+            TYPE_SYMLINK => EntryKind::SymbolicLink(parse_str(&header[LINK_NAME..LINK_NAME + NAME_SIZE])?),
+            other => EntryKind::Other(other),
+        };
+
+        self.offset = data_start + align_up_to(size, BLOCK_SIZE);
+
+        Ok(Some(Entry {
+            path: path(header)?,
+            kind,
+            mode: parse_octal(&header[MODE..MODE + 8])? as u32,
+            data,
+        }))
+    }
+
+    fn block(&self, offset: usize) -> Result<&'a [u8], TarError> {
+        self.data.get(offset..offset + BLOCK_SIZE).ok_or(TarError::Truncated)
+    }
+}
+
+impl<'a> Iterator for Archive<'a> {
+    type Item = Result<Entry<'a>, TarError>;
+
+    fn next(&mut self) -> Option<Result<Entry<'a>, TarError>> {
+        if self.done {
+            return None;
+        }
+
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Unpacks the ustar archive `archive` into the directory `root`, creating missing parent
+/// directories along the way. Entries other than files, directories and symbolic links are
+/// skipped. Paths with a `..` component are rejected, so nothing is written outside `root`.
+/// Existing entries of the same type are overwritten, entries of another type are an error.
+/// Returns the amount of entries that were created.
+pub fn unpack(archive: &[u8], root: &Arc<dyn INode>) -> Result<usize, TarError> {
+    let mut entries = 0;
+
+    for entry in Archive::new(archive) {
+        let entry = entry?;
+        let mut components = entry.path.split('/').filter(|c| !c.is_empty() && *c != ".");
+        if entry.path.split('/').any(|c| c == "..") {
+            return Err(TarError::InvalidName);
+        }
+
+        let name = match components.next_back() {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let mut parent = root.clone();
+        for component in components {
+            parent = find_or_create_dir(&parent, component)?;
+        }
+
+        match entry.kind {
+            EntryKind::File => {
+                create_or_truncate(&parent, name, FileType::File, entry.mode)?.write_all(0, entry.data)?;
+            }
+            EntryKind::Directory => {
+                find_or_create_dir(&parent, name)?;
+            }
+            EntryKind::SymbolicLink(target) => {
+                create_or_truncate(&parent, name, FileType::SymbolicLink, 0o777)?.write_all(0, target.as_bytes())?;
+            }
+            EntryKind::Other(_) => continue,
+        }
+
+        entries += 1;
+    }
+
+    Ok(entries)
+}
+
+/// Reads the ustar archive stored in the file `archive` and unpacks it like `unpack`.
+pub fn unpack_file(archive: &Arc<dyn INode>, root: &Arc<dyn INode>) -> Result<usize, TarError> {
+    let mut data = Vec::new();
+    archive.read_until_eof_into(&mut data)?;
+    unpack(&data, root)
+}
+
+/// Directories can appear in an archive after their contents, or not at all, so they are created
+/// when they are first needed.
+fn find_or_create_dir(parent: &Arc<dyn INode>, name: &str) -> Result<Arc<dyn INode>, TarError> {
+    match parent.find(name) {
+        Ok(inode) => match inode.metadata()?.type_ {
+            FileType::Directory => Ok(inode),
+            _ => Err(TarError::Fs(FsError::NotDirectory)),
+        },
+        Err(FsError::EntryNotFound) => Ok(parent.create(name, FileType::Directory, 0o755)?),
+        Err(error) => Err(TarError::Fs(error)),
+    }
+}
+
+/// Creates the entry `name` in `parent`, or empties it if it already exists with the same type.
+/// Replacing an entry of another type could throw away a whole directory, so that is an error.
+fn create_or_truncate(parent: &Arc<dyn INode>, name: &str, type_: FileType, mode: u32) -> Result<Arc<dyn INode>, TarError> {
+    match parent.find(name) {
+        Ok(inode) => {
+            if inode.metadata()?.type_ != type_ {
+                return Err(TarError::Fs(FsError::EntryExists));
+            }
+
+            inode.resize(0)?;
+            Ok(inode)
+        }
+        Err(FsError::EntryNotFound) => Ok(parent.create(name, type_, mode)?),
+        Err(error) => Err(TarError::Fs(error)),
+    }
+}
+
+/// Returns the full path of an entry, which ustar splits into a prefix and a name.
+fn path(header: &[u8]) -> Result<String, TarError> {
+    let prefix = parse_str(&header[PREFIX..PREFIX + PREFIX_SIZE])?;
+    let name = parse_str(&header[NAME..NAME + NAME_SIZE])?;
+
+    let mut path = String::from(prefix);
+    if !path.is_empty() {
+        path.push('/');
+    }
+
+    path.push_str(name);
+    Ok(path)
+}
+
+/// The checksum is the sum of all header bytes, with the checksum field itself taken as spaces.
+fn checksum(header: &[u8]) -> usize {
+    header.iter().enumerate().map(|(i, &b)| {
+        if (CHECKSUM..CHECKSUM + 8).contains(&i) { usize::from(b' ') } else { usize::from(b) }
+    }).sum()
+}
+
+/// Parses a null or space terminated octal number.
+fn parse_octal(field: &[u8]) -> Result<usize, TarError> {
+    let mut value: usize = 0;
+    for &b in field.iter().skip_while(|&&b| b == b' ') {
+        match b {
+            b'0'..=b'7' => value = value.checked_mul(8)
+                .map(|value| value + usize::from(b - b'0'))
+                .ok_or(TarError::InvalidHeader)?,
+            0 | b' ' => break,
+            _ => return Err(TarError::InvalidHeader),
+        }
+    }
+
+    Ok(value)
+}
+
+fn is_zero(block: &[u8]) -> bool {
+    block.iter().all(|&b| b == 0)
+}
+
+fn parse_str(field: &[u8]) -> Result<&str, TarError> {
+    let len = field.iter().position(|&b| b == 0).unwrap_or_else(|| field.len());
+    str::from_utf8(&field[..len]).map_err(|_| TarError::InvalidName)
+}
\ No newline at end of file
@@ -0,0 +1,108 @@
+//! Helpers to build filesystems for tests. `tree!` describes a tree of directories, files and
+//! symbolic links, and builds it on a new `Ramdisk`:
+//!
+//! ```ignore
+//! let root = tree! {
+//!     dir "etc" {
+//!         file "hostname" = "os\n";
+//!     }
+//!     symlink "hostname" -> "etc/hostname";
+//! };
+//! ```
+//!
+//! Building fails the boot test if an entry can't be created, like a failing `kassert!`.
+
+use alloc::sync::Arc;
+
+use fs::mount::MountFS;
+use fs::ramdisk::Ramdisk;
+use fs::vfs::{FileSystem, FileType, INode};
+
+/// Builds a filesystem on a new `Ramdisk`, see the module documentation.
+#[macro_export]
+macro_rules! tree {
+    (@entries $dir:expr;) => {};
+    (@entries $dir:expr; file $name:literal = $content:expr; $($rest:tt)*) => {
+        $dir.file($name, $content);
+        $crate::tree!(@entries $dir; $($rest)*);
+    };
+    (@entries $dir:expr; symlink $name:literal -> $target:expr; $($rest:tt)*) => {
+        $dir.symlink($name, $target);
+        $crate::tree!(@entries $dir; $($rest)*);
+    };
+    (@entries $dir:expr; dir $name:literal { $($entries:tt)* } $($rest:tt)*) => {
+        {
+            let child = $dir.dir($name);
+            $crate::tree!(@entries child; $($entries)*);
+        }
+        $crate::tree!(@entries $dir; $($rest)*);
+    };
+    ($($entries:tt)*) => {{
+        let builder = $crate::fs::testutil::FsBuilder::new();
+        {
+            let root = builder.root();
+            $crate::tree!(@entries root; $($entries)*);
+        }
+        builder.build()
+    }};
+}
+
+/// Builds a filesystem on a new `Ramdisk`. `tree!` is usually shorter.
+pub struct FsBuilder {
+    ramdisk: Arc<Ramdisk>,
+}
+
+impl FsBuilder {
+    pub fn new() -> FsBuilder {
+        FsBuilder {
+            ramdisk: Ramdisk::new(),
+        }
+    }
+
+    /// Returns the root directory, to add entries to.
+    pub fn root(&self) -> DirBuilder {
+        DirBuilder {
+            inode: self.ramdisk.root(),
+        }
+    }
+
+    /// Returns the filesystem, wrapped in a `MountFS` so other filesystems can be mounted on it.
+    pub fn build(self) -> Arc<MountFS> {
+        MountFS::new(self.ramdisk)
+    }
+}
+
+/// A directory of a `FsBuilder`.
+pub struct DirBuilder {
+    inode: Arc<dyn INode>,
+}
+
+impl DirBuilder {
+    /// Creates a subdirectory and returns it.
+    pub fn dir(&self, name: &str) -> DirBuilder {
+        DirBuilder {
+            inode: self.create(name, FileType::Directory),
+        }
+    }
+
+    /// Creates a file that contains `content`.
+    pub fn file(&self, name: &str, content: impl AsRef<[u8]>) -> &DirBuilder {
+        let content = content.as_ref();
+        let written = self.create(name, FileType::File).write_at(0, content);
+        crate::kassert_eq!(written, Ok(content.len()), "Could not write {}", name);
+        self
+    }
+
+    /// Creates a symbolic link to `target`.
+    pub fn symlink(&self, name: &str, target: &str) -> &DirBuilder {
+        let written = self.create(name, FileType::SymbolicLink).write_at(0, target.as_bytes());
+        crate::kassert_eq!(written, Ok(target.len()), "Could not write {}", name);
+        self
+    }
+
+    fn create(&self, name: &str, type_: FileType) -> Arc<dyn INode> {
+        let created = self.inode.create(name, type_, 0o777);
+        crate::kassert_eq!(created.as_ref().err(), None, "Could not create {}", name);
+        created.unwrap()
+    }
+}
\ No newline at end of file
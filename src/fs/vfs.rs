@@ -4,8 +4,13 @@ use alloc::vec::Vec;
 use core::any::Any;
 use core::str;
 
+use task::wait_queue::WaitQueue;
+
 pub type Result<T> = core::result::Result<T, FsError>;
 
+/// The amount of bytes `read_until_eof_into` asks for with every read.
+const READ_CHUNK_SIZE: usize = 512;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum FsError {
     Unsupported,
@@ -17,6 +22,13 @@ pub enum FsError {
     NotSameFileSystem,
     DirectoryNotEmpty,
     Busy,
+    InvalidArgument,
+    /// The file would grow larger than the filesystem allows.
+    FileTooLarge,
+    /// There is no space left on the device.
+    NoSpace,
+    /// The current user isn't allowed to access the inode.
+    PermissionDenied,
 }
 
 /// Abstract representation for any file system object, such as a directory or file.
@@ -27,7 +39,30 @@ pub trait INode: Any {
     /// Write bytes at `offset` from `buf`, returns the amount of bytes written.
     fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize>;
 
-    // fn poll(&self) -> Result<PollStatus, FsError>;
+    /// Write bytes from `buf` at the end of the file, returns the amount of bytes written. The
+    /// default implementation is not atomic: another writer can extend the file between reading
+    /// the size and writing. Inodes that can be shared should override this.
+    fn append(&self, buf: &[u8]) -> Result<usize> {
+        self.write_at(self.metadata()?.size, buf)
+    }
+
+    /// Check if the inode is ready to be read from or written to without blocking. By default an
+    /// inode is always ready, inodes backed by a buffer (like pipes or serial input) should
+    /// override this to reflect the state of the buffer.
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            readable: true,
+            writable: true,
+            error: false,
+        })
+    }
+
+    /// Returns the queue to wait on until the inode may have become readable, used by
+    /// `read_blocking`. Inodes that read from a device that can have no data yet, like the serial
+    /// port, should return a queue that is woken when data arrives.
+    fn wait_queue(&self) -> Option<&WaitQueue> {
+        None
+    }
 
     /// Returns the metadata of the inode.
     fn metadata(&self) -> Result<INodeMetadata>;
@@ -35,10 +70,12 @@ pub trait INode: Any {
     /// Sets the metadata of an inode.
     fn set_metadata(&self, metadata: INodeMetadata) -> Result<()>;
 
-    /// Sync data and metadata to the drive.
+    /// Writes back everything `sync_data` does, and the metadata of the inode itself, like its
+    /// size and times. Filesystem-wide structures are left to `FileSystem::sync`.
     fn sync_all(&self) -> Result<()>;
 
-    /// Only sync data to the drive.
+    /// Writes back the blocks holding the contents of the inode that were changed, so they are on
+    /// the drive once this returns. Filesystems that aren't backed by a drive have nothing to do.
     fn sync_data(&self) -> Result<()>;
 
     /// Resize the file to the amount of bytes given.
@@ -65,7 +102,11 @@ pub trait INode: Any {
     /// Get the name of the nth entry if this inode is a directory.
     fn get_entry(&self, index: usize) -> Result<String>;
 
-    // fn io_control(&self, cmd: u32, data: usize) -> Result<()>;
+    /// Perform a device specific command, see `fs::ioctl` for the available commands. Returns a
+    /// command specific value.
+    fn io_control(&self, _cmd: u32, _arg: usize) -> Result<usize> {
+        Err(FsError::Unsupported)
+    }
 
     /// Get the parent filesystem this inode belongs to.
     fn filesystem(&self) -> Arc<dyn FileSystem>;
@@ -95,6 +136,72 @@ impl dyn INode {
         Ok(files)
     }
 
+    /// Writes all of `buf` at `offset`, calling `write_at` until everything is written. Fails with
+    /// `NoSpace` if the inode stops accepting bytes.
+    pub fn write_all(&self, mut offset: usize, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write_at(offset, buf)? {
+                0 => return Err(FsError::NoSpace),
+                len => {
+                    offset += len;
+                    buf = &buf[len..];
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a symbolic link called `name` in this directory, that points to `target`.
+    pub fn symlink(&self, name: &str, target: &str) -> Result<Arc<dyn INode>> {
+        let link = self.create(name, FileType::SymbolicLink, 0o777)?;
+        link.write_all(0, target.as_bytes())?;
+        Ok(link)
+    }
+
+    /// Reads into `buf` like `read_at`, but waits on the wait queue of the inode until at least one
+    /// byte was read, instead of returning 0. Inodes without a wait queue are read only once.
+    pub fn read_blocking(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let queue = match self.wait_queue() {
+            Some(queue) if !buf.is_empty() => queue,
+            _ => return self.read_at(offset, buf),
+        };
+
+        queue.wait_until(|| match self.read_at(offset, buf) {
+            Ok(0) => None,
+            result => Some(result),
+        })
+    }
+
+    /// Reads from offset 0 until a read returns 0 bytes and appends everything to `buf`. Returns
+    /// the amount of bytes read. Unlike reading `metadata().size` bytes, this also works for
+    /// devices without a meaningful size. Devices that ignore the offset are read until they have
+    /// no more data, so a device that never runs out, like `/dev/zero`, is read forever.
+    pub fn read_until_eof_into(&self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let mut offset = 0;
+
+        loop {
+            buf.resize(start + offset + READ_CHUNK_SIZE, 0);
+
+            let len = match self.read_at(offset, &mut buf[start + offset..]) {
+                Ok(len) => len,
+                Err(error) => {
+                    buf.truncate(start + offset);
+                    return Err(error);
+                }
+            };
+
+            buf.truncate(start + offset + len);
+
+            if len == 0 {
+                return Ok(offset);
+            }
+
+            offset += len;
+        }
+    }
+
     /// Resolve a path starting from this inode (except when the path starts with /, then it starts
     /// from the root inode) and follow symbolic links at most `follow_times` times.
     pub fn resolve_follow(&self, path: &str, mut follow_times: usize) -> Result<Arc<dyn INode>> {
@@ -152,7 +259,8 @@ impl dyn INode {
 }
 
 pub trait FileSystem {
-    /// Synchronize everything in this filesystem
+    /// Writes back everything that was changed in this filesystem, including filesystem-wide
+    /// structures like the superblock or the allocation tables, like `sync_all` on every inode.
     fn sync(&self) -> Result<()>;
 
     /// Get the root inode of this filesystem
@@ -223,6 +331,19 @@ pub struct FileSystemMetadata {
     pub max_name_len: usize,
 }
 
+/// The readiness of an inode, returned by `INode::poll`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PollStatus {
+    /// A read will return data without blocking
+    pub readable: bool,
+
+    /// A write will accept data without blocking
+    pub writable: bool,
+
+    /// An error condition is pending
+    pub error: bool,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct Timespec {
     pub sec: i64,
@@ -4,11 +4,27 @@ use bit_field::BitField;
 use flagset::{flags, FlagSet};
 use spin::Once;
 
-use x86_64::instructions::tables::{DescriptorTablePointer, load_gdt, load_tss};
+use x86_64::instructions::tables::{DescriptorTablePointer, lgdt, load_tss};
 use x86_64::registers::segment::{CodeSegment, DataSegment};
+use memory::Stack;
 use x86_64::VirtualAddress;
 
-static TSS: Once<TaskStateSegment> = Once::new();
+/// The index in the interrupt stack table of the stack used by the double fault handler.
+pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
+
+/// The index in the interrupt stack table of the stack used by the page fault handler, so a page
+/// fault caused by a kernel stack overflow doesn't immediately escalate to a double fault.
+pub const PAGE_FAULT_IST_INDEX: usize = 1;
+
+/// The index in the interrupt stack table of the stack used by the NMI handler. An NMI can arrive
+/// at any point, including while the current stack is in an inconsistent state.
+pub const NMI_IST_INDEX: usize = 2;
+
+/// The interrupt stack table indices that are in use.
+pub const IST_INDICES: [usize; 3] = [DOUBLE_FAULT_IST_INDEX, PAGE_FAULT_IST_INDEX, NMI_IST_INDEX];
+
+// The TSS is mutable so the interrupt stacks can be replaced once the memory manager is up.
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 static GDT: Once<GlobalDescriptorTable> = Once::new();
 
 flags! {
@@ -77,6 +93,12 @@ impl GlobalDescriptorTable {
         )
     }
 
+    /// Loads this table into the CPU. The CPU keeps using the table after this returns, so it
+    /// has to live forever, which the `'static` receiver makes the compiler check.
+    pub fn load(&'static self) {
+        unsafe { lgdt(&self.pointer()) };
+    }
+
     pub fn add_entry(&mut self, entry: Descriptor) -> SegmentSelector {
         let index = match entry {
             Descriptor::UserSegment(value) => self.push(value),
@@ -146,19 +168,20 @@ pub fn init() {
     let mut data_selector = SegmentSelector::default();
     let mut tss_selector = SegmentSelector::default();
 
-    let tss = TSS.call_once(|| {
-        let mut tss = TaskStateSegment::new();
-        tss.interrupt_stack_table[0] = {
-            // TODO: Don't allocate this on the stack.
-            const STACK_SIZE: usize = 4096;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+    let tss = unsafe {
+        // Bootstrap stacks for the interrupt stack table, used until `set_interrupt_stack` is
+        // called with stacks that have a guard page.
+        const STACK_SIZE: usize = 4096;
+        static mut STACKS: [[u8; STACK_SIZE]; IST_INDICES.len()] = [[0; STACK_SIZE]; IST_INDICES.len()];
 
-            let stack_start = unsafe { STACK.as_mut_ptr() as u64 };
+        for (stack, &index) in STACKS.iter_mut().zip(IST_INDICES.iter()) {
+            let stack_start = stack.as_mut_ptr() as u64;
             let stack_end = stack_start + STACK_SIZE as u64;
-            VirtualAddress::new(stack_end)
-        };
-        tss
-    });
+            TSS.interrupt_stack_table[index] = VirtualAddress::new(stack_end);
+        }
+
+        &TSS
+    };
 
     let gdt = GDT.call_once(|| {
         let mut gdt = GlobalDescriptorTable::new();
@@ -171,7 +194,7 @@ pub fn init() {
     });
 
     crate::kprintln!("Loading GDT...");
-    load_gdt(gdt.pointer());
+    gdt.load();
 
     crate::kprintln!("Loading segment selectors...");
     CodeSegment::write(code_selector);
@@ -186,4 +209,17 @@ pub fn init() {
 
     crate::kprintln!("Loading TSS...");
     load_tss(tss_selector);
+}
+
+/// Replaces the bootstrap stack at `index` in the interrupt stack table with `stack`. The stack
+/// should have a guard page, so an overflow in the handler can't silently corrupt other memory.
+pub fn set_interrupt_stack(index: usize, stack: Stack) {
+    x86_64::instructions::interrupts::with_disabled(|| unsafe {
+        TSS.interrupt_stack_table[index] = stack.top();
+    });
+}
+
+/// Returns the top of the stack at `index` in the interrupt stack table.
+pub fn interrupt_stack(index: usize) -> VirtualAddress {
+    unsafe { TSS.interrupt_stack_table[index] }
 }
\ No newline at end of file
@@ -1,10 +1,14 @@
 use flagset::{FlagSet, flags};
-use interrupts::StackFrame;
+use debug::gdbstub;
+use interrupts::{ExceptionFrame, ExceptionFrameWithCode, InterruptContext};
+use memory::guard_page;
+use memory::vma;
 use panic::PanicType;
+use watchdog;
 use x86_64::registers::control::Cr2;
 
 flags! {
-    enum PageFaultErrorCode: u32 {
+    pub enum PageFaultErrorCode: u32 {
         ProtectionViolation,
         Write,
         UserSpace,
@@ -13,9 +17,87 @@ flags! {
     }
 }
 
+/// The vector of the page fault exception.
+const PAGE_FAULT: u8 = 0x0e;
+
+/// The vectors of the exceptions that can interrupt code holding any lock: the NMI and the double
+/// fault. The panic path doesn't lock anything after these.
+const EMERGENCY_EXCEPTIONS: [u8; 2] = [0x02, 0x08];
+
+/// The vectors of the exceptions that push a segment selector error code.
+const SELECTOR_EXCEPTIONS: [u8; 4] = [0x0a, 0x0b, 0x0c, 0x0d];
+
+/// The error code pushed by an exception, decoded according to the exception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The cause of a page fault.
+    PageFault(FlagSet<PageFaultErrorCode>),
+    /// The selector that caused a TSS, segment or general protection fault. `None` if the fault
+    /// isn't related to a selector.
+    Selector(Option<SelectorErrorCode>),
+    /// The error code of the other exceptions, which is always zero or undocumented.
+    Other(u64),
+}
+
+impl ErrorCode {
+    /// Decodes the error code `code` that was pushed by the exception with vector `vector`.
+    pub fn decode(vector: u8, code: u64) -> ErrorCode {
+        if vector == PAGE_FAULT {
+            ErrorCode::PageFault(FlagSet::new_truncated(code as u32))
+        } else if SELECTOR_EXCEPTIONS.contains(&vector) {
+            if code == 0 {
+                ErrorCode::Selector(None)
+            } else {
+                ErrorCode::Selector(Some(SelectorErrorCode::decode(code)))
+            }
+        } else {
+            ErrorCode::Other(code)
+        }
+    }
+}
+
+/// The descriptor table a selector error code refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorTable {
+    Gdt,
+    Idt,
+    Ldt,
+}
+
+/// A segment selector error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelectorErrorCode {
+    /// Whether the exception was caused by something external to the program, like an interrupt.
+    pub external: bool,
+    pub table: DescriptorTable,
+    /// The index of the descriptor in `table`.
+    pub index: u16,
+}
+
+impl SelectorErrorCode {
+    pub fn decode(code: u64) -> SelectorErrorCode {
+        let table = match (code >> 1) & 0b11 {
+            0b00 => DescriptorTable::Gdt,
+            0b10 => DescriptorTable::Ldt,
+            _ => DescriptorTable::Idt,
+        };
+
+        SelectorErrorCode {
+            external: code & 1 != 0,
+            table,
+            index: ((code >> 3) & 0x1fff) as u16,
+        }
+    }
+}
+
 macro_rules! exception_handler {
     ($index:expr, $func:ident, $name:expr) => {
-        pub extern "C" fn $func(stack_frame: &StackFrame) {
+        pub extern "C" fn $func(stack_frame: &mut ExceptionFrame) {
+            let _context = InterruptContext::enter();
+            if EMERGENCY_EXCEPTIONS.contains(&$index) {
+                crate::panic::enter_emergency();
+            }
+
             crate::panic::panic(PanicType::KernelException{
                 name: $name,
                 stack_frame,
@@ -27,20 +109,22 @@ macro_rules! exception_handler {
 
 macro_rules! exception_handler_error_code {
     ($index:expr, $func:ident, $name:expr) => {
-        pub extern "C" fn $func(stack_frame: &StackFrame) {
+        pub extern "C" fn $func(stack_frame: &mut ExceptionFrameWithCode) {
+            let _context = InterruptContext::enter();
+            if EMERGENCY_EXCEPTIONS.contains(&$index) {
+                crate::panic::enter_emergency();
+            }
+
             crate::panic::panic(PanicType::KernelException{
                 name: $name,
                 stack_frame,
-                additional_info: Some(format_args!("\x1b[37mError Code: \x1b[97m{}", stack_frame.error_code)),
+                additional_info: Some(format_args!("\x1b[37mError Code: \x1b[97m{:?}", stack_frame.code())),
             })
         }
     };
 }
 
 exception_handler!(0x00, divide_by_zero_handler, "Divide-by-zero Error");
-exception_handler!(0x01, debug_handler, "Debug");
-exception_handler!(0x02, non_maskable_handler, "Non-maskable Interrupt");
-exception_handler!(0x03, breakpoint_handler, "Breakpoint");
 exception_handler!(0x04, overflow_handler, "Overflow");
 exception_handler!(0x05, bound_range_handler, "Bound Range Exceeded");
 exception_handler!(0x06, invalid_opcode_handler, "Invalid Opcode");
@@ -57,14 +141,94 @@ exception_handler!(0x13, simd_floating_point_handler, "SIMD Floating-Point Excep
 exception_handler!(0x14, virtualization_handler, "Virtualization Exception");
 exception_handler_error_code!(0x1e, security_handler, "Security Exception");
 
-pub extern "C" fn page_fault_handler(stack_frame: &StackFrame) {
+/// Maps the page if the fault is in a demand paged region, after which `iretq` retries the
+/// faulting instruction. Panics for every other fault.
+pub extern "C" fn page_fault_handler(stack_frame: &mut ExceptionFrameWithCode) {
+    let _context = InterruptContext::enter();
+    let address = Cr2::read();
+
+    if let Some(guard_page) = guard_page::find(address) {
+        #[cfg(feature = "testutil")]
+        {
+            if crate::interrupts::testutil::catch(stack_frame, "Kernel Stack Overflow") {
+                return;
+            }
+        }
+
+        crate::panic::panic(PanicType::KernelException{
+            name: "Kernel Stack Overflow",
+            stack_frame,
+            additional_info: Some(format_args!(
+                "\x1b[37mStack: \x1b[97m{:?} - {:?}\n\x1b[37mGuard Page: \x1b[97m{:?}\n\x1b[37mAddress: \x1b[97m{:?}",
+                guard_page.stack_bottom,
+                guard_page.stack_top,
+                guard_page.page.start_address(),
+                address,
+            )),
+        });
+    }
+
+    let error = match stack_frame.code() {
+        ErrorCode::PageFault(code) => match vma::handle_page_fault(address, code) {
+            Ok(()) => return,
+            Err(error) => error,
+        },
+        _ => unreachable!(),
+    };
+
     crate::panic::panic(PanicType::KernelException{
         name: "Page Fault",
         stack_frame,
         additional_info: Some(format_args!(
-            "\x1b[37mError Code: \x1b[97m{:#?}\n\x1b[37mAddress: \x1b[97m{:?}",
-            FlagSet::<PageFaultErrorCode>::new_truncated(stack_frame.error_code as u32),
-            Cr2::read(),
+            "\x1b[37mError Code: \x1b[97m{:#?}\n\x1b[37mAddress: \x1b[97m{:?}\n\x1b[37mNot Resolved: \x1b[97m{:?}",
+            stack_frame.code(),
+            address,
+            error,
         )),
     });
+}
+
+/// Lets the watchdog check the heartbeat if it raised the NMI. Panics for every other NMI.
+pub extern "C" fn non_maskable_handler(stack_frame: &mut ExceptionFrame) {
+    let _context = InterruptContext::enter();
+    if watchdog::handle_nmi(stack_frame) {
+        return;
+    }
+
+    crate::panic::enter_emergency();
+    crate::panic::panic(PanicType::KernelException{
+        name: "Non-maskable Interrupt",
+        stack_frame,
+        additional_info: None,
+    });
+}
+
+pub extern "C" fn debug_handler(stack_frame: &mut ExceptionFrame) {
+    let _context = InterruptContext::enter();
+
+    #[cfg(feature = "testutil")]
+    {
+        if crate::interrupts::testutil::count_single_step() {
+            return;
+        }
+    }
+
+    if !gdbstub::handle_exception(stack_frame) {
+        crate::panic::panic(PanicType::KernelException{
+            name: "Debug",
+            stack_frame,
+            additional_info: None,
+        });
+    }
+}
+
+pub extern "C" fn breakpoint_handler(stack_frame: &mut ExceptionFrame) {
+    let _context = InterruptContext::enter();
+    if !gdbstub::handle_exception(stack_frame) {
+        crate::panic::panic(PanicType::KernelException{
+            name: "Breakpoint",
+            stack_frame,
+            additional_info: None,
+        });
+    }
 }
\ No newline at end of file
@@ -3,7 +3,7 @@ use core::mem::size_of;
 use bit_field::BitField;
 
 use gdt::SegmentSelector;
-use x86_64::instructions::tables::DescriptorTablePointer;
+use x86_64::instructions::tables::{DescriptorTablePointer, lidt};
 use x86_64::registers::segment::CodeSegment;
 use x86_64::VirtualAddress;
 
@@ -29,6 +29,12 @@ impl InterruptDescriptorTable {
             (size_of::<InterruptDescriptorTable>() - 1) as u16,
         )
     }
+
+    /// Loads this table into the CPU. The CPU keeps using the table after this returns, so it
+    /// has to live forever, which the `'static` receiver makes the compiler check.
+    pub fn load(&'static self) {
+        unsafe { lidt(&self.pointer()) };
+    }
 }
 
 #[repr(C)]
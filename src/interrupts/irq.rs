@@ -0,0 +1,118 @@
+use driver::apic;
+use driver::pic::{IRQ_COUNT, PIC_1_OFFSET, PICS};
+use interrupts::{InterruptContext, StackFrame};
+use task::scheduler;
+use util::irq_lock::IrqLock;
+
+/// The IRQ of the PIT tick, which also drives the scheduler.
+const TIMER_IRQ: u8 = 0;
+
+/// The handlers drivers registered for every IRQ.
+static IRQ_HANDLERS: IrqLock<[Option<fn()>; IRQ_COUNT as usize]> = IrqLock::new([None; IRQ_COUNT as usize]);
+
+/// The interrupt controller that delivers the IRQs.
+static CONTROLLER: IrqLock<InterruptController> = IrqLock::new(InterruptController::Pic);
+
+/// The interrupt controllers IRQs can be delivered by. Both deliver IRQ 'n' at vector
+/// `PIC_1_OFFSET + n`, but they need a different end of interrupt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptController {
+    /// The legacy 8259 PICs.
+    Pic,
+    /// The local APIC and IO APIC.
+    Apic,
+}
+
+/// Returns the vector IRQ `irq` is delivered at, which is the same for both interrupt controllers.
+pub fn irq_vector(irq: u8) -> u8 {
+    PIC_1_OFFSET + irq
+}
+
+/// Returns the interrupt controller that currently delivers the IRQs.
+pub fn controller() -> InterruptController {
+    *CONTROLLER.lock()
+}
+
+/// Switches to `controller` and unmasks every IRQ that has a handler on it. Called by the driver
+/// of the controller once it took over.
+pub fn set_controller(controller: InterruptController) {
+    *CONTROLLER.lock() = controller;
+
+    let handlers = *IRQ_HANDLERS.lock();
+    for irq in (0..IRQ_COUNT).filter(|&irq| handlers[irq as usize].is_some()) {
+        unmask(irq);
+    }
+}
+
+/// Registers `handler` to be called when IRQ `irq` is raised and unmasks the IRQ. Replaces the
+/// handler that was registered before, if any.
+pub fn register_irq(irq: u8, handler: fn()) {
+    assert!(irq < IRQ_COUNT, "Invalid IRQ: {}", irq);
+
+    IRQ_HANDLERS.lock()[irq as usize] = Some(handler);
+    unmask(irq);
+}
+
+/// Removes the handler of IRQ `irq`. The IRQ stays unmasked, it is only acknowledged from now on.
+pub fn unregister_irq(irq: u8) {
+    assert!(irq < IRQ_COUNT, "Invalid IRQ: {}", irq);
+
+    IRQ_HANDLERS.lock()[irq as usize] = None;
+}
+
+/// Allows IRQ `irq` to be raised on the current interrupt controller.
+fn unmask(irq: u8) {
+    match controller() {
+        InterruptController::Pic => PICS.lock().unmask(irq),
+        InterruptController::Apic => {
+            if let Some(io_apic) = apic::IO_APIC.lock().as_mut() {
+                io_apic.unmask(apic::isa_irq_to_gsi(irq));
+            }
+        },
+    }
+}
+
+/// Notifies the current interrupt controller that the interrupt with id `id` is handled.
+pub fn end_of_interrupt(id: u8) {
+    match controller() {
+        InterruptController::Pic => PICS.lock().end_of_interrupt(id),
+        InterruptController::Apic => apic::eoi(),
+    }
+}
+
+/// The handler of every IRQ. Calls the handler that was registered for the IRQ and notifies the
+/// interrupt controller afterwards. Spurious interrupts are ignored.
+pub extern "C" fn irq_handler(stack_frame: &mut StackFrame) {
+    let _context = InterruptContext::enter();
+    let id = stack_frame.kind as u8;
+
+    if controller() == InterruptController::Pic && PICS.lock().check_spurious(id) {
+        return;
+    }
+
+    // The lock is released before calling the handler, so handlers can register other handlers.
+    let handler = IRQ_HANDLERS.lock()[(id - PIC_1_OFFSET) as usize];
+    if let Some(handler) = handler {
+        handler();
+    }
+
+    end_of_interrupt(id);
+
+    if id == irq_vector(TIMER_IRQ) {
+        scheduler::timer_tick(stack_frame);
+    }
+}
+
+/// The handler of the local APIC timer.
+pub extern "C" fn apic_timer_handler(_stack_frame: &mut StackFrame) {
+    let _context = InterruptContext::enter();
+
+    apic::timer_interrupt();
+    apic::eoi();
+}
+
+/// The handler of spurious interrupts, of the local APIC and of the disabled PICs. These are not
+/// in service, so they must not get an end of interrupt.
+pub extern "C" fn spurious_handler(_stack_frame: &mut StackFrame) {
+    let _context = InterruptContext::enter();
+}
\ No newline at end of file
@@ -1,16 +1,53 @@
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use spin::Once;
 
+use interrupts::exceptions::ErrorCode;
 use interrupts::idt::InterruptDescriptorTable;
-use x86_64::instructions::tables::load_idt;
 use x86_64::VirtualAddress;
 use x86_64::registers::segment::{CodeSegment, DataSegment};
+use gdt;
 use gdt::SegmentSelector;
 
 pub mod idt;
 pub mod exceptions;
+pub mod irq;
+#[cfg(feature = "testutil")]
+pub mod testutil;
+
+pub use interrupts::irq::register_irq;
 
 static IDT: Once<InterruptDescriptorTable> = Once::new();
 
+/// The amount of interrupt handlers that are currently running. Larger than 1 for nested
+/// interrupts, like a page fault in an interrupt handler.
+static INTERRUPT_DEPTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether the CPU is currently executing an interrupt handler.
+pub fn in_interrupt() -> bool {
+    INTERRUPT_DEPTH.load(Ordering::SeqCst) > 0
+}
+
+/// Marks the current code as running in interrupt context until it is dropped. Created at the
+/// start of every interrupt handler.
+pub struct InterruptContext {
+    _private: (),
+}
+
+impl InterruptContext {
+    pub fn enter() -> InterruptContext {
+        INTERRUPT_DEPTH.fetch_add(1, Ordering::SeqCst);
+        InterruptContext { _private: () }
+    }
+}
+
+impl Drop for InterruptContext {
+    fn drop(&mut self) {
+        INTERRUPT_DEPTH.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 macro_rules! push_registers {
     () => {
         asm!("push rax
@@ -55,7 +92,10 @@ macro_rules! pop_registers {
 
 #[macro_export]
 macro_rules! idt_handler {
-    ($kind: expr, $name: ident) => {{
+    ($kind: expr, $name: ident) => {
+        idt_handler!($kind, $name, StackFrame)
+    };
+    ($kind: expr, $name: ident, $frame: ty) => {{
         #[naked]
         extern "C" fn wrapper() -> ! {
             unsafe {
@@ -64,7 +104,7 @@ macro_rules! idt_handler {
                 push_registers!();
                 asm!("mov rdi, rsp
                       call $0"
-                      :: "i" ($name as extern "C" fn(&StackFrame))
+                      :: "i" ($name as extern "C" fn(&mut $frame))
                       : "rdi" : "intel");
                 pop_registers!();
                 asm!("add rsp, 16
@@ -88,7 +128,7 @@ macro_rules! idt_handler_error_code {
                 push_registers!();
                 asm!("mov rdi, rsp
                       call $0"
-                      :: "i" ($name as extern "C" fn(&StackFrame))
+                      :: "i" ($name as extern "C" fn(&mut ExceptionFrameWithCode))
                       : "rdi" : "intel");
                 pop_registers!();
                 asm!("add rsp, 16
@@ -102,8 +142,12 @@ macro_rules! idt_handler_error_code {
     }};
 }
 
+/// The registers of the interrupted code, as pushed by the `idt_handler` wrappers and the CPU.
+/// `error_code` is only meaningful for the exceptions that push one, the wrappers of all other
+/// interrupts push 0 in its place. Exception handlers get an `ExceptionFrame` or an
+/// `ExceptionFrameWithCode` instead, so it's clear which one applies.
 #[repr(C)]
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct StackFrame {
     pub rbp: u64,
     pub r15: u64,
@@ -143,33 +187,109 @@ impl StackFrame {
     }
 }
 
+/// The stack frame of an exception that doesn't push an error code.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct ExceptionFrame {
+    frame: StackFrame,
+}
+
+impl Deref for ExceptionFrame {
+    type Target = StackFrame;
+
+    fn deref(&self) -> &StackFrame {
+        &self.frame
+    }
+}
+
+impl DerefMut for ExceptionFrame {
+    fn deref_mut(&mut self) -> &mut StackFrame {
+        &mut self.frame
+    }
+}
+
+/// The stack frame of an exception that pushes an error code.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct ExceptionFrameWithCode {
+    frame: StackFrame,
+}
+
+impl ExceptionFrameWithCode {
+    /// Returns the error code, decoded according to the exception that pushed it.
+    pub fn code(&self) -> ErrorCode {
+        ErrorCode::decode(self.frame.kind as u8, self.frame.error_code)
+    }
+}
+
+impl Deref for ExceptionFrameWithCode {
+    type Target = StackFrame;
+
+    fn deref(&self) -> &StackFrame {
+        &self.frame
+    }
+}
+
+impl DerefMut for ExceptionFrameWithCode {
+    fn deref_mut(&mut self) -> &mut StackFrame {
+        &mut self.frame
+    }
+}
+
 pub fn init() {
     let idt = IDT.call_once(|| {
         let mut idt = InterruptDescriptorTable::new();
         use interrupts::exceptions::*;
-        idt.set_handler(0x00, idt_handler!(0x00, divide_by_zero_handler));
-        idt.set_handler(0x01, idt_handler!(0x01, debug_handler));
-        idt.set_handler(0x02, idt_handler!(0x02, non_maskable_handler));
-        idt.set_handler(0x03, idt_handler!(0x03, breakpoint_handler));
-        idt.set_handler(0x04, idt_handler!(0x04, overflow_handler));
-        idt.set_handler(0x05, idt_handler!(0x05, bound_range_handler));
-        idt.set_handler(0x06, idt_handler!(0x06, invalid_opcode_handler));
-        idt.set_handler(0x07, idt_handler!(0x07, device_not_available_handler));
-        idt.set_handler(0x08, idt_handler_error_code!(0x08, double_fault_handler)).set_stack_index(0);
+        idt.set_handler(0x00, idt_handler!(0x00, divide_by_zero_handler, ExceptionFrame));
+        idt.set_handler(0x01, idt_handler!(0x01, debug_handler, ExceptionFrame));
+        idt.set_handler(0x02, idt_handler!(0x02, non_maskable_handler, ExceptionFrame))
+            .set_stack_index(gdt::NMI_IST_INDEX as u16);
+        idt.set_handler(0x03, idt_handler!(0x03, breakpoint_handler, ExceptionFrame));
+        idt.set_handler(0x04, idt_handler!(0x04, overflow_handler, ExceptionFrame));
+        idt.set_handler(0x05, idt_handler!(0x05, bound_range_handler, ExceptionFrame));
+        idt.set_handler(0x06, idt_handler!(0x06, invalid_opcode_handler, ExceptionFrame));
+        idt.set_handler(0x07, idt_handler!(0x07, device_not_available_handler, ExceptionFrame));
+        idt.set_handler(0x08, idt_handler_error_code!(0x08, double_fault_handler))
+            .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX as u16);
         idt.set_handler(0x0a, idt_handler_error_code!(0x0a, invalid_tss_handler));
         idt.set_handler(0x0b, idt_handler_error_code!(0x0b, segment_not_present_handler));
         idt.set_handler(0x0c, idt_handler_error_code!(0x0c, stack_segment_handler));
         idt.set_handler(0x0d, idt_handler_error_code!(0x0d, general_protection_handler));
-        idt.set_handler(0x0e, idt_handler_error_code!(0x0e, page_fault_handler));
-        idt.set_handler(0x10, idt_handler!(0x10, x87_floating_point_handler));
+        idt.set_handler(0x0e, idt_handler_error_code!(0x0e, page_fault_handler))
+            .set_stack_index(gdt::PAGE_FAULT_IST_INDEX as u16);
+        idt.set_handler(0x10, idt_handler!(0x10, x87_floating_point_handler, ExceptionFrame));
         idt.set_handler(0x11, idt_handler_error_code!(0x11, alignment_check_handler));
-        idt.set_handler(0x12, idt_handler!(0x12, machine_check_handler));
-        idt.set_handler(0x13, idt_handler!(0x13, simd_floating_point_handler));
-        idt.set_handler(0x14, idt_handler!(0x14, virtualization_handler));
+        idt.set_handler(0x12, idt_handler!(0x12, machine_check_handler, ExceptionFrame));
+        idt.set_handler(0x13, idt_handler!(0x13, simd_floating_point_handler, ExceptionFrame));
+        idt.set_handler(0x14, idt_handler!(0x14, virtualization_handler, ExceptionFrame));
         idt.set_handler(0x1e, idt_handler_error_code!(0x1e, security_handler));
+
+        use interrupts::irq::*;
+        idt.set_handler(0x20, idt_handler!(0x20, irq_handler));
+        idt.set_handler(0x21, idt_handler!(0x21, irq_handler));
+        idt.set_handler(0x22, idt_handler!(0x22, irq_handler));
+        idt.set_handler(0x23, idt_handler!(0x23, irq_handler));
+        idt.set_handler(0x24, idt_handler!(0x24, irq_handler));
+        idt.set_handler(0x25, idt_handler!(0x25, irq_handler));
+        idt.set_handler(0x26, idt_handler!(0x26, irq_handler));
+        idt.set_handler(0x27, idt_handler!(0x27, irq_handler));
+        idt.set_handler(0x28, idt_handler!(0x28, irq_handler));
+        idt.set_handler(0x29, idt_handler!(0x29, irq_handler));
+        idt.set_handler(0x2a, idt_handler!(0x2a, irq_handler));
+        idt.set_handler(0x2b, idt_handler!(0x2b, irq_handler));
+        idt.set_handler(0x2c, idt_handler!(0x2c, irq_handler));
+        idt.set_handler(0x2d, idt_handler!(0x2d, irq_handler));
+        idt.set_handler(0x2e, idt_handler!(0x2e, irq_handler));
+        idt.set_handler(0x2f, idt_handler!(0x2f, irq_handler));
+        idt.set_handler(0x30, idt_handler!(0x30, apic_timer_handler));
+
+        // The disabled PICs can still raise spurious interrupts for IRQ 7 and 15.
+        idt.set_handler(0xe7, idt_handler!(0xe7, spurious_handler));
+        idt.set_handler(0xef, idt_handler!(0xef, spurious_handler));
+        idt.set_handler(0xff, idt_handler!(0xff, spurious_handler));
         idt
     });
 
     crate::kprintln!("Loading IDT...");
-    load_idt(idt.pointer());
+    idt.load();
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+//! Lets the boot tests provoke CPU exceptions, like a stack overflow, in a thread of their own.
+//! Instead of panicking, the exception handler records the fault and ends that thread. Debug
+//! exceptions can be counted instead, to test the trap flag.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use interrupts::StackFrame;
+use memory::guard_page::{self, GuardPage};
+use task::scheduler;
+use util::irq_lock::IrqLock;
+use x86_64::VirtualAddress;
+use x86_64::instructions::read_rsp;
+
+/// The id of the thread that called `catch_faults` plus one, or 0 if no thread did.
+static CATCHING_THREAD: AtomicU64 = AtomicU64::new(0);
+
+static CAUGHT_FAULT: IrqLock<Option<CaughtFault>> = IrqLock::new(None);
+
+/// Whether debug exceptions are counted in `SINGLE_STEPS` instead of handled.
+static COUNTING_STEPS: AtomicBool = AtomicBool::new(false);
+
+static SINGLE_STEPS: AtomicU64 = AtomicU64::new(0);
+
+/// A fault that was caught instead of causing a panic.
+#[derive(Debug, Clone, Copy)]
+pub struct CaughtFault {
+    /// The name the fault would have been reported with, like "Kernel Stack Overflow".
+    pub name: &'static str,
+    /// The stack of the thread that faulted.
+    pub stack: GuardPage,
+    /// The stack the exception handler ran on, `None` if it has no guard page.
+    pub handler_stack: Option<GuardPage>,
+}
+
+/// Makes the next fault of the current thread end the thread instead of panicking. The fault can
+/// be inspected with `take_caught_fault`.
+pub fn catch_faults() {
+    if let Some(id) = scheduler::current_id() {
+        CATCHING_THREAD.store(id + 1, Ordering::SeqCst);
+    }
+}
+
+/// Returns the fault that was caught since the last call, if any.
+pub fn take_caught_fault() -> Option<CaughtFault> {
+    CAUGHT_FAULT.lock().take()
+}
+
+/// Called by an exception handler before it panics. If the faulting thread called `catch_faults`,
+/// records the fault and changes `stack_frame` to resume the thread in `scheduler::exit` on a reset
+/// stack, and returns true. The handler then has to return instead of panicking.
+pub fn catch(stack_frame: &mut StackFrame, name: &'static str) -> bool {
+    let thread = CATCHING_THREAD.load(Ordering::SeqCst);
+    if thread == 0 || scheduler::current_id().map(|id| id + 1) != Some(thread) {
+        return false;
+    }
+
+    // After an overflow the stack pointer can be in the guard page already.
+    let stack_pointer = stack_frame.stack_pointer;
+    let stack = match guard_page::stack_containing(stack_pointer).or_else(|| guard_page::find(stack_pointer)) {
+        Some(stack) => stack,
+        None => return false,
+    };
+
+    *CAUGHT_FAULT.lock() = Some(CaughtFault {
+        name,
+        stack,
+        handler_stack: guard_page::stack_containing(read_rsp()),
+    });
+    CATCHING_THREAD.store(0, Ordering::SeqCst);
+
+    // Nothing on the stack of the thread is used again, so it can start over at the top. The
+    // stack pointer is aligned like at the start of a function.
+    stack_frame.instruction_pointer = VirtualAddress::new(exit_thread as u64);
+    stack_frame.stack_pointer = VirtualAddress::new(stack.stack_top.as_u64() - 8);
+    true
+}
+
+/// Makes the debug exception handler count debug exceptions and return, instead of handing them
+/// to the GDB stub or panicking.
+pub fn count_single_steps(enabled: bool) {
+    COUNTING_STEPS.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns the amount of debug exceptions that were counted.
+pub fn single_steps() -> u64 {
+    SINGLE_STEPS.load(Ordering::SeqCst)
+}
+
+/// Called by the debug exception handler first. Counts the exception and returns true if
+/// `count_single_steps` is enabled, the handler then has to return.
+pub fn count_single_step() -> bool {
+    if !COUNTING_STEPS.load(Ordering::SeqCst) {
+        return false;
+    }
+
+    SINGLE_STEPS.fetch_add(1, Ordering::SeqCst);
+    true
+}
+
+extern "C" fn exit_thread() -> ! {
+    scheduler::exit()
+}
\ No newline at end of file
@@ -0,0 +1,38 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+use util::irq_lock::IrqLock;
+use util::ring_buffer::RingBuffer;
+
+/// The size of the kernel message log. The oldest messages are discarded once it is full.
+pub const KLOG_SIZE: usize = 64 * 1024;
+
+/// Everything printed with `kprint!`, including the ANSI escape codes.
+static KLOG: IrqLock<RingBuffer<[u8; KLOG_SIZE]>> = IrqLock::new(RingBuffer::new([0; KLOG_SIZE]));
+
+/// Appends `args` to the kernel message log. Called by `kprint!`. The message is dropped if the
+/// log is locked, which can only happen in an interrupt handler that interrupted a writer.
+pub fn log(args: fmt::Arguments) {
+    if let Some(mut klog) = KLOG.try_lock() {
+        let _ = KlogWriter(&mut klog).write_fmt(args);
+    }
+}
+
+/// Returns a copy of the kernel message log, oldest messages first.
+pub fn dmesg() -> Vec<u8> {
+    let klog = KLOG.lock();
+    let mut contents = vec![0; klog.len()];
+    klog.read_at(0, &mut contents);
+    contents
+}
+
+struct KlogWriter<'a>(&'a mut RingBuffer<[u8; KLOG_SIZE]>);
+
+impl<'a> Write for KlogWriter<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.push(s.as_bytes());
+        Ok(())
+    }
+}
\ No newline at end of file
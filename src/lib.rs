@@ -16,8 +16,6 @@ extern crate bit_field;
 extern crate flagset;
 extern crate lazy_static;
 extern crate linked_list_allocator;
-/// TODO: Replace with custom structure
-extern crate multiboot2;
 extern crate spin;
 extern crate volatile;
 
@@ -25,80 +23,168 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec;
 
-use linked_list_allocator::LockedHeap;
-
 use fs::dev::DevFS;
+use fs::dev::console::ConsoleDevice;
+use fs::dev::full::FullDevice;
+use fs::dev::mem::MemDevice;
+use fs::dev::serial::SerialDevice;
 use fs::dev::zeronull::ZeroNullDevice;
 use fs::mount::MountFS;
 use fs::ramdisk::Ramdisk;
 use fs::vfs::{FileSystem, FileType, INode};
 use memory::frame::AreaFrameAllocator;
-use x86_64::{PhysicalAddress, VirtualAddress};
 use x86_64::registers::control::{Cr0, Cr0Flags};
 use x86_64::registers::msr::{EFER, EFERFlags};
-use task::context::Context;
-use memory::{HEAP_START, HEAP_SIZE};
-use memory::stack_allocator::StackAllocator;
-use memory::paging::Page;
+use memory::heap::Heap;
+use panic::PanicAction;
 
+pub mod boot;
+pub mod debug;
+pub mod clock;
 pub mod driver;
 pub mod macros;
 pub mod panic;
 pub mod interrupts;
+pub mod klog;
 pub mod x86_64;
 pub mod memory;
 pub mod fs;
 pub mod gdt;
 pub mod util;
 pub mod task;
+pub mod shell;
+pub mod time;
+pub mod watchdog;
+pub mod percpu;
+pub mod loader;
+#[cfg(feature = "testutil")]
+mod boot_tests;
 
 // TODO: Replace with custom implementation?
+/// Whether to print detailed information during boot, like the physical memory map.
+const VERBOSE_BOOT: bool = cfg!(debug_assertions);
+
+/// What to do after a panic. Automated test runs use `PanicAction::QemuExit`, so a failure ends
+/// the VM instead of leaving it halted until a timeout.
+const PANIC_ACTION: PanicAction = PanicAction::Halt;
+
+/// Whether to wait for GDB on the second serial port during boot, see `debug::gdbstub`.
+const GDB_STUB: bool = false;
+
+/// Whether to stop booting for an interactive shell on the console, see `shell::Shell`. Booting
+/// continues once the shell exits.
+const INTERACTIVE_SHELL: bool = false;
+
 /// Global heap allocator. Used for allocating things on the heap, like Vec and Box.
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Heap = memory::heap::empty_heap();
 
 /// Kernel entry function. Called from assembly boot code
 #[no_mangle]
 pub extern "C" fn kmain(multiboot_information_address: usize) -> ! {
-    driver::uart16550::UART.lock().init();
+    panic::set_action(PANIC_ACTION);
     driver::vga::WRITER.lock().clear_screen();
 
+    let serial = driver::uart16550::init();
+    if let Err(error) = serial {
+        kprintln!("Serial port unavailable ({:?}), only using the screen", error);
+    }
+
     kprintln!("\x1b[92m- \x1b[97mLoading interrupts...");
     gdt::init();
     interrupts::init();
     x86_64::instructions::interrupts::enable();
+    driver::pit::init();
+    clock::init();
 
-    kprintln!("\x1b[92m- \x1b[97mLoading multiboot information structure...");
-    let boot_info = unsafe { multiboot2::load(multiboot_information_address) };
-    let memory_map_tag = boot_info.memory_map_tag()
-        .expect("Memory map tag required");
+    if GDB_STUB {
+        kprintln!("Waiting for GDB on the second serial port...");
+        if let Err(error) = debug::gdbstub::init() {
+            kprintln!("GDB stub unavailable ({:?})", error);
+        }
+    }
 
-    let elf_sections_tag = boot_info.elf_sections_tag()
-        .expect("Elf-Sections tag required!");
+    kprintln!("\x1b[92m- \x1b[97mLoading multiboot information structure...");
+    let boot_info = unsafe { boot::init(multiboot_information_address) }
+        .expect("Invalid multiboot information structure!");
 
-    if let Some(name_tag) = boot_info.boot_loader_name_tag() {
-        kprintln!("Bootloader: {}", name_tag.name());
+    if let Some(name) = boot_info.bootloader_name() {
+        kprintln!("Bootloader: {}", name);
     }
 
-    let kernel_start = elf_sections_tag.sections().map(|s| s.start_address()).min().unwrap();
-    let kernel_end = elf_sections_tag.sections().map(|s| s.end_address()).max().unwrap();
-
     let mut frame_allocator = AreaFrameAllocator::new(
-        PhysicalAddress::new(kernel_start), PhysicalAddress::new(kernel_end),
-        PhysicalAddress::new(boot_info.start_address() as u64),
-        PhysicalAddress::new(boot_info.end_address() as u64),
-        memory_map_tag.memory_areas()
+        boot_info.kernel_start(), boot_info.kernel_end(),
+        boot_info.start_address(), boot_info.end_address(),
+        boot_info.memory_map(), boot_info.modules()
     );
 
+    if VERBOSE_BOOT {
+        memory::print_memory_map(boot_info);
+    }
+
     kprintln!("\x1b[92m- \x1b[97mInitializing memory...");
     EFER::append(EFERFlags::NoExecuteEnable);
     Cr0::append(Cr0Flags::WriteProtect);
-    let mut active_table = memory::paging::remap_kernel(&mut frame_allocator, &boot_info);
+    let mut active_table = memory::paging::remap_kernel(&mut frame_allocator, boot_info);
+    memory::paging::verify_kernel_protection(&active_table, boot_info);
+
+    #[cfg(feature = "testutil")]
+    boot_tests::before_heap(&mut active_table, &mut frame_allocator);
 
     kprintln!("Allocating heap...");
     memory::init_heap(&mut active_table, &mut frame_allocator);
 
-    kprintln!("\x1b[92m- \x1b[97mTesting filesystem...");
+    kprintln!("Setting up per-CPU data...");
+    percpu::init();
+
+    for &index in gdt::IST_INDICES.iter() {
+        let stack = memory::alloc_kernel_stack(&mut active_table, &mut frame_allocator, 4)
+            .expect("Could not allocate an interrupt stack!");
+        gdt::set_interrupt_stack(index, stack);
+    }
+
+    kprintln!("Reserving DMA pool...");
+    if let Err(error) = memory::dma::init(&mut active_table, &mut frame_allocator) {
+        kprintln!("DMA pool unavailable ({:?})", error);
+    }
+
+    if driver::framebuffer::init(boot_info, &mut active_table, &mut frame_allocator) {
+        kprintln!("Found linear framebuffer");
+    }
+
+    #[cfg(feature = "testutil")]
+    boot_tests::before_global_memory(boot_info, &mut active_table, &mut frame_allocator);
+
+    memory::init_global(active_table, frame_allocator);
+
+    kprintln!("Starting the scheduler...");
+    task::scheduler::init();
+
+    kprintln!("\x1b[92m- \x1b[97mLoading APIC...");
+    match driver::apic::init() {
+        Ok(()) => kprintln!("Using the APIC instead of the 8259 PIC"),
+        Err(error) => kprintln!("APIC unavailable ({:?}), using the 8259 PIC", error),
+    }
+
+    let watchdog_running = watchdog::init();
+    if !watchdog_running {
+        kprintln!("Watchdog unavailable without a cycle counter and the local APIC");
+    }
+
+    if VERBOSE_BOOT {
+        for device in driver::pci::enumerate() {
+            kprintln!(
+                "PCI {:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}:{:02x}",
+                device.bus, device.slot, device.function, device.vendor_id, device.device_id,
+                device.class, device.subclass
+            );
+        }
+    }
+
+    #[cfg(feature = "testutil")]
+    boot_tests::kernel(watchdog_running);
+
+    kprintln!("\x1b[92m- \x1b[97mSetting up filesystems...");
     let ramdisk = Ramdisk::new();
     {
         let inode = ramdisk.root().create("hello.txt", FileType::File, 0o777)
@@ -118,16 +204,41 @@ pub extern "C" fn kmain(multiboot_information_address: usize) -> ! {
     {
         root_ramdisk.root().create("tmp", FileType::Directory, 0o666).unwrap();
         root_ramdisk.root().create("dev", FileType::Directory, 0o666).unwrap();
+        root_ramdisk.root().create("initrd", FileType::Directory, 0o555).unwrap();
         let text_node = root_ramdisk.root().create("text.txt", FileType::File, 0o777).unwrap();
         text_node.write_at(0, b"test file").unwrap();
     }
 
+    if VERBOSE_BOOT {
+        for filesystem in [&ramdisk, &root_ramdisk].iter() {
+            if let Err(error) = filesystem.check() {
+                panic!("Ramdisk is inconsistent: {}", error);
+            }
+        }
+    }
+
     let root = MountFS::new(root_ramdisk);
     root.root().find("tmp").unwrap().mount(ramdisk).unwrap();
     let devfs = DevFS::new();
     root.root().find("dev").unwrap().mount(devfs.clone()).unwrap();
     devfs.add("null", ZeroNullDevice::new(devfs.clone(), true)).unwrap();
     devfs.add("zero", ZeroNullDevice::new(devfs.clone(), false)).unwrap();
+    devfs.add("full", FullDevice::new(devfs.clone())).unwrap();
+    devfs.add("ttyS0", SerialDevice::new(devfs.clone())).unwrap();
+    devfs.add("console", ConsoleDevice::new(devfs.clone())).unwrap();
+    devfs.add("mem", MemDevice::new(devfs.clone())).unwrap();
+
+    let initrd = Ramdisk::new();
+    if let Some(module) = boot_info.module("initrd") {
+        match fs::tar::unpack(unsafe { module.data() }, &initrd.root()) {
+            Ok(entries) => kprintln!("Unpacked {} entries from the initial ramdisk", entries),
+            Err(error) => kprintln!("Could not unpack the initial ramdisk ({:?})", error),
+        }
+    }
+    root.root().find("initrd").unwrap().mount(initrd).unwrap();
+
+    devfs.root().create("block", FileType::Directory, 0o755).unwrap();
+    devfs.add("block/zero", ZeroNullDevice::new(devfs.clone(), false)).unwrap();
 
     {
         let new_inode = root.root().find("text.txt").unwrap();
@@ -155,23 +266,17 @@ pub extern "C" fn kmain(multiboot_information_address: usize) -> ! {
     kprintln!("files /tmp: {:?}", root_inode.find("tmp").unwrap().list());
     kprintln!("files /dev: {:?}", root_inode.find("dev").unwrap().list());
 
-    kprintln!("\x1b[92m- \x1b[97mTesting context switching...");
-    let heap_end = VirtualAddress::new(HEAP_START.as_u64() + HEAP_SIZE as u64);
-    let heap_end_page = Page::containing_address(heap_end);
-    let mut stack_allocator = StackAllocator::new(Page::range_inclusive(Page(heap_end_page.0 + 1), Page(heap_end_page.0 + 101)));
-
-    let stack = stack_allocator.alloc_stack(&mut active_table, &mut frame_allocator, 4).unwrap();
-    kprintln!("stack: {:?}", stack.top());
-    let ctx = Context::new(stack.top(), test_1 as u64);
-    Context::empty().switch_to(&ctx);
+    #[cfg(feature = "testutil")]
+    boot_tests::filesystem(&root, &devfs);
 
-    // Some inspiration: https://github.com/SerenityOS/serenity/blob/de7c54545a913d72fdd2620c833beeb00a9434d7/Kernel/Task.h
-
-    x86_64::instructions::hlt_loop();
-}
+    if INTERACTIVE_SHELL {
+        kprintln!("\x1b[92m- \x1b[97mStarting shell, type 'exit' to continue booting...");
+        let console = root_inode.resolve_follow("dev/console", 0).unwrap();
+        shell::Shell::new(root_inode.clone(), console).run();
+    }
 
-extern "C" fn test_1() {
-    kprintln!("=> test 1");
+    #[cfg(feature = "testutil")]
+    boot_tests::tasks(&root, &devfs);
 
-    unsafe { asm!("int3") }
+    task::idle();
 }
\ No newline at end of file
@@ -0,0 +1,295 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp;
+
+use flagset::FlagSet;
+
+use fs::vfs::{FsError, INode};
+use memory::PAGE_SIZE;
+use memory::paging::Page;
+use memory::paging::entry::EntryFlags;
+use memory::paging::mapper::MapError;
+use task::address_space::{AddressSpace, USER_END, USER_START};
+use x86_64::VirtualAddress;
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LITTLE_ENDIAN: u8 = 1;
+const ELF_TYPE_EXECUTABLE: u16 = 2;
+const ELF_MACHINE_X86_64: u16 = 0x3e;
+
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// The largest amount of program headers that is accepted, to keep a corrupt header from making
+/// the loader read a huge table.
+const MAX_PROGRAM_HEADERS: usize = 64;
+
+/// The program header type of segments that have to be loaded into memory.
+const PT_LOAD: u32 = 1;
+
+/// Program header flags.
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// Reading the file failed.
+    Fs(FsError),
+    /// The file is not an ELF file, or it ends in the middle of a header.
+    InvalidHeader,
+    /// The file is not a little endian, 64 bit, x86-64 executable.
+    Unsupported,
+    /// A segment is invalid or lies outside of the part of the address space that belongs to the
+    /// task.
+    InvalidSegment,
+    /// A segment overlaps a page that was mapped before loading started.
+    Overlap,
+    /// There are not enough frames left for the segments.
+    OutOfMemory,
+}
+
+impl From<FsError> for ElfError {
+    fn from(error: FsError) -> ElfError {
+        ElfError::Fs(error)
+    }
+}
+
+impl From<MapError> for ElfError {
+    fn from(_: MapError) -> ElfError {
+        ElfError::OutOfMemory
+    }
+}
+
+/// A segment of an ELF file that is loaded into memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Segment {
+    /// The offset of the contents of the segment in the file.
+    pub offset: usize,
+    pub virtual_address: VirtualAddress,
+    /// The amount of bytes of the segment that are stored in the file.
+    pub file_size: usize,
+    /// The size of the segment in memory. Everything past `file_size` is zeroed, like `.bss`.
+    pub memory_size: usize,
+    /// The flags the pages of the segment are mapped with.
+    pub flags: FlagSet<EntryFlags>,
+}
+
+/// The parts of an ELF executable that are needed to load it.
+#[derive(Debug, Clone)]
+pub struct ElfFile {
+    pub entry: VirtualAddress,
+    pub segments: Vec<Segment>,
+}
+
+/// Parses the header and the loadable segments of the ELF executable in `inode`.
+pub fn parse(inode: &Arc<dyn INode>) -> Result<ElfFile, ElfError> {
+    let mut header = [0; HEADER_SIZE];
+    read_exact(inode, 0, &mut header)?;
+
+    if header[0..4] != ELF_MAGIC {
+        return Err(ElfError::InvalidHeader);
+    }
+
+    if header[4] != ELF_CLASS_64 || header[5] != ELF_DATA_LITTLE_ENDIAN
+        || read_u16(&header, 16) != ELF_TYPE_EXECUTABLE || read_u16(&header, 18) != ELF_MACHINE_X86_64 {
+        return Err(ElfError::Unsupported);
+    }
+
+    let entry = read_u64(&header, 24);
+    let program_headers_offset = read_u64(&header, 32) as usize;
+    let program_header_size = read_u16(&header, 54) as usize;
+    let program_header_count = read_u16(&header, 56) as usize;
+
+    if program_header_size != PROGRAM_HEADER_SIZE || program_header_count > MAX_PROGRAM_HEADERS {
+        return Err(ElfError::InvalidHeader);
+    }
+
+    let mut segments = Vec::new();
+    for i in 0..program_header_count {
+        let offset = i.checked_mul(PROGRAM_HEADER_SIZE)
+            .and_then(|offset| program_headers_offset.checked_add(offset))
+            .ok_or(ElfError::InvalidHeader)?;
+
+        let mut program_header = [0; PROGRAM_HEADER_SIZE];
+        read_exact(inode, offset, &mut program_header)?;
+
+        if read_u32(&program_header, 0) != PT_LOAD {
+            continue;
+        }
+
+        let segment = Segment {
+            offset: read_u64(&program_header, 8) as usize,
+            virtual_address: VirtualAddress::new(read_u64(&program_header, 16)),
+            file_size: read_u64(&program_header, 32) as usize,
+            memory_size: read_u64(&program_header, 40) as usize,
+            flags: segment_flags(read_u32(&program_header, 4)),
+        };
+
+        check_segment(&segment)?;
+        segments.push(segment);
+    }
+
+    Ok(ElfFile { entry: VirtualAddress::new(entry), segments })
+}
+
+/// Maps every loadable segment of the ELF executable in `inode` into `address_space` and returns
+/// the entry point. Segments don't have to start at a page boundary, the rest of their first and
+/// last page is zeroed. Segments that share a page share its frame, which is mapped with the
+/// permissions of all of them. On an error, every page that was mapped is unmapped again.
+pub fn load(inode: &Arc<dyn INode>, address_space: &mut AddressSpace) -> Result<VirtualAddress, ElfError> {
+    let file = parse(inode)?;
+    let mut mapped = Vec::new();
+
+    for segment in &file.segments {
+        if let Err(error) = load_segment(inode, segment, address_space, &mut mapped) {
+            for &(page, _) in &mapped {
+                address_space.unmap_user(page);
+            }
+
+            return Err(error);
+        }
+    }
+
+    Ok(file.entry)
+}
+
+/// Returns the flags to map a segment with the program header flags `flags` with. Segments are
+/// always readable, because pages can't be mapped without read access.
+pub fn segment_flags(flags: u32) -> FlagSet<EntryFlags> {
+    let mut entry_flags = FlagSet::new_truncated(0);
+
+    if flags & PF_W != 0 {
+        entry_flags |= EntryFlags::Writable;
+    }
+
+    if flags & PF_X == 0 {
+        entry_flags |= EntryFlags::NoExecute;
+    }
+
+    entry_flags
+}
+
+/// Checks that `segment` fits in the part of the address space that belongs to the task.
+fn check_segment(segment: &Segment) -> Result<(), ElfError> {
+    let start = segment.virtual_address.as_u64();
+    let end = start.checked_add(segment.memory_size as u64).ok_or(ElfError::InvalidSegment)?;
+
+    if segment.file_size > segment.memory_size || start < USER_START.as_u64() || end > USER_END.as_u64() {
+        return Err(ElfError::InvalidSegment);
+    }
+
+    segment.offset.checked_add(segment.file_size).ok_or(ElfError::InvalidSegment)?;
+    Ok(())
+}
+
+/// Returns the flags for a page that is shared by segments mapped with `a` and `b`. It is writable
+/// or executable if either of them is.
+pub fn merge_flags(a: FlagSet<EntryFlags>, b: FlagSet<EntryFlags>) -> FlagSet<EntryFlags> {
+    let mut flags = FlagSet::new_truncated(0);
+
+    if a.contains(EntryFlags::Writable) || b.contains(EntryFlags::Writable) {
+        flags |= EntryFlags::Writable;
+    }
+
+    if a.contains(EntryFlags::NoExecute) && b.contains(EntryFlags::NoExecute) {
+        flags |= EntryFlags::NoExecute;
+    }
+
+    flags
+}
+
+/// Maps the pages of `segment` and copies its contents from the file. `mapped` holds the pages
+/// earlier segments mapped and their flags. A page in there is reused, the pages this maps are
+/// added to it.
+fn load_segment(inode: &Arc<dyn INode>, segment: &Segment, address_space: &mut AddressSpace,
+                mapped: &mut Vec<(Page, FlagSet<EntryFlags>)>) -> Result<(), ElfError> {
+    if segment.memory_size == 0 {
+        return Ok(());
+    }
+
+    let start = segment.virtual_address.as_u64() as usize;
+    let file_end = start + segment.file_size;
+    let memory_end = start + segment.memory_size;
+    let first_page = Page::containing_address(segment.virtual_address);
+    let last_page = Page::containing_address(VirtualAddress::new((memory_end - 1) as u64));
+
+    for page in Page::range_inclusive(first_page, last_page) {
+        // The part of the page that belongs to the segment, and the part of that which is backed
+        // by the file. The rest of the segment is zeroed.
+        let page_start = page.start_address().as_u64() as usize;
+        let segment_from = cmp::max(start, page_start);
+        let segment_to = cmp::min(memory_end, page_start + PAGE_SIZE);
+        let file_to = cmp::min(file_end, segment_to);
+
+        let fill = |contents: &mut [u8; PAGE_SIZE]| -> Result<(), ElfError> {
+            for byte in &mut contents[segment_from - page_start..segment_to - page_start] {
+                *byte = 0;
+            }
+
+            if segment_from < file_to {
+                let offset = segment.offset + (segment_from - start);
+                read_exact(inode, offset, &mut contents[segment_from - page_start..file_to - page_start])?;
+            }
+
+            Ok(())
+        };
+
+        match mapped.iter().position(|&(mapped_page, _)| mapped_page.0 == page.0) {
+            Some(index) => {
+                let flags = merge_flags(mapped[index].1, segment.flags);
+                address_space.update_user_with(page, flags, fill)?;
+                mapped[index].1 = flags;
+            }
+            None => {
+                if address_space.translate(page).is_some() {
+                    return Err(ElfError::Overlap);
+                }
+
+                // A new page is zeroed first, so the parts outside of the segment don't leak the
+                // old contents of the frame.
+                address_space.map_user_with(page, segment.flags, |contents| {
+                    for byte in contents.iter_mut() {
+                        *byte = 0;
+                    }
+
+                    fill(contents)
+                })?;
+                mapped.push((page, segment.flags));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads exactly `buf.len()` bytes at `offset`. Fails with `ElfError::InvalidHeader` if the file
+/// ends before that, or if the bytes would end past the largest possible offset.
+fn read_exact(inode: &Arc<dyn INode>, offset: usize, buf: &mut [u8]) -> Result<(), ElfError> {
+    if offset.checked_add(buf.len()).is_none() {
+        return Err(ElfError::InvalidHeader);
+    }
+
+    let mut done = 0;
+
+    while done < buf.len() {
+        match inode.read_at(offset + done, &mut buf[done..])? {
+            0 => return Err(ElfError::InvalidHeader),
+            len => done += len,
+        }
+    }
+
+    Ok(())
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | u64::from(read_u32(bytes, offset + 4)) << 32
+}
\ No newline at end of file
@@ -0,0 +1 @@
+pub mod elf;
\ No newline at end of file
@@ -1,5 +1,8 @@
 use core::fmt;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use driver::vga::color::Color;
 
 /// Print something to the VGA Buffer. Calls `driver::vga::_print internally`. Line breaks will not
 /// be automatically added.
@@ -16,8 +19,173 @@ macro_rules! kprintln {
     ($($arg:tt)*) => ($crate::kprint!("{}\x1b[37m\n", format_args!($($arg)*)));
 }
 
-/// Internal function used by the `kprint!` macro.
+/// Prints something like `kprint!`, in the foreground color `$color` on the screen. The previous
+/// color is restored afterwards. The serial port gets the text without color.
+#[macro_export]
+macro_rules! with_color {
+    ($color:expr, $($arg:tt)*) => ($crate::macros::_print_colored($color, format_args!($($arg)*)));
+}
+
+/// Prints a line like `kprintln!`, but only the first time this call site is reached. Returns
+/// whether the line was printed.
+#[macro_export]
+macro_rules! log_once {
+    ($($arg:tt)*) => ({
+        static ONCE: $crate::macros::LogOnce = $crate::macros::LogOnce::new();
+        let first = ONCE.first();
+        if first {
+            $crate::kprintln!($($arg)*);
+        }
+
+        first
+    });
+}
+
+/// Prints a line like `kprintln!`, but at most once every `interval_ms` milliseconds for this call
+/// site. The amount of suppressed lines is appended to the next line that is printed. Returns
+/// whether the line was printed.
+#[macro_export]
+macro_rules! log_ratelimited {
+    ($interval_ms:expr, $($arg:tt)*) => ({
+        static LIMIT: $crate::macros::RateLimit = $crate::macros::RateLimit::new();
+        match LIMIT.check($interval_ms, $crate::time::uptime_ms()) {
+            Some(0) => {
+                $crate::kprintln!($($arg)*);
+                true
+            },
+            Some(suppressed) => {
+                $crate::kprintln!("{} \x1b[37m(message repeated {} times)", format_args!($($arg)*), suppressed);
+                true
+            },
+            None => false,
+        }
+    });
+}
+
+/// Asserts that an expression is true. On failure, the kernel panics with the stringified
+/// expression and an optional message, formatted like the rest of the panic screen.
+#[macro_export]
+macro_rules! kassert {
+    ($cond:expr) => ({
+        if !$cond {
+            panic!("assertion failed: \x1b[91m{}", stringify!($cond));
+        }
+    });
+    ($cond:expr, $($arg:tt)+) => ({
+        if !$cond {
+            panic!("assertion failed: \x1b[91m{}\n\x1b[37m// \x1b[97m{}", stringify!($cond), format_args!($($arg)+));
+        }
+    });
+}
+
+/// Asserts that two expressions are equal. On failure, the kernel panics with both stringified
+/// expressions and their values, and an optional message.
+#[macro_export]
+macro_rules! kassert_eq {
+    ($left:expr, $right:expr) => ({
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    panic!("assertion failed: \x1b[91m{} == {}\n\x1b[37m  left: \x1b[97m{:?}\n\x1b[37m right: \x1b[97m{:?}",
+                           stringify!($left), stringify!($right), left, right);
+                }
+            }
+        }
+    });
+    ($left:expr, $right:expr, $($arg:tt)+) => ({
+        match (&$left, &$right) {
+            (left, right) => {
+                if !(*left == *right) {
+                    panic!("assertion failed: \x1b[91m{} == {}\n\x1b[37m  left: \x1b[97m{:?}\n\x1b[37m right: \x1b[97m{:?}\n\x1b[37m// \x1b[97m{}",
+                           stringify!($left), stringify!($right), left, right, format_args!($($arg)+));
+                }
+            }
+        }
+    });
+}
+
+/// Internal function used by the `kprint!` macro. In interrupt context, the output only goes to
+/// the serial port and is dropped if the serial port is locked, because the interrupted code could
+/// be holding the lock and spinning on it would deadlock.
 pub fn _print(args: fmt::Arguments) {
+    crate::klog::log(args);
+
+    if crate::interrupts::in_interrupt() {
+        if let Some(mut uart) = crate::driver::uart16550::UART.try_lock() {
+            uart.write_fmt(args).unwrap();
+        }
+
+        return;
+    }
+
     crate::driver::vga::WRITER.lock().write_fmt(args).unwrap();
     crate::driver::uart16550::UART.lock().write_fmt(args).unwrap();
+}
+
+/// Internal function used by the `with_color!` macro. Behaves like `_print`.
+pub fn _print_colored(color: Color, args: fmt::Arguments) {
+    if crate::interrupts::in_interrupt() {
+        _print(args);
+        return;
+    }
+
+    crate::klog::log(args);
+
+    {
+        let mut writer = crate::driver::vga::WRITER.lock();
+        let mut writer = writer.color_guard();
+        writer.fg(color);
+        writer.write_fmt(args).unwrap();
+    }
+
+    crate::driver::uart16550::UART.lock().write_fmt(args).unwrap();
+}
+
+/// The state of a `log_once!` call site.
+pub struct LogOnce {
+    logged: AtomicBool,
+}
+
+impl LogOnce {
+    pub const fn new() -> LogOnce {
+        LogOnce {
+            logged: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true the first time it is called, false afterwards.
+    pub fn first(&self) -> bool {
+        !self.logged.swap(true, Ordering::SeqCst)
+    }
+}
+
+/// The state of a `log_ratelimited!` call site.
+pub struct RateLimit {
+    /// The time of the last printed line plus one, 0 if nothing was printed yet.
+    last: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl RateLimit {
+    pub const fn new() -> RateLimit {
+        RateLimit {
+            last: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Decides whether a line may be printed at time `now_ms`. Returns the amount of lines that
+    /// were suppressed since the last printed line if so, `None` if this line is suppressed too.
+    pub fn check(&self, interval_ms: u64, now_ms: u64) -> Option<u64> {
+        let last = self.last.load(Ordering::SeqCst);
+        let due = last == 0 || now_ms >= (last - 1).saturating_add(interval_ms);
+
+        // Only one of several callers that see the interval elapsed at the same time prints.
+        if due && self.last.compare_exchange(last, now_ms + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            return Some(self.suppressed.swap(0, Ordering::SeqCst));
+        }
+
+        self.suppressed.fetch_add(1, Ordering::SeqCst);
+        None
+    }
 }
\ No newline at end of file
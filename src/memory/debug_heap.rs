@@ -0,0 +1,114 @@
+//! An allocator wrapper that finds heap corruption. Every allocation is surrounded by canary bytes,
+//! which are checked when it's freed. The kernel heap is wrapped in it when the `debug_heap`
+//! feature is enabled.
+
+use alloc::alloc::{self, GlobalAlloc, Layout};
+use core::ops::Deref;
+use core::ptr;
+
+/// The value every canary byte is set to.
+pub const CANARY_BYTE: u8 = 0xca;
+
+/// The minimum amount of canary bytes on each side of an allocation.
+pub const CANARY_SIZE: usize = 16;
+
+/// Where the canaries of an allocation were overwritten.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeapCorruption {
+    /// Something wrote before the start of the allocation.
+    Underflow,
+    /// Something wrote past the end of the allocation.
+    Overflow,
+}
+
+/// Wraps the allocator `A` and puts canaries around its allocations.
+pub struct DebugHeap<A> {
+    inner: A,
+}
+
+impl<A> DebugHeap<A> {
+    pub const fn new(inner: A) -> DebugHeap<A> {
+        DebugHeap { inner }
+    }
+}
+
+impl<A: GlobalAlloc> DebugHeap<A> {
+    /// Checks the canaries of the allocation at `ptr` with layout `layout`, which has to be
+    /// allocated by this allocator.
+    ///
+    /// # Safety
+    /// `ptr` has to be returned by `alloc` of this allocator with `layout`, and not be freed yet.
+    pub unsafe fn check(&self, ptr: *const u8, layout: Layout) -> Result<(), HeapCorruption> {
+        let front = front_size(layout);
+        let intact = |start: *const u8, len: usize| (0..len).all(|i| *start.add(i) == CANARY_BYTE);
+
+        if !intact(ptr.sub(front), front) {
+            Err(HeapCorruption::Underflow)
+        } else if !intact(ptr.add(layout.size()), CANARY_SIZE) {
+            Err(HeapCorruption::Overflow)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<A> Deref for DebugHeap<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for DebugHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let outer = match outer_layout(layout) {
+            Some(outer) => outer,
+            None => return ptr::null_mut(),
+        };
+
+        let start = self.inner.alloc(outer);
+        if start.is_null() {
+            return start;
+        }
+
+        ptr::write_bytes(start, CANARY_BYTE, outer.size());
+        start.add(front_size(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Err(corruption) = self.check(ptr, layout) {
+            panic!("Heap corruption ({:?}) of the allocation at {:p} with {:?}", corruption, ptr, layout);
+        }
+
+        let outer = outer_layout(layout).expect("Freeing an allocation that was never made!");
+        self.inner.dealloc(ptr.sub(front_size(layout)), outer);
+    }
+}
+
+/// Forwards to the global allocator. Lets a `DebugHeap` check the allocations of a single user,
+/// without the `debug_heap` feature.
+pub struct GlobalHeap;
+
+unsafe impl GlobalAlloc for GlobalHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        alloc::alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        alloc::dealloc(ptr, layout)
+    }
+}
+
+/// The size of the canary in front of an allocation. At least `CANARY_SIZE`, and a multiple of
+/// the alignment so the allocation stays aligned.
+fn front_size(layout: Layout) -> usize {
+    let align = layout.align();
+    (CANARY_SIZE + align - 1) / align * align
+}
+
+/// The layout of an allocation together with its canaries.
+fn outer_layout(layout: Layout) -> Option<Layout> {
+    let size = front_size(layout).checked_add(layout.size())?.checked_add(CANARY_SIZE)?;
+    Layout::from_size_align(size, layout.align()).ok()
+}
\ No newline at end of file
@@ -0,0 +1,141 @@
+use alloc::vec::Vec;
+
+use memory::frame::{Frame, FrameAllocator};
+use memory::PAGE_SIZE;
+use memory::paging::{ActivePageTable, Page};
+use util::bitmap::Bitmap;
+use util::irq_lock::IrqLock;
+use x86_64::{PhysicalAddress, VirtualAddress};
+
+/// Legacy ISA DMA can only address the first 16 MiB of physical memory.
+pub const DMA_BOUNDARY: u64 = 0x100_0000;
+
+/// The virtual address the DMA pool is mapped at.
+pub const DMA_START: VirtualAddress = VirtualAddress::new(0x5555_5555_0000);
+
+/// The amount of pages in the DMA pool.
+pub const DMA_POOL_PAGES: usize = 64;
+
+static DMA_POOL: IrqLock<Option<DmaPool>> = IrqLock::new(None);
+
+/// An error while reserving the DMA pool.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DmaError {
+    /// The frame allocator ran out before a large enough contiguous run was found.
+    OutOfFrames,
+    /// The frame allocator handed out a frame above `DMA_BOUNDARY`.
+    AboveBoundary,
+}
+
+/// A pool of physically contiguous frames below `DMA_BOUNDARY`, reserved at boot.
+struct DmaPool {
+    start_frame: Frame,
+    used: Bitmap<[u8; DMA_POOL_PAGES / 8]>,
+}
+
+/// Reserves the DMA pool by taking physically contiguous frames from `allocator` and maps them
+/// cache-disabled at `DMA_START`. This should be called early during boot, while the frame
+/// allocator is still handing out frames below `DMA_BOUNDARY`. On an error, every frame that was
+/// taken is handed back to `allocator`.
+pub fn init<A>(active_table: &mut ActivePageTable, allocator: &mut A) -> Result<(), DmaError> where A: FrameAllocator {
+    let start_frame = reserve(allocator)?;
+
+    active_table.map_mmio(
+        Page::containing_address(DMA_START), start_frame.start_address(), DMA_POOL_PAGES * PAGE_SIZE, allocator
+    );
+
+    *DMA_POOL.lock() = Some(DmaPool {
+        start_frame,
+        used: Bitmap::new([0; DMA_POOL_PAGES / 8]),
+    });
+
+    Ok(())
+}
+
+/// Takes frames from `allocator` until it has handed out `DMA_POOL_PAGES` contiguous frames below
+/// `DMA_BOUNDARY`, and returns the first one. Frames that break the contiguous run are kept until
+/// the end, so the allocator doesn't hand them out again, and are then deallocated.
+pub fn reserve<A>(allocator: &mut A) -> Result<Frame, DmaError> where A: FrameAllocator {
+    let mut skipped = Vec::new();
+    let mut start_frame: Option<Frame> = None;
+    let mut count = 0;
+
+    let result = loop {
+        if count == DMA_POOL_PAGES {
+            break Ok(());
+        }
+
+        let frame = match allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => break Err(DmaError::OutOfFrames),
+        };
+
+        if frame.start_address().as_u64() + PAGE_SIZE as u64 > DMA_BOUNDARY {
+            skipped.push(frame);
+            break Err(DmaError::AboveBoundary);
+        }
+
+        match start_frame {
+            Some(ref start) if frame.0 == start.0 + count => count += 1,
+            _ => {
+                if let Some(start) = start_frame.take() {
+                    skipped.extend((0..count).map(|i| Frame(start.0 + i)));
+                }
+
+                start_frame = Some(frame);
+                count = 1;
+            }
+        }
+    };
+
+    if result.is_err() {
+        if let Some(start) = start_frame.take() {
+            skipped.extend((0..count).map(|i| Frame(start.0 + i)));
+        }
+    }
+
+    for frame in skipped {
+        allocator.deallocate_frame(frame);
+    }
+
+    result.map(|()| start_frame.unwrap())
+}
+
+/// Allocates `pages` physically contiguous pages below `DMA_BOUNDARY` that are suitable for legacy
+/// DMA. Returns both the virtual and the physical address of the buffer, or `None` if the pool is
+/// not initialized or has no large enough free run left.
+pub fn alloc_dma(pages: usize) -> Option<(VirtualAddress, PhysicalAddress)> {
+    if pages == 0 || pages > DMA_POOL_PAGES {
+        return None;
+    }
+
+    let mut pool = DMA_POOL.lock();
+    let pool = pool.as_mut()?;
+
+    let index = pool.used.find_first_clear_range(pages)?;
+    for i in index..index + pages {
+        pool.used.set(i);
+    }
+
+    let virtual_address = DMA_START + (index * PAGE_SIZE) as u64;
+    let physical_address = Frame(pool.start_frame.0 + index).start_address();
+
+    Some((virtual_address, physical_address))
+}
+
+/// Frees `pages` pages that were allocated with `alloc_dma`, starting at `address`.
+pub fn free_dma(address: VirtualAddress, pages: usize) {
+    assert_eq!(address.as_u64() % PAGE_SIZE as u64, 0, "DMA buffer address is not page aligned!");
+    assert!(address.as_u64() >= DMA_START.as_u64(), "Address {:?} is not a DMA buffer!", address);
+
+    let index = ((address.as_u64() - DMA_START.as_u64()) as usize) / PAGE_SIZE;
+    assert!(index + pages <= DMA_POOL_PAGES, "Address {:?} is not a DMA buffer!", address);
+
+    let mut pool = DMA_POOL.lock();
+    let pool = pool.as_mut().expect("DMA pool is not initialized!");
+
+    for i in index..index + pages {
+        assert!(pool.used.get(i), "Double free of DMA page at {:?}", address);
+        pool.used.clear(i);
+    }
+}
\ No newline at end of file
@@ -1,11 +1,55 @@
-use multiboot2::{MemoryArea, MemoryAreaIter};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use memory::PAGE_SIZE;
+use boot::{BootModule, MemoryRegion};
+use memory::{PAGE_SIZE, USABLE_BYTES, usable_bytes};
+use memory::paging::ActivePageTable;
+use util::range::InclusiveRange;
 use x86_64::PhysicalAddress;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// The maximum amount of deallocated frames `AreaFrameAllocator` keeps around for reuse.
+pub const RECYCLED_FRAMES: usize = 32;
+
+/// The total amount of usable frames in the memory map.
+static TOTAL_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// The amount of frames that are in use, including the frames occupied by the kernel, the
+/// multiboot information structure and the boot modules.
+static USED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// The amount of deallocated frames that could not be kept for reuse, because the recycle list was
+/// full. They stay counted as used.
+static LEAKED_FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the total amount of usable frames in the memory map.
+pub fn total_frames() -> usize {
+    TOTAL_FRAMES.load(Ordering::SeqCst)
+}
+
+/// Returns the amount of frames that are currently in use.
+pub fn used_frames() -> usize {
+    USED_FRAMES.load(Ordering::SeqCst)
+}
+
+/// Returns the amount of frames that were deallocated but lost, see `AreaFrameAllocator`.
+pub fn leaked_frames() -> usize {
+    LEAKED_FRAMES.load(Ordering::SeqCst)
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
 pub struct Frame(pub usize);
 
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Indices past the end of the address space have no start address, tests use them for the
+        // edge cases of ranges.
+        match self.0.checked_mul(PAGE_SIZE) {
+            Some(_) => write!(f, "Frame({:?})", self.start_address()),
+            None => write!(f, "Frame(#{})", self.0),
+        }
+    }
+}
+
 impl Frame {
     pub fn containing_address(address: PhysicalAddress) -> Frame {
         Frame((address.as_u64() as usize) / PAGE_SIZE)
@@ -17,50 +61,74 @@ impl Frame {
 
     pub fn range_inclusive(start: Frame, end: Frame) -> FrameIter {
         FrameIter {
-            start,
-            end,
+            range: InclusiveRange::new(start.0, end.0),
         }
     }
 }
 
 pub struct FrameIter {
-    start: Frame,
-    end: Frame,
+    range: InclusiveRange,
 }
 
 impl Iterator for FrameIter {
     type Item = Frame;
 
     fn next(&mut self) -> Option<Frame> {
-        if self.start <= self.end {
-            let frame = Frame(self.start.0);
-            self.start.0 += 1;
-            Some(frame)
-        } else {
-            None
-        }
+        self.range.next().map(Frame)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Frame> {
+        self.range.nth(n).map(Frame)
     }
 }
 
+impl DoubleEndedIterator for FrameIter {
+    fn next_back(&mut self) -> Option<Frame> {
+        self.range.next_back().map(Frame)
+    }
+}
+
+impl ExactSizeIterator for FrameIter {}
+
 pub trait FrameAllocator {
     fn allocate_frame(&mut self) -> Option<Frame>;
     fn deallocate_frame(&mut self, frame: Frame);
+
+    /// Allocates a frame and fills it with zeros through a temporary mapping, so no stale data of
+    /// a previous owner ends up in the new mapping. Page table frames don't need this, because
+    /// they are zeroed by `PageTable::zero` when they are created.
+    fn allocate_zeroed_frame(&mut self, active_table: &mut ActivePageTable) -> Option<Frame> where Self: Sized {
+        let frame = self.allocate_frame()?;
+        active_table.with_frame_mapped(Frame(frame.0), self, |contents| {
+            for byte in contents.iter_mut() {
+                *byte = 0;
+            }
+        });
+
+        Some(frame)
+    }
 }
 
 pub struct AreaFrameAllocator<'a> {
     next_free_frame: Frame,
-    current_area: Option<&'a MemoryArea>,
-    areas: MemoryAreaIter<'a>,
+    current_area: Option<&'a MemoryRegion>,
+    areas: &'a [MemoryRegion],
     kernel_start: Frame,
     kernel_end: Frame,
     multiboot_start: Frame,
     multiboot_end: Frame,
+    modules: &'a [BootModule],
+    recycled: [Option<Frame>; RECYCLED_FRAMES],
 }
 
 impl<'a> AreaFrameAllocator<'a> {
     pub fn new(kernel_start: PhysicalAddress, kernel_end: PhysicalAddress,
                multiboot_start: PhysicalAddress, multiboot_end: PhysicalAddress,
-               memory_areas: MemoryAreaIter<'a>) -> AreaFrameAllocator {
+               memory_areas: &'a [MemoryRegion], modules: &'a [BootModule]) -> AreaFrameAllocator<'a> {
         let mut allocator = AreaFrameAllocator {
             next_free_frame: Frame::containing_address(PhysicalAddress::new(0)),
             current_area: None,
@@ -68,15 +136,29 @@ impl<'a> AreaFrameAllocator<'a> {
             kernel_start: Frame::containing_address(kernel_start),
             kernel_end: Frame::containing_address(kernel_end),
             multiboot_start: Frame::containing_address(multiboot_start),
-            multiboot_end: Frame::containing_address(multiboot_end)
+            multiboot_end: Frame::containing_address(multiboot_end),
+            modules,
+            recycled: Default::default(),
         };
 
+        let total_frames: usize = allocator.areas.iter()
+            .filter(|area| area.is_usable())
+            .map(|area| (area.end_address() - area.start_address()) as usize / PAGE_SIZE)
+            .sum();
+        let reserved_frames = (allocator.kernel_end.0 - allocator.kernel_start.0 + 1) +
+            (allocator.multiboot_end.0 - allocator.multiboot_start.0 + 1) +
+            modules.iter().filter_map(module_frames).map(|(start, end)| end.0 - start.0 + 1).sum::<usize>();
+
+        TOTAL_FRAMES.store(total_frames, Ordering::SeqCst);
+        USABLE_BYTES.store(usable_bytes(memory_areas), Ordering::SeqCst);
+        USED_FRAMES.store(reserved_frames, Ordering::SeqCst);
+
         allocator.choose_next_area();
         allocator
     }
 
     fn choose_next_area(&mut self) {
-        self.current_area = self.areas.clone().filter(|area| {
+        self.current_area = self.areas.iter().filter(|area| area.is_usable()).filter(|area| {
             let address = area.end_address() - 1;
             Frame::containing_address(PhysicalAddress::new(address)) >= self.next_free_frame
         }).min_by_key(|area| area.start_address());
@@ -92,6 +174,13 @@ impl<'a> AreaFrameAllocator<'a> {
 
 impl<'a> FrameAllocator for AreaFrameAllocator<'a> {
     fn allocate_frame(&mut self) -> Option<Frame> {
+        for frame_option in self.recycled.iter_mut() {
+            if frame_option.is_some() {
+                USED_FRAMES.fetch_add(1, Ordering::SeqCst);
+                return frame_option.take();
+            }
+        }
+
         if let Some(area) = self.current_area {
             let frame = Frame(self.next_free_frame.0);
 
@@ -106,8 +195,12 @@ impl<'a> FrameAllocator for AreaFrameAllocator<'a> {
                 self.next_free_frame = Frame(self.kernel_end.0 + 1);
             } else if frame >= self.multiboot_start && frame <= self.multiboot_end {
                 self.next_free_frame = Frame(self.multiboot_end.0 + 1);
+            } else if let Some((_, end)) = self.modules.iter().filter_map(module_frames)
+                .find(|(start, end)| frame >= *start && frame <= *end) {
+                self.next_free_frame = Frame(end.0 + 1);
             } else {
                 self.next_free_frame.0 += 1;
+                USED_FRAMES.fetch_add(1, Ordering::SeqCst);
                 return Some(frame);
             }
 
@@ -117,7 +210,28 @@ impl<'a> FrameAllocator for AreaFrameAllocator<'a> {
         }
     }
 
-    fn deallocate_frame(&mut self, _frame: Frame) {
-        // TODO: unimplemented!()
+    fn deallocate_frame(&mut self, frame: Frame) {
+        for frame_option in self.recycled.iter_mut() {
+            if frame_option.is_none() {
+                *frame_option = Some(frame);
+                USED_FRAMES.fetch_sub(1, Ordering::SeqCst);
+                return;
+            }
+        }
+
+        // TODO: Keep the frames in a bitmap instead, this needs storage sized to physical memory.
+        if LEAKED_FRAMES.fetch_add(1, Ordering::SeqCst) == 0 {
+            crate::kprintln!("Frame recycle list is full, leaking {:?}", frame);
+        }
+    }
+}
+
+/// Returns the first and last frame occupied by `module`, or `None` if the module is empty.
+pub fn module_frames(module: &BootModule) -> Option<(Frame, Frame)> {
+    if module.size() == 0 {
+        return None;
     }
+
+    let end = PhysicalAddress::new(module.end_address().as_u64() - 1);
+    Some((Frame::containing_address(module.start_address()), Frame::containing_address(end)))
 }
\ No newline at end of file
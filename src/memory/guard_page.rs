@@ -0,0 +1,61 @@
+use memory::PAGE_SIZE;
+use memory::paging::Page;
+use util::irq_lock::IrqLock;
+use x86_64::VirtualAddress;
+
+/// The amount of guard pages that can be registered. This is a fixed array instead of a `Vec`,
+/// because the first guard page is registered before the heap exists.
+const MAX_GUARD_PAGES: usize = 64;
+
+static GUARD_PAGES: IrqLock<[Option<GuardPage>; MAX_GUARD_PAGES]> = IrqLock::new([None; MAX_GUARD_PAGES]);
+
+/// An unmapped page below a stack. A stack overflow faults on it instead of overwriting the memory
+/// below the stack.
+#[derive(Debug, Clone, Copy)]
+pub struct GuardPage {
+    pub page: Page,
+    pub stack_bottom: VirtualAddress,
+    pub stack_top: VirtualAddress,
+}
+
+impl GuardPage {
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        let start = self.page.start_address().as_u64();
+        start <= address.as_u64() && address.as_u64() < start + PAGE_SIZE as u64
+    }
+
+    /// Returns whether `address` is part of the stack this page guards.
+    pub fn guards(&self, address: VirtualAddress) -> bool {
+        self.stack_bottom.as_u64() <= address.as_u64() && address.as_u64() < self.stack_top.as_u64()
+    }
+}
+
+/// Registers `guard_page`, so a fault on it is reported as a stack overflow. Returns false if the
+/// registry is full, the page still guards the stack then but overflows aren't recognized.
+pub fn register(guard_page: GuardPage) -> bool {
+    match GUARD_PAGES.lock().iter_mut().find(|slot| slot.is_none()) {
+        Some(slot) => {
+            *slot = Some(guard_page);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Returns the guard page that contains `address`, if any. Doesn't wait for the lock, it is used
+/// by the page fault handler. Returns `None` if the lock is held.
+pub fn find(address: VirtualAddress) -> Option<GuardPage> {
+    GUARD_PAGES.try_lock()?.iter()
+        .flatten()
+        .find(|guard_page| guard_page.contains(address))
+        .cloned()
+}
+
+/// Returns the guard page of the stack that contains `address`, like the current stack pointer.
+/// Returns `None` if the lock is held.
+pub fn stack_containing(address: VirtualAddress) -> Option<GuardPage> {
+    GUARD_PAGES.try_lock()?.iter()
+        .flatten()
+        .find(|guard_page| guard_page.guards(address))
+        .cloned()
+}
\ No newline at end of file
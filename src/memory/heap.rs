@@ -0,0 +1,95 @@
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use linked_list_allocator::LockedHeap;
+
+#[cfg(feature = "debug_heap")]
+use memory::debug_heap::DebugHeap;
+#[cfg(feature = "heap_stats")]
+use memory::heap_stats::StatsHeap;
+
+/// The `KernelHeap`, counted by a `StatsHeap` with the `heap_stats` feature.
+#[cfg(feature = "heap_stats")]
+pub type CountedHeap = StatsHeap<KernelHeap>;
+#[cfg(not(feature = "heap_stats"))]
+pub type CountedHeap = KernelHeap;
+
+/// The global allocator: the `CountedHeap`, checked by a `DebugHeap` with the `debug_heap`
+/// feature. The counts then include the canaries.
+#[cfg(feature = "debug_heap")]
+pub type Heap = DebugHeap<CountedHeap>;
+#[cfg(not(feature = "debug_heap"))]
+pub type Heap = CountedHeap;
+
+/// Creates the empty global allocator, with the layers of the enabled features.
+pub const fn empty_heap() -> Heap {
+    debug_layer(counted_layer(KernelHeap::empty()))
+}
+
+#[cfg(feature = "heap_stats")]
+const fn counted_layer(heap: KernelHeap) -> CountedHeap {
+    StatsHeap::new(heap)
+}
+
+#[cfg(not(feature = "heap_stats"))]
+const fn counted_layer(heap: KernelHeap) -> CountedHeap {
+    heap
+}
+
+#[cfg(feature = "debug_heap")]
+const fn debug_layer(heap: CountedHeap) -> Heap {
+    DebugHeap::new(heap)
+}
+
+#[cfg(not(feature = "debug_heap"))]
+const fn debug_layer(heap: CountedHeap) -> Heap {
+    heap
+}
+
+/// A wrapper around `LockedHeap` that keeps track of the amount of bytes in use, so it can be
+/// reported by `memory::stats`.
+pub struct KernelHeap {
+    heap: LockedHeap,
+    used: AtomicUsize,
+}
+
+impl KernelHeap {
+    /// Creates an empty `KernelHeap`. All allocations will fail until `init` is called.
+    pub const fn empty() -> KernelHeap {
+        KernelHeap {
+            heap: LockedHeap::empty(),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Initializes the heap with the memory range starting at `start` of `size` bytes.
+    ///
+    /// # Safety
+    /// The memory range needs to be mapped and unused, and this function should only be called
+    /// once.
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        self.heap.lock().init(start, size);
+    }
+
+    /// Returns the amount of bytes that are currently allocated.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+
+        if !ptr.is_null() {
+            self.used.fetch_add(layout.size(), Ordering::SeqCst);
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.dealloc(ptr, layout);
+        self.used.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
\ No newline at end of file
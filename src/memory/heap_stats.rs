@@ -0,0 +1,124 @@
+//! An allocator wrapper that counts allocations, to find out how the heap is used and to find
+//! leaks. The kernel heap is wrapped in it when the `heap_stats` feature is enabled, see
+//! `memory::heap_stats`.
+
+use alloc::alloc::{GlobalAlloc, Layout};
+use core::ops::Deref;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use util::irq_lock::IrqLock;
+
+/// The amount of outstanding allocations that are remembered for leak reports. Allocations made
+/// while all slots are in use are only counted.
+pub const TRACKED_ALLOCATIONS: usize = 64;
+
+/// A snapshot of the counters of a `StatsHeap`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// The amount of bytes that are allocated right now.
+    pub current: usize,
+    /// The largest `current` ever was.
+    pub peak: usize,
+    pub allocations: usize,
+    pub frees: usize,
+}
+
+/// An allocation that wasn't freed yet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pub address: usize,
+    pub size: usize,
+}
+
+/// Wraps the allocator `A` and counts its allocations.
+pub struct StatsHeap<A> {
+    inner: A,
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+    frees: AtomicUsize,
+    tracked: IrqLock<[Option<Allocation>; TRACKED_ALLOCATIONS]>,
+}
+
+impl<A> StatsHeap<A> {
+    pub const fn new(inner: A) -> StatsHeap<A> {
+        StatsHeap {
+            inner,
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            tracked: IrqLock::new([None; TRACKED_ALLOCATIONS]),
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            current: self.current.load(Ordering::SeqCst),
+            peak: self.peak.load(Ordering::SeqCst),
+            allocations: self.allocations.load(Ordering::SeqCst),
+            frees: self.frees.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Forgets the outstanding allocations, so a later `outstanding` only reports the
+    /// allocations made after this checkpoint.
+    pub fn checkpoint(&self) {
+        *self.tracked.lock() = [None; TRACKED_ALLOCATIONS];
+    }
+
+    /// Calls `f` for every remembered allocation that wasn't freed yet.
+    pub fn outstanding(&self, mut f: impl FnMut(Allocation)) {
+        let tracked = *self.tracked.lock();
+        for &allocation in tracked.iter().flatten() {
+            f(allocation);
+        }
+    }
+}
+
+impl<A> Deref for StatsHeap<A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for StatsHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if ptr.is_null() {
+            return ptr;
+        }
+
+        self.allocations.fetch_add(1, Ordering::SeqCst);
+        let current = self.current.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+
+        let mut peak = self.peak.load(Ordering::SeqCst);
+        while current > peak {
+            match self.peak.compare_exchange(peak, current, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(actual) => peak = actual,
+            }
+        }
+
+        let mut tracked = self.tracked.lock();
+        if let Some(slot) = tracked.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(Allocation { address: ptr as usize, size: layout.size() });
+        }
+
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+
+        self.frees.fetch_add(1, Ordering::SeqCst);
+        self.current.fetch_sub(layout.size(), Ordering::SeqCst);
+
+        let mut tracked = self.tracked.lock();
+        if let Some(slot) = tracked.iter_mut().find(|slot| slot.map(|allocation| allocation.address) == Some(ptr as usize)) {
+            *slot = None;
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,135 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use flagset::FlagSet;
+
+use fs::vfs::{FsError, INode};
+use memory::frame::{Frame, FrameAllocator};
+use memory::PAGE_SIZE;
+use memory::paging::{ActivePageTable, Page};
+use memory::paging::entry::EntryFlags;
+use memory::vma::{self, Backing, Vma};
+use x86_64::VirtualAddress;
+
+/// The start of the virtual memory range files are mapped in.
+pub const MMAP_START: VirtualAddress = VirtualAddress::new(0x5555_0000_0000);
+
+/// The size of the virtual memory range files are mapped in.
+pub const MMAP_SIZE: usize = 1024 * 1024 * 1024;
+
+/// The first page that has not been handed out to a mapping yet. Pages are never handed out
+/// twice, so unmapped ranges are not reused.
+static NEXT_PAGE: AtomicUsize = AtomicUsize::new((MMAP_START.as_u64() as usize) / PAGE_SIZE);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmapError {
+    /// Reading the file failed.
+    Fs(FsError),
+    /// There are not enough frames left for the contents of the file.
+    OutOfMemory,
+    /// The range for mappings is used up.
+    OutOfAddressSpace,
+}
+
+impl From<FsError> for MmapError {
+    fn from(error: FsError) -> MmapError {
+        MmapError::Fs(error)
+    }
+}
+
+/// Maps the contents of `inode` read-only into the kernel address space and returns the address
+/// they start at. The whole file is read right away, changes to the file afterwards are not
+/// visible through the mapping. The rest of the last page is zeroed. Empty files get one zeroed
+/// page, so the address can always be read.
+pub fn mmap_inode<A>(inode: &Arc<dyn INode>, active_table: &mut ActivePageTable, allocator: &mut A) -> Result<VirtualAddress, MmapError>
+    where A: FrameAllocator {
+    let size = inode.metadata()?.size;
+    let pages = if size == 0 { 1 } else { (size + PAGE_SIZE - 1) / PAGE_SIZE };
+    let start = reserve_pages(pages)?;
+
+    for i in 0..pages {
+        if let Err(error) = map_file_page(inode, Page(start.0 + i), i * PAGE_SIZE, active_table, allocator) {
+            if i > 0 {
+                active_table.unmap_range(Page::range_inclusive(start, Page(start.0 + i - 1)), allocator);
+            }
+
+            return Err(error);
+        }
+    }
+
+    Ok(start.start_address())
+}
+
+/// Reserves `size` bytes of zeroed memory in the kernel address space and returns the address they
+/// start at. No frames are allocated yet, every page is mapped with `flags` by the page fault
+/// handler when it is first accessed.
+pub fn mmap_anonymous(size: usize, flags: impl Into<FlagSet<EntryFlags>>) -> Result<VirtualAddress, MmapError> {
+    let pages = if size == 0 { 1 } else { (size + PAGE_SIZE - 1) / PAGE_SIZE };
+    let start = reserve_pages(pages)?.start_address().as_u64();
+
+    vma::register(Vma {
+        range: start..start + (pages * PAGE_SIZE) as u64,
+        backing: Backing::Anonymous,
+        flags: flags.into(),
+    }).expect("Reserved pages overlap a region!");
+
+    Ok(VirtualAddress::new(start))
+}
+
+/// Unmaps a mapping created by `mmap_inode` and frees its frames. `size` is the size the file had
+/// when it was mapped.
+pub fn munmap<A>(address: VirtualAddress, size: usize, active_table: &mut ActivePageTable, allocator: &mut A)
+    where A: FrameAllocator {
+    let start = Page::containing_address(address);
+    let pages = if size == 0 { 1 } else { (size + PAGE_SIZE - 1) / PAGE_SIZE };
+
+    active_table.unmap_range(Page::range_inclusive(start, Page(start.0 + pages - 1)), allocator);
+}
+
+/// Takes `pages` pages from the mapping range.
+fn reserve_pages(pages: usize) -> Result<Page, MmapError> {
+    let end = (MMAP_START.as_u64() as usize + MMAP_SIZE) / PAGE_SIZE;
+    let start = NEXT_PAGE.fetch_add(pages, Ordering::SeqCst);
+
+    if start + pages > end {
+        NEXT_PAGE.fetch_sub(pages, Ordering::SeqCst);
+        return Err(MmapError::OutOfAddressSpace);
+    }
+
+    Ok(Page(start))
+}
+
+/// Reads the page of `inode` at `offset` into a new frame and maps it read-only at `page`.
+fn map_file_page<A>(inode: &Arc<dyn INode>, page: Page, offset: usize, active_table: &mut ActivePageTable, allocator: &mut A) -> Result<(), MmapError>
+    where A: FrameAllocator {
+    let frame = allocator.allocate_frame().ok_or(MmapError::OutOfMemory)?;
+
+    let read = active_table.with_frame_mapped(Frame(frame.0), allocator, |contents| {
+        let mut done = 0;
+
+        while done < PAGE_SIZE {
+            let len = inode.read_at(offset + done, &mut contents[done..])?;
+            if len == 0 {
+                break;
+            }
+
+            done += len;
+        }
+
+        for byte in contents[done..].iter_mut() {
+            *byte = 0;
+        }
+
+        Ok(())
+    });
+
+    let result = read.map_err(MmapError::from)
+        .and_then(|_| active_table.try_map_to(page, Frame(frame.0), EntryFlags::NoExecute, allocator)
+            .map_err(|_| MmapError::OutOfMemory));
+
+    if result.is_err() {
+        allocator.deallocate_frame(frame);
+    }
+
+    result
+}
\ No newline at end of file
@@ -1,17 +1,146 @@
-use memory::frame::FrameAllocator;
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use boot::{BootInfo, MemoryRegion};
+use memory::frame::{AreaFrameAllocator, FrameAllocator};
 use memory::paging::{ActivePageTable, Page};
 use memory::paging::entry::EntryFlags;
+use memory::stack_allocator::StackAllocator;
+use util::irq_lock::IrqLock;
 use x86_64::VirtualAddress;
 
+pub mod debug_heap;
+pub mod dma;
 pub mod frame;
+pub mod guard_page;
+pub mod heap;
+pub mod heap_stats;
+pub mod mmap;
 pub mod paging;
 pub mod stack_allocator;
+pub mod vma;
+
+pub use memory::mmap::{MmapError, mmap_anonymous, mmap_inode, munmap};
 
 pub const PAGE_SIZE: usize = 4096;
 
 pub const HEAP_START: VirtualAddress = VirtualAddress::new(0x4444_4444_0000);
 pub const HEAP_SIZE: usize = 1024 * 1024;
 
+/// The amount of pages reserved directly after the heap for kernel stacks and their guard pages.
+pub const KERNEL_STACKS_PAGES: usize = 100;
+
+static STACK_ALLOCATOR: IrqLock<Option<StackAllocator>> = IrqLock::new(None);
+
+/// The active page table and the frame allocator, once the kernel has finished setting up memory.
+static MEMORY: IrqLock<Option<(ActivePageTable, AreaFrameAllocator<'static>)>> = IrqLock::new(None);
+
+/// The total amount of usable physical memory in bytes. Set when the frame allocator is created.
+static USABLE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// A snapshot of the physical and heap memory usage, returned by `memory::stats`.
+#[derive(Debug, Copy, Clone)]
+pub struct MemoryStats {
+    /// Total amount of usable frames
+    pub total_frames: usize,
+
+    /// Amount of frames in use
+    pub used_frames: usize,
+
+    /// Amount of frames that can still be allocated
+    pub free_frames: usize,
+
+    /// Amount of deallocated frames that were lost because the recycle list was full
+    pub leaked_frames: usize,
+
+    /// Amount of heap bytes in use
+    pub heap_used: usize,
+
+    /// Amount of heap bytes that can still be allocated
+    pub heap_free: usize,
+
+    /// Total amount of usable physical memory in bytes
+    pub usable_bytes: usize,
+}
+
+/// Returns the current physical and heap memory usage.
+pub fn stats() -> MemoryStats {
+    let total_frames = frame::total_frames();
+    let used_frames = frame::used_frames().min(total_frames);
+    let heap_used = crate::ALLOCATOR.used();
+
+    MemoryStats {
+        total_frames,
+        used_frames,
+        free_frames: total_frames - used_frames,
+        leaked_frames: frame::leaked_frames(),
+        heap_used,
+        heap_free: HEAP_SIZE.saturating_sub(heap_used),
+        usable_bytes: total_usable_bytes(),
+    }
+}
+
+/// Returns the allocation counters of the kernel heap.
+#[cfg(feature = "heap_stats")]
+pub fn heap_stats() -> heap_stats::HeapStats {
+    crate::ALLOCATOR.stats()
+}
+
+/// Returns the total amount of usable physical memory in bytes, according to the memory map.
+pub fn total_usable_bytes() -> usize {
+    USABLE_BYTES.load(Ordering::SeqCst)
+}
+
+/// Sums the sizes of all usable regions in `regions`.
+pub fn usable_bytes(regions: &[MemoryRegion]) -> usize {
+    regions.iter()
+        .filter(|region| region.is_usable())
+        .map(|region| region.size() as usize)
+        .sum()
+}
+
+/// Prints every region of the physical memory map with its type and size, followed by the total
+/// amount of usable memory.
+pub fn print_memory_map(boot_info: &BootInfo) {
+    crate::kprintln!("Physical memory map:");
+
+    for region in boot_info.memory_map() {
+        crate::kprintln!(
+            "  {:#014x} - {:#014x} {:?} ({})",
+            region.start_address(), region.end_address(), region.region_type(),
+            ByteSize(region.size() as usize)
+        );
+    }
+
+    crate::kprintln!("Usable memory: {}", ByteSize(usable_bytes(boot_info.memory_map())));
+}
+
+/// A size in bytes that is displayed in the largest binary unit it fits in, like `1.5 MiB`.
+#[derive(Debug, Copy, Clone)]
+pub struct ByteSize(pub usize);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+        let mut value = self.0;
+        let mut remainder = 0;
+        let mut unit = 0;
+
+        while value >= 1024 && unit < UNITS.len() - 1 {
+            remainder = value % 1024;
+            value /= 1024;
+            unit += 1;
+        }
+
+        if unit == 0 {
+            write!(f, "{} B", value)
+        } else {
+            write!(f, "{}.{} {}", value, remainder * 10 / 1024, UNITS[unit])
+        }
+    }
+}
+
 /// A struct that represents a memory stack for a program or the kernel.
 pub struct Stack {
     top: VirtualAddress,
@@ -29,21 +158,59 @@ impl Stack {
 }
 
 pub fn init_heap<A>(active_table: &mut ActivePageTable, allocator: &mut A) where A: FrameAllocator {
+    let heap_end = VirtualAddress::new(HEAP_START.as_u64() + HEAP_SIZE as u64);
+    let heap_end_page = Page::containing_address(heap_end);
+
     let page_range = {
-        let heap_end = VirtualAddress::new(HEAP_START.as_u64() + HEAP_SIZE as u64);
         let heap_start_page = Page::containing_address(HEAP_START);
-        let heap_end_page = Page::containing_address(heap_end);
-
         Page::range_inclusive(heap_start_page, heap_end_page)
     };
 
-    let flags = EntryFlags::Present | EntryFlags::Writable;
-
-    for page in page_range {
-        active_table.map(page, flags, allocator);
-    }
+    active_table.map_range_zeroed(page_range, EntryFlags::Present | EntryFlags::Writable, allocator);
 
     unsafe {
-        crate::ALLOCATOR.lock().init(HEAP_START.as_u64() as usize, HEAP_SIZE);
+        crate::ALLOCATOR.init(HEAP_START.as_u64() as usize, HEAP_SIZE);
     }
+
+    let stack_range = Page::range_inclusive(
+        Page(heap_end_page.0 + 1),
+        Page(heap_end_page.0 + KERNEL_STACKS_PAGES)
+    );
+
+    *STACK_ALLOCATOR.lock() = Some(StackAllocator::new(stack_range));
+}
+
+/// Hands the active page table and the frame allocator over to the rest of the kernel, so code that
+/// runs after boot, like device drivers, can change mappings with `with_memory`.
+pub fn init_global(active_table: ActivePageTable, frame_allocator: AreaFrameAllocator<'static>) {
+    *MEMORY.lock() = Some((active_table, frame_allocator));
+}
+
+/// Runs `f` with the active page table and the frame allocator. Interrupts are disabled while `f`
+/// runs. Panics if `init_global` has not been called yet.
+pub fn with_memory<F, R>(f: F) -> R where F: FnOnce(&mut ActivePageTable, &mut AreaFrameAllocator<'static>) -> R {
+    let mut memory = MEMORY.lock();
+    let (active_table, frame_allocator) = memory.as_mut()
+        .expect("Memory is not initialized!");
+
+    f(active_table, frame_allocator)
+}
+
+/// Like `with_memory`, but returns `None` instead of waiting if the memory lock is held, or if
+/// memory isn't initialized yet. For code that may have interrupted the holder of the lock, like
+/// the page fault handler.
+pub fn try_with_memory<F, R>(f: F) -> Option<R> where F: FnOnce(&mut ActivePageTable, &mut AreaFrameAllocator<'static>) -> R {
+    let mut memory = MEMORY.try_lock()?;
+    let (active_table, frame_allocator) = memory.as_mut()?;
+
+    Some(f(active_table, frame_allocator))
+}
+
+/// Allocates a kernel stack of `pages` pages, with an unmapped guard page below it. Returns `None`
+/// if the reserved kernel stack range or physical memory is exhausted.
+pub fn alloc_kernel_stack<A>(active_table: &mut ActivePageTable, frame_allocator: &mut A, pages: usize) -> Option<Stack>
+    where A: FrameAllocator {
+    STACK_ALLOCATOR.lock().as_mut()
+        .expect("The kernel stack allocator is not initialized!")
+        .alloc_stack(active_table, frame_allocator, pages)
 }
\ No newline at end of file
@@ -18,6 +18,13 @@ flags! {
     }
 }
 
+/// Returns the flags for a writable mapping of device memory. Caching is disabled, because reads
+/// and writes of MMIO registers have side effects. Write-combining would need the PAT, which is not
+/// set up, so framebuffers use these flags as well.
+pub fn mmio_flags() -> FlagSet<EntryFlags> {
+    EntryFlags::Writable | EntryFlags::NoCache | EntryFlags::WriteThrough | EntryFlags::NoExecute
+}
+
 pub struct Entry(u64);
 
 impl Entry {
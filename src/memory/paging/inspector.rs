@@ -0,0 +1,79 @@
+use flagset::FlagSet;
+
+use memory::frame::Frame;
+use memory::PAGE_SIZE;
+use memory::paging::{ActivePageTable, Page, TABLE_ENTRY_COUNT};
+use memory::paging::entry::EntryFlags;
+use memory::paging::temporary_page::TemporaryPage;
+use x86_64::{PhysicalAddress, VirtualAddress};
+
+/// A read-only view of an inactive page table. The tables are walked by mapping their frames at a
+/// temporary page one at a time, instead of through the recursive entry.
+pub struct TableInspector<'a> {
+    p4_frame: Frame,
+    active_table: &'a mut ActivePageTable,
+    temporary_page: &'a mut TemporaryPage,
+}
+
+impl<'a> TableInspector<'a> {
+    pub fn new(p4_frame: Frame, active_table: &'a mut ActivePageTable, temporary_page: &'a mut TemporaryPage) -> TableInspector<'a> {
+        TableInspector {
+            p4_frame,
+            active_table,
+            temporary_page,
+        }
+    }
+
+    /// Translates `address` to the physical address it is mapped to in the inspected table.
+    pub fn translate(&mut self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        let offset = address.as_u64() % PAGE_SIZE as u64;
+        self.translate_page(Page::containing_address(address))
+            .map(|(frame, _)| PhysicalAddress::new(frame.start_address().as_u64() + offset))
+    }
+
+    /// Translates `page` to the frame it is mapped to in the inspected table, together with the
+    /// flags of the entry that maps it.
+    pub fn translate_page(&mut self, page: Page) -> Option<(Frame, FlagSet<EntryFlags>)> {
+        let p3 = self.next_table(Frame(self.p4_frame.0), page.p4_index())?;
+
+        let p2 = match self.entry(Frame(p3.0), page.p3_index()) {
+            (Some(start_frame), flags) if flags.contains(EntryFlags::HugePage) => {
+                let offset = page.p2_index() * TABLE_ENTRY_COUNT + page.p1_index();
+                return Some((Frame(start_frame.0 + offset), flags));
+            }
+            (frame, _) => frame?,
+        };
+
+        let p1 = match self.entry(Frame(p2.0), page.p2_index()) {
+            (Some(start_frame), flags) if flags.contains(EntryFlags::HugePage) => {
+                return Some((Frame(start_frame.0 + page.p1_index()), flags));
+            }
+            (frame, _) => frame?,
+        };
+
+        match self.entry(p1, page.p1_index()) {
+            (Some(frame), flags) => Some((frame, flags)),
+            (None, _) => None,
+        }
+    }
+
+    /// Returns the frame of the table that entry `index` of the table in `table` points to.
+    fn next_table(&mut self, table: Frame, index: usize) -> Option<Frame> {
+        match self.entry(table, index) {
+            (Some(frame), flags) if !flags.contains(EntryFlags::HugePage) => Some(frame),
+            _ => None,
+        }
+    }
+
+    /// Reads entry `index` of the table in `table`. Returns the frame it points to, if it is
+    /// present, and its flags.
+    fn entry(&mut self, table: Frame, index: usize) -> (Option<Frame>, FlagSet<EntryFlags>) {
+        let entry = {
+            let table = self.temporary_page.map_table_frame_read_only(table, self.active_table);
+            (table[index].pointed_frame(), table[index].flags())
+        };
+
+        self.temporary_page.unmap(self.active_table);
+        entry
+    }
+}
\ No newline at end of file
@@ -1,29 +1,61 @@
-use core::ptr::Unique;
-
 use flagset::FlagSet;
 
-use memory::frame::{Frame, FrameAllocator};
+use memory::frame::{Frame, FrameAllocator, FrameIter};
 use memory::PAGE_SIZE;
-use memory::paging::{Page, TABLE_ENTRY_COUNT};
-use memory::paging::entry::EntryFlags;
-use memory::paging::table::{Level4, P4, PageTable};
+use memory::paging::{Page, PageIter, TABLE_ENTRY_COUNT};
+use memory::paging::entry::{EntryFlags, mmio_flags};
+use memory::paging::table::{Level4, PageTable, RecursivePageTable};
 use x86_64::{PhysicalAddress, VirtualAddress};
 use x86_64::instructions::TLB;
 
+pub use x86_64::instructions::TLB_FLUSH_ALL_THRESHOLD;
+
+/// The amount of normal pages that fit in a 2 MiB huge page.
+pub const HUGE_PAGE_PAGES: usize = TABLE_ENTRY_COUNT;
+
+/// An error while creating a mapping.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapError {
+    /// There was no free frame left for the page or for one of the page tables.
+    OutOfFrames,
+}
+
 pub struct Mapper {
-    p4: Unique<PageTable<Level4>>,
+    table: RecursivePageTable,
 }
 
 impl Mapper {
-    pub unsafe fn new() -> Mapper {
+    pub fn new(table: RecursivePageTable) -> Mapper {
         Mapper {
-            p4: Unique::new_unchecked(P4),
+            table,
         }
     }
 
     pub fn map<A>(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
-        let frame = allocator.allocate_frame().expect("Out of memory!");
-        self.map_to(page, frame, flags, allocator)
+        self.try_map(page, flags, allocator).expect("Out of memory!")
+    }
+
+    /// Maps `page` to a newly allocated frame, like `map`, but returns an error instead of
+    /// panicking when there are no frames left. Nothing is mapped in that case.
+    pub fn try_map<A>(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) -> Result<(), MapError>
+        where A: FrameAllocator {
+        let frame = allocator.allocate_frame().ok_or(MapError::OutOfFrames)?;
+
+        if let Err(error) = self.try_map_to(page, Frame(frame.0), flags, allocator) {
+            allocator.deallocate_frame(frame);
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Maps every page in `pages` to a newly allocated frame.
+    pub fn map_range<A>(&mut self, pages: PageIter, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
+        let flags = flags.into();
+
+        for page in pages {
+            self.map(page, flags, allocator);
+        }
     }
 
     pub fn identity_map<A>(&mut self, frame: Frame, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
@@ -34,39 +66,200 @@ impl Mapper {
         self.map_to(page, frame, flags, allocator);
     }
 
+    /// Identity maps every frame in `frames`.
+    pub fn identity_map_range<A>(&mut self, frames: FrameIter, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
+        let flags = flags.into();
+
+        for frame in frames {
+            self.identity_map(frame, flags, allocator);
+        }
+    }
+
+    /// Maps the `size` bytes of device memory at `physical` to the pages starting at `start`, with
+    /// caching disabled. Returns the virtual address `physical` ends up at.
+    pub fn map_mmio<A>(&mut self, start: Page, physical: PhysicalAddress, size: usize, allocator: &mut A) -> VirtualAddress
+        where A: FrameAllocator {
+        assert!(size > 0);
+
+        let start_frame = Frame::containing_address(physical);
+        let end_frame = Frame::containing_address(PhysicalAddress::new(physical.as_u64() + size as u64 - 1));
+        let flags = mmio_flags();
+
+        for (i, frame) in Frame::range_inclusive(start_frame, end_frame).enumerate() {
+            self.map_to(Page(start.0 + i), frame, flags, allocator);
+        }
+
+        start.start_address() + physical.as_u64() % PAGE_SIZE as u64
+    }
+
     pub fn map_to<A>(&mut self, page: Page, frame: Frame, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
-        let p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
-        let p2 = p3.next_table_create(page.p3_index(), allocator);
-        let p1 = p2.next_table_create(page.p2_index(), allocator);
+        self.try_map_to(page, frame, flags, allocator).expect("No frames available!")
+    }
+
+    /// Maps `page` to `frame`, like `map_to`, but returns an error instead of panicking when there
+    /// are no frames left for the page tables. The tables created before that are freed again.
+    pub fn try_map_to<A>(&mut self, page: Page, frame: Frame, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) -> Result<(), MapError>
+        where A: FrameAllocator {
+        let new_p3 = self.p4().next_table(page.p4_index()).is_none();
+        let p3 = self.p4_mut().try_next_table_create(page.p4_index(), allocator)?;
+        let new_p2 = p3.next_table(page.p3_index()).is_none();
+
+        let result = p3.try_next_table_create(page.p3_index(), allocator)
+            .and_then(|p2| p2.try_next_table_create(page.p2_index(), allocator));
+
+        let p1 = match result {
+            Ok(p1) => p1,
+            Err(error) => {
+                // Creating the P1 table failed at the latest, so the new tables above it are empty.
+                if new_p2 && p3.next_table(page.p3_index()).is_some() {
+                    p3.free_next_table(page.p3_index(), allocator);
+                }
+
+                if new_p3 {
+                    self.p4_mut().free_next_table(page.p4_index(), allocator);
+                }
+
+                return Err(error);
+            }
+        };
 
         assert!(p1[page.p1_index()].is_unused());
         p1[page.p1_index()].set(frame, flags.into() | EntryFlags::Present);
+
+        Ok(())
     }
 
+    /// Maps the 2 MiB huge page starting at `page` to the 2 MiB of physical memory starting at
+    /// `frame`. Both have to be aligned to 2 MiB.
+    pub fn map_huge_to<A>(&mut self, page: Page, frame: Frame, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
+        assert_eq!(page.0 % HUGE_PAGE_PAGES, 0, "Huge page is not aligned to 2 MiB");
+        assert_eq!(frame.0 % HUGE_PAGE_PAGES, 0, "Huge page frame is not aligned to 2 MiB");
+
+        let p3 = self.p4_mut().next_table_create(page.p4_index(), allocator);
+        let p2 = p3.next_table_create(page.p3_index(), allocator);
+
+        assert!(p2[page.p2_index()].is_unused(), "{:?} is already mapped", page.start_address());
+        p2[page.p2_index()].set(frame, flags.into() | EntryFlags::Present | EntryFlags::HugePage);
+    }
+
+    /// Unmaps `page` and deallocates the frame it points to. If `page` is part of a huge page, the
+    /// whole huge page is unmapped, but its frames are not deallocated: they were passed to
+    /// `map_huge_to` by the caller instead of coming from the allocator.
     pub fn unmap<A>(&mut self, page: Page, allocator: &mut A) where A: FrameAllocator {
-        assert!(self.translate(page.start_address()).is_some());
+        let (frame, count) = self.unmap_entry(page);
+        shootdown(page, page);
+
+        // TODO: Unmap p1 p2 p3 if empty.
+        if count == 1 {
+            allocator.deallocate_frame(frame);
+        }
+    }
+
+    /// Unmaps every page in `pages` and deallocates the frames they point to, except for those of
+    /// huge pages, like `unmap`. The TLB is flushed once at the end, which flushes all of it for
+    /// ranges larger than `TLB_FLUSH_ALL_THRESHOLD` pages.
+    pub fn unmap_range<A>(&mut self, pages: PageIter, allocator: &mut A) where A: FrameAllocator {
+        let bounds = pages.clone().next().and_then(|first| pages.clone().next_back().map(|last| (first, last)));
+        let mut huge_page_end = 0;
+
+        for page in pages {
+            // The rest of a huge page is unmapped together with the first page of it in the range.
+            if page.0 < huge_page_end {
+                continue;
+            }
+
+            let (frame, count) = self.unmap_entry(page);
+            if count > 1 {
+                huge_page_end = page.0 - page.0 % HUGE_PAGE_PAGES + HUGE_PAGE_PAGES;
+            } else {
+                allocator.deallocate_frame(frame);
+            }
+        }
 
-        let p1 = self.p4_mut().next_table_mut(page.p4_index())
+        if let Some((first, last)) = bounds {
+            shootdown(first, last);
+        }
+    }
+
+    /// Unmaps a page without deallocating the frame it points to, because that frame is owned by
+    /// something else. Returns the frame that was mapped, or the first frame for a huge page.
+    pub fn unmap_borrowed(&mut self, page: Page) -> Frame {
+        let (frame, _) = self.unmap_entry(page);
+        shootdown(page, page);
+
+        frame
+    }
+
+    /// Replaces the flags of the entry that maps `page`, keeping the frame. For a huge page, the
+    /// flags of the whole huge page are replaced.
+    pub fn update_flags(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>) {
+        assert!(self.is_mapped(page.start_address()), "{:?} is not mapped", page.start_address());
+
+        let flags = flags.into() | EntryFlags::Present;
+        let p2 = self.p4_mut().next_table_mut(page.p4_index())
             .and_then(|p3| p3.next_table_mut(page.p3_index()))
-            .and_then(|p2| p2.next_table_mut(page.p2_index()))
-            .expect("Huge pages are not supported!");
+            .expect("1 GiB huge pages are not supported!");
+
+        let entry = if p2[page.p2_index()].flags().contains(EntryFlags::HugePage) {
+            &mut p2[page.p2_index()]
+        } else {
+            &mut p2.next_table_mut(page.p2_index()).unwrap()[page.p1_index()]
+        };
+
+        let frame = entry.pointed_frame().unwrap();
+        let huge_page = entry.flags() & EntryFlags::HugePage;
+        entry.set(frame, flags | huge_page);
 
+        shootdown(page, page);
+    }
+
+    /// Clears the page table entry of `page` without flushing it from the TLB. Returns the first
+    /// frame that was mapped and the amount of frames the entry covered.
+    fn unmap_entry(&mut self, page: Page) -> (Frame, usize) {
+        assert!(self.is_mapped(page.start_address()), "{:?} is not mapped", page.start_address());
+
+        let p2 = self.p4_mut().next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .expect("1 GiB huge pages are not supported!");
+
+        if p2[page.p2_index()].flags().contains(EntryFlags::HugePage) {
+            let frame = p2[page.p2_index()].pointed_frame().unwrap();
+            p2[page.p2_index()].set_unused();
+
+            return (frame, HUGE_PAGE_PAGES);
+        }
+
+        let p1 = p2.next_table_mut(page.p2_index()).unwrap();
         let frame = p1[page.p1_index()].pointed_frame().unwrap();
         p1[page.p1_index()].set_unused();
 
-        TLB::flush(page.start_address());
-
-        // TODO: Unmap p1 p2 p3 if empty.
-        allocator.deallocate_frame(frame);
+        (frame, 1)
     }
 
     pub fn translate(&self, address: VirtualAddress) -> Option<PhysicalAddress> {
+        self.translate_with_flags(address).map(|(address, _)| address)
+    }
+
+    /// Translates `address` to a physical address and also returns the flags of the entry that
+    /// maps it, so callers can check if the mapping is writable or executable.
+    pub fn translate_with_flags(&self, address: VirtualAddress) -> Option<(PhysicalAddress, FlagSet<EntryFlags>)> {
         let offset = address.as_u64() % PAGE_SIZE as u64;
-        self.translate_page(Page::containing_address(address))
-            .map(|frame| PhysicalAddress::new((frame.0 * PAGE_SIZE) as u64 + offset))
+        self.translate_page_with_flags(Page::containing_address(address))
+            .map(|(frame, flags)| (PhysicalAddress::new((frame.0 * PAGE_SIZE) as u64 + offset), flags))
+    }
+
+    /// Returns whether `address` is mapped to a frame.
+    pub fn is_mapped(&self, address: VirtualAddress) -> bool {
+        self.translate_with_flags(address).is_some()
     }
 
     pub fn translate_page(&self, page: Page) -> Option<Frame> {
+        self.translate_page_with_flags(page).map(|(frame, _)| frame)
+    }
+
+    /// Translates `page` to the frame it is mapped to, together with the flags of the entry that
+    /// maps it. For huge pages, these are the flags of the huge page entry.
+    pub fn translate_page_with_flags(&self, page: Page) -> Option<(Frame, FlagSet<EntryFlags>)> {
         let p3 = self.p4().next_table(page.p4_index());
 
         let huge_page = || {
@@ -77,9 +270,9 @@ impl Mapper {
                     if p3_entry.flags().contains(EntryFlags::HugePage) {
                         assert_eq!(start_frame.0 % (TABLE_ENTRY_COUNT.pow(2)), 0);
 
-                        return Some(Frame(
+                        return Some((Frame(
                             start_frame.0 + page.p2_index() * TABLE_ENTRY_COUNT + page.p1_index()
-                        ));
+                        ), p3_entry.flags()));
                     }
                 }
 
@@ -90,7 +283,7 @@ impl Mapper {
                         if p2_entry.flags().contains(EntryFlags::HugePage) {
                             assert_eq!(start_frame.0 % TABLE_ENTRY_COUNT, 0);
 
-                            return Some(Frame(start_frame.0 + page.p1_index()));
+                            return Some((Frame(start_frame.0 + page.p1_index()), p2_entry.flags()));
                         }
                     }
                 }
@@ -101,15 +294,24 @@ impl Mapper {
 
         p3.and_then(|p3| p3.next_table(page.p3_index()))
             .and_then(|p2| p2.next_table(page.p2_index()))
-            .and_then(|p1| p1[page.p1_index()].pointed_frame())
+            .and_then(|p1| {
+                let p1_entry = &p1[page.p1_index()];
+                p1_entry.pointed_frame().map(|frame| (frame, p1_entry.flags()))
+            })
             .or_else(huge_page)
     }
 
     pub fn p4(&self) -> &PageTable<Level4> {
-        unsafe { self.p4.as_ref() }
+        self.table.p4()
     }
 
     pub fn p4_mut(&mut self) -> &mut PageTable<Level4> {
-        unsafe { self.p4.as_mut() }
+        self.table.p4_mut()
     }
+}
+
+/// Removes the pages `first..=last` from the TLB after their entries were changed. Map operations
+/// never need this, because non-present entries are not cached.
+fn shootdown(first: Page, last: Page) {
+    TLB::shootdown(first.start_address(), last.start_address());
 }
\ No newline at end of file
@@ -0,0 +1,145 @@
+use boot::{self, BootInfo, MemoryRegionType};
+use memory::frame::{Frame, FrameIter};
+use util::irq_lock::IrqLock;
+use x86_64::PhysicalAddress;
+
+/// The physical address of the local APIC registers.
+pub const LAPIC_ADDRESS: u64 = 0xfee0_0000;
+
+/// The physical address of the registers of the first IO APIC on practically every PC.
+pub const IO_APIC_ADDRESS: u64 = 0xfec0_0000;
+
+/// The amount of regions that can be registered.
+pub const MAX_MMIO_REGIONS: usize = 16;
+
+/// The physical ranges outside of usable memory that the kernel has to access. `remap_kernel`
+/// identity maps these, every other reserved range stays unmapped.
+static REGIONS: IrqLock<MmioRegions> = IrqLock::new(MmioRegions::new());
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MmioError {
+    /// All `MAX_MMIO_REGIONS` slots are used by regions the new one doesn't touch.
+    TooManyRegions,
+}
+
+/// A range of physical memory that belongs to a device or the firmware instead of the frame
+/// allocator, like device registers or ACPI tables.
+#[derive(Debug, Copy, Clone)]
+pub struct MmioRegion {
+    pub name: &'static str,
+    pub start: PhysicalAddress,
+    pub size: usize,
+}
+
+impl MmioRegion {
+    /// Returns the frames this region covers.
+    pub fn frames(&self) -> FrameIter {
+        Frame::range_inclusive(
+            Frame::containing_address(self.start),
+            Frame::containing_address(PhysicalAddress::new(self.start.as_u64() + self.size as u64 - 1))
+        )
+    }
+
+    /// Returns whether `address` lies in this region.
+    pub fn contains(&self, address: PhysicalAddress) -> bool {
+        address.as_u64() >= self.start.as_u64() && address.as_u64() - self.start.as_u64() < self.size as u64
+    }
+
+    fn end(&self) -> u64 {
+        self.start.as_u64() + self.size as u64
+    }
+}
+
+/// A fixed set of regions. Regions that overlap or touch are merged, so a memory map with many
+/// adjacent firmware ranges doesn't use up the slots.
+#[derive(Debug, Copy, Clone)]
+pub struct MmioRegions([Option<MmioRegion>; MAX_MMIO_REGIONS]);
+
+impl MmioRegions {
+    pub const fn new() -> MmioRegions {
+        MmioRegions([None; MAX_MMIO_REGIONS])
+    }
+
+    /// Adds `size` bytes at `start`, merged into a region it overlaps or touches, which keeps its
+    /// name. Fails if it touches no region and every slot is used.
+    pub fn add(&mut self, name: &'static str, start: PhysicalAddress, size: usize) -> Result<(), MmioError> {
+        let mut new = MmioRegion { name, start, size };
+
+        // Merging can make the grown region touch others, so those are merged into it as well.
+        while let Some(index) = self.0.iter().position(|region| region.map_or(false, |region| touches(&region, &new))) {
+            let region = self.0[index].take().unwrap();
+            let start = region.start.as_u64().min(new.start.as_u64());
+            let end = region.end().max(new.end());
+            new = MmioRegion { name: region.name, start: PhysicalAddress::new(start), size: (end - start) as usize };
+        }
+
+        let slot = self.0.iter_mut()
+            .find(|region| region.is_none())
+            .ok_or(MmioError::TooManyRegions)?;
+
+        *slot = Some(new);
+        Ok(())
+    }
+
+    /// Returns every region, in no particular order.
+    pub fn regions(&self) -> [Option<MmioRegion>; MAX_MMIO_REGIONS] {
+        self.0
+    }
+}
+
+/// Returns whether `a` and `b` overlap or are directly next to each other.
+fn touches(a: &MmioRegion, b: &MmioRegion) -> bool {
+    a.start.as_u64() <= b.end() && b.start.as_u64() <= a.end()
+}
+
+/// Registers `size` bytes of physical memory at `start` to be identity mapped when the kernel is
+/// remapped, see `MmioRegions::add`. Regions registered after `remap_kernel` are not mapped,
+/// drivers have to use `Mapper::map_mmio` for those.
+pub fn register(name: &'static str, start: PhysicalAddress, size: usize) -> Result<(), MmioError> {
+    assert!(size > 0, "MMIO region '{}' is empty", name);
+
+    REGIONS.lock().add(name, start, size)
+}
+
+/// Registers the regions every boot needs: the APICs and the ACPI tables from the memory map. The
+/// APICs are registered first, so they always get a slot. ACPI ranges that don't fit are skipped.
+pub fn register_boot_regions(boot_info: &BootInfo) {
+    register("Local APIC", PhysicalAddress::new(LAPIC_ADDRESS), 4096).expect("No slot for the local APIC!");
+    register("IO APIC", PhysicalAddress::new(IO_APIC_ADDRESS), 4096).expect("No slot for the IO APIC!");
+
+    for region in boot_info.memory_map() {
+        let name = match region.region_type() {
+            MemoryRegionType::AcpiReclaimable => "ACPI tables",
+            MemoryRegionType::AcpiNvs => "ACPI NVS",
+            _ => continue,
+        };
+
+        let start = PhysicalAddress::new(region.start_address());
+        if register(name, start, region.size() as usize).is_err() {
+            crate::kprintln!("Too many MMIO regions, {} at {:?} are not mapped", name, start);
+        }
+    }
+}
+
+/// Returns a copy of every registered region.
+pub fn regions() -> [Option<MmioRegion>; MAX_MMIO_REGIONS] {
+    REGIONS.lock().regions()
+}
+
+/// Returns whether `frame` is device memory instead of RAM, which is every frame outside of the
+/// usable regions of the memory map. Before the boot information is loaded, every frame is RAM.
+pub fn is_device_frame(frame: Frame) -> bool {
+    let address = frame.start_address().as_u64();
+
+    boot::boot_info().map_or(false, |boot_info| {
+        !boot_info.memory_map().iter()
+            .any(|region| region.is_usable() && address >= region.start_address() && address < region.end_address())
+    })
+}
+
+/// Returns the registered region that contains `address`, if any.
+pub fn find(address: PhysicalAddress) -> Option<MmioRegion> {
+    REGIONS.lock().0.iter()
+        .filter_map(|region| *region)
+        .find(|region| region.contains(address))
+}
\ No newline at end of file
@@ -1,25 +1,41 @@
-use core::ops::{Deref, DerefMut};
+use core::fmt;
+use core::ops::{Deref, DerefMut, Range};
 
 use flagset::FlagSet;
-use multiboot2::{BootInformation, ElfSectionFlags};
 
-use memory::frame::{Frame, FrameAllocator};
+use boot::{BootInfo, ElfSectionFlags};
+use memory::frame::{self, Frame, FrameAllocator};
+use memory::guard_page::{self, GuardPage};
 use memory::PAGE_SIZE;
 use memory::paging::entry::EntryFlags;
-use memory::paging::mapper::Mapper;
+use memory::paging::inspector::TableInspector;
+use memory::paging::mapper::{MapError, Mapper};
+use memory::paging::table::RecursivePageTable;
 use memory::paging::temporary_page::TemporaryPage;
+use util::range::InclusiveRange;
 use x86_64::{PhysicalAddress, VirtualAddress};
 use x86_64::instructions::TLB;
-use x86_64::registers::control::Cr3;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr3};
+use x86_64::registers::msr::{EFER, EFERFlags};
 
 pub mod entry;
 pub mod table;
 pub mod mapper;
-
-mod temporary_page;
+pub mod inspector;
+pub mod mmio;
+pub mod temporary_page;
 
 const TABLE_ENTRY_COUNT: usize = 512;
 
+/// The page inactive page tables are temporarily mapped at while they are being edited.
+pub const TEMPORARY_PAGE: Page = Page(0xcafe_babe);
+
+/// The page frames are temporarily mapped at by `ActivePageTable::with_frame_mapped`.
+const FRAME_MAPPING_PAGE: Page = Page(0xdead_beef);
+
+/// The size of the stack `boot.asm` sets up. Has to match the size reserved there.
+const BOOT_STACK_SIZE: usize = 32768;
+
 pub struct ActivePageTable {
     mapper: Mapper,
 }
@@ -27,7 +43,7 @@ pub struct ActivePageTable {
 impl ActivePageTable {
     unsafe fn new() -> ActivePageTable {
         ActivePageTable {
-            mapper: Mapper::new(),
+            mapper: Mapper::new(RecursivePageTable::active()),
         }
     }
 
@@ -49,6 +65,111 @@ impl ActivePageTable {
         temporary_page.unmap(self);
     }
 
+    /// Runs `f` with a mapper for `table` instead of the active table, so mappings can be added to
+    /// a page table that is not loaded in CR3.
+    pub fn with_inactive<A, F>(&mut self, table: &mut InactivePageTable, allocator: &mut A, f: F)
+        where A: FrameAllocator, F: FnOnce(&mut Mapper, &mut A) {
+        let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, allocator);
+        self.with(table, &mut temporary_page, |mapper| f(mapper, allocator));
+        temporary_page.free(allocator);
+    }
+
+    /// Creates a new inactive page table that shares every P4 entry of this table, except for the
+    /// recursive entry and the entries in `private_entries`, which start out empty. Shared entries
+    /// point to the same P3 tables, so mappings below them stay in sync between both tables.
+    pub fn new_table_sharing<A>(&mut self, private_entries: Range<usize>, allocator: &mut A) -> InactivePageTable
+        where A: FrameAllocator {
+        let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, allocator);
+        let frame = allocator.allocate_frame().expect("Out of memory!");
+        let mut table = InactivePageTable::new(Frame(frame.0), self, &mut temporary_page);
+
+        self.share_entries(&mut table, private_entries, &mut temporary_page);
+        temporary_page.free(allocator);
+
+        table
+    }
+
+    /// Copies the shared P4 entries of this table that `table` doesn't have yet, so P3 tables the
+    /// kernel created after `table` was made are visible in it too. Shared entries are never
+    /// removed, so the entries `table` already has stay the same.
+    pub fn share_entries(&mut self, table: &mut InactivePageTable, private_entries: Range<usize>, temporary_page: &mut TemporaryPage) {
+        {
+            let other_p4 = temporary_page.map_table_frame(Frame(table.p4_frame.0), self);
+
+            for i in (0..TABLE_ENTRY_COUNT - 1).filter(|i| !private_entries.contains(i)) {
+                let entry = &self.p4()[i];
+
+                if let (Some(p3_frame), true) = (entry.pointed_frame(), other_p4[i].is_unused()) {
+                    other_p4[i].set(p3_frame, entry.flags());
+                }
+            }
+        }
+
+        temporary_page.unmap(self);
+    }
+
+    /// Temporarily maps `frame`, runs `f` with the contents of the frame and unmaps it again. The
+    /// frame is borrowed, it is not deallocated afterwards. Calls can't be nested, because they
+    /// all use the same page. Device memory is mapped uncached, see `frame_mapping_flags`.
+    pub fn with_frame_mapped<A, F, R>(&mut self, frame: Frame, allocator: &mut A, f: F) -> R
+        where A: FrameAllocator, F: FnOnce(&mut [u8; PAGE_SIZE]) -> R {
+        self.map_to(FRAME_MAPPING_PAGE, frame, frame_mapping_flags(frame), allocator);
+
+        let result = {
+            let contents = unsafe { &mut *FRAME_MAPPING_PAGE.start_address().as_mut_ptr::<[u8; PAGE_SIZE]>() };
+            f(contents)
+        };
+
+        self.unmap_borrowed(FRAME_MAPPING_PAGE);
+        result
+    }
+
+    /// Maps `page` to a freshly zeroed frame. Use `map` instead when the contents of the page will
+    /// be overwritten anyway, to skip the zeroing.
+    pub fn map_zeroed<A>(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
+        self.try_map_zeroed(page, flags, allocator).expect("Out of memory!")
+    }
+
+    /// Maps `page` to a freshly zeroed frame, like `map_zeroed`, but returns an error instead of
+    /// panicking when there are no frames left. Nothing is mapped in that case.
+    pub fn try_map_zeroed<A>(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) -> Result<(), MapError>
+        where A: FrameAllocator {
+        let frame = allocator.allocate_zeroed_frame(self).ok_or(MapError::OutOfFrames)?;
+
+        if let Err(error) = self.try_map_to(page, Frame(frame.0), flags, allocator) {
+            allocator.deallocate_frame(frame);
+            return Err(error);
+        }
+
+        Ok(())
+    }
+
+    /// Maps every page in `pages` to a freshly zeroed frame.
+    pub fn map_range_zeroed<A>(&mut self, pages: PageIter, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) where A: FrameAllocator {
+        self.try_map_range_zeroed(pages, flags, allocator).expect("Out of memory!")
+    }
+
+    /// Maps every page in `pages` to a freshly zeroed frame, like `map_range_zeroed`. When the
+    /// frames run out, the pages that were already mapped are unmapped again and an error is
+    /// returned.
+    pub fn try_map_range_zeroed<A>(&mut self, pages: PageIter, flags: impl Into<FlagSet<EntryFlags>>, allocator: &mut A) -> Result<(), MapError>
+        where A: FrameAllocator {
+        let flags = flags.into();
+        let first = pages.clone().next();
+
+        for page in pages {
+            if let Err(error) = self.try_map_zeroed(page, flags, allocator) {
+                if let Some(first) = first.filter(|first| first.0 < page.0) {
+                    self.unmap_range(Page::range_inclusive(first, Page(page.0 - 1)), allocator);
+                }
+
+                return Err(error);
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
         let old_table = InactivePageTable {
             p4_frame: Frame::containing_address(Cr3::read()),
@@ -74,6 +195,8 @@ impl DerefMut for ActivePageTable {
     }
 }
 
+/// A page table that is not currently loaded in CR3. Dropping it does not free its frames, they
+/// have to be deallocated by whoever owns the table.
 pub struct InactivePageTable {
     p4_frame: Frame,
 }
@@ -92,11 +215,30 @@ impl InactivePageTable {
             p4_frame: frame,
         }
     }
+
+    /// Runs `f` with a read-only view of this table, for example to translate an address in the
+    /// address space of another task. Unlike `ActivePageTable::with`, this does not touch the
+    /// recursive entry or flush the whole TLB, it maps the tables one at a time instead.
+    pub fn inspect<F, R>(&self, active_table: &mut ActivePageTable, temporary_page: &mut TemporaryPage, f: F) -> R
+        where F: FnOnce(&mut TableInspector) -> R {
+        f(&mut TableInspector::new(Frame(self.p4_frame.0), active_table, temporary_page))
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Copy, Clone)]
 pub struct Page(pub usize);
 
+impl fmt::Debug for Page {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Indices past the end of the address space have no start address, like for `Frame`.
+        match self.0.checked_mul(PAGE_SIZE) {
+            Some(_) => write!(f, "Page({:?} [p4: {}, p3: {}, p2: {}, p1: {}])", self.start_address(),
+                              self.p4_index(), self.p3_index(), self.p2_index(), self.p1_index()),
+            None => write!(f, "Page(#{})", self.0),
+        }
+    }
+}
+
 impl Page {
     pub fn containing_address(address: VirtualAddress) -> Page {
         assert!(address.as_u64() < 0x0000_8000_0000_0000 ||
@@ -127,33 +269,115 @@ impl Page {
 
     pub fn range_inclusive(start: Page, end: Page) -> PageIter {
         PageIter {
-            start,
-            end,
+            range: InclusiveRange::new(start.0, end.0),
         }
     }
 }
 
 #[derive(Clone)]
 pub struct PageIter {
-    start: Page,
-    end: Page,
+    range: InclusiveRange,
 }
 
 impl Iterator for PageIter {
     type Item = Page;
 
     fn next(&mut self) -> Option<Page> {
-        if self.start.0 <= self.end.0 {
-            let page = self.start;
-            self.start.0 += 1;
-            Some(page)
-        } else {
-            None
+        self.range.next().map(Page)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Page> {
+        self.range.nth(n).map(Page)
+    }
+}
+
+impl DoubleEndedIterator for PageIter {
+    fn next_back(&mut self) -> Option<Page> {
+        self.range.next_back().map(Page)
+    }
+}
+
+impl ExactSizeIterator for PageIter {}
+
+/// Returns the flags `with_frame_mapped` maps `frame` with. Device memory is mapped with
+/// `mmio_flags`, so accesses reach the device instead of the cache.
+pub fn frame_mapping_flags(frame: Frame) -> FlagSet<EntryFlags> {
+    if mmio::is_device_frame(frame) {
+        entry::mmio_flags()
+    } else {
+        EntryFlags::Writable | EntryFlags::NoExecute
+    }
+}
+
+/// Returns the flags the pages of a kernel section with flags `section` are mapped with.
+pub fn section_entry_flags(section: FlagSet<ElfSectionFlags>) -> FlagSet<EntryFlags> {
+    let mut flags = FlagSet::new_truncated(0);
+
+    if section.contains(ElfSectionFlags::Allocated) {
+        flags |= EntryFlags::Present
+    }
+
+    if section.contains(ElfSectionFlags::Writable) {
+        flags |= EntryFlags::Writable;
+    }
+
+    if !section.contains(ElfSectionFlags::Executable) {
+        flags |= EntryFlags::NoExecute;
+    }
+
+    flags
+}
+
+/// Returns what is wrong if a page of a kernel section with flags `section` is mapped with
+/// `page`, or `None` if the page allows nothing the section doesn't. Executable pages are never
+/// allowed to be writable, even if the section is both.
+pub fn protection_violation(section: FlagSet<ElfSectionFlags>, page: FlagSet<EntryFlags>) -> Option<&'static str> {
+    let writable = page.contains(EntryFlags::Writable);
+    let executable = !page.contains(EntryFlags::NoExecute);
+
+    if !page.contains(EntryFlags::Present) {
+        Some("not mapped")
+    } else if writable && executable {
+        Some("writable and executable")
+    } else if writable && !section.contains(ElfSectionFlags::Writable) {
+        Some("writable, but the section is read-only")
+    } else if executable && !section.contains(ElfSectionFlags::Executable) {
+        Some("executable, but the section is not")
+    } else {
+        None
+    }
+}
+
+/// Checks that every page of the kernel is mapped no more permissive than its section, and that
+/// the CPU enforces it. Panics otherwise, since a mistake here would silently leave the kernel
+/// writable or its data executable.
+pub fn verify_kernel_protection(mapper: &Mapper, boot_info: &BootInfo) {
+    assert!(Cr0::read().contains(Cr0Flags::WriteProtect), "Write protection is not enabled!");
+    assert!(EFER::read().contains(EFERFlags::NoExecuteEnable), "No-execute is not enabled!");
+
+    for section in boot_info.elf_sections().iter().filter(|section| section.is_allocated()) {
+        let start = Page::containing_address(VirtualAddress::new(section.start_address()));
+        let end = Page::containing_address(VirtualAddress::new(section.end_address() - 1));
+
+        for page in Page::range_inclusive(start, end) {
+            let flags = mapper.translate_page_with_flags(page)
+                .map_or(FlagSet::new_truncated(0), |(_, flags)| flags);
+
+            if let Some(violation) = protection_violation(section.flags(), flags) {
+                panic!("Kernel page {:?} of the section at {:#x} is {} (W^X violation)",
+                       page.start_address(), section.start_address(), violation);
+            }
         }
     }
 }
-pub fn remap_kernel<A>(allocator: &mut A, boot_info: &BootInformation) -> ActivePageTable where A: FrameAllocator {
-    let mut temporary_page = TemporaryPage::new(Page(0xcafe_babe), allocator);
+
+pub fn remap_kernel<A>(allocator: &mut A, boot_info: &BootInfo) -> ActivePageTable where A: FrameAllocator {
+    let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, allocator);
+    mmio::register_boot_regions(boot_info);
 
     let mut active_table = unsafe { ActivePageTable::new() };
     let mut new_table = {
@@ -162,51 +386,45 @@ pub fn remap_kernel<A>(allocator: &mut A, boot_info: &BootInformation) -> Active
     };
 
     active_table.with(&mut new_table, &mut temporary_page, |mapper| {
-        let elf_sections_tag = boot_info.elf_sections_tag()
-            .expect("Memory map tag required!");
-
-        for section in elf_sections_tag.sections() {
+        for section in boot_info.elf_sections() {
             if !section.is_allocated() {
                 continue;
             }
 
             assert_eq!(section.start_address() % PAGE_SIZE as u64, 0);
 
-            let mut flags = FlagSet::new_truncated(0);
-
-            if section.flags().contains(ElfSectionFlags::ALLOCATED) {
-                flags |= EntryFlags::Present
-            }
-
-            if section.flags().contains(ElfSectionFlags::WRITABLE) {
-                flags |= EntryFlags::Writable;
-            }
-
-            if !section.flags().contains(ElfSectionFlags::EXECUTABLE) {
-                flags |= EntryFlags::NoExecute;
-            }
+            let flags = section_entry_flags(section.flags());
 
             let start_frame = Frame::containing_address(PhysicalAddress::new(section.start_address()));
             let end_frame = Frame::containing_address(PhysicalAddress::new(section.end_address() - 1));
 
-            for frame in Frame::range_inclusive(start_frame, end_frame) {
-                mapper.identity_map(frame, flags, allocator);
-            }
+            mapper.identity_map_range(Frame::range_inclusive(start_frame, end_frame), flags, allocator);
         }
 
-        let vga_buffer_frame = Frame::containing_address(PhysicalAddress::new(0xb8000));
-        mapper.identity_map(vga_buffer_frame, EntryFlags::Writable, allocator);
+        // The whole vga memory window, which holds the 80x50 text buffer and the fonts.
+        let vga_start = Frame::containing_address(PhysicalAddress::new(0xa0000));
+        let vga_end = Frame::containing_address(PhysicalAddress::new(0xbffff));
+        mapper.identity_map_range(Frame::range_inclusive(vga_start, vga_end), entry::mmio_flags(), allocator);
 
-        let multiboot_start = Frame::containing_address(
-            PhysicalAddress::new(boot_info.start_address() as u64)
-        );
+        let multiboot_start = Frame::containing_address(boot_info.start_address());
 
         let multiboot_end = Frame::containing_address(
-            PhysicalAddress::new(boot_info.end_address() as u64 - 1)
+            PhysicalAddress::new(boot_info.end_address().as_u64() - 1)
         );
 
-        for frame in Frame::range_inclusive(multiboot_start, multiboot_end) {
-            mapper.identity_map(frame, EntryFlags::Present, allocator);
+        mapper.identity_map_range(Frame::range_inclusive(multiboot_start, multiboot_end), EntryFlags::Present, allocator);
+
+        for (start, end) in boot_info.modules().iter().filter_map(frame::module_frames) {
+            mapper.identity_map_range(Frame::range_inclusive(start, end), EntryFlags::Present | EntryFlags::NoExecute, allocator);
+        }
+
+        // Regions are not page aligned, so neighbouring regions can share a frame.
+        for region in mmio::regions().iter().filter_map(|region| *region) {
+            for frame in region.frames() {
+                if mapper.translate_page(Page(frame.0)).is_none() {
+                    mapper.identity_map(frame, entry::mmio_flags(), allocator);
+                }
+            }
         }
     });
 
@@ -217,8 +435,20 @@ pub fn remap_kernel<A>(allocator: &mut A, boot_info: &BootInformation) -> Active
         VirtualAddress::new(old_table.p4_frame.start_address().as_u64())
     );
 
-    active_table.unmap(old_p4_page, allocator);
-    crate::kprintln!("Created kernel stack guard page at {:?}", old_p4_page.start_address());
+    // In `boot.asm`, the P4 table is followed by the P3 and P2 tables and then the boot stack. None
+    // of the old tables are referenced now that CR3 points to the new table, so their frames are
+    // handed back to the allocator. The pages stay unmapped as guard pages, so an overflow of the
+    // boot stack faults right below it.
+    let stack_bottom = old_p4_page.start_address() + (3 * PAGE_SIZE) as u64;
+    for page in Page::range_inclusive(old_p4_page, Page(old_p4_page.0 + 2)) {
+        active_table.unmap(page, allocator);
+        guard_page::register(GuardPage {
+            page,
+            stack_bottom,
+            stack_top: stack_bottom + BOOT_STACK_SIZE as u64,
+        });
+    }
+    crate::kprintln!("Created kernel stack guard pages at {:?} - {:?}", old_p4_page.start_address(), stack_bottom);
 
     active_table
 }
\ No newline at end of file
@@ -1,14 +1,82 @@
 use core::ops::{Index, IndexMut};
+use core::ptr::Unique;
 
 use memory::paging::entry::{Entry, EntryFlags};
 use memory::paging::TABLE_ENTRY_COUNT;
 use x86_64::VirtualAddress;
 use core::marker::PhantomData;
-use memory::frame::FrameAllocator;
+use memory::frame::{Frame, FrameAllocator};
+use memory::paging::mapper::MapError;
+use x86_64::instructions::TLB;
+use x86_64::registers::control::Cr3;
 
+/// The entry of the P4 table that points to the P4 table itself.
+pub const RECURSIVE_ENTRY: usize = TABLE_ENTRY_COUNT - 1;
+
+/// The address of the P4 table through the recursive entry. Only valid while the recursive entry
+/// of the active table points to itself, so it is only dereferenced through `RecursivePageTable`.
 #[allow(clippy::inconsistent_digit_grouping)]
 pub const P4: *mut PageTable<Level4> = 0o177777_777_777_777_777_0000 as *mut _;
 
+/// A P4 table is not the active table at `P4`, or its recursive entry does not point to it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NotRecursive;
+
+/// A P4 table whose last entry points to the table itself, so the page tables below it can be
+/// reached at fixed virtual addresses. This is what a `Mapper` needs to work.
+///
+/// Switching tables replaces the recursive mapping, so a `RecursivePageTable` for the active
+/// table is only valid until CR3 changes, except for the temporary switch in
+/// `ActivePageTable::with`.
+pub struct RecursivePageTable {
+    p4: Unique<PageTable<Level4>>,
+}
+
+impl RecursivePageTable {
+    /// Wraps the P4 table at `table`, which is stored in `frame`. A `Mapper` finds the tables below
+    /// it through the recursive mapping of the active table, so this fails unless `table` is `P4`,
+    /// `frame` is the frame in CR3, and the recursive entry of the table points to `frame`.
+    ///
+    /// # Safety
+    /// `table` has to point to a valid P4 table, which is not accessed in other ways while the
+    /// returned table is in use.
+    pub unsafe fn new(table: *mut PageTable<Level4>, frame: Frame) -> Result<RecursivePageTable, NotRecursive> {
+        if table != P4 || frame != Frame::containing_address(Cr3::read()) {
+            return Err(NotRecursive);
+        }
+
+        if (*table)[RECURSIVE_ENTRY].pointed_frame() != Some(frame) {
+            return Err(NotRecursive);
+        }
+
+        Ok(RecursivePageTable {
+            p4: Unique::new_unchecked(table),
+        })
+    }
+
+    /// Returns the active P4 table, through its recursive mapping.
+    ///
+    /// # Safety
+    /// The recursive entry of the active table has to point to the table itself, which is checked
+    /// in debug builds. The table should not be accessed by other `Mapper`s at the same time.
+    pub unsafe fn active() -> RecursivePageTable {
+        debug_assert_eq!((*P4)[RECURSIVE_ENTRY].pointed_frame(), Some(Frame::containing_address(Cr3::read())),
+                         "The active P4 table is not recursively mapped");
+
+        RecursivePageTable {
+            p4: Unique::new_unchecked(P4),
+        }
+    }
+
+    pub fn p4(&self) -> &PageTable<Level4> {
+        unsafe { self.p4.as_ref() }
+    }
+
+    pub fn p4_mut(&mut self) -> &mut PageTable<Level4> {
+        unsafe { self.p4.as_mut() }
+    }
+}
+
 pub struct Level4;
 pub struct Level3;
 pub struct Level2;
@@ -50,16 +118,37 @@ impl<L: HierarchicalLevel> PageTable<L> {
     }
 
     pub fn next_table_create<A>(&mut self, index: usize, allocator: &mut A) -> &mut PageTable<L::NextLevel>
+        where A: FrameAllocator {
+        self.try_next_table_create(index, allocator).expect("No frames available!")
+    }
+
+    /// Returns the next table at `index`, creating it if it doesn't exist yet. Fails if there is
+    /// no frame left for a new table.
+    pub fn try_next_table_create<A>(&mut self, index: usize, allocator: &mut A) -> Result<&mut PageTable<L::NextLevel>, MapError>
         where A: FrameAllocator {
         if self.next_table(index).is_none() {
             assert!(!self.entries[index].flags().contains(EntryFlags::HugePage));
 
-            let frame = allocator.allocate_frame().expect("No frames available!");
+            let frame = allocator.allocate_frame().ok_or(MapError::OutOfFrames)?;
             self.entries[index].set(frame, EntryFlags::Present | EntryFlags::Writable);
             self.next_table_mut(index).unwrap().zero();
         }
 
-        self.next_table_mut(index).unwrap()
+        Ok(self.next_table_mut(index).unwrap())
+    }
+
+    /// Removes the empty next table at `index` and returns its frame to `allocator`, to undo
+    /// `try_next_table_create` when a mapping fails halfway.
+    pub fn free_next_table<A>(&mut self, index: usize, allocator: &mut A) where A: FrameAllocator {
+        let address = self.next_table_address(index).expect("There is no next table");
+        debug_assert!(self.next_table(index).unwrap().entries.iter().all(|entry| entry.is_unused()));
+
+        let frame = self.entries[index].pointed_frame().unwrap();
+        self.entries[index].set_unused();
+
+        // The table itself was accessed through the recursive mapping, which is cached now.
+        TLB::shootdown(address, address);
+        allocator.deallocate_frame(frame);
     }
 
     fn next_table_address(&self, index: usize) -> Option<VirtualAddress> {
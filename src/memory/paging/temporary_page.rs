@@ -23,14 +23,34 @@ impl TemporaryPage {
         }
     }
 
+    /// Maps the page table in `frame` without write access, so it can be read safely.
+    pub fn map_table_frame_read_only(&mut self, frame: Frame, active_table: &mut ActivePageTable) -> &PageTable<Level1> {
+        assert!(active_table.translate_page(self.page).is_none());
+        active_table.map_to(self.page, frame, EntryFlags::NoExecute, &mut self.allocator);
+
+        unsafe {
+            &*self.page.start_address().as_ptr()
+        }
+    }
+
     pub fn map(&mut self, frame: Frame, active_table: &mut ActivePageTable) -> VirtualAddress {
         assert!(active_table.translate_page(self.page).is_none());
         active_table.map_to(self.page, frame, EntryFlags::Writable, &mut self.allocator);
         self.page.start_address()
     }
 
+    /// Unmaps the temporary page. The mapped frame belongs to the caller, so it is not handed to
+    /// the tiny allocator, which could then give it out as a page table or return it through
+    /// `free`.
     pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
-        active_table.unmap(self.page, &mut self.allocator);
+        active_table.unmap_borrowed(self.page);
+    }
+
+    /// Hands the frames that were reserved for page tables, but not used, back to `allocator`.
+    pub fn free<A>(mut self, allocator: &mut A) where A: FrameAllocator {
+        for frame in (self.allocator.0).iter_mut().filter_map(|frame| frame.take()) {
+            allocator.deallocate_frame(frame);
+        }
     }
 }
 
@@ -1,6 +1,7 @@
 use memory::paging::{PageIter, ActivePageTable, Page};
 use memory::frame::FrameAllocator;
 use memory::{Stack, PAGE_SIZE};
+use memory::guard_page::{self, GuardPage};
 use memory::paging::entry::EntryFlags;
 
 pub struct StackAllocator {
@@ -30,14 +31,18 @@ impl StackAllocator {
         };
 
         match (guard_page, stack_start, stack_end) {
-            (Some(_), Some(start), Some(end)) => {
+            (Some(guard), Some(start), Some(end)) => {
+                // The range is only used up if the stack could be mapped.
+                active_table.try_map_range_zeroed(Page::range_inclusive(start, end), EntryFlags::Writable, frame_allocator).ok()?;
                 self.range = range;
 
-                for page in Page::range_inclusive(start, end) {
-                    active_table.map(page, EntryFlags::Writable, frame_allocator);
-                }
-
                 let top_of_stack = end.start_address() + PAGE_SIZE as u64;
+                guard_page::register(GuardPage {
+                    page: guard,
+                    stack_bottom: start.start_address(),
+                    stack_top: top_of_stack,
+                });
+
                 Some(Stack { top: top_of_stack, bottom: start.start_address() })
             },
             _ => None
@@ -0,0 +1,185 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use flagset::FlagSet;
+
+use fs::vfs::{FsError, INode};
+use interrupts::exceptions::PageFaultErrorCode;
+use memory;
+use memory::PAGE_SIZE;
+use memory::frame::{Frame, FrameAllocator};
+use memory::paging::Page;
+use memory::paging::entry::EntryFlags;
+use util::irq_lock::IrqLock;
+use x86_64::VirtualAddress;
+
+/// The demand paged regions of the kernel address space.
+static KERNEL_VMAS: IrqLock<VmaList> = IrqLock::new(VmaList::new());
+
+/// Where the contents of the pages of a region come from.
+#[derive(Clone)]
+pub enum Backing {
+    /// Zeroed memory.
+    Anonymous,
+    /// The contents of `inode`, starting at `offset`. The part past the end of the file is zeroed.
+    File { inode: Arc<dyn INode>, offset: usize },
+}
+
+/// A range of virtual memory whose pages are only mapped when they are first accessed.
+#[derive(Clone)]
+pub struct Vma {
+    /// The page aligned addresses the region covers.
+    pub range: Range<u64>,
+    pub backing: Backing,
+    /// The flags the pages are mapped with.
+    pub flags: FlagSet<EntryFlags>,
+}
+
+impl Vma {
+    pub fn contains(&self, address: VirtualAddress) -> bool {
+        self.range.start <= address.as_u64() && address.as_u64() < self.range.end
+    }
+
+    /// Returns whether an access with the page fault error code `code` is allowed in this region.
+    fn allows(&self, code: FlagSet<PageFaultErrorCode>) -> bool {
+        !(code.contains(PageFaultErrorCode::Write) && !self.flags.contains(EntryFlags::Writable))
+            && !(code.contains(PageFaultErrorCode::InstructionFetch) && self.flags.contains(EntryFlags::NoExecute))
+            && !(code.contains(PageFaultErrorCode::UserSpace) && !self.flags.contains(EntryFlags::UserAccessible))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// The range is empty or not page aligned.
+    InvalidRange,
+    /// The range overlaps a region that was registered before.
+    Overlap,
+}
+
+/// The reason a page fault could not be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultError {
+    /// The address is not in any region.
+    NoRegion,
+    /// The page is present, or the region doesn't allow the kind of access.
+    AccessViolation,
+    /// The faulting code holds the memory or region lock, so the page can't be mapped.
+    Busy,
+    /// There are no frames left for the page.
+    OutOfMemory,
+    /// Reading the backing file failed.
+    Fs(FsError),
+}
+
+impl From<FsError> for FaultError {
+    fn from(error: FsError) -> FaultError {
+        FaultError::Fs(error)
+    }
+}
+
+/// A list of regions that don't overlap.
+pub struct VmaList {
+    vmas: Vec<Vma>,
+}
+
+impl VmaList {
+    pub const fn new() -> VmaList {
+        VmaList {
+            vmas: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, vma: Vma) -> Result<(), VmaError> {
+        let page_size = PAGE_SIZE as u64;
+        if vma.range.start >= vma.range.end || vma.range.start % page_size != 0 || vma.range.end % page_size != 0 {
+            return Err(VmaError::InvalidRange);
+        }
+
+        if self.vmas.iter().any(|other| other.range.start < vma.range.end && vma.range.start < other.range.end) {
+            return Err(VmaError::Overlap);
+        }
+
+        self.vmas.push(vma);
+        Ok(())
+    }
+
+    /// Removes the region that starts at `start` and returns it. Pages that were already mapped
+    /// stay mapped.
+    pub fn remove(&mut self, start: VirtualAddress) -> Option<Vma> {
+        let index = self.vmas.iter().position(|vma| vma.range.start == start.as_u64())?;
+        Some(self.vmas.remove(index))
+    }
+
+    pub fn find(&self, address: VirtualAddress) -> Option<&Vma> {
+        self.vmas.iter().find(|vma| vma.contains(address))
+    }
+}
+
+/// Registers a demand paged region in the kernel address space.
+pub fn register(vma: Vma) -> Result<(), VmaError> {
+    KERNEL_VMAS.lock().insert(vma)
+}
+
+/// Unregisters the region that starts at `start`, see `VmaList::remove`.
+pub fn unregister(start: VirtualAddress) -> Option<Vma> {
+    KERNEL_VMAS.lock().remove(start)
+}
+
+/// Called by the page fault handler. Maps the page that contains `address` if it is not present
+/// and lies in a registered region that allows the access, after which the faulting instruction
+/// can be retried.
+pub fn handle_page_fault(address: VirtualAddress, code: FlagSet<PageFaultErrorCode>) -> Result<(), FaultError> {
+    if code.contains(PageFaultErrorCode::ProtectionViolation) {
+        return Err(FaultError::AccessViolation);
+    }
+
+    // The region is cloned, so the lock isn't held while reading a backing file.
+    let vma = {
+        let vmas = KERNEL_VMAS.try_lock().ok_or(FaultError::Busy)?;
+        vmas.find(address).cloned().ok_or(FaultError::NoRegion)?
+    };
+
+    if !vma.allows(code) {
+        return Err(FaultError::AccessViolation);
+    }
+
+    let page = Page::containing_address(address);
+    memory::try_with_memory(|active_table, allocator| {
+        let frame = allocator.allocate_frame().ok_or(FaultError::OutOfMemory)?;
+
+        let filled = active_table.with_frame_mapped(Frame(frame.0), allocator, |contents| fill_page(&vma, page, contents));
+        let result = filled.and_then(|_| active_table.try_map_to(page, Frame(frame.0), vma.flags, allocator)
+            .map_err(|_| FaultError::OutOfMemory));
+
+        if result.is_err() {
+            allocator.deallocate_frame(frame);
+        }
+
+        result
+    }).unwrap_or(Err(FaultError::Busy))
+}
+
+/// Fills the contents of `page` according to the backing of `vma`.
+fn fill_page(vma: &Vma, page: Page, contents: &mut [u8; PAGE_SIZE]) -> Result<(), FaultError> {
+    let mut done = 0;
+
+    if let Backing::File { ref inode, offset } = vma.backing {
+        let offset = offset + (page.start_address().as_u64() - vma.range.start) as usize;
+
+        while done < PAGE_SIZE {
+            let len = inode.read_at(offset + done, &mut contents[done..])?;
+            if len == 0 {
+                break;
+            }
+
+            done += len;
+        }
+    }
+
+    for byte in contents[done..].iter_mut() {
+        *byte = 0;
+    }
+
+    Ok(())
+}
\ No newline at end of file
@@ -1,10 +1,150 @@
 use alloc::alloc::Layout;
 use core::fmt;
+use core::fmt::Write;
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+use driver::uart16550::UART16550;
+use driver::vga::ScreenWriter;
 use interrupts::StackFrame;
+use memory::paging::mapper::Mapper;
+use memory::paging::table::RecursivePageTable;
+use watchdog;
+use x86_64::VirtualAddress;
+use x86_64::instructions::{read_rbp, read_rsp};
+use x86_64::power;
 
-use crate::kprintln;
+/// Prints a line of a panic report to `$out`, the same way `kprintln!` does for the normal console.
+macro_rules! panic_println {
+    ($out:expr) => (panic_println!($out, ""));
+    ($out:expr, $($arg:tt)*) => ({
+        let _ = write!($out, "{}\x1b[37m\n", format_args!($($arg)*));
+    });
+}
+
+/// A writer used while panicking. It writes to temporary instances of the screen writer and the
+/// serial port instead of the locked global ones, so a panic that occurs while one of those locks
+/// is held can still print its message.
+struct PanicWriter {
+    screen: ScreenWriter,
+    serial: UART16550,
+}
+
+impl PanicWriter {
+    fn new() -> PanicWriter {
+        PanicWriter {
+            screen: ScreenWriter::raw(),
+            serial: UART16550::new(0x3F8),
+        }
+    }
+}
+
+impl Write for PanicWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.screen.write_str_raw(s);
+        self.serial.write_str(s)
+    }
+}
+
+/// Whether the kernel is handling an NMI or a double fault. Those can interrupt code that holds any
+/// lock, so nothing may be locked from then on.
+static EMERGENCY: AtomicBool = AtomicBool::new(false);
+
+/// Marks that the kernel is handling an NMI or a double fault. Called by the exception handlers of
+/// those, before they panic.
+pub fn enter_emergency() {
+    EMERGENCY.store(true, Ordering::SeqCst);
+}
+
+/// Whether `enter_emergency` was called.
+pub fn is_emergency() -> bool {
+    EMERGENCY.load(Ordering::SeqCst)
+}
+
+/// Prints to the screen and the first serial port without taking any lock, so it works even if
+/// the interrupted code holds the locks of the normal console. The text starts on a new line at
+/// the bottom of the screen, and the serial port is written to by polling.
+pub fn emergency_print(args: fmt::Arguments) {
+    let _ = PanicWriter::new().write_fmt(args);
+}
+
+/// The action taken after a panic is printed, encoded by `PanicAction::to_raw`.
+static PANIC_ACTION: AtomicU64 = AtomicU64::new(0);
+
+/// What the kernel does after it printed a panic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Halts the CPU, so the panic stays on the screen.
+    Halt,
+    /// Reboots the machine.
+    Reboot,
+    /// Exits QEMU with the code through the `isa-debug-exit` device, for automated test runs.
+    QemuExit(u32),
+}
+
+impl PanicAction {
+    fn to_raw(self) -> u64 {
+        match self {
+            PanicAction::Halt => 0,
+            PanicAction::Reboot => 1,
+            PanicAction::QemuExit(code) => 2 | u64::from(code) << 32,
+        }
+    }
+
+    fn from_raw(raw: u64) -> PanicAction {
+        match raw & 0xffff_ffff {
+            1 => PanicAction::Reboot,
+            2 => PanicAction::QemuExit((raw >> 32) as u32),
+            _ => PanicAction::Halt,
+        }
+    }
+}
+
+/// Sets what the kernel does after a panic. The default is `PanicAction::Halt`.
+pub fn set_action(action: PanicAction) {
+    PANIC_ACTION.store(action.to_raw(), Ordering::SeqCst);
+}
+
+/// Returns what the kernel does after a panic.
+pub fn action() -> PanicAction {
+    PanicAction::from_raw(PANIC_ACTION.load(Ordering::SeqCst))
+}
+
+/// Carries out `action` with `qemu_exit` and `reboot`. Returns if the action is
+/// `PanicAction::Halt`, or if it didn't work, after which the CPU should be halted.
+pub fn run_action<E, R>(action: PanicAction, qemu_exit: E, reboot: R) where E: FnOnce(u32), R: FnOnce() {
+    match action {
+        PanicAction::Halt => {},
+        PanicAction::Reboot => reboot(),
+        PanicAction::QemuExit(code) => qemu_exit(code),
+    }
+}
+
+/// The width names are padded to in a `Register`, enough for "Instruction Pointer".
+const REGISTER_NAME_WIDTH: usize = 19;
+
+/// The maximum amount of return addresses a panic prints.
+pub const MAX_BACKTRACE_FRAMES: usize = 16;
+
+/// Displays a register for the register dump: the padded name, the value as `0x` with 16 zero
+/// padded hex digits, and two spaces to separate it from the next column. The name is padded to
+/// `REGISTER_NAME_WIDTH` if it is longer than 3 characters, so the general purpose registers fit
+/// three columns on a line. Every column with a name of the same kind has the same width,
+/// whatever the value is.
+pub struct Register<'a>(pub &'a str, pub u64);
+
+impl<'a> fmt::Display for Register<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = if self.0.len() > 3 { REGISTER_NAME_WIDTH } else { 3 };
+
+        write!(f, "\x1b[37m{}:", self.0)?;
+        for _ in self.0.len()..width {
+            f.write_char(' ')?;
+        }
+
+        write!(f, " \x1b[97m{:#018x}  ", self.1)
+    }
+}
 
 /// An enum to indicate what kind of panic has occurred. This is used in conjunction with the
 /// `panic::panic` function.
@@ -24,49 +164,127 @@ pub enum PanicType<'a> {
     AllocationError(Layout)
 }
 
+/// Whether `panic` was called, to recognize a panic in the panic path itself.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 /// Panic and halt the kernel. Will print all available debugging information to the console.
 pub fn panic(panic: PanicType) -> ! {
-    unsafe { crate::driver::vga::WRITER.force_unlock() };
-    kprintln!("\n\x1b[31m!!! \x1b[91mKERNEL PANIC");
+    watchdog::disable();
 
-    match panic {
-        PanicType::KernelAssert(info) => {
-            let message = info.message().copied()
-                .unwrap_or_else(|| format_args!("No message."));
-            kprintln!("\x1b[37m// \x1b[97m{}", message);
+    // A panic in the panic path may have interrupted code that holds a lock.
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        enter_emergency();
+    }
 
-            if let Some(location) = info.location() {
-                kprintln!("\n\x1b[91mat {}", location);
-            }
-        },
-        PanicType::KernelException { name, stack_frame, additional_info } => {
-            kprintln!("\x1b[37m// \x1b[97mCPU EXCEPTION: '{}' (IDX: 0x{:02.x})", name, stack_frame.kind);
-
-            kprintln!("\n\x1b[91mStack Frame:");
-
-            // TODO: Fix padding
-            kprintln!("\x1b[37mInstruction Pointer: \x1b[97m{:_<12?}\x1b[37m  Code Segment: \x1b[97m{}", stack_frame.instruction_pointer, stack_frame.code_segment.0);
-            kprintln!("\x1b[37mStack Pointer: \x1b[97m{:_<12?}\x1b[37m        Stack Segment: \x1b[97m{}", stack_frame.stack_pointer, stack_frame.stack_segment.0);
-            kprintln!("\x1b[37mCPU Flags: \x1b[97m0x{:x}", stack_frame.cpu_flags);
-            kprintln!();
-            kprintln!("\x1b[37mRAX: \x1b[97m0x{: <16x}  \x1b[37mRDI: \x1b[97m0x{: <16x}  \x1b[37mR12: \x1b[97m0x{: <16x}", stack_frame.rax, stack_frame.rdi, stack_frame.r12);
-            kprintln!("\x1b[37mRBX: \x1b[97m0x{: <16x}  \x1b[37mR8:  \x1b[97m0x{: <16x}  \x1b[37mR13: \x1b[97m0x{: <16x}", stack_frame.rbx, stack_frame.r8, stack_frame.r13);
-            kprintln!("\x1b[37mRCX: \x1b[97m0x{: <16x}  \x1b[37mR9:  \x1b[97m0x{: <16x}  \x1b[37mR14: \x1b[97m0x{: <16x}", stack_frame.rcx, stack_frame.r9, stack_frame.r14);
-            kprintln!("\x1b[37mRDX: \x1b[97m0x{: <16x}  \x1b[37mR10: \x1b[97m0x{: <16x}  \x1b[37mR15: \x1b[97m0x{: <16x}", stack_frame.rdx, stack_frame.r10, stack_frame.r15);
-            kprintln!("\x1b[37mRSI: \x1b[97m0x{: <16x}  \x1b[37mR11: \x1b[97m0x{: <16x}  \x1b[37mRBP: \x1b[97m0x{: <16x}", stack_frame.rsi, stack_frame.r11, stack_frame.rbp);
-
-
-            if let Some(info) = additional_info {
-                kprintln!("\n\x1b[91mAdditional Info:");
-                kprintln!("{}", info);
+    let frame_pointer = match panic {
+        PanicType::KernelException { stack_frame, .. } => VirtualAddress::new(stack_frame.rbp),
+        _ => read_rbp(),
+    };
+
+    emergency_print(format_args!("{}", Report { panic: &panic, frame_pointer, stack_pointer: read_rsp() }));
+
+    // The normal reboot waits on the PIT between methods, which needs a lock.
+    run_action(action(), power::qemu_exit, || if is_emergency() {
+        power::try_in_order(&power::REBOOT_METHODS, |method| {
+            method.attempt();
+            false
+        });
+    } else {
+        power::reboot();
+    });
+    crate::x86_64::instructions::hlt_loop()
+}
+
+/// The text of a panic: the message or the state of the CPU, followed by a backtrace that starts
+/// at `frame_pointer`. `stack_pointer` is where `panic` was called.
+struct Report<'a> {
+    panic: &'a PanicType<'a>,
+    frame_pointer: VirtualAddress,
+    stack_pointer: VirtualAddress,
+}
+
+impl<'a> fmt::Display for Report<'a> {
+    fn fmt(&self, out: &mut fmt::Formatter) -> fmt::Result {
+        panic_println!(out, "\n\x1b[31m!!! \x1b[91mKERNEL PANIC");
+
+        match *self.panic {
+            PanicType::KernelAssert(info) => {
+                let message = info.message().copied()
+                    .unwrap_or_else(|| format_args!("No message."));
+                panic_println!(out, "\x1b[37m// \x1b[97m{}", message);
+
+                if let Some(location) = info.location() {
+                    panic_println!(out, "\n\x1b[91mat {}", location);
+                }
+
+                panic_println!(out, "\n{}", Register("Stack Pointer", self.stack_pointer.as_u64()));
+            },
+            PanicType::KernelException { name, stack_frame, additional_info } => {
+                panic_println!(out, "\x1b[37m// \x1b[97mCPU EXCEPTION: '{}' (IDX: 0x{:02.x})", name, stack_frame.kind);
+
+                panic_println!(out, "\n\x1b[91mStack Frame:");
+
+                panic_println!(out, "{}\x1b[37mCode Segment:  \x1b[97m{:#06x}", Register("Instruction Pointer", stack_frame.instruction_pointer.as_u64()), stack_frame.code_segment.0);
+                panic_println!(out, "{}\x1b[37mStack Segment: \x1b[97m{:#06x}", Register("Stack Pointer", stack_frame.stack_pointer.as_u64()), stack_frame.stack_segment.0);
+                panic_println!(out, "{}", Register("CPU Flags", stack_frame.cpu_flags));
+                panic_println!(out);
+                panic_println!(out, "{}{}{}", Register("RAX", stack_frame.rax), Register("RDI", stack_frame.rdi), Register("R12", stack_frame.r12));
+                panic_println!(out, "{}{}{}", Register("RBX", stack_frame.rbx), Register("R8", stack_frame.r8), Register("R13", stack_frame.r13));
+                panic_println!(out, "{}{}{}", Register("RCX", stack_frame.rcx), Register("R9", stack_frame.r9), Register("R14", stack_frame.r14));
+                panic_println!(out, "{}{}{}", Register("RDX", stack_frame.rdx), Register("R10", stack_frame.r10), Register("R15", stack_frame.r15));
+                panic_println!(out, "{}{}{}", Register("RSI", stack_frame.rsi), Register("R11", stack_frame.r11), Register("RBP", stack_frame.rbp));
+
+
+                if let Some(info) = additional_info {
+                    panic_println!(out, "\n\x1b[91mAdditional Info:");
+                    panic_println!(out, "{}", info);
+                }
+            },
+            PanicType::AllocationError(layout) => {
+                panic_println!(out, "\x1b[37m// \x1b[97mAllocation error: {:?}", layout);
             }
-        },
-        PanicType::AllocationError(layout) => {
-            kprintln!("\x1b[37m// \x1b[97mAllocation error: {:?}", layout);
         }
+
+        panic_println!(out, "\n\x1b[91mBacktrace:");
+        walk_stack(self.frame_pointer, is_mapped, |address| panic_println!(out, "\x1b[97m  {:#018x}", address));
+
+        Ok(())
     }
+}
 
-    crate::x86_64::instructions::hlt_loop()
+/// Follows the chain of saved frame pointers starting at `rbp` and calls `f` with the return
+/// address of every frame, for at most `MAX_BACKTRACE_FRAMES` frames. Stops at a null or unaligned
+/// frame pointer, or one that `is_readable` rejects.
+pub fn walk_stack<R, F>(rbp: VirtualAddress, is_readable: R, mut f: F) where R: Fn(VirtualAddress) -> bool, F: FnMut(u64) {
+    let mut frame = rbp.as_u64();
+
+    for _ in 0..MAX_BACKTRACE_FRAMES {
+        if frame == 0 || frame % 8 != 0 || !is_readable(VirtualAddress::new(frame)) ||
+            !is_readable(VirtualAddress::new(frame + 8)) {
+            return;
+        }
+
+        // Every frame starts with the frame pointer of the caller, followed by the return address.
+        let (next, return_address) = unsafe { (*(frame as *const u64), *((frame + 8) as *const u64)) };
+        if return_address == 0 {
+            return;
+        }
+
+        f(return_address);
+
+        // Stacks grow down, so a caller's frame is always at a higher address.
+        if next <= frame {
+            return;
+        }
+
+        frame = next;
+    }
+}
+
+/// Returns whether `address` is mapped. Only reads the page tables, the memory lock could be held
+/// by the code that panicked.
+fn is_mapped(address: VirtualAddress) -> bool {
+    Mapper::new(unsafe { RecursivePageTable::active() }).is_mapped(address)
 }
 
 /// Default Rust panic handler. Calls `panic::panic` internally.
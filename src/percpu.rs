@@ -0,0 +1,59 @@
+//! Data that every CPU has its own copy of. The GS base of a CPU points at its `PerCpu`, so the
+//! data can be reached without knowing which CPU the code runs on. There is only one CPU for now.
+
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::registers::msr::{GsBase, KernelGsBase};
+
+/// The data of a single CPU. The fields are `Cell`s, since only the CPU itself uses them.
+#[repr(C)]
+pub struct PerCpu {
+    /// Points at this struct itself, so `current` can find it with a single `gs`-relative load.
+    /// Must stay the first field.
+    this: *const PerCpu,
+    /// The index of the CPU.
+    pub id: u32,
+    /// The number of times preemption is disabled on this CPU.
+    pub preempt_count: Cell<u64>,
+}
+
+/// Set by `init` once the GS base points at the data of the boot CPU.
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Sets up the per-CPU data of the boot CPU. Must be called after the heap is initialized, and
+/// after the GDT is loaded, since loading the GS selector resets the GS base.
+pub fn init() {
+    let per_cpu = Box::leak(Box::new(PerCpu {
+        this: ptr::null(),
+        id: 0,
+        preempt_count: Cell::new(0),
+    }));
+    let address = per_cpu as *const PerCpu;
+    per_cpu.this = address;
+
+    GsBase::write(address as u64);
+    KernelGsBase::write(address as u64);
+    INITIALIZED.store(true, Ordering::SeqCst);
+}
+
+/// Returns the data of the CPU this runs on. Must not be called before `init`.
+pub fn current() -> &'static PerCpu {
+    let this: *const PerCpu;
+    unsafe {
+        asm!("mov $0, gs:[0]" : "=r" (this) ::: "intel");
+        &*this
+    }
+}
+
+/// Returns the data of the CPU this runs on, or `None` before `init`, for code that also runs
+/// early in the boot.
+pub fn try_current() -> Option<&'static PerCpu> {
+    if INITIALIZED.load(Ordering::SeqCst) {
+        Some(current())
+    } else {
+        None
+    }
+}
\ No newline at end of file
@@ -0,0 +1,211 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use fs;
+use fs::file::OpenFlags;
+use fs::mount::MountedNode;
+use fs::path::Path;
+use fs::ramdisk::Ramdisk;
+use fs::vfs::{FileType, FsError, INode, Result};
+use klog;
+use x86_64::power;
+
+/// The maximum amount of symbolic links followed when resolving a path.
+const MAX_FOLLOW: usize = 8;
+
+/// A minimal interactive shell that operates on a filesystem through the console device. Commands
+/// are read from the console and all output is written back to it.
+pub struct Shell {
+    root: Arc<dyn INode>,
+    console: Arc<dyn INode>,
+    cwd: Path,
+}
+
+impl Shell {
+    /// Creates a new `Shell` operating on the filesystem with root `root`, reading commands from
+    /// `console`. The console should be in cooked mode, so it returns one line at a time.
+    pub fn new(root: Arc<dyn INode>, console: Arc<dyn INode>) -> Shell {
+        Shell {
+            root,
+            console,
+            cwd: Path::root(),
+        }
+    }
+
+    /// Runs the shell until the `exit` command is entered.
+    pub fn run(&mut self) {
+        loop {
+            self.print(&format!("\x1b[92m{} \x1b[97m$\x1b[37m ", self.cwd.as_str()));
+
+            let line = self.read_line();
+            let args = tokenize(&line);
+
+            if args.first() == Some(&"exit") {
+                return;
+            }
+
+            if let Err(err) = self.execute(&args) {
+                self.print(&format!("\x1b[91m{}: {:?}\n", args[0], err));
+            }
+        }
+    }
+
+    /// Executes a single tokenized command.
+    pub fn execute(&mut self, args: &[&str]) -> Result<()> {
+        let command = match args.first() {
+            Some(command) => *command,
+            None => return Ok(()),
+        };
+
+        match (command, args.get(1).cloned()) {
+            ("ls", None) => self.ls("."),
+            ("ls", Some(path)) => self.ls(path),
+            ("cat", Some(path)) => self.cat(path),
+            ("cd", None) => self.cd("/"),
+            ("cd", Some(path)) => self.cd(path),
+            ("mkdir", Some(path)) => self.create(path, FileType::Directory).map(|_| ()),
+            ("rm", Some(path)) => self.rm(path),
+            ("mount", Some(path)) => self.mount(path),
+            ("echo", _) => self.echo(&args[1..]),
+            ("dmesg", _) => {
+                self.write(&klog::dmesg());
+                Ok(())
+            },
+            ("reboot", _) => power::reboot(),
+            ("poweroff", _) => power::shutdown(),
+            ("cat", None) | ("mkdir", None) | ("rm", None) | ("mount", None) => Err(FsError::InvalidArgument),
+            (command, _) => {
+                self.print(&format!("\x1b[91mUnknown command: {}\n", command));
+                Ok(())
+            }
+        }
+    }
+
+    /// Reads a line from the console, waiting until one is available.
+    fn read_line(&self) -> String {
+        let mut buf = [0; 256];
+
+        loop {
+            match self.console.read_blocking(0, &mut buf) {
+                Ok(0) | Err(_) => core::sync::atomic::spin_loop_hint(),
+                Ok(len) => return String::from_utf8_lossy(&buf[..len]).into_owned(),
+            }
+        }
+    }
+
+    /// Writes `bytes` to the console. Output errors are ignored, there is nowhere to report them.
+    fn write(&self, bytes: &[u8]) {
+        let _ = self.console.write_all(0, bytes);
+    }
+
+    fn print(&self, text: &str) {
+        self.write(text.as_bytes());
+    }
+
+    fn ls(&self, path: &str) -> Result<()> {
+        for name in self.resolve(path)?.list()? {
+            self.print(&format!("{}\n", name));
+        }
+
+        Ok(())
+    }
+
+    fn cat(&self, path: &str) -> Result<()> {
+        let inode = self.resolve(path)?;
+        let mut buf = [0; 256];
+        let mut offset = 0;
+
+        loop {
+            let len = inode.read_at(offset, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+
+            self.write(&buf[..len]);
+            offset += len;
+        }
+
+        self.print("\n");
+        Ok(())
+    }
+
+    fn cd(&mut self, path: &str) -> Result<()> {
+        let path = self.cwd.join(path);
+
+        if self.root.resolve_follow(path.relative(), MAX_FOLLOW)?.metadata()?.type_ != FileType::Directory {
+            return Err(FsError::NotDirectory);
+        }
+
+        self.cwd = path;
+        Ok(())
+    }
+
+    fn create(&self, path: &str, type_: FileType) -> Result<Arc<dyn INode>> {
+        let (parent, name) = self.resolve_parent(path)?;
+        parent.create(name, type_, 0o755)
+    }
+
+    fn rm(&self, path: &str) -> Result<()> {
+        let (parent, name) = self.resolve_parent(path)?;
+        parent.unlink(name)
+    }
+
+    /// Mounts a new, empty ramdisk at `path`.
+    fn mount(&self, path: &str) -> Result<()> {
+        let inode = self.resolve(path)?;
+        let node = inode.downcast_ref::<MountedNode>().ok_or(FsError::Unsupported)?;
+
+        node.mount(Ramdisk::new())?;
+        Ok(())
+    }
+
+    /// Prints its arguments, or writes them to a file when followed by `> file`, or appends them
+    /// to a file when followed by `>> file`.
+    fn echo(&self, args: &[&str]) -> Result<()> {
+        let (words, file) = match args.iter().position(|arg| *arg == ">" || *arg == ">>") {
+            Some(pos) if pos + 2 == args.len() => (&args[..pos], Some((args[pos], args[pos + 1]))),
+            Some(_) => return Err(FsError::InvalidArgument),
+            None => (args, None),
+        };
+
+        let mut text = words.join(" ");
+        text.push('\n');
+
+        match file {
+            Some((redirect, path)) => {
+                let flags = if redirect == ">>" {
+                    OpenFlags::Create | OpenFlags::Append
+                } else {
+                    OpenFlags::Create | OpenFlags::Truncate
+                };
+
+                fs::open(&self.root, self.cwd.join(path).relative(), flags, 0o755)?.write(text.as_bytes())?;
+            },
+            None => self.print(&text),
+        }
+
+        Ok(())
+    }
+
+    /// Resolves `path` relative to the current working directory.
+    fn resolve(&self, path: &str) -> Result<Arc<dyn INode>> {
+        self.root.resolve_follow(self.cwd.join(path).relative(), MAX_FOLLOW)
+    }
+
+    /// Resolves the parent directory of `path` and returns it together with the last component.
+    fn resolve_parent<'a>(&self, path: &'a str) -> Result<(Arc<dyn INode>, &'a str)> {
+        let path = path.trim_end_matches('/');
+
+        match path.rfind('/') {
+            Some(pos) => Ok((self.resolve(&path[..=pos])?, &path[pos + 1..])),
+            None => Ok((self.resolve(".")?, path)),
+        }
+    }
+}
+
+/// Splits a command line into whitespace separated arguments.
+pub fn tokenize(line: &str) -> Vec<&str> {
+    line.split_whitespace().collect()
+}
\ No newline at end of file
@@ -0,0 +1,200 @@
+use core::ops::Range;
+
+use flagset::FlagSet;
+
+use memory;
+use memory::PAGE_SIZE;
+use memory::frame::{Frame, FrameAllocator};
+use memory::paging::mapper::MapError;
+use memory::paging::{self, InactivePageTable, Page};
+use memory::paging::temporary_page::TemporaryPage;
+use memory::paging::entry::EntryFlags;
+use x86_64::VirtualAddress;
+
+/// The start of the part of the address space that is private to a task.
+pub const USER_START: VirtualAddress = VirtualAddress::new(0x1000_0000_0000);
+
+/// The end (exclusive) of the part of the address space that is private to a task.
+pub const USER_END: VirtualAddress = VirtualAddress::new(0x2000_0000_0000);
+
+/// The address space of a task. Everything between `USER_START` and `USER_END` belongs to the
+/// task, all other mappings are shared with the kernel. That way interrupt handlers, the heap and
+/// kernel stacks keep working after switching to another address space.
+///
+/// Dropping an address space does not free its page tables or the frames mapped in it.
+pub struct AddressSpace {
+    /// The page table of this address space, or `None` while it is the active page table.
+    table: Option<InactivePageTable>,
+}
+
+impl AddressSpace {
+    /// Creates a new address space without any private mappings.
+    pub fn new() -> AddressSpace {
+        let table = memory::with_memory(|active_table, frame_allocator| {
+            active_table.new_table_sharing(user_p4_entries(), frame_allocator)
+        });
+
+        AddressSpace {
+            table: Some(table),
+        }
+    }
+
+    /// Creates an `AddressSpace` for the page table that is active right now.
+    ///
+    /// # Safety
+    /// There can only be one `AddressSpace` for the active page table. Otherwise, one of them no
+    /// longer refers to the active page table after switching.
+    pub unsafe fn active() -> AddressSpace {
+        AddressSpace {
+            table: None,
+        }
+    }
+
+    /// Returns whether this is the address space that is loaded in CR3.
+    pub fn is_active(&self) -> bool {
+        self.table.is_none()
+    }
+
+    /// Maps `page` to a newly allocated, zeroed frame in this address space only. Panics if `page`
+    /// is not between `USER_START` and `USER_END`.
+    pub fn map_user(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>) {
+        assert_user_page(page);
+        let flags = flags.into();
+
+        memory::with_memory(|active_table, frame_allocator| {
+            let frame = frame_allocator.allocate_zeroed_frame(active_table).expect("Out of memory!");
+
+            match self.table {
+                Some(ref mut table) => active_table.with_inactive(table, frame_allocator, |mapper, frame_allocator| {
+                    mapper.map_to(page, frame, flags, frame_allocator)
+                }),
+                None => active_table.map_to(page, frame, flags, frame_allocator),
+            }
+        });
+    }
+
+    /// Maps `page` to a newly allocated frame in this address space only, after `fill` initialized
+    /// the contents of the frame. Fails if `fill` fails or there are no frames left, nothing is
+    /// mapped in that case. Panics if `page` is not between `USER_START` and `USER_END`.
+    pub fn map_user_with<F, E>(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>, fill: F) -> Result<(), E>
+        where F: FnOnce(&mut [u8; PAGE_SIZE]) -> Result<(), E>, E: From<MapError> {
+        assert_user_page(page);
+        let flags = flags.into();
+
+        memory::with_memory(|active_table, frame_allocator| {
+            let frame = frame_allocator.allocate_frame().ok_or(MapError::OutOfFrames)?;
+
+            if let Err(error) = active_table.with_frame_mapped(Frame(frame.0), frame_allocator, fill) {
+                frame_allocator.deallocate_frame(frame);
+                return Err(error);
+            }
+
+            let result = match self.table {
+                Some(ref mut table) => {
+                    let mut result = Ok(());
+                    active_table.with_inactive(table, frame_allocator, |mapper, frame_allocator| {
+                        result = mapper.try_map_to(page, Frame(frame.0), flags, frame_allocator);
+                    });
+                    result
+                }
+                None => active_table.try_map_to(page, Frame(frame.0), flags, frame_allocator),
+            };
+
+            if let Err(error) = result {
+                frame_allocator.deallocate_frame(frame);
+                return Err(error.into());
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Runs `fill` on the contents of the frame `page` is already mapped to in this address space,
+    /// then replaces the flags it is mapped with by `flags`. The flags are left alone if `fill`
+    /// fails. Panics if `page` is not mapped, or not between `USER_START` and `USER_END`.
+    pub fn update_user_with<F, E>(&mut self, page: Page, flags: impl Into<FlagSet<EntryFlags>>, fill: F) -> Result<(), E>
+        where F: FnOnce(&mut [u8; PAGE_SIZE]) -> Result<(), E> {
+        assert_user_page(page);
+        let flags = flags.into();
+        let frame = self.translate(page).expect("Page is not mapped!");
+
+        memory::with_memory(|active_table, frame_allocator| {
+            active_table.with_frame_mapped(frame, frame_allocator, fill)?;
+
+            match self.table {
+                Some(ref mut table) => active_table.with_inactive(table, frame_allocator, |mapper, _| {
+                    mapper.update_flags(page, flags)
+                }),
+                None => active_table.update_flags(page, flags),
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Unmaps `page` from this address space and deallocates the frame it was mapped to. Panics if
+    /// `page` is not mapped, or not between `USER_START` and `USER_END`.
+    pub fn unmap_user(&mut self, page: Page) {
+        assert_user_page(page);
+
+        memory::with_memory(|active_table, frame_allocator| {
+            match self.table {
+                Some(ref mut table) => active_table.with_inactive(table, frame_allocator, |mapper, frame_allocator| {
+                    mapper.unmap(page, frame_allocator)
+                }),
+                None => active_table.unmap(page, frame_allocator),
+            }
+        });
+    }
+
+    /// Returns the frame `page` is mapped to in this address space.
+    pub fn translate(&self, page: Page) -> Option<Frame> {
+        memory::with_memory(|active_table, frame_allocator| {
+            match self.table {
+                Some(ref table) => {
+                    let mut temporary_page = TemporaryPage::new(paging::TEMPORARY_PAGE, frame_allocator);
+                    let frame = table.inspect(active_table, &mut temporary_page, |inspector| {
+                        inspector.translate_page(page).map(|(frame, _)| frame)
+                    });
+
+                    temporary_page.free(frame_allocator);
+                    frame
+                }
+                None => active_table.translate_page(page),
+            }
+        })
+    }
+
+    /// Loads the page table of `next` into CR3. This has to be the active address space, it takes
+    /// over the page table that was active until now. Kernel P4 entries that were created since
+    /// `next` was last active are copied into it first, so every address space keeps seeing all
+    /// kernel mappings.
+    pub fn switch_to(&mut self, next: &mut AddressSpace) {
+        assert!(self.is_active(), "Can only switch away from the active address space!");
+        let mut table = next.table.take().expect("Address space is already active!");
+
+        self.table = Some(memory::with_memory(|active_table, frame_allocator| {
+            let mut temporary_page = TemporaryPage::new(paging::TEMPORARY_PAGE, frame_allocator);
+            active_table.share_entries(&mut table, user_p4_entries(), &mut temporary_page);
+            temporary_page.free(frame_allocator);
+
+            active_table.switch(table)
+        }));
+    }
+}
+
+/// Returns the P4 entries that map the part of the address space that is private to a task.
+fn user_p4_entries() -> Range<usize> {
+    p4_index(USER_START)..p4_index(USER_END)
+}
+
+fn p4_index(address: VirtualAddress) -> usize {
+    ((address.as_u64() >> 39) & 0o777) as usize
+}
+
+fn assert_user_page(page: Page) {
+    let address = page.start_address().as_u64();
+
+    assert!(address >= USER_START.as_u64() && address < USER_END.as_u64(),
+            "{:?} is not a user address", page.start_address());
+}
\ No newline at end of file
@@ -1 +1,28 @@
-pub mod context;
\ No newline at end of file
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use watchdog;
+use x86_64::instructions::interrupts;
+
+pub mod address_space;
+pub mod scheduler;
+pub mod sync;
+pub mod wait_queue;
+
+/// The amount of times `idle` halted the CPU.
+static IDLE_HALTS: AtomicU64 = AtomicU64::new(0);
+
+/// Halts until the next interrupt, forever. Bumps the watchdog heartbeat every time it wakes up,
+/// there is nothing to do so the kernel isn't hung.
+pub fn idle() -> ! {
+    loop {
+        watchdog::pet();
+        IDLE_HALTS.fetch_add(1, Ordering::SeqCst);
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Returns the amount of times `idle` halted the CPU, to check that nothing spins while there is
+/// nothing to do.
+pub fn idle_halts() -> u64 {
+    IDLE_HALTS.load(Ordering::SeqCst)
+}
\ No newline at end of file
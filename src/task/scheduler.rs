@@ -0,0 +1,366 @@
+//! A preemptive round-robin scheduler for kernel threads. The PIT tick counts down the time slice
+//! of the running thread, and once it's used up the tick interrupt returns into the next ready
+//! thread instead: the registers the interrupt wrapper saved are stored in the preempted thread and
+//! replaced with the saved registers of the next one, so the `iretq` at the end of the wrapper
+//! resumes that thread on its own stack. The switch happens after the end of interrupt was sent,
+//! and the interrupt stack frame lives on the stack of the preempted thread, which isn't used again
+//! until that thread is resumed.
+//!
+//! Every thread runs in an address space, which is loaded into CR3 together with its registers.
+//! Kernel threads share the kernel address space, so switching between them keeps CR3 as it is.
+//!
+//! Threads that wait on a `WaitQueue` are blocked until it's woken, and aren't scheduled until then.
+//! When no thread can run, the idle thread halts the CPU until the next interrupt.
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::mem;
+
+use interrupts::StackFrame;
+use memory;
+use memory::Stack;
+use percpu;
+use task::address_space::AddressSpace;
+use util::irq_lock::IrqLock;
+use x86_64::VirtualAddress;
+
+/// The amount of PIT ticks a thread may run before it's preempted.
+pub const TIME_SLICE_TICKS: u64 = 10;
+
+/// The size of the stack of a spawned thread, in pages.
+const STACK_PAGES: usize = 4;
+
+/// The id of the idle thread.
+const IDLE_THREAD: u64 = u64::max_value();
+
+static SCHEDULER: IrqLock<Option<Scheduler>> = IrqLock::new(None);
+
+/// A kernel thread. Its registers are only up to date while it isn't running.
+struct Thread {
+    id: u64,
+    frame: StackFrame,
+    /// The stack of the thread, `None` for the boot thread, which runs on the boot stack. Stacks
+    /// can't be freed yet, so the stack of an exited thread is leaked.
+    _stack: Option<Stack>,
+    /// The key of the wait queue the thread waits on, see `block_on`.
+    blocked_on: Option<usize>,
+    /// The user the thread runs as, 0 is root.
+    uid: u32,
+    /// The address space the thread runs in, `None` for the kernel address space.
+    address_space: Option<AddressSpace>,
+}
+
+impl Thread {
+    fn new(id: u64, frame: StackFrame, stack: Option<Stack>) -> Thread {
+        Thread {
+            id,
+            frame,
+            _stack: stack,
+            blocked_on: None,
+            uid: 0,
+            address_space: None,
+        }
+    }
+}
+
+struct Scheduler {
+    current: Thread,
+    /// The address space kernel threads run in. Like every `AddressSpace`, it only holds its page
+    /// table while another address space is active.
+    kernel_space: AddressSpace,
+    ready: VecDeque<Thread>,
+    blocked: Vec<Thread>,
+    /// The idle thread while it isn't running, `None` until it's spawned.
+    idle: Option<Thread>,
+    /// The amount of ticks left in the time slice of `current`.
+    slice_left: u64,
+    /// Set by `exit`, the current thread is dropped instead of queued at the next switch.
+    exiting: bool,
+    next_id: u64,
+}
+
+impl Scheduler {
+    /// Counts down the time slice and switches to the next ready thread when it ran out, or when
+    /// the current thread exited or blocked. `frame` holds the registers of the interrupted
+    /// thread, and is overwritten with those of the thread to resume. A thread that isn't
+    /// `preemptible` keeps running until it is. The idle thread runs only when no other thread
+    /// can, and is switched away from as soon as one is ready.
+    fn tick(&mut self, frame: &mut StackFrame, preemptible: bool) {
+        self.slice_left = self.slice_left.saturating_sub(1);
+
+        let stopped = self.exiting || self.current.blocked_on.is_some();
+        if !stopped && self.current.id != IDLE_THREAD && (self.slice_left > 0 || !preemptible) {
+            return;
+        }
+
+        self.slice_left = TIME_SLICE_TICKS;
+        let next = match self.ready.pop_front() {
+            Some(next) => next,
+            None if stopped => match self.idle.take() {
+                Some(idle) => idle,
+                None => return,
+            },
+            None => return,
+        };
+
+        let mut previous = mem::replace(&mut self.current, next);
+        previous.frame = frame.clone();
+        *frame = self.current.frame.clone();
+        self.switch_address_space(&mut previous);
+
+        if previous.id == IDLE_THREAD {
+            self.idle = Some(previous);
+        } else if mem::replace(&mut self.exiting, false) {
+            // The thread exited, drop it.
+        } else if previous.blocked_on.is_some() {
+            self.make_blocked(previous);
+        } else {
+            self.make_ready(previous);
+        }
+    }
+
+    /// Loads the address space of the current thread, which was just switched to from `previous`.
+    /// Kernel mappings are shared by every address space, so the interrupt handler keeps running
+    /// on the stack of `previous` after CR3 changed.
+    fn switch_address_space(&mut self, previous: &mut Thread) {
+        match (previous.address_space.as_mut(), self.current.address_space.as_mut()) {
+            (None, None) => {}
+            (None, Some(next)) => self.kernel_space.switch_to(next),
+            (Some(previous), None) => previous.switch_to(&mut self.kernel_space),
+            (Some(previous), Some(next)) => previous.switch_to(next),
+        }
+    }
+
+    /// Queues `thread` to run after the ready threads. Never allocates, the room was reserved when
+    /// the thread was added, so this works in interrupt handlers.
+    fn make_ready(&mut self, thread: Thread) {
+        assert!(self.ready.len() < self.ready.capacity(), "No room reserved for thread {}!", thread.id);
+        self.ready.push_back(thread);
+    }
+
+    /// Parks `thread` until its wait queue is woken. Never allocates, like `make_ready`.
+    fn make_blocked(&mut self, thread: Thread) {
+        assert!(self.blocked.len() < self.blocked.capacity(), "No room reserved for thread {}!", thread.id);
+        self.blocked.push(thread);
+    }
+
+    /// Makes room for every thread in both the ready and the blocked queue, so `tick` and `wake`
+    /// never have to grow them. They run in interrupt handlers, and growing a queue takes the lock
+    /// of the heap, which the interrupted thread may hold. Must be called whenever a thread is
+    /// added, before it's queued.
+    fn reserve(&mut self, threads: usize) {
+        let ready = self.ready.len();
+        self.ready.reserve(threads.saturating_sub(ready));
+
+        let blocked = self.blocked.len();
+        self.blocked.reserve(threads.saturating_sub(blocked));
+    }
+
+    /// Returns the amount of threads, including the running and the blocked ones, but not the
+    /// idle thread.
+    fn thread_count(&self) -> usize {
+        let current = if self.current.id == IDLE_THREAD { 0 } else { 1 };
+        self.ready.len() + self.blocked.len() + current
+    }
+
+    /// Makes the threads that are blocked on `key` ready again, in the order they blocked.
+    fn wake(&mut self, key: usize) {
+        if self.current.blocked_on == Some(key) {
+            self.current.blocked_on = None;
+        }
+
+        let mut index = 0;
+        while index < self.blocked.len() {
+            if self.blocked[index].blocked_on == Some(key) {
+                let mut thread = self.blocked.remove(index);
+                thread.blocked_on = None;
+                self.make_ready(thread);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+/// Starts scheduling, with the code that calls this as the first thread, and spawns the idle
+/// thread. Must be called after the per-CPU data and the memory are set up, while the kernel
+/// address space is active.
+pub fn init() {
+    let mut scheduler = Scheduler {
+        current: Thread::new(0, StackFrame::default(), None),
+        kernel_space: unsafe { AddressSpace::active() },
+        ready: VecDeque::new(),
+        blocked: Vec::new(),
+        idle: None,
+        slice_left: TIME_SLICE_TICKS,
+        exiting: false,
+        next_id: 1,
+    };
+    scheduler.reserve(1);
+    *SCHEDULER.lock() = Some(scheduler);
+
+    let idle = new_thread(IDLE_THREAD, idle_thread, 0).expect("Could not allocate the idle thread!");
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.idle = Some(idle);
+    }
+}
+
+/// Called by the PIT tick with the registers of the interrupted code, which may be replaced with
+/// those of another thread. Does nothing before `init`, or if the interrupted code holds the lock
+/// of the scheduler.
+pub fn timer_tick(frame: &mut StackFrame) {
+    let mut scheduler = match SCHEDULER.try_lock() {
+        Some(scheduler) => scheduler,
+        None => return,
+    };
+
+    if let Some(scheduler) = scheduler.as_mut() {
+        let preemptible = percpu::current().preempt_count.get() == 0;
+        scheduler.tick(frame, preemptible);
+    }
+}
+
+/// Starts a thread that calls `entry` with `argument`, and exits when it returns. The thread runs
+/// as the same user as the current one, in the kernel address space. Returns the id of the thread,
+/// or `None` if there is no memory for its stack or the scheduler isn't running.
+pub fn spawn(entry: extern "C" fn(u64), argument: u64) -> Option<u64> {
+    spawn_thread(entry, argument, None)
+}
+
+/// Starts a thread like `spawn`, which runs in `address_space` instead of the kernel address
+/// space. Panics if `address_space` is the active one.
+pub fn spawn_in(entry: extern "C" fn(u64), argument: u64, address_space: AddressSpace) -> Option<u64> {
+    assert!(!address_space.is_active(), "The active address space belongs to the running thread!");
+    spawn_thread(entry, argument, Some(address_space))
+}
+
+fn spawn_thread(entry: extern "C" fn(u64), argument: u64, address_space: Option<AddressSpace>) -> Option<u64> {
+    let (id, uid) = {
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = scheduler.as_mut()?;
+
+        scheduler.next_id += 1;
+        (scheduler.next_id - 1, scheduler.current.uid)
+    };
+
+    let mut thread = new_thread(id, entry, argument)?;
+    thread.uid = uid;
+    thread.address_space = address_space;
+
+    let mut scheduler = SCHEDULER.lock();
+    let scheduler = scheduler.as_mut()?;
+    let threads = scheduler.thread_count() + 1;
+    scheduler.reserve(threads);
+    scheduler.make_ready(thread);
+
+    Some(id)
+}
+
+/// Creates a thread with a new stack that calls `entry` with `argument`.
+fn new_thread(id: u64, entry: extern "C" fn(u64), argument: u64) -> Option<Thread> {
+    let stack = memory::with_memory(|active_table, frame_allocator| {
+        memory::alloc_kernel_stack(active_table, frame_allocator, STACK_PAGES)
+    })?;
+
+    // `entry` returns into `thread_exit`. The stack is 16-byte aligned after the return address,
+    // like after a call.
+    let stack_pointer = VirtualAddress::new(stack.top().as_u64() - 8);
+    unsafe { *stack_pointer.as_mut_ptr::<u64>() = thread_exit as u64 };
+
+    let mut frame = StackFrame::new(VirtualAddress::new(entry as u64), stack_pointer);
+    frame.rdi = argument;
+
+    Some(Thread::new(id, frame, Some(stack)))
+}
+
+/// Ends the current thread. It is dropped at the next tick, until then it idles.
+pub fn exit() -> ! {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.exiting = true;
+    }
+
+    super::idle()
+}
+
+extern "C" fn thread_exit() -> ! {
+    exit()
+}
+
+/// Halts until there is something else to do. `task::idle` enables interrupts together with
+/// halting, so the next interrupt always wakes it.
+extern "C" fn idle_thread(_argument: u64) {
+    super::idle()
+}
+
+/// Blocks the current thread on the wait queue identified by `key` from the next tick on, until
+/// `wake` is called with the same key. The thread has to halt or spin until then, and to call
+/// `cancel_block` if it stops waiting for another reason. Before `init`, this does nothing.
+pub fn block_on(key: usize) {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.current.blocked_on = Some(key);
+    }
+}
+
+/// Undoes `block_on` for the current thread, if it wasn't switched away from yet.
+pub fn cancel_block() {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.current.blocked_on = None;
+    }
+}
+
+/// Makes every thread blocked on `key` ready again. Can be called from interrupt handlers.
+pub fn wake(key: usize) {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.wake(key);
+    }
+}
+
+/// Returns the id of the running thread, or `None` before `init`.
+pub fn current_id() -> Option<u64> {
+    SCHEDULER.lock().as_ref().map(|scheduler| scheduler.current.id)
+}
+
+/// Returns the user the running thread runs as. Before `init`, everything runs as root.
+pub fn current_uid() -> u32 {
+    SCHEDULER.lock().as_ref().map_or(0, |scheduler| scheduler.current.uid)
+}
+
+/// Changes the user the running thread runs as. Does nothing before `init`.
+pub fn set_uid(uid: u32) {
+    if let Some(scheduler) = SCHEDULER.lock().as_mut() {
+        scheduler.current.uid = uid;
+    }
+}
+
+/// Returns the amount of threads, including the running and the blocked ones, but not the idle
+/// thread.
+pub fn thread_count() -> usize {
+    SCHEDULER.lock().as_ref().map_or(0, Scheduler::thread_count)
+}
+
+/// Keeps the current thread from being preempted until it is dropped. Nests.
+pub struct PreemptGuard {
+    /// Whether the preempt count was raised. It isn't before the per-CPU data is set up, nothing
+    /// is preempted that early.
+    counted: bool,
+}
+
+/// Disables preemption of the current thread, for code that holds a lock another thread may spin
+/// on. Interrupts stay enabled. The locks in `util::preempt_lock` take this while they're held.
+pub fn disable_preemption() -> PreemptGuard {
+    let per_cpu = percpu::try_current();
+    if let Some(per_cpu) = per_cpu {
+        per_cpu.preempt_count.set(per_cpu.preempt_count.get() + 1);
+    }
+
+    PreemptGuard { counted: per_cpu.is_some() }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        if self.counted {
+            let count = &percpu::current().preempt_count;
+            count.set(count.get() - 1);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,116 @@
+//! Locks that park the waiting thread on a `WaitQueue` instead of spinning, so a holder that is
+//! preempted doesn't keep the waiters busy. Waiters are served in the order they started waiting.
+//! These can't be used in interrupt handlers, which have to use the `IrqLock` instead.
+
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use task::wait_queue::WaitQueue;
+
+/// A counting semaphore. Every waiter takes a ticket, and is let through once as many permits were
+/// released as there are tickets before it.
+pub struct Semaphore {
+    tickets: AtomicUsize,
+    released: AtomicUsize,
+    queue: WaitQueue,
+}
+
+impl Semaphore {
+    /// Creates a semaphore that lets `permits` acquirers through before one has to wait.
+    pub const fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            tickets: AtomicUsize::new(0),
+            released: AtomicUsize::new(permits),
+            queue: WaitQueue::new(),
+        }
+    }
+
+    /// Takes a permit, and waits until one is released if there is none.
+    pub fn acquire(&self) {
+        let ticket = self.tickets.fetch_add(1, Ordering::SeqCst);
+        self.queue.wait_until(|| if ticket < self.released.load(Ordering::SeqCst) { Some(()) } else { None });
+    }
+
+    /// Takes a permit if there is one and nobody is waiting for it. Returns whether it did.
+    pub fn try_acquire(&self) -> bool {
+        let ticket = self.tickets.load(Ordering::SeqCst);
+
+        ticket < self.released.load(Ordering::SeqCst)
+            && self.tickets.compare_exchange(ticket, ticket + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok()
+    }
+
+    /// Returns a permit, which lets the longest waiting acquirer through.
+    pub fn release(&self) {
+        self.released.fetch_add(1, Ordering::SeqCst);
+        self.queue.wake_all();
+    }
+
+    /// Returns the amount of threads that wait for a permit.
+    pub fn waiters(&self) -> usize {
+        self.queue.waiters()
+    }
+}
+
+/// A mutex that parks the threads waiting for it.
+pub struct Mutex<T> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> {}
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            semaphore: Semaphore::new(1),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Locks the mutex, and waits until it's unlocked if another thread holds it.
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.semaphore.acquire();
+        MutexGuard { mutex: self }
+    }
+
+    /// Locks the mutex if it isn't held and nobody waits for it.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.semaphore.try_acquire() {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the amount of threads that wait for the mutex.
+    pub fn waiters(&self) -> usize {
+        self.semaphore.waiters()
+    }
+}
+
+/// Unlocks the mutex when it's dropped.
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<'a, T> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.semaphore.release();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,80 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use task::scheduler;
+use watchdog;
+use x86_64::instructions::interrupts;
+
+/// A queue of waiters that are parked until an interrupt handler reports that the thing they wait
+/// for may have happened, like data arriving on the serial port.
+///
+/// A parked thread is blocked, so the scheduler runs other threads until the queue is woken.
+/// Before the scheduler runs, parking halts the CPU until the next interrupt instead.
+pub struct WaitQueue {
+    waiters: AtomicUsize,
+    wakeups: AtomicUsize,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            waiters: AtomicUsize::new(0),
+            wakeups: AtomicUsize::new(0),
+        }
+    }
+
+    /// Parks the caller until `condition` returns `Some`, and returns that value. The condition is
+    /// checked with interrupts disabled and they are only enabled again together with parking, so
+    /// a wakeup from an interrupt handler between the check and parking can't get lost.
+    pub fn wait_until<F, R>(&self, mut condition: F) -> R where F: FnMut() -> Option<R> {
+        assert!(!crate::interrupts::in_interrupt(), "Can't wait in an interrupt handler!");
+        let enabled = interrupts::are_enabled();
+
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+
+        let result = loop {
+            interrupts::disable();
+            // Resumed by something other than a wakeup, like an unrelated interrupt.
+            scheduler::cancel_block();
+
+            if let Some(result) = condition() {
+                break result;
+            }
+
+            // Waiting is idling, not hanging.
+            watchdog::pet();
+            scheduler::block_on(self.key());
+            interrupts::enable_and_hlt();
+        };
+
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+
+        if enabled {
+            interrupts::enable();
+        }
+
+        result
+    }
+
+    /// Wakes everything that waits on this queue, so they check their condition again. Called by
+    /// the producer side, usually from an interrupt handler.
+    pub fn wake_all(&self) {
+        self.wakeups.fetch_add(1, Ordering::SeqCst);
+        scheduler::wake(self.key());
+    }
+
+    /// Identifies this queue to the scheduler. Wait queues are statics or live as long as the
+    /// threads that wait on them, so the address is unique while anything waits.
+    fn key(&self) -> usize {
+        self as *const WaitQueue as usize
+    }
+
+    /// Returns the amount of waiters that are parked right now.
+    pub fn waiters(&self) -> usize {
+        self.waiters.load(Ordering::SeqCst)
+    }
+
+    /// Returns how often the queue was woken.
+    pub fn wakeups(&self) -> usize {
+        self.wakeups.load(Ordering::SeqCst)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,66 @@
+use core::cmp;
+
+use driver::pit::{self, PIT, PIT_FREQUENCY, TICK_FREQUENCY, TICK_QUEUE};
+
+/// Returns the amount of PIT cycles that take at least `us` microseconds.
+pub fn pit_cycles(us: u32) -> u64 {
+    (u64::from(us) * PIT_FREQUENCY + 999_999) / 1_000_000
+}
+
+/// Busy waits for at least `us` microseconds, timed by channel 2 of the PIT. The PIT counts at a
+/// fixed frequency, so this needs no calibration and works before anything else is set up.
+/// Interrupts stay enabled, so the delay can be longer than requested.
+pub fn delay_us(us: u32) {
+    let mut cycles = pit_cycles(us);
+
+    while cycles > 0 {
+        let chunk = cmp::min(cycles, u64::from(u16::max_value()));
+        wait_cycles(chunk as u16);
+        cycles -= chunk;
+    }
+}
+
+/// The amount of spin loop hints between two polls of the PIT.
+const POLL_PAUSE: u32 = 64;
+
+/// Busy waits until channel 2 of the PIT counted down `cycles` cycles. The PIT is only locked to
+/// start the count and for every poll, never across the whole wait. Holding the lock disables
+/// preemption, so the polls are spaced out to leave the tick room to preempt the delay.
+fn wait_cycles(cycles: u16) {
+    if cycles == 0 {
+        return;
+    }
+
+    while !PIT.lock().start_one_shot(cycles) {
+        pause();
+    }
+
+    while !PIT.lock().one_shot_done() {
+        pause();
+    }
+}
+
+fn pause() {
+    for _ in 0..POLL_PAUSE {
+        core::sync::atomic::spin_loop_hint();
+    }
+}
+
+/// Busy waits for at least `ms` milliseconds, like `delay_us`.
+pub fn delay_ms(ms: u32) {
+    for _ in 0..ms {
+        delay_us(1000);
+    }
+}
+
+/// Blocks the current thread for at least `ms` milliseconds. Unlike `delay_ms`, other threads run
+/// in the meantime, or the CPU halts if there are none. Can't be used in interrupt handlers.
+pub fn sleep_ms(ms: u64) {
+    let end = uptime_ms() + ms;
+    TICK_QUEUE.wait_until(|| if uptime_ms() >= end { Some(()) } else { None });
+}
+
+/// Returns the time since the PIT tick interrupt was started, in milliseconds.
+pub fn uptime_ms() -> u64 {
+    pit::ticks() * 1000 / TICK_FREQUENCY
+}
\ No newline at end of file
@@ -0,0 +1,79 @@
+/// A bitmap over any byte storage, like a borrowed slice, a fixed size array or a `Vec<u8>`. Bit
+/// `n` is stored in byte `n / 8` at bit position `n % 8`.
+pub struct Bitmap<T: AsRef<[u8]> + AsMut<[u8]>> {
+    bytes: T,
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Bitmap<T> {
+    /// Creates a new `Bitmap` over `bytes`. The existing contents are kept.
+    pub fn new(bytes: T) -> Bitmap<T> {
+        Bitmap {
+            bytes,
+        }
+    }
+
+    /// Returns the amount of bits in the bitmap.
+    pub fn len(&self) -> usize {
+        self.bytes.as_ref().len() * 8
+    }
+
+    /// Returns true if the bitmap has no bits at all.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.as_ref().is_empty()
+    }
+
+    /// Returns the value of bit `index`. Panics if `index` is out of range.
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len(), "Bitmap index {} out of range ({})", index, self.len());
+        self.bytes.as_ref()[index / 8] & (1 << (index % 8)) != 0
+    }
+
+    /// Sets bit `index` to one. Panics if `index` is out of range.
+    pub fn set(&mut self, index: usize) {
+        assert!(index < self.len(), "Bitmap index {} out of range ({})", index, self.len());
+        self.bytes.as_mut()[index / 8] |= 1 << (index % 8);
+    }
+
+    /// Sets bit `index` to zero. Panics if `index` is out of range.
+    pub fn clear(&mut self, index: usize) {
+        assert!(index < self.len(), "Bitmap index {} out of range ({})", index, self.len());
+        self.bytes.as_mut()[index / 8] &= !(1 << (index % 8));
+    }
+
+    /// Returns the index of the first bit that is zero, if any.
+    pub fn find_first_clear(&self) -> Option<usize> {
+        self.bytes.as_ref().iter().enumerate()
+            .find(|(_, byte)| **byte != 0xff)
+            .map(|(i, byte)| i * 8 + (!*byte).trailing_zeros() as usize)
+    }
+
+    /// Returns the index of the first bit of the first run of `count` contiguous zero bits, if
+    /// any. Runs can span multiple bytes. An empty run is never found, so a `count` of zero
+    /// returns `None`.
+    pub fn find_first_clear_range(&self, count: usize) -> Option<usize> {
+        if count == 0 {
+            return None;
+        }
+
+        let mut run = 0;
+
+        for index in 0..self.len() {
+            if self.get(index) {
+                run = 0;
+            } else {
+                run += 1;
+
+                if run == count {
+                    return Some(index + 1 - count);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the amount of bits that are one.
+    pub fn count_ones(&self) -> usize {
+        self.bytes.as_ref().iter().map(|byte| byte.count_ones() as usize).sum()
+    }
+}
\ No newline at end of file
@@ -19,14 +19,37 @@ impl<T> IrqLock<T> {
 }
 
 impl<T: ?Sized> IrqLock<T> {
-    /// Locks the data and disables the interrupts
+    /// Locks the data and disables the interrupts. Interrupts are disabled before the lock is
+    /// taken, so an interrupt handler can never spin on a lock held by the code it interrupted.
     pub fn lock(&self) -> IrqLockGuard<T> {
-        let guard = IrqLockGuard {
-            interrupts_enabled: interrupts::are_enabled(),
+        let interrupts_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        IrqLockGuard {
+            interrupts_enabled,
             data: self.data.lock(),
-        };
+        }
+    }
+
+    /// Tries to lock the data without spinning. Returns `None` if the data is already locked, in
+    /// which case the interrupt state is left untouched.
+    pub fn try_lock(&self) -> Option<IrqLockGuard<T>> {
+        let interrupts_enabled = interrupts::are_enabled();
         interrupts::disable();
-        guard
+
+        match self.data.try_lock() {
+            Some(data) => Some(IrqLockGuard {
+                interrupts_enabled,
+                data,
+            }),
+            None => {
+                if interrupts_enabled {
+                    interrupts::enable();
+                }
+
+                None
+            }
+        }
     }
 
     /// Force unlock the data
@@ -1,7 +1,21 @@
-pub fn align_down(x: usize, log2: usize) -> usize {
+/// Aligns `x` down to a multiple of `2^log2`.
+pub fn align_down_log2(x: usize, log2: usize) -> usize {
     x & (!0 << log2)
 }
 
-pub fn align_up(x: usize, log2: usize) -> usize {
+/// Aligns `x` up to a multiple of `2^log2`.
+pub fn align_up_log2(x: usize, log2: usize) -> usize {
     (x + (1 << log2) - 1) & (!0 << log2)
+}
+
+/// Aligns `x` down to a multiple of `align`, which has to be a power of two.
+pub fn align_down_to(x: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two(), "Alignment {} is not a power of two", align);
+    x & !(align - 1)
+}
+
+/// Aligns `x` up to a multiple of `align`, which has to be a power of two.
+pub fn align_up_to(x: usize, align: usize) -> usize {
+    debug_assert!(align.is_power_of_two(), "Alignment {} is not a power of two", align);
+    (x + align - 1) & !(align - 1)
 }
\ No newline at end of file
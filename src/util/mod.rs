@@ -1,2 +1,6 @@
+pub mod bitmap;
 pub mod math;
-pub mod irq_lock;
\ No newline at end of file
+pub mod range;
+pub mod irq_lock;
+pub mod preempt_lock;
+pub mod ring_buffer;
\ No newline at end of file
@@ -0,0 +1,141 @@
+use core::ops::{Deref, DerefMut};
+
+use spin::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use task::scheduler::{self, PreemptGuard};
+
+/// A spin lock that keeps the thread that holds it from being preempted, so other threads never
+/// spin on it for a whole time slice. Interrupts stay enabled, use `IrqLock` for data interrupt
+/// handlers use as well.
+pub struct PreemptMutex<T: ?Sized> {
+    data: Mutex<T>,
+}
+
+impl<T> PreemptMutex<T> {
+    /// Creates a new 'PreemptMutex' with contents 'data'
+    pub const fn new(data: T) -> PreemptMutex<T> {
+        PreemptMutex {
+            data: Mutex::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> PreemptMutex<T> {
+    /// Disables preemption and locks the data. Preemption is disabled first, so the thread can't
+    /// be switched away from between taking the lock and disabling it.
+    pub fn lock(&self) -> PreemptMutexGuard<T> {
+        let preempt = scheduler::disable_preemption();
+
+        PreemptMutexGuard {
+            data: self.data.lock(),
+            _preempt: preempt,
+        }
+    }
+
+    /// Tries to lock the data without spinning. Returns `None` if the data is already locked, in
+    /// which case preemption is left enabled.
+    pub fn try_lock(&self) -> Option<PreemptMutexGuard<T>> {
+        let preempt = scheduler::disable_preemption();
+
+        self.data.try_lock().map(|data| PreemptMutexGuard {
+            data,
+            _preempt: preempt,
+        })
+    }
+}
+
+/// A guard around the data obtained from 'PreemptMutex'. When dropped, the data is unlocked and
+/// preemption is enabled again.
+pub struct PreemptMutexGuard<'a, T: ?Sized + 'a> {
+    // Fields are dropped in order, so the lock is released before preemption is enabled.
+    data: MutexGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<'a, T: ?Sized> Deref for PreemptMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for PreemptMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.data
+    }
+}
+
+/// A reader-writer spin lock that keeps the threads that hold it from being preempted, like
+/// `PreemptMutex`.
+pub struct PreemptRwLock<T: ?Sized> {
+    data: RwLock<T>,
+}
+
+impl<T> PreemptRwLock<T> {
+    /// Creates a new 'PreemptRwLock' with contents 'data'
+    pub const fn new(data: T) -> PreemptRwLock<T> {
+        PreemptRwLock {
+            data: RwLock::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> PreemptRwLock<T> {
+    /// Disables preemption and locks the data for reading, other readers can hold it at the same
+    /// time.
+    pub fn read(&self) -> PreemptRwLockReadGuard<T> {
+        let preempt = scheduler::disable_preemption();
+
+        PreemptRwLockReadGuard {
+            data: self.data.read(),
+            _preempt: preempt,
+        }
+    }
+
+    /// Disables preemption and locks the data for writing, once no readers hold it.
+    pub fn write(&self) -> PreemptRwLockWriteGuard<T> {
+        let preempt = scheduler::disable_preemption();
+
+        PreemptRwLockWriteGuard {
+            data: self.data.write(),
+            _preempt: preempt,
+        }
+    }
+}
+
+/// A guard around the data obtained from 'PreemptRwLock::read'.
+pub struct PreemptRwLockReadGuard<'a, T: ?Sized + 'a> {
+    // Fields are dropped in order, so the lock is released before preemption is enabled.
+    data: RwLockReadGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<'a, T: ?Sized> Deref for PreemptRwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.data
+    }
+}
+
+/// A guard around the data obtained from 'PreemptRwLock::write'.
+pub struct PreemptRwLockWriteGuard<'a, T: ?Sized + 'a> {
+    // Fields are dropped in order, so the lock is released before preemption is enabled.
+    data: RwLockWriteGuard<'a, T>,
+    _preempt: PreemptGuard,
+}
+
+impl<'a, T: ?Sized> Deref for PreemptRwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &*self.data
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for PreemptRwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut *self.data
+    }
+}
\ No newline at end of file
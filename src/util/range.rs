@@ -0,0 +1,85 @@
+/// An inclusive range of `usize` numbers that is iterated from both ends without overflowing, so
+/// it can end at `usize::MAX`. This is the counter behind `PageIter` and `FrameIter`.
+#[derive(Debug, Clone)]
+pub struct InclusiveRange {
+    start: usize,
+    end: usize,
+    /// Set once the last number was returned, or when `start > end`.
+    exhausted: bool,
+}
+
+impl InclusiveRange {
+    pub fn new(start: usize, end: usize) -> InclusiveRange {
+        InclusiveRange {
+            start,
+            end,
+            exhausted: start > end,
+        }
+    }
+
+    /// Returns the amount of numbers left. The range `0..=usize::MAX` holds one more number than
+    /// fits in a `usize`, so its length saturates at `usize::MAX`.
+    pub fn len(&self) -> usize {
+        if self.exhausted {
+            0
+        } else {
+            (self.end - self.start).saturating_add(1)
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exhausted
+    }
+}
+
+impl Iterator for InclusiveRange {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.exhausted {
+            return None;
+        }
+
+        let value = self.start;
+        if self.start == self.end {
+            self.exhausted = true;
+        } else {
+            self.start += 1;
+        }
+
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len(), Some(self.len()))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<usize> {
+        if self.exhausted || n > self.end - self.start {
+            self.exhausted = true;
+            return None;
+        }
+
+        self.start += n;
+        self.next()
+    }
+}
+
+impl DoubleEndedIterator for InclusiveRange {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.exhausted {
+            return None;
+        }
+
+        let value = self.end;
+        if self.start == self.end {
+            self.exhausted = true;
+        } else {
+            self.end -= 1;
+        }
+
+        Some(value)
+    }
+}
+
+impl ExactSizeIterator for InclusiveRange {}
\ No newline at end of file
@@ -0,0 +1,76 @@
+use core::cmp;
+
+/// A byte ring buffer over any byte storage, like `Bitmap`. When it is full, pushing more bytes
+/// discards the oldest ones.
+pub struct RingBuffer<T: AsRef<[u8]> + AsMut<[u8]>> {
+    bytes: T,
+    /// The index of the oldest byte in `bytes`.
+    start: usize,
+    len: usize,
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> RingBuffer<T> {
+    /// Creates a new, empty `RingBuffer` over `bytes`.
+    pub const fn new(bytes: T) -> RingBuffer<T> {
+        RingBuffer {
+            bytes,
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the amount of bytes the buffer can hold.
+    pub fn capacity(&self) -> usize {
+        self.bytes.as_ref().len()
+    }
+
+    /// Returns the amount of bytes in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends `data`, discarding the oldest bytes if there isn't enough room.
+    pub fn push(&mut self, data: &[u8]) {
+        let capacity = self.capacity();
+        if capacity == 0 {
+            return;
+        }
+
+        // Only the last `capacity` bytes of `data` would survive anyway.
+        let data = &data[data.len().saturating_sub(capacity)..];
+
+        for &byte in data {
+            let end = (self.start + self.len) % capacity;
+            self.bytes.as_mut()[end] = byte;
+
+            if self.len == capacity {
+                self.start = (self.start + 1) % capacity;
+            } else {
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Copies the bytes from `offset` on, where offset 0 is the oldest byte, to `buf`. Returns the
+    /// amount of bytes copied, which is 0 if `offset` is past the end.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let capacity = self.capacity();
+        let len = cmp::min(buf.len(), self.len.saturating_sub(offset));
+
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = self.bytes.as_ref()[(self.start + offset + i) % capacity];
+        }
+
+        len
+    }
+
+    /// Discards every byte in the buffer.
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+}
\ No newline at end of file
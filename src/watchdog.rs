@@ -0,0 +1,159 @@
+//! A watchdog that turns a hung kernel into a panic. Code that makes progress bumps a heartbeat,
+//! the idle loop and waiting do so automatically. The heartbeat is checked from a non-maskable
+//! interrupt, raised by a performance counter every `CHECK_INTERVAL_MS` of unhalted CPU time, so
+//! hangs with interrupts disabled and in IRQ handlers are detected as well. The checks are the
+//! only clock of the watchdog. A halted CPU doesn't raise them, but it isn't hung either.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use driver::apic;
+use interrupts::StackFrame;
+use panic::PanicType;
+use time;
+use util::irq_lock::IrqLock;
+use x86_64::perf;
+
+/// The time the heartbeat may stay the same before the watchdog panics, in milliseconds.
+pub const TIMEOUT_MS: u64 = 5000;
+
+/// The unhalted CPU time between two checks, in milliseconds.
+pub const CHECK_INTERVAL_MS: u64 = 100;
+
+/// How long the cycle counter is measured against the PIT at boot, to find the amount of cycles
+/// per check, in milliseconds.
+const CALIBRATION_MS: u32 = 10;
+
+static HEARTBEAT: AtomicU64 = AtomicU64::new(0);
+
+/// The amount of checks since `init`.
+static CHECKS: AtomicU64 = AtomicU64::new(0);
+
+/// The amount of cycles between two checks, or 0 before `init` started the counter.
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+
+/// Cleared by `disable`, checked before taking the lock, so panicking never waits on it.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static WATCHDOG: IrqLock<Option<Watchdog<fn() -> u64>>> = IrqLock::new(None);
+
+/// Starts the watchdog. Returns false if there is no cycle counter or local APIC to raise the
+/// checks with.
+pub fn init() -> bool {
+    if !perf::has_cycle_counter() {
+        return false;
+    }
+
+    // Busy waiting keeps the CPU from halting, so every cycle of the delay is counted.
+    perf::start_cycle_counter(0, false);
+    time::delay_ms(CALIBRATION_MS);
+    let cycles_per_ms = perf::read_cycle_counter() / u64::from(CALIBRATION_MS);
+    perf::stop_cycle_counter();
+
+    let period = (cycles_per_ms * CHECK_INTERVAL_MS).min(perf::MAX_PERIOD);
+    if period == 0 || !apic::set_perf_counter_nmi() {
+        return false;
+    }
+
+    *WATCHDOG.lock() = Some(Watchdog::new(TIMEOUT_MS, HEARTBEAT.load(Ordering::SeqCst), elapsed_ms as fn() -> u64));
+    PERIOD.store(period, Ordering::SeqCst);
+    ENABLED.store(true, Ordering::SeqCst);
+    perf::start_cycle_counter(period, true);
+    true
+}
+
+/// Returns the amount of checks since `init`.
+pub fn checks() -> u64 {
+    CHECKS.load(Ordering::SeqCst)
+}
+
+/// The clock of the watchdog: the unhalted CPU time since `init`, in milliseconds.
+fn elapsed_ms() -> u64 {
+    checks() * CHECK_INTERVAL_MS
+}
+
+/// Stops checking the heartbeat, e.g. when the kernel panics and halts on purpose.
+pub fn disable() {
+    ENABLED.store(false, Ordering::SeqCst);
+}
+
+/// Bumps the heartbeat. Long-running operations call this now and then, so they aren't mistaken
+/// for a hang.
+pub fn pet() {
+    HEARTBEAT.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Called by the NMI handler with the state of the interrupted code. Returns false if the NMI was
+/// not raised by the watchdog. Otherwise, starts the next check and panics with that state if the
+/// heartbeat didn't advance within `TIMEOUT_MS`. After `disable`, the NMI is claimed but no next
+/// check is started.
+pub fn handle_nmi(stack_frame: &StackFrame) -> bool {
+    let period = PERIOD.load(Ordering::SeqCst);
+    if period == 0 || !perf::has_overflowed() {
+        return false;
+    }
+
+    if !ENABLED.load(Ordering::SeqCst) {
+        perf::stop_cycle_counter();
+        return true;
+    }
+
+    CHECKS.fetch_add(1, Ordering::SeqCst);
+    perf::restart_cycle_counter(period);
+    apic::unmask_perf_counter_nmi();
+
+    if let Some(mut watchdog) = WATCHDOG.try_lock() {
+        if let Some(watchdog) = watchdog.as_mut() {
+            watchdog.check(HEARTBEAT.load(Ordering::SeqCst), |stalled| {
+                disable();
+                crate::panic::enter_emergency();
+                crate::panic::panic(PanicType::KernelException {
+                    name: "Watchdog Timeout",
+                    stack_frame,
+                    additional_info: Some(format_args!("\x1b[37mNo heartbeat for: \x1b[97m{} ms", stalled)),
+                })
+            });
+        }
+    }
+
+    true
+}
+
+/// Detects a heartbeat that stopped advancing. `clock` returns the current time in milliseconds.
+pub struct Watchdog<C> where C: Fn() -> u64 {
+    timeout: u64,
+    clock: C,
+    /// The heartbeat at the last check.
+    heartbeat: u64,
+    /// The time the heartbeat last changed.
+    last_progress: u64,
+}
+
+impl<C> Watchdog<C> where C: Fn() -> u64 {
+    pub fn new(timeout: u64, heartbeat: u64, clock: C) -> Watchdog<C> {
+        let last_progress = clock();
+
+        Watchdog {
+            timeout,
+            clock,
+            heartbeat,
+            last_progress,
+        }
+    }
+
+    /// Compares `heartbeat` to the heartbeat of the last check. Calls `on_timeout` with the time
+    /// it stayed the same if that is longer than the timeout.
+    pub fn check<F>(&mut self, heartbeat: u64, on_timeout: F) where F: FnOnce(u64) {
+        let now = (self.clock)();
+
+        if heartbeat != self.heartbeat {
+            self.heartbeat = heartbeat;
+            self.last_progress = now;
+            return;
+        }
+
+        let stalled = now.saturating_sub(self.last_progress);
+        if stalled > self.timeout {
+            on_timeout(stalled);
+        }
+    }
+}
\ No newline at end of file
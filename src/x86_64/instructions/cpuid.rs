@@ -0,0 +1,25 @@
+/// The registers returned by the `cpuid` instruction.
+#[derive(Debug, Copy, Clone)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Executes `cpuid` for `leaf`, with sub-leaf 0.
+pub fn cpuid(leaf: u32) -> CpuidResult {
+    let eax: u32;
+    let ebx: u32;
+    let ecx: u32;
+    let edx: u32;
+
+    unsafe {
+        asm!("cpuid"
+             : "={eax}" (eax), "={ebx}" (ebx), "={ecx}" (ecx), "={edx}" (edx)
+             : "{eax}" (leaf), "{ecx}" (0)
+             :: "volatile");
+    }
+
+    CpuidResult { eax, ebx, ecx, edx }
+}
\ No newline at end of file
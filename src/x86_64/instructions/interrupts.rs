@@ -14,10 +14,28 @@ pub fn disable() {
     }
 }
 
-pub fn with_disabled<T>(f: impl Fn() -> T) -> T {
+/// Enables interrupts and halts until the next interrupt. `sti` only takes effect after the next
+/// instruction, so an interrupt that became pending while interrupts were disabled wakes the `hlt`
+/// instead of firing before it.
+#[inline]
+pub fn enable_and_hlt() {
+    unsafe {
+        asm!("sti
+              hlt" :::: "volatile");
+    }
+}
+
+/// Runs `f` with interrupts disabled. Interrupts are only enabled again afterwards if they were
+/// enabled before, so this can safely be nested or used from interrupt handlers.
+pub fn with_disabled<T>(f: impl FnOnce() -> T) -> T {
+    let enabled = are_enabled();
+
     disable();
     let out = f();
-    enable();
+
+    if enabled {
+        enable();
+    }
 
     out
 }
@@ -1,8 +1,20 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use x86_64::VirtualAddress;
 use x86_64::registers::control::Cr3;
 
 pub mod tables;
 pub mod interrupts;
+pub mod cpuid;
+
+/// The amount of pages above which `TLB::flush_range` flushes the whole TLB once, instead of
+/// invalidating every page separately.
+pub const TLB_FLUSH_ALL_THRESHOLD: usize = 32;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The amount of times the whole TLB was flushed by `TLB::flush_all`.
+static FULL_FLUSHES: AtomicUsize = AtomicUsize::new(0);
 
 pub struct TLB;
 
@@ -12,8 +24,76 @@ impl TLB {
     }
 
     pub fn flush_all() {
+        FULL_FLUSHES.fetch_add(1, Ordering::SeqCst);
         Cr3::write(Cr3::read());
     }
+
+    /// Returns the amount of times `flush_all` was called since boot.
+    pub fn full_flushes() -> usize {
+        FULL_FLUSHES.load(Ordering::SeqCst)
+    }
+
+    /// Invalidates every page that overlaps `start..end`, or flushes the whole TLB if that are
+    /// more than `TLB_FLUSH_ALL_THRESHOLD` pages.
+    pub fn flush_range(start: VirtualAddress, end: VirtualAddress) {
+        TLB::flush_range_with(start, end, TLB::flush, TLB::flush_all);
+    }
+
+    /// Like `flush_range`, but calls `flush` and `flush_all` to do the invalidation, so tests can
+    /// count them.
+    pub fn flush_range_with<F, G>(start: VirtualAddress, end: VirtualAddress, flush: F, flush_all: G)
+        where F: FnMut(VirtualAddress), G: FnOnce() {
+        if end.as_u64() > start.as_u64() {
+            TLB::flush_pages_with(start, VirtualAddress::new(end.as_u64() - 1), flush, flush_all);
+        }
+    }
+
+    /// Like `flush_range_with`, but for the pages from the one that contains `first` up to and
+    /// including the one that contains `last`, so the last page of the address space can be
+    /// flushed without its end overflowing.
+    pub fn flush_pages_with<F, G>(first: VirtualAddress, last: VirtualAddress, mut flush: F, flush_all: G)
+        where F: FnMut(VirtualAddress), G: FnOnce() {
+        let first = first.as_u64() & !(PAGE_SIZE - 1);
+        let last = last.as_u64() & !(PAGE_SIZE - 1);
+        if last < first {
+            return;
+        }
+
+        let pages = (last - first) / PAGE_SIZE + 1;
+        if pages as usize > TLB_FLUSH_ALL_THRESHOLD {
+            flush_all();
+            return;
+        }
+
+        for i in 0..pages {
+            flush(VirtualAddress::new(first + i * PAGE_SIZE));
+        }
+    }
+
+    /// Removes the pages from the one that contains `first` up to and including the one that
+    /// contains `last` from the TLB of every CPU that could have cached them. There is only one CPU
+    /// for now, so this flushes locally. Page table changes that other CPUs have to see go through
+    /// here, so this is where an IPI-based shootdown goes with SMP.
+    pub fn shootdown(first: VirtualAddress, last: VirtualAddress) {
+        TLB::flush_pages_with(first, last, TLB::flush, TLB::flush_all);
+    }
+}
+
+/// Returns the stack pointer of the caller.
+#[inline(always)]
+pub fn read_rsp() -> VirtualAddress {
+    let rsp: u64;
+    unsafe { asm!("mov $0, rsp" : "=r" (rsp) ::: "intel") };
+    VirtualAddress::new(rsp)
+}
+
+/// Returns the frame pointer of the caller. Frame pointers are kept in every function, see
+/// `x86_64-os.json`, so the saved frame pointers on the stack form a chain.
+#[inline(always)]
+pub fn read_rbp() -> VirtualAddress {
+    let rbp: u64;
+    unsafe { asm!("mov $0, rbp" : "=r" (rbp) ::: "intel") };
+    VirtualAddress::new(rbp)
 }
 
 pub fn hlt_loop() -> ! {
@@ -16,12 +16,32 @@ impl DescriptorTablePointer {
     }
 }
 
+/// Loads the IDT described by `ptr`.
+///
+/// # Safety
+/// The table `ptr` points to must stay valid for as long as it is loaded. Use
+/// `InterruptDescriptorTable::load` instead, which only accepts `'static` tables.
+pub unsafe fn lidt(ptr: &DescriptorTablePointer) {
+    asm!("lidt [$0]" :: "r" (ptr) : "memory" : "intel");
+}
+
+/// Loads the GDT described by `ptr`.
+///
+/// # Safety
+/// The table `ptr` points to must stay valid for as long as it is loaded. Use
+/// `GlobalDescriptorTable::load` instead, which only accepts `'static` tables.
+pub unsafe fn lgdt(ptr: &DescriptorTablePointer) {
+    asm!("lgdt [$0]" :: "r" (ptr) : "memory" : "intel");
+}
+
+#[deprecated(note = "use `InterruptDescriptorTable::load`, which requires the table to be `'static`")]
 pub fn load_idt(ptr: DescriptorTablePointer) {
-    unsafe { asm!("lidt [$0]" :: "r" (&ptr) : "memory" : "intel") };
+    unsafe { lidt(&ptr) };
 }
 
+#[deprecated(note = "use `GlobalDescriptorTable::load`, which requires the table to be `'static`")]
 pub fn load_gdt(ptr: DescriptorTablePointer) {
-    unsafe { asm!("lgdt [$0]" :: "r" (&ptr) : "memory" : "intel") };
+    unsafe { lgdt(&ptr) };
 }
 
 pub fn load_tss(selector: SegmentSelector) {
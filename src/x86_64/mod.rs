@@ -2,9 +2,13 @@ use core::fmt;
 use core::fmt::{Formatter, Error};
 use core::ops::{Add, Sub, AddAssign, SubAssign};
 
+use util::math::{align_down_to, align_up_to};
+
 pub mod instructions;
 pub mod registers;
+pub mod perf;
 pub mod port;
+pub mod power;
 
 #[derive(Default, Copy, Clone)]
 #[repr(transparent)]
@@ -34,6 +38,21 @@ impl VirtualAddress {
     pub fn as_mut_ptr<T>(self) -> *mut T {
         self.0 as *mut T
     }
+
+    /// Aligns the address up to a multiple of `align`, which has to be a power of two.
+    pub fn align_up(self, align: u64) -> VirtualAddress {
+        VirtualAddress(align_up_to(self.0 as usize, align as usize) as u64)
+    }
+
+    /// Aligns the address down to a multiple of `align`, which has to be a power of two.
+    pub fn align_down(self, align: u64) -> VirtualAddress {
+        VirtualAddress(align_down_to(self.0 as usize, align as usize) as u64)
+    }
+
+    /// Checks if the address is a multiple of `align`, which has to be a power of two.
+    pub fn is_aligned(self, align: u64) -> bool {
+        self.align_down(align).0 == self.0
+    }
 }
 
 impl From<VirtualAddress> for u64 {
@@ -86,8 +105,23 @@ impl PhysicalAddress {
     }
 
     pub fn as_u64(self) -> u64 {
-    self.0
-}
+        self.0
+    }
+
+    /// Aligns the address up to a multiple of `align`, which has to be a power of two.
+    pub fn align_up(self, align: u64) -> PhysicalAddress {
+        PhysicalAddress(align_up_to(self.0 as usize, align as usize) as u64)
+    }
+
+    /// Aligns the address down to a multiple of `align`, which has to be a power of two.
+    pub fn align_down(self, align: u64) -> PhysicalAddress {
+        PhysicalAddress(align_down_to(self.0 as usize, align as usize) as u64)
+    }
+
+    /// Checks if the address is a multiple of `align`, which has to be a power of two.
+    pub fn is_aligned(self, align: u64) -> bool {
+        self.align_down(align).0 == self.0
+    }
 }
 
 impl From<PhysicalAddress> for u64 {
@@ -0,0 +1,98 @@
+//! Performance counter 0 of architectural performance monitoring, counting the core cycles the CPU
+//! is not halted. When it overflows, it raises the performance counter interrupt of the local
+//! APIC, which can be delivered as an NMI.
+
+use x86_64::instructions::cpuid::cpuid;
+use x86_64::registers::msr::{Msr, read_msr, write_msr};
+
+/// The `cpuid` leaf that describes architectural performance monitoring.
+const CPUID_PERFORMANCE_MONITORING: u32 = 0x0a;
+
+/// Set in EBX of the leaf when the unhalted core cycles event is not available.
+const CPUID_NO_UNHALTED_CYCLES: u32 = 1;
+
+/// The event number of unhalted core cycles, its unit mask is zero.
+const EVENT_UNHALTED_CORE_CYCLES: u64 = 0x3c;
+
+/// Bits of the event select register: count in user mode and kernel mode, raise the interrupt on
+/// overflow and enable the counter.
+const SELECT_USER: u64 = 1 << 16;
+const SELECT_KERNEL: u64 = 1 << 17;
+const SELECT_INTERRUPT: u64 = 1 << 20;
+const SELECT_ENABLE: u64 = 1 << 22;
+
+/// The bit of counter 0 in the global control and status registers.
+const GLOBAL_COUNTER_0: u64 = 1;
+
+/// The longest period a counter can be started with. `wrmsr` only writes the low 32 bits of a
+/// counter and sign extends them.
+pub const MAX_PERIOD: u64 = (1 << 31) - 1;
+
+/// Returns the version of architectural performance monitoring, or 0 if there is none.
+fn version() -> u32 {
+    if cpuid(0).eax < CPUID_PERFORMANCE_MONITORING {
+        return 0;
+    }
+
+    cpuid(CPUID_PERFORMANCE_MONITORING).eax & 0xff
+}
+
+/// Returns whether the CPU has a counter for unhalted core cycles.
+pub fn has_cycle_counter() -> bool {
+    if version() == 0 {
+        return false;
+    }
+
+    let leaf = cpuid(CPUID_PERFORMANCE_MONITORING);
+    let counters = (leaf.eax >> 8) & 0xff;
+    let events = (leaf.eax >> 24) & 0xff;
+
+    counters > 0 && events > 0 && leaf.ebx & CPUID_NO_UNHALTED_CYCLES == 0
+}
+
+/// Starts counting unhalted core cycles on counter 0. With `interrupt`, the counter overflows and
+/// raises its interrupt after `period` cycles, otherwise it counts up from zero.
+pub fn start_cycle_counter(period: u64, interrupt: bool) {
+    assert!(period <= MAX_PERIOD, "Invalid performance counter period: {}", period);
+
+    let interrupt = if interrupt { SELECT_INTERRUPT } else { 0 };
+    write_msr(Msr::PerfEventSelect0, 0);
+    write_msr(Msr::PerfCounter0, period.wrapping_neg());
+    write_msr(Msr::PerfEventSelect0, EVENT_UNHALTED_CORE_CYCLES | SELECT_USER | SELECT_KERNEL | interrupt | SELECT_ENABLE);
+
+    if version() >= 2 {
+        write_msr(Msr::PerfGlobalCtrl, read_msr(Msr::PerfGlobalCtrl) | GLOBAL_COUNTER_0);
+    }
+}
+
+/// Starts the next `period` cycles after counter 0 overflowed, and clears the overflow. Takes no
+/// lock, so it can be used in interrupt handlers.
+pub fn restart_cycle_counter(period: u64) {
+    write_msr(Msr::PerfCounter0, period.wrapping_neg());
+
+    if version() >= 2 {
+        write_msr(Msr::PerfGlobalOverflowCtrl, GLOBAL_COUNTER_0);
+    }
+}
+
+/// Stops counter 0.
+pub fn stop_cycle_counter() {
+    write_msr(Msr::PerfEventSelect0, 0);
+}
+
+/// Returns the value of counter 0. For a counter started with a period, this is the amount of
+/// cycles since it overflowed.
+pub fn read_cycle_counter() -> u64 {
+    read_msr(Msr::PerfCounter0)
+}
+
+/// Returns whether counter 0 overflowed since it was last started. Version 1 has no overflow
+/// status, but a counter that was started with a period only has its upper bits cleared after
+/// it overflowed.
+pub fn has_overflowed() -> bool {
+    if version() >= 2 {
+        read_msr(Msr::PerfGlobalStatus) & GLOBAL_COUNTER_0 != 0
+    } else {
+        read_cycle_counter() <= MAX_PERIOD
+    }
+}
\ No newline at end of file
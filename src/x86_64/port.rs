@@ -1,5 +1,7 @@
 use core::marker::PhantomData;
 
+use x86_64::registers::rflags::RFlags;
+
 pub trait PortValue {}
 impl PortValue for u8 {}
 impl PortValue for u16 {}
@@ -49,20 +51,44 @@ impl Port<u16> {
             asm!("outw %ax, %dx" :: "{dx}" (self.port), "{ax}" (value) :: "volatile")
         }
     }
+
+    /// Fills `buf` with words read from the port with `rep insw`, like the data port of an ATA
+    /// controller needs.
+    pub fn read_string(&self, buf: &mut [u16]) {
+        // The instruction advances both registers, the final values are not needed.
+        let mut _address = buf.as_mut_ptr();
+        let mut _count = buf.len();
+
+        RFlags::clear_direction_flag();
+        unsafe {
+            asm!("rep insw %dx, (%rdi)" : "+{rdi}" (_address), "+{rcx}" (_count) : "{dx}" (self.port) : "memory" : "volatile");
+        }
+    }
+
+    /// Writes all words in `buf` to the port with `rep outsw`.
+    pub fn write_string(&self, buf: &[u16]) {
+        let mut _address = buf.as_ptr();
+        let mut _count = buf.len();
+
+        RFlags::clear_direction_flag();
+        unsafe {
+            asm!("rep outsw (%rsi), %dx" : "+{rsi}" (_address), "+{rcx}" (_count) : "{dx}" (self.port) : "memory" : "volatile");
+        }
+    }
 }
 
 impl Port<u32> {
     pub fn read(&self) -> u32 {
         let value: u32;
         unsafe {
-            asm!("inb %dx, %eax" : "={eax}" (value) : "{dx}" (self.port) :: "volatile");
+            asm!("inl %dx, %eax" : "={eax}" (value) : "{dx}" (self.port) :: "volatile");
         }
         value
     }
 
     pub fn write(&self, value: u32) {
         unsafe {
-            asm!("outb %eax, %dx" :: "{eax}" (self.port), "{al}" (value) :: "volatile")
+            asm!("outl %eax, %dx" :: "{dx}" (self.port), "{eax}" (value) :: "volatile")
         }
     }
 }
\ No newline at end of file
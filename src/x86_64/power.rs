@@ -0,0 +1,128 @@
+//! Rebooting and powering off the machine.
+//!
+//! On real hardware, powering off needs ACPI: the sleep type for S5 comes from the `_S5` object in
+//! the DSDT, which needs an AML interpreter that the kernel does not have. Only emulators can be
+//! powered off for now, through the ports they provide for it. Rebooting through the keyboard
+//! controller works on emulators and on most hardware, and a triple fault resets every CPU.
+
+use time;
+use x86_64::VirtualAddress;
+use x86_64::instructions::{hlt_loop, interrupts};
+use x86_64::instructions::tables::{DescriptorTablePointer, lidt};
+use x86_64::port::Port;
+
+/// The ways to reboot, in the order they are tried.
+pub const REBOOT_METHODS: [RebootMethod; 2] = [RebootMethod::KeyboardController, RebootMethod::TripleFault];
+
+/// The ways to power off, in the order they are tried.
+pub const SHUTDOWN_METHODS: [ShutdownMethod; 3] = [ShutdownMethod::Qemu, ShutdownMethod::Bochs, ShutdownMethod::VirtualBox];
+
+/// The port of the `isa-debug-exit` device of QEMU, when it is added with
+/// `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
+pub const QEMU_EXIT_PORT: u16 = 0xf4;
+
+/// How long to wait for a method to take effect before the next one is tried.
+const METHOD_TIMEOUT_MS: u32 = 100;
+
+/// Set in the status register of the keyboard controller while it has not read the last command.
+const KEYBOARD_INPUT_FULL: u8 = 1 << 1;
+
+/// The amount of times the keyboard controller status is polled before giving up on it.
+const KEYBOARD_SPIN_LIMIT: usize = 100_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RebootMethod {
+    /// Pulses the reset line through the keyboard controller.
+    KeyboardController,
+    /// Loads an empty IDT and raises an exception, which the CPU can't handle.
+    TripleFault,
+}
+
+impl RebootMethod {
+    /// Tries to reboot with this method. Returns if the machine did not reboot.
+    pub fn attempt(self) {
+        match self {
+            RebootMethod::KeyboardController => {
+                let status: Port<u8> = Port::new(0x64);
+
+                for _ in 0..KEYBOARD_SPIN_LIMIT {
+                    if status.read() & KEYBOARD_INPUT_FULL == 0 {
+                        break;
+                    }
+                }
+
+                status.write(0xfe);
+            },
+            RebootMethod::TripleFault => {
+                // An empty IDT turns the breakpoint into a triple fault.
+                unsafe { lidt(&DescriptorTablePointer::new(VirtualAddress::new(0), 0)) };
+                unsafe { asm!("int3" :::: "volatile") };
+            },
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ShutdownMethod {
+    /// The ACPI power management port of QEMU 2.0 and later.
+    Qemu,
+    /// The port of Bochs and older versions of QEMU.
+    Bochs,
+    /// The ACPI power management port of VirtualBox.
+    VirtualBox,
+}
+
+impl ShutdownMethod {
+    /// Returns the port and the value that power off the emulator.
+    pub fn port(self) -> (u16, u16) {
+        match self {
+            ShutdownMethod::Qemu => (0x604, 0x2000),
+            ShutdownMethod::Bochs => (0xb004, 0x2000),
+            ShutdownMethod::VirtualBox => (0x4004, 0x3400),
+        }
+    }
+
+    /// Tries to power off with this method. Returns if the machine is still running.
+    pub fn attempt(self) {
+        let (port, value) = self.port();
+        Port::<u16>::new(port).write(value);
+    }
+}
+
+/// Reboots the machine.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    try_in_order(&REBOOT_METHODS, |method| {
+        method.attempt();
+        time::delay_ms(METHOD_TIMEOUT_MS);
+        false
+    });
+
+    hlt_loop()
+}
+
+/// Powers off the machine. Only works on emulators, see the module documentation. On other
+/// machines, the CPU is halted instead.
+pub fn shutdown() -> ! {
+    try_in_order(&SHUTDOWN_METHODS, |method| {
+        method.attempt();
+        time::delay_ms(METHOD_TIMEOUT_MS);
+        false
+    });
+
+    crate::kprintln!("Could not power off, it is now safe to turn off the computer.");
+    interrupts::disable();
+    hlt_loop()
+}
+
+/// Exits QEMU through the `isa-debug-exit` device. QEMU exits with status `(code << 1) | 1`, so it
+/// can't be mistaken for a normal exit. Returns if the device is missing.
+pub fn qemu_exit(code: u32) {
+    Port::<u32>::new(QEMU_EXIT_PORT).write(code);
+}
+
+/// Calls `attempt` for the methods in `methods` in order, until one returns true. Returns that
+/// method, or `None` once every method failed. Attempts that work usually don't return at all.
+pub fn try_in_order<M: Copy>(methods: &[M], mut attempt: impl FnMut(M) -> bool) -> Option<M> {
+    methods.iter().cloned().find(|&method| attempt(method))
+}
\ No newline at end of file
@@ -1,5 +1,66 @@
 use flagset::{flags, FlagSet};
 
+use x86_64::PhysicalAddress;
+
+/// The model specific registers the kernel knows about. Use `read_msr` and `write_msr`, or the
+/// typed wrappers below for registers with defined bits, instead of raw register numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msr {
+    /// The physical address of the local APIC and whether it is enabled, see `ApicBase`.
+    ApicBase,
+    /// Extended features like long mode and no-execute pages, see `EFER`.
+    Efer,
+    /// The segment selectors `syscall` and `sysret` load.
+    Star,
+    /// The address `syscall` jumps to in 64-bit mode.
+    Lstar,
+    /// The RFLAGS bits `syscall` clears.
+    Sfmask,
+    FsBase,
+    GsBase,
+    KernelGsBase,
+    /// The event performance counter 0 counts, see `x86_64::perf`.
+    PerfEventSelect0,
+    /// Performance counter 0.
+    PerfCounter0,
+    /// Which performance counters overflowed, on architectural performance monitoring version 2
+    /// and up.
+    PerfGlobalStatus,
+    /// Which performance counters are enabled, on version 2 and up.
+    PerfGlobalCtrl,
+    /// Clears bits of `PerfGlobalStatus` that are written as ones, on version 2 and up.
+    PerfGlobalOverflowCtrl,
+}
+
+impl Msr {
+    /// Returns the number `rdmsr` and `wrmsr` identify the register with.
+    pub fn number(self) -> u64 {
+        match self {
+            Msr::ApicBase => 0x1b,
+            Msr::Efer => 0xc000_0080,
+            Msr::Star => 0xc000_0081,
+            Msr::Lstar => 0xc000_0082,
+            Msr::Sfmask => 0xc000_0084,
+            Msr::FsBase => 0xc000_0100,
+            Msr::GsBase => 0xc000_0101,
+            Msr::KernelGsBase => 0xc000_0102,
+            Msr::PerfEventSelect0 => 0x186,
+            Msr::PerfCounter0 => 0xc1,
+            Msr::PerfGlobalStatus => 0x38e,
+            Msr::PerfGlobalCtrl => 0x38f,
+            Msr::PerfGlobalOverflowCtrl => 0x390,
+        }
+    }
+}
+
+pub fn read_msr(msr: Msr) -> u64 {
+    MSR::read(msr.number())
+}
+
+pub fn write_msr(msr: Msr, value: u64) {
+    MSR::write(msr.number(), value);
+}
+
 flags! {
     pub enum EFERFlags: u64 {
         SystemCallExtensions = 1,
@@ -37,27 +98,88 @@ impl MSR {
     }
 }
 
+flags! {
+    pub enum ApicBaseFlags: u64 {
+        /// Set on the bootstrap processor, the CPU that started first.
+        BootstrapProcessor = 1 << 8,
+        X2ApicEnable = 1 << 10,
+        Enable = 1 << 11,
+    }
+}
+
+/// The APIC base MSR, which holds the physical address of the local APIC registers and flags.
+pub struct ApicBase;
+
+impl ApicBase {
+    /// The bits of the register that hold the page aligned address.
+    pub const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+    pub fn read() -> (PhysicalAddress, FlagSet<ApicBaseFlags>) {
+        ApicBase::decode(read_msr(Msr::ApicBase))
+    }
+
+    /// Writes `address` and `flags`, keeping the reserved bits.
+    pub fn write(address: PhysicalAddress, flags: impl Into<FlagSet<ApicBaseFlags>>) {
+        let reserved = read_msr(Msr::ApicBase) & !(ApicBase::ADDRESS_MASK | FlagSet::<ApicBaseFlags>::full().bits());
+        write_msr(Msr::ApicBase, reserved | ApicBase::encode(address, flags));
+    }
+
+    /// Splits a value of the register into the address and the flags.
+    pub fn decode(value: u64) -> (PhysicalAddress, FlagSet<ApicBaseFlags>) {
+        (PhysicalAddress::new(value & ApicBase::ADDRESS_MASK), FlagSet::new_truncated(value))
+    }
+
+    /// Combines an address and flags into a value of the register, without reserved bits.
+    pub fn encode(address: PhysicalAddress, flags: impl Into<FlagSet<ApicBaseFlags>>) -> u64 {
+        (address.as_u64() & ApicBase::ADDRESS_MASK) | flags.into().bits()
+    }
+}
+
 pub struct EFER;
 
 impl EFER {
-    pub const MSR_REG: u64 = 0xc000_0080;
-
     pub fn read() -> FlagSet<EFERFlags> {
-        FlagSet::new_truncated(MSR::read(EFER::MSR_REG))
+        FlagSet::new_truncated(read_msr(Msr::Efer))
     }
 
     pub fn write(flags: impl Into<FlagSet<EFERFlags>>) {
-        let old_value = MSR::read(EFER::MSR_REG);
+        let old_value = read_msr(Msr::Efer);
         let reserved = old_value & !(FlagSet::<EFERFlags>::full().bits());
         let new_value = reserved | flags.into().bits();
 
-        MSR::write(EFER::MSR_REG, new_value);
+        write_msr(Msr::Efer, new_value);
     }
 
     pub fn append(flags: impl Into<FlagSet<EFERFlags>>) {
-        let old_value = MSR::read(EFER::MSR_REG);
+        let old_value = read_msr(Msr::Efer);
         let new_value = old_value | flags.into().bits();
 
-        MSR::write(EFER::MSR_REG, new_value);
+        write_msr(Msr::Efer, new_value);
+    }
+}
+
+/// The base address of the GS segment, which the kernel points at its per-CPU data.
+pub struct GsBase;
+
+impl GsBase {
+    pub fn read() -> u64 {
+        read_msr(Msr::GsBase)
+    }
+
+    pub fn write(base: u64) {
+        write_msr(Msr::GsBase, base);
+    }
+}
+
+/// The GS base that `swapgs` swaps with `GsBase`.
+pub struct KernelGsBase;
+
+impl KernelGsBase {
+    pub fn read() -> u64 {
+        read_msr(Msr::KernelGsBase)
+    }
+
+    pub fn write(base: u64) {
+        write_msr(Msr::KernelGsBase, base);
     }
 }
\ No newline at end of file
@@ -25,8 +25,55 @@ flags! {
 
 impl RFlags {
     pub fn read() -> FlagSet<RFlags> {
+        FlagSet::new_truncated(RFlags::read_raw())
+    }
+
+    pub fn read_raw() -> u64 {
         let raw: u64;
         unsafe { asm!("pushfq; pop $0" : "=r" (raw) :: "memory" : "intel") };
-        FlagSet::new_truncated(raw)
+        raw
+    }
+
+    /// Writes `flags`, keeping the reserved bits.
+    ///
+    /// # Safety
+    /// Changing flags like the interrupt or trap flag changes how the following code runs, and
+    /// the direction flag has to be clear whenever compiled code runs.
+    pub unsafe fn write(flags: impl Into<FlagSet<RFlags>>) {
+        let reserved = RFlags::read_raw() & !FlagSet::<RFlags>::full().bits();
+        RFlags::write_raw(reserved | flags.into().bits());
+    }
+
+    /// Writes `raw` to the register as it is.
+    ///
+    /// # Safety
+    /// See `write`.
+    pub unsafe fn write_raw(raw: u64) {
+        asm!("push $0; popfq" :: "r" (raw) : "memory", "cc" : "intel", "volatile");
+    }
+
+    /// Returns `raw` with the trap flag set or cleared, and all other bits unchanged. This is
+    /// also used for the flags in a saved interrupt frame.
+    pub fn with_trap_flag(raw: u64, enabled: bool) -> u64 {
+        let trap_flag = FlagSet::from(RFlags::TrapFlag).bits();
+        if enabled {
+            raw | trap_flag
+        } else {
+            raw & !trap_flag
+        }
+    }
+
+    /// Sets or clears the trap flag. With the flag set, the CPU raises a debug exception after
+    /// every instruction.
+    ///
+    /// # Safety
+    /// A debug exception handler has to be installed before the trap flag is set.
+    pub unsafe fn set_trap_flag(enabled: bool) {
+        RFlags::write_raw(RFlags::with_trap_flag(RFlags::read_raw(), enabled));
+    }
+
+    /// Clears the direction flag, so string instructions like `rep insw` move forward.
+    pub fn clear_direction_flag() {
+        unsafe { asm!("cld" :::: "volatile") };
     }
 }
\ No newline at end of file